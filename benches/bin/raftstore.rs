@@ -11,8 +11,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::mpsc;
+
 use test::BenchSamples;
 use test_util::*;
+use util::*;
 use cluster::*;
 use node::new_node_cluster;
 use server::new_server_cluster;
@@ -23,6 +26,7 @@ use tikv::raftstore::store::*;
 use super::print_result;
 
 const DEFAULT_DATA_SIZE: usize = 100_000;
+const CONCURRENT_PUT_COUNT: usize = 32;
 
 fn enc_write_kvs(db: &DB, kvs: &[(Vec<u8>, Vec<u8>)]) {
     let wb = WriteBatch::new();
@@ -93,6 +97,34 @@ fn bench_delete<T: Simulator>(mut cluster: Cluster<T>) -> BenchSamples {
     }
 }
 
+// Sends many puts to the leader at once, so the small proposals can be batched.
+fn bench_concurrent_set<T: Simulator>(mut cluster: Cluster<T>, batch: bool) -> BenchSamples {
+    if batch {
+        cluster.cfg.raft_store.max_proposal_batch_size = 256 * 1024;
+    }
+    prepare_cluster(&mut cluster, &[]);
+
+    let leader = cluster.leader_of_region(1).unwrap();
+    let epoch = cluster.get_region_epoch(1);
+    let ch = cluster.sim.rl().get_store_sendch(leader.get_store_id()).unwrap();
+    let mut kvs = KvGenerator::new(100, 128);
+
+    bench!{
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..CONCURRENT_PUT_COUNT {
+            let (k, v) = kvs.next().unwrap();
+            let mut req = new_request(1, epoch.clone(), vec![new_put_cmd(&k, &v)], false);
+            req.mut_header().set_peer(leader.clone());
+            let tx = tx.clone();
+            ch.send(Msg::new_raft_cmd(req, box move |resp| tx.send(resp).unwrap())).unwrap();
+        }
+        for _ in 0..CONCURRENT_PUT_COUNT {
+            let resp = rx.recv().unwrap();
+            assert!(!resp.get_header().has_error(), "{:?}", resp);
+        }
+    }
+}
+
 fn bench_raft_cluster<T, F>(factory: F, tag: &'static str)
     where T: Simulator,
           F: Fn(u64, usize) -> Cluster<T>
@@ -105,6 +137,11 @@ fn bench_raft_cluster<T, F>(factory: F, tag: &'static str)
             print_set_progress(tag, ncnt, vlen);
             print_result(bench_set(factory(1, ncnt), vlen));
         }
+        for &batch in &[false, true] {
+            let action = if batch { "Batched Set" } else { "Concurrent Set" };
+            print_other_progress(tag, action, ncnt);
+            print_result(bench_concurrent_set(factory(1, ncnt), batch));
+        }
         print_other_progress(tag, "Get", ncnt);
         print_result(bench_get(factory(1, ncnt)));
         print_other_progress(tag, "Delete", ncnt);
@@ -0,0 +1,170 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str;
+
+use serde_json::{self, Map, Value};
+
+use super::{Error, Result};
+
+pub const MANIFEST_FILE_NAME: &'static str = "manifest.json";
+
+/// An SST file of the backup.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileMeta {
+    pub name: String,
+    pub region_id: u64,
+    pub cf: String,
+    /// The first and the last data key in the file.
+    pub start_key: Vec<u8>,
+    pub end_key: Vec<u8>,
+    pub crc32: u32,
+    pub kvs: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub backup_ts: u64,
+    pub files: Vec<FileMeta>,
+}
+
+impl Manifest {
+    pub fn new(backup_ts: u64) -> Manifest {
+        Manifest {
+            backup_ts: backup_ts,
+            files: vec![],
+        }
+    }
+
+    /// Writes the manifest to `dir`.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let files = self.files.iter().map(file_to_json).collect();
+        let mut m = Map::new();
+        m.insert("backup_ts".to_owned(), Value::from(self.backup_ts));
+        m.insert("files".to_owned(), Value::Array(files));
+        let data = try!(serde_json::to_vec_pretty(&Value::Object(m)));
+
+        let mut f = try!(File::create(dir.join(MANIFEST_FILE_NAME)));
+        try!(f.write_all(&data));
+        try!(f.sync_all());
+        Ok(())
+    }
+
+    /// Reads the manifest from `dir`.
+    pub fn load(dir: &Path) -> Result<Manifest> {
+        let mut data = vec![];
+        try!(try!(File::open(dir.join(MANIFEST_FILE_NAME))).read_to_end(&mut data));
+        let v: Value = try!(serde_json::from_slice(&data));
+        let mut manifest = Manifest::new(try!(get_u64(&v, "backup_ts")));
+        let files = match v.get("files").and_then(|f| f.as_array()) {
+            Some(files) => files,
+            None => return Err(Error::InvalidManifest("files are missing".to_owned())),
+        };
+        for f in files {
+            manifest.files.push(try!(file_from_json(f)));
+        }
+        Ok(manifest)
+    }
+}
+
+fn file_to_json(f: &FileMeta) -> Value {
+    let mut m = Map::new();
+    m.insert("name".to_owned(), Value::from(f.name.clone()));
+    m.insert("region_id".to_owned(), Value::from(f.region_id));
+    m.insert("cf".to_owned(), Value::from(f.cf.clone()));
+    m.insert("start_key".to_owned(), Value::from(to_hex(&f.start_key)));
+    m.insert("end_key".to_owned(), Value::from(to_hex(&f.end_key)));
+    m.insert("crc32".to_owned(), Value::from(f.crc32));
+    m.insert("kvs".to_owned(), Value::from(f.kvs));
+    m.insert("size".to_owned(), Value::from(f.size));
+    Value::Object(m)
+}
+
+fn file_from_json(v: &Value) -> Result<FileMeta> {
+    Ok(FileMeta {
+        name: try!(get_str(v, "name")).to_owned(),
+        region_id: try!(get_u64(v, "region_id")),
+        cf: try!(get_str(v, "cf")).to_owned(),
+        start_key: try!(from_hex(try!(get_str(v, "start_key")))),
+        end_key: try!(from_hex(try!(get_str(v, "end_key")))),
+        crc32: try!(get_u64(v, "crc32")) as u32,
+        kvs: try!(get_u64(v, "kvs")),
+        size: try!(get_u64(v, "size")),
+    })
+}
+
+fn get_u64(v: &Value, field: &str) -> Result<u64> {
+    v.get(field)
+        .and_then(|f| f.as_u64())
+        .ok_or_else(|| Error::InvalidManifest(format!("{} is missing", field)))
+}
+
+fn get_str<'a>(v: &'a Value, field: &str) -> Result<&'a str> {
+    v.get(field)
+        .and_then(|f| f.as_str())
+        .ok_or_else(|| Error::InvalidManifest(format!("{} is missing", field)))
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Result<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return Err(Error::InvalidManifest(format!("invalid hex {}", s)));
+    }
+    s.as_bytes()
+        .chunks(2)
+        .map(|c| {
+            str::from_utf8(c)
+                .ok()
+                .and_then(|c| u8::from_str_radix(c, 16).ok())
+                .ok_or_else(|| Error::InvalidManifest(format!("invalid hex {}", s)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let dir = TempDir::new("test-backup-manifest").unwrap();
+        assert!(Manifest::load(dir.path()).is_err());
+
+        let mut manifest = Manifest::new(10);
+        manifest.files.push(FileMeta {
+            name: "1_write.sst".to_owned(),
+            region_id: 1,
+            cf: "write".to_owned(),
+            start_key: b"za\x00\xff".to_vec(),
+            end_key: b"zb".to_vec(),
+            crc32: 123,
+            kvs: 2,
+            size: 1024,
+        });
+        manifest.save(dir.path()).unwrap();
+        assert_eq!(Manifest::load(dir.path()).unwrap(), manifest);
+
+        assert_eq!(from_hex("7a00ff").unwrap(), b"z\x00\xff".to_vec());
+        assert!(from_hex("7a0").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Consistent backup of the transactional data.
+//!
+//! A backup at `backup_ts` reads every region in the RocksDB of a stopped store, and keeps the
+//! latest version of each key committed before or at `backup_ts`. The write records and the
+//! long values of a region are streamed to an SST file per column family, and the files are
+//! listed in a JSON manifest with their key ranges, checksums and key counts. The backup fails
+//! if any key is locked by a transaction started before `backup_ts`, because the transaction
+//! may still be committed before `backup_ts`.
+//!
+//! `backup_ts` should be fetched from PD, so that any transaction committed later has a larger
+//! commit ts. Run `tikv-ctl backup` on every store to back up the whole cluster.
+//!
+//! Restoring ingests the files into the RocksDB of a stopped store directly, the regions
+//! covering the data should be created in advance.
+
+mod manifest;
+mod restore;
+mod writer;
+
+use std::error;
+use std::io::Error as IoError;
+use std::result;
+
+use serde_json::Error as JsonError;
+
+use import::Error as ImportError;
+use raftstore::Error as RaftStoreError;
+use storage::EngineError;
+use storage::mvcc::Error as MvccError;
+use util::codec::Error as CodecError;
+use util::escape;
+
+pub use self::manifest::{FileMeta, Manifest, MANIFEST_FILE_NAME};
+pub use self::restore::restore;
+pub use self::writer::{backup_region, backup_store};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Io(err: IoError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Import(err: ImportError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Engine(err: EngineError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Mvcc(err: MvccError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Codec(err: CodecError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        RaftStore(err: RaftStoreError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Json(err: JsonError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        KeyIsLocked(key: Vec<u8>, ts: u64) {
+            description("key is locked")
+            display("key {} is locked by transaction {}", escape(key), ts)
+        }
+        InvalidManifest(msg: String) {
+            description("invalid backup manifest")
+            display("invalid backup manifest: {}", msg)
+        }
+        Other(err: Box<error::Error + Sync + Send>) {
+            from()
+            cause(err.as_ref())
+            description(err.description())
+            display("{:?}", err)
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
@@ -0,0 +1,158 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use rocksdb::DB;
+
+use import;
+use storage::{CF_DEFAULT, CF_WRITE};
+use super::{Error, Manifest, Result};
+
+/// Ingests the files of the backup in `dir` into `db`, which must not be used by a running
+/// store.
+pub fn restore(db: &DB, dir: &Path) -> Result<Manifest> {
+    let manifest = try!(Manifest::load(dir));
+    for file in &manifest.files {
+        if file.cf != CF_DEFAULT && file.cf != CF_WRITE {
+            return Err(Error::InvalidManifest(format!("can't restore {} to cf {}",
+                                                      file.name,
+                                                      file.cf)));
+        }
+        let mut data = vec![];
+        try!(try!(File::open(dir.join(&file.name))).read_to_end(&mut data));
+        try!(import::ingest_sst(db, &file.cf, &data, file.crc32));
+        info!("restored {} with {} keys to cf {}", file.name, file.kvs, file.cf);
+    }
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use kvproto::kvrpcpb::{Context, IsolationLevel};
+    use kvproto::metapb::Region;
+    use kvproto::raft_serverpb::{PeerState, RegionLocalState};
+    use tempdir::TempDir;
+
+    use backup::{backup_region, backup_store, Error};
+    use raftstore::coprocessor::RegionSnapshot;
+    use raftstore::store::engine::Mutable;
+    use raftstore::store::keys;
+    use storage::{make_key, new_local_engine, Engine, Mutation, Options, Statistics, ALL_CFS,
+                  CF_WRITE, SHORT_VALUE_MAX_LEN, TEMP_DIR};
+    use storage::mvcc::{MvccReader, MvccTxn};
+    use util::rocksdb;
+    use super::*;
+
+    fn must_prewrite_put(engine: &Engine, key: &[u8], value: &[u8], ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   ts,
+                                   None,
+                                   IsolationLevel::SI);
+        txn.prewrite(Mutation::Put((make_key(key), value.to_vec())),
+                      key,
+                      &Options::default())
+            .unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_commit(engine: &Engine, key: &[u8], start_ts: u64, commit_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   start_ts,
+                                   None,
+                                   IsolationLevel::SI);
+        txn.commit(&make_key(key), commit_ts).unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let engine = new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let long_value = vec![b'v'; SHORT_VALUE_MAX_LEN + 1];
+        must_prewrite_put(engine.as_ref(), b"k1", b"v1", 1);
+        must_commit(engine.as_ref(), b"k1", 1, 2);
+        must_prewrite_put(engine.as_ref(), b"k2", &long_value, 3);
+        must_commit(engine.as_ref(), b"k2", 3, 4);
+        // Committed after the backup ts.
+        must_prewrite_put(engine.as_ref(), b"k1", b"v2", 6);
+        must_commit(engine.as_ref(), b"k1", 6, 7);
+
+        let dir = TempDir::new("test-backup").unwrap();
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut manifest = Manifest::new(5);
+        manifest.files = backup_region(snapshot.as_ref(), 1, 5, dir.path()).unwrap();
+        manifest.save(dir.path()).unwrap();
+        assert_eq!(manifest.files.len(), 2);
+        assert_eq!(manifest.files[0].cf, CF_WRITE);
+        assert_eq!(manifest.files[0].kvs, 2);
+        assert_eq!(manifest.files[1].kvs, 1);
+
+        // A pending lock started before the backup ts fails the backup.
+        must_prewrite_put(engine.as_ref(), b"k3", b"v3", 5);
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        match backup_region(snapshot.as_ref(), 1, 5, dir.path()) {
+            Err(Error::KeyIsLocked(key, 5)) => assert_eq!(key, b"k3".to_vec()),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let path = TempDir::new("test-restore").unwrap();
+        let db = Arc::new(rocksdb::new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap());
+        assert_eq!(restore(&db, dir.path()).unwrap(), manifest);
+        let snapshot = RegionSnapshot::from_raw(db.clone(), Region::new());
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(&snapshot,
+                                         &mut statistics,
+                                         None,
+                                         true,
+                                         None,
+                                         IsolationLevel::SI);
+        assert_eq!(reader.get(&make_key(b"k1"), 10).unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(reader.get(&make_key(b"k2"), 10).unwrap(), Some(long_value));
+        assert_eq!(reader.get(&make_key(b"k3"), 10).unwrap(), None);
+
+        // Back up the restored store, the tombstone region is skipped.
+        let mut state = RegionLocalState::new();
+        state.mut_region().set_id(1);
+        db.put_msg(&keys::region_state_key(1), &state).unwrap();
+        state.mut_region().set_id(2);
+        state.set_state(PeerState::Tombstone);
+        db.put_msg(&keys::region_state_key(2), &state).unwrap();
+        let store_dir = TempDir::new("test-backup-store").unwrap();
+        let store_manifest = backup_store(db.clone(), 10, store_dir.path()).unwrap();
+        assert_eq!(Manifest::load(store_dir.path()).unwrap(), store_manifest);
+        assert_eq!(store_manifest.files.len(), manifest.files.len());
+        for (f1, f2) in store_manifest.files.iter().zip(&manifest.files) {
+            assert_eq!((&f1.name, &f1.start_key, &f1.end_key, f1.kvs),
+                       (&f2.name, &f2.start_key, &f2.end_key, f2.kvs));
+        }
+
+        // A corrupted file is rejected.
+        let mut f = File::create(dir.path().join(&manifest.files[0].name)).unwrap();
+        f.write_all(b"corrupted").unwrap();
+        assert!(restore(&db, dir.path()).is_err());
+    }
+}
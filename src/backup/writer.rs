@@ -0,0 +1,226 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+use crc::crc32::{self, Digest, Hasher32};
+use kvproto::kvrpcpb::IsolationLevel;
+use kvproto::raft_serverpb::{PeerState, RegionLocalState};
+use protobuf;
+use rocksdb::{DB, EnvOptions, Options, SstFileWriter};
+
+use raftstore::coprocessor::RegionSnapshot;
+use raftstore::store::engine::Iterable;
+use raftstore::store::keys;
+use storage::{CfName, Key, ScanMode, Snapshot, Statistics, CF_DEFAULT, CF_WRITE};
+use storage::mvcc::{MvccReader, WriteType};
+use super::{Error, FileMeta, Manifest, Result};
+
+const SCAN_BATCH_SIZE: usize = 1024;
+const CHECKSUM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Backs up the regions of the store at `backup_ts` from `db` to `dir`, and writes the manifest
+/// of the files.
+///
+/// The store must be stopped, so that the regions don't change during the backup. A follower
+/// may lag behind its leader, so stop the store after all its regions have caught up.
+pub fn backup_store(db: Arc<DB>, backup_ts: u64, dir: &Path) -> Result<Manifest> {
+    let mut regions = vec![];
+    try!(db.scan(keys::REGION_META_MIN_KEY,
+                 keys::REGION_META_MAX_KEY,
+                 false,
+                 &mut |key, value| {
+        let (_, suffix) = try!(keys::decode_region_meta_key(key));
+        if suffix != keys::REGION_STATE_SUFFIX {
+            return Ok(true);
+        }
+        let mut local_state = try!(protobuf::parse_from_bytes::<RegionLocalState>(value));
+        if local_state.get_state() == PeerState::Normal {
+            regions.push(local_state.take_region());
+        }
+        Ok(true)
+    }));
+
+    try!(fs::create_dir_all(dir));
+    let mut manifest = Manifest::new(backup_ts);
+    for region in regions {
+        let region_id = region.get_id();
+        let snapshot = RegionSnapshot::from_raw(db.clone(), region);
+        let files = try!(backup_region(&snapshot, region_id, backup_ts, dir));
+        info!("[region {}] backed up to {} files at ts {}",
+              region_id,
+              files.len(),
+              backup_ts);
+        manifest.files.extend(files);
+    }
+    try!(manifest.save(dir));
+    Ok(manifest)
+}
+
+/// Writes the latest versions of the keys in `snapshot` committed before or at `backup_ts` to
+/// SST files in `dir`.
+///
+/// The keys are scanned in batches and written to the files as they are scanned.
+pub fn backup_region(snapshot: &Snapshot,
+                     region_id: u64,
+                     backup_ts: u64,
+                     dir: &Path)
+                     -> Result<Vec<FileMeta>> {
+    let mut statistics = Statistics::default();
+    let mut reader = MvccReader::new(snapshot,
+                                     &mut statistics,
+                                     Some(ScanMode::Forward),
+                                     false,
+                                     None,
+                                     IsolationLevel::SI);
+    let (locks, _) = try!(reader.scan_lock(None, |lock| lock.ts <= backup_ts, Some(1)));
+    if let Some((key, lock)) = locks.into_iter().next() {
+        return Err(Error::KeyIsLocked(try!(key.raw()), lock.ts));
+    }
+
+    let mut writes = SSTWriter::new(dir, region_id, CF_WRITE);
+    let mut values = SSTWriter::new(dir, region_id, CF_DEFAULT);
+    let mut start = None;
+    loop {
+        let (keys, next_start) = try!(reader.scan_keys(start, SCAN_BATCH_SIZE));
+        for key in keys {
+            try!(backup_key(&mut reader, key, backup_ts, &mut writes, &mut values));
+        }
+        if next_start.is_none() {
+            break;
+        }
+        start = next_start;
+    }
+
+    let mut files = vec![];
+    for writer in vec![writes, values] {
+        if let Some(file) = try!(writer.finish()) {
+            files.push(file);
+        }
+    }
+    Ok(files)
+}
+
+/// Writes the kvs of a column family of a region to an SST file. The file is created when the
+/// first kv is added, the kvs must be added in order.
+struct SSTWriter<'a> {
+    dir: &'a Path,
+    region_id: u64,
+    cf: CfName,
+    name: String,
+    writer: Option<SstFileWriter>,
+    range: Option<(Vec<u8>, Vec<u8>)>,
+    kvs: u64,
+}
+
+impl<'a> SSTWriter<'a> {
+    fn new(dir: &'a Path, region_id: u64, cf: CfName) -> SSTWriter<'a> {
+        SSTWriter {
+            dir: dir,
+            region_id: region_id,
+            cf: cf,
+            name: format!("{}_{}.sst", region_id, cf),
+            writer: None,
+            range: None,
+            kvs: 0,
+        }
+    }
+
+    fn add(&mut self, key: Vec<u8>, value: &[u8]) -> Result<()> {
+        if self.writer.is_none() {
+            let mut writer = SstFileWriter::new(EnvOptions::new(), Options::new());
+            box_try!(writer.open(self.dir.join(&self.name).to_str().unwrap()));
+            self.writer = Some(writer);
+        }
+        box_try!(self.writer.as_mut().unwrap().add(&key, value));
+        self.range = match self.range.take() {
+            Some((first, _)) => Some((first, key)),
+            None => Some((key.clone(), key)),
+        };
+        self.kvs += 1;
+        Ok(())
+    }
+
+    /// Finishes the file and returns its meta, or `None` if no kv is added.
+    fn finish(self) -> Result<Option<FileMeta>> {
+        let mut writer = match self.writer {
+            Some(writer) => writer,
+            None => return Ok(None),
+        };
+        box_try!(writer.finish());
+
+        // Computes the checksum by reading the file in chunks, so the file is never loaded
+        // into memory as a whole.
+        let mut f = try!(File::open(self.dir.join(&self.name)));
+        try!(f.sync_all());
+        let mut digest = Digest::new(crc32::IEEE);
+        let mut buf = vec![0; CHECKSUM_BUFFER_SIZE];
+        let mut size = 0;
+        loop {
+            let n = try!(f.read(&mut buf));
+            if n == 0 {
+                break;
+            }
+            digest.write(&buf[..n]);
+            size += n as u64;
+        }
+
+        let (start_key, end_key) = self.range.unwrap();
+        Ok(Some(FileMeta {
+            name: self.name,
+            region_id: self.region_id,
+            cf: self.cf.to_owned(),
+            start_key: start_key,
+            end_key: end_key,
+            crc32: digest.sum32(),
+            kvs: self.kvs,
+            size: size,
+        }))
+    }
+}
+
+/// Writes the write record and the long value of the version of `key` visible at `backup_ts`,
+/// as data keys that can be ingested directly.
+fn backup_key(reader: &mut MvccReader,
+              key: Key,
+              backup_ts: u64,
+              writes: &mut SSTWriter,
+              values: &mut SSTWriter)
+              -> Result<()> {
+    let mut ts = backup_ts;
+    while let Some((commit_ts, write)) = try!(reader.seek_write(&key, ts)) {
+        match write.write_type {
+            WriteType::Put => {
+                if write.short_value.is_none() {
+                    let value = try!(reader.load_data(&key, write.start_ts));
+                    try!(values.add(keys::data_key(key.append_ts(write.start_ts).encoded()),
+                                    &value));
+                }
+                try!(writes.add(keys::data_key(key.append_ts(commit_ts).encoded()),
+                                &write.to_bytes()));
+                return Ok(());
+            }
+            WriteType::Delete => return Ok(()),
+            WriteType::Lock | WriteType::Rollback => {
+                if commit_ts == 0 {
+                    return Ok(());
+                }
+                ts = commit_ts - 1;
+            }
+        }
+    }
+    Ok(())
+}
@@ -16,14 +16,18 @@
 mod imp {
     use std::{ptr, slice};
     use std::sync::Arc;
+    use std::sync::mpsc;
+    use std::time::Duration;
     use libc::{self, c_void, c_char};
 
     use rocksdb::DB;
     use prometheus::{self, Encoder, TextEncoder};
     use profiling;
+    use tikv::storage::{Storage, LatchInfo};
 
     const ROCKSDB_DB_STATS_KEY: &'static str = "rocksdb.dbstats";
     const ROCKSDB_CF_STATS_KEY: &'static str = "rocksdb.cfstats";
+    const DUMP_LATCHES_TIMEOUT_SECS: u64 = 1;
 
     extern "C" {
         #[cfg_attr(target_os = "macos", link_name = "je_malloc_stats_print")]
@@ -51,8 +55,28 @@ mod imp {
         info!("{}", String::from_utf8_lossy(&buf));
     }
 
+    fn print_latches(storage: &Storage) {
+        let (tx, rx) = mpsc::channel();
+        let cb = Box::new(move |infos: Vec<LatchInfo>| {
+            let _ = tx.send(infos);
+        });
+        if let Err(e) = storage.async_dump_latches(cb) {
+            warn!("failed to dump latches: {:?}", e);
+            return;
+        }
+        match rx.recv_timeout(Duration::from_secs(DUMP_LATCHES_TIMEOUT_SECS)) {
+            Ok(infos) => {
+                info!("{} latches are in use", infos.len());
+                for info in infos {
+                    info!("{:?}", info);
+                }
+            }
+            Err(e) => warn!("failed to dump latches: {:?}", e),
+        }
+    }
+
     // TODO: remove backup_path from configuration
-    pub fn handle_signal(engine: Arc<DB>, _: &str) {
+    pub fn handle_signal(engine: Arc<DB>, storage: &Storage, _: &str) {
         use signal::trap::Trap;
         use nix::sys::signal::{SIGTERM, SIGINT, SIGHUP, SIGUSR1, SIGUSR2};
         let trap = Trap::trap(&[SIGTERM, SIGINT, SIGHUP, SIGUSR1, SIGUSR2]);
@@ -88,6 +112,9 @@ mod imp {
                         info!("{}", v)
                     }
                     print_malloc_stats();
+
+                    // Log the commands owning and waiting for the scheduler latches.
+                    print_latches(storage);
                 }
                 SIGUSR2 => profiling::dump_prof(None),
                 // TODO: handle more signal
@@ -111,8 +138,9 @@ mod imp {
     use std::sync::Arc;
 
     use rocksdb::DB;
+    use tikv::storage::Storage;
 
-    pub fn handle_signal(engine: Arc<DB>, _: &str) {}
+    pub fn handle_signal(engine: Arc<DB>, storage: &Storage, _: &str) {}
 }
 
 pub use self::imp::handle_signal;
@@ -25,17 +25,20 @@ extern crate rocksdb;
 extern crate tempdir;
 
 use std::{str, u64};
+use std::path::Path;
+use std::sync::Arc;
 use clap::{Arg, App, SubCommand};
 use protobuf::Message;
 use kvproto::raft_cmdpb::RaftCmdRequest;
 use kvproto::raft_serverpb::{RaftLocalState, RegionLocalState, RaftApplyState, PeerState};
 use kvproto::eraftpb::Entry;
 use rocksdb::DB;
+use tikv::backup;
 use tikv::util::{self, escape, unescape};
 use tikv::util::codec::bytes::encode_bytes;
 use tikv::raftstore::store::keys;
 use tikv::raftstore::store::engine::{Peekable, Iterable, IterOption};
-use tikv::storage::{ALL_CFS, CF_RAFT, CF_LOCK, CF_WRITE, CF_DEFAULT, CfName};
+use tikv::storage::{ALL_CFS, CF_RAFT, CF_LOCK, CF_WRITE, CF_DEFAULT, CF_RAW_TTL, CfName};
 use tikv::storage::mvcc::{Lock, Write};
 use tikv::storage::types::Key;
 
@@ -134,7 +137,26 @@ fn main() {
                 .help("set start_ts as filter"))
             .arg(Arg::with_name("commit_ts")
                 .takes_value(true)
-                .help("set commit_ts as filter")));
+                .help("set commit_ts as filter")))
+        .subcommand(SubCommand::with_name("backup")
+            .about("back up the regions to sst files at a ts, the store must be stopped")
+            .arg(Arg::with_name("ts")
+                .short("t")
+                .takes_value(true)
+                .required(true)
+                .help("set the backup ts, should be fetched from pd"))
+            .arg(Arg::with_name("path")
+                .short("p")
+                .takes_value(true)
+                .required(true)
+                .help("set the backup directory")))
+        .subcommand(SubCommand::with_name("restore")
+            .about("restore a backup to the db, the store must be stopped")
+            .arg(Arg::with_name("path")
+                .short("p")
+                .takes_value(true)
+                .required(true)
+                .help("set the backup directory")));
     let matches = app.clone().get_matches();
 
     let db_path = matches.value_of("db").unwrap();
@@ -214,6 +236,34 @@ fn main() {
                 let _ = app.print_help();
             }
         }
+    } else if let Some(matches) = matches.subcommand_matches("backup") {
+        let backup_ts = match matches.value_of("ts").unwrap().parse() {
+            Ok(ts) => ts,
+            Err(e) => {
+                println!("The ts: {} is invalid: {:?}", matches.value_of("ts").unwrap(), e);
+                let _ = app.print_help();
+                return;
+            }
+        };
+        let path = matches.value_of("path").unwrap();
+        match backup::backup_store(Arc::new(db), backup_ts, Path::new(path)) {
+            Ok(manifest) => {
+                println!("backed up {} files at ts {}",
+                         manifest.files.len(),
+                         manifest.backup_ts)
+            }
+            Err(e) => println!("failed to back up to {}: {:?}", path, e),
+        }
+    } else if let Some(matches) = matches.subcommand_matches("restore") {
+        let path = matches.value_of("path").unwrap();
+        match backup::restore(&db, Path::new(path)) {
+            Ok(manifest) => {
+                println!("restored {} files of backup at ts {}",
+                         manifest.files.len(),
+                         manifest.backup_ts)
+            }
+            Err(e) => println!("failed to restore {}: {:?}", path, e),
+        }
     } else {
         let _ = app.print_help();
     }
@@ -441,7 +491,7 @@ fn get_region_size(db: &DB, region_id: u64) -> u64 {
     let start_key = &keys::data_key(region.get_start_key());
     let end_key = &keys::data_end_key(region.get_end_key());
     let mut size: u64 = 0;
-    let cf_arr = [CF_DEFAULT, CF_WRITE, CF_LOCK];
+    let cf_arr = [CF_DEFAULT, CF_WRITE, CF_LOCK, CF_RAW_TTL];
     for cf in &cf_arr {
         db.scan_cf(cf,
                      start_key,
@@ -56,19 +56,22 @@ use std::env;
 use clap::{Arg, App, ArgMatches};
 use rocksdb::{Options as RocksdbOptions, BlockBasedOptions};
 use fs2::FileExt;
+use grpc::Environment;
 use sys_info::{cpu_num, mem_info};
 
-use tikv::storage::{TEMP_DIR, CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAFT};
+use tikv::storage::{TEMP_DIR, CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAFT, CF_RAW_TTL};
+use tikv::storage::monitor_latches;
 use tikv::util::{self, panic_hook, rocksdb as rocksdb_util};
 use tikv::util::collections::HashMap;
 use tikv::util::logger::{self, StderrLogger};
 use tikv::util::file_log::RotatingFileLogger;
 use tikv::util::transport::SendCh;
 use tikv::server::{DEFAULT_LISTENING_ADDR, DEFAULT_CLUSTER_ID, Server, Node, Config,
-                   create_raft_storage};
+                   ImportClient, create_raft_storage};
 use tikv::server::transport::ServerRaftStoreRouter;
 use tikv::server::PdStoreAddrResolver;
 use tikv::raftstore::store::{self, SnapManager};
+use tikv::import::SSTImporter;
 use tikv::pd::{RpcClient, PdClient};
 use tikv::raftstore::store::keys::region_raft_prefix_len;
 use tikv::util::time_monitor::TimeMonitor;
@@ -83,6 +86,8 @@ const LOCKCF_MAX_MEM: u64 = GB;
 // [default cf, write cf, raft cf, lock cf]
 const DEFAULT_BLOCK_CACHE_RATIO: &'static [f64] = &[0.25, 0.15, 0.02, 0.02];
 const SEC_TO_MS: i64 = 1000;
+// Runs after the split observer of the store, whose priority is 100.
+const CDC_OBSERVER_PRIORITY: u32 = 200;
 
 fn sanitize_memory_usage() -> bool {
     let mut ratio = 0.0;
@@ -587,6 +592,18 @@ fn get_rocksdb_lock_cf_option(config: &toml::Value, total_mem: u64) -> RocksdbOp
     opts
 }
 
+fn get_rocksdb_raw_ttl_cf_option(config: &toml::Value) -> RocksdbOptions {
+    let mut default_values = CfOptValues::default();
+    default_values.use_bloom_filter = true;
+    default_values.whole_key_filtering = true;
+
+    let mut opts = get_rocksdb_cf_option(config, "rawttlcf", default_values);
+    // Every value in this cf carries its expiry time, see `storage::ttl`.
+    rocksdb_util::set_ttl_compaction_filter(&mut opts)
+        .unwrap_or_else(|err| exit_with_err(format!("{:?}", err)));
+    opts
+}
+
 fn adjust_block_cache_size(cache_size: u64, min_limit: u64, max_limit: u64) -> u64 {
     if cache_size < min_limit {
         return min_limit;
@@ -668,6 +685,8 @@ fn build_cfg(matches: &ArgMatches,
     check_advertise_address(&cfg.advertise_addr);
 
     cfg.raft_store.sync_log = get_toml_boolean(config, "raftstore.sync-log", Some(true));
+    cfg.raft_store.raft_async_persist =
+        get_toml_boolean(config, "raftstore.raft-async-persist", Some(false));
     cfg_usize(&mut cfg.raft_store.notify_capacity,
               config,
               "raftstore.notify-capacity");
@@ -725,9 +744,15 @@ fn build_cfg(matches: &ArgMatches,
     cfg_u64(&mut cfg.raft_store.raft_entry_max_size,
             config,
             "raftstore.raft-entry-max-size");
+    cfg_u64(&mut cfg.raft_store.max_proposal_batch_size,
+            config,
+            "raftstore.max-proposal-batch-size");
     cfg_duration(&mut cfg.raft_store.max_peer_down_duration,
                  config,
                  "raftstore.max-peer-down-duration");
+    cfg_duration(&mut cfg.raft_store.max_leader_stall_duration,
+                 config,
+                 "raftstore.max-leader-stall-duration");
     cfg_u64(&mut cfg.raft_store.pd_heartbeat_tick_interval,
             config,
             "raftstore.pd-heartbeat-tick-interval");
@@ -737,6 +762,9 @@ fn build_cfg(matches: &ArgMatches,
     cfg_u64(&mut cfg.raft_store.consistency_check_tick_interval,
             config,
             "raftstore.consistency-check-interval");
+    cfg_u64(&mut cfg.raft_store.safe_ts_tick_interval,
+            config,
+            "raftstore.safe-ts-tick-interval");
     cfg.raft_store.use_sst_file_snapshot =
         get_toml_boolean(config, "raftstore.use-sst-file-snapshot", Some(true));
     cfg_usize(&mut cfg.storage.sched_notify_capacity,
@@ -756,6 +784,31 @@ fn build_cfg(matches: &ArgMatches,
     cfg_usize(&mut cfg.storage.sched_too_busy_threshold,
               config,
               "storage.scheduler-too-busy-threshold");
+    cfg_u64(&mut cfg.storage.sched_pending_compaction_bytes_soft_limit,
+            config,
+            "storage.scheduler-pending-compaction-bytes-soft-limit");
+    cfg_u64(&mut cfg.storage.sched_pending_compaction_bytes_hard_limit,
+            config,
+            "storage.scheduler-pending-compaction-bytes-hard-limit");
+    cfg_u64(&mut cfg.storage.sched_l0_files_soft_limit,
+            config,
+            "storage.scheduler-l0-files-soft-limit");
+    cfg_u64(&mut cfg.storage.sched_l0_files_hard_limit,
+            config,
+            "storage.scheduler-l0-files-hard-limit");
+    cfg_u64(&mut cfg.storage.sched_immutable_mem_tables_soft_limit,
+            config,
+            "storage.scheduler-immutable-mem-tables-soft-limit");
+    cfg_u64(&mut cfg.storage.sched_immutable_mem_tables_hard_limit,
+            config,
+            "storage.scheduler-immutable-mem-tables-hard-limit");
+    cfg.storage.enable_ttl = get_toml_boolean(config, "storage.enable-ttl", Some(false));
+    cfg_duration(&mut cfg.storage.wait_for_lock_timeout,
+                 config,
+                 "storage.wait-for-lock-timeout");
+    cfg_duration(&mut cfg.storage.gc_check_interval,
+                 config,
+                 "storage.gc-check-interval");
 
     cfg
 }
@@ -823,6 +876,7 @@ fn run_raft_server(pd_client: RpcClient,
     let lock_path = store_path.join(Path::new("LOCK"));
     let db_path = store_path.join(Path::new("db"));
     let snap_path = store_path.join(Path::new("snap"));
+    let import_path = store_path.join(Path::new("import"));
 
     let f = File::create(lock_path).unwrap_or_else(|err| exit_with_err(format!("{:?}", err)));
     if f.try_lock_exclusive().is_err() {
@@ -846,17 +900,25 @@ fn run_raft_server(pd_client: RpcClient,
              rocksdb_util::CFOptions::new(CF_WRITE,
                                           get_rocksdb_write_cf_option(config, total_mem)),
              rocksdb_util::CFOptions::new(CF_RAFT,
-                                          get_rocksdb_raftlog_cf_option(config, total_mem))];
+                                          get_rocksdb_raftlog_cf_option(config, total_mem)),
+             rocksdb_util::CFOptions::new(CF_RAW_TTL, get_rocksdb_raw_ttl_cf_option(config))];
     let engine = Arc::new(rocksdb_util::new_engine_opt(db_path.to_str()
                                                            .unwrap(),
                                                        db_opts,
                                                        cfs_opts)
         .unwrap_or_else(|err| exit_with_err(format!("{:?}", err))));
-    let mut storage = create_raft_storage(raft_router.clone(), engine.clone(), &cfg)
+    let pd_client = Arc::new(pd_client);
+    let importer = Arc::new(SSTImporter::new(&import_path)
+        .unwrap_or_else(|err| exit_with_err(format!("{:?}", err))));
+    let sst_sender = Arc::new(ImportClient::new(Arc::new(Environment::new(1)), pd_client.clone()));
+    let mut storage = create_raft_storage(raft_router.clone(),
+                                          engine.clone(),
+                                          importer.clone(),
+                                          sst_sender,
+                                          &cfg)
         .unwrap_or_else(|err| exit_with_err(format!("{:?}", err)));
 
-    // Create pd client, snapshot manager, server.
-    let pd_client = Arc::new(pd_client);
+    // Create snapshot manager, server.
     let resolver = PdStoreAddrResolver::new(pd_client.clone())
         .unwrap_or_else(|err| exit_with_err(format!("{:?}", err)));
     let snap_mgr = SnapManager::new(snap_path.as_path().to_str().unwrap().to_owned(),
@@ -864,20 +926,24 @@ fn run_raft_server(pd_client: RpcClient,
                                     cfg.raft_store.use_sst_file_snapshot);
     let mut server = Server::new(&cfg,
                                  storage.clone(),
-                                 raft_router,
+                                 raft_router.clone(),
                                  snap_status_sender,
                                  resolver,
-                                 snap_mgr.clone())
+                                 snap_mgr.clone(),
+                                 importer.clone())
         .unwrap_or_else(|err| exit_with_err(format!("{:?}", err)));
     let trans = server.transport();
 
     // Create node.
-    let mut node = Node::new(&mut event_loop, &cfg, pd_client);
+    let mut node = Node::new(&mut event_loop, &cfg, pd_client.clone());
+    node.register_observer(CDC_OBSERVER_PRIORITY, Box::new(server.cdc_observer()));
     node.start(event_loop,
                engine.clone(),
                trans,
                snap_mgr,
-               snap_status_receiver)
+               snap_status_receiver,
+               importer,
+               storage.get_max_read_ts())
         .unwrap_or_else(|err| exit_with_err(format!("{:?}", err)));
     initial_metric(config, Some(node.id()));
 
@@ -886,10 +952,15 @@ fn run_raft_server(pd_client: RpcClient,
     if let Err(e) = storage.start(&cfg.storage) {
         panic!("failed to start storage, error = {:?}", e);
     }
+    monitor_latches(storage.clone()).unwrap_or_else(|err| exit_with_err(format!("{:?}", err)));
+    if cfg.storage.gc_check_interval > Duration::from_secs(0) {
+        storage.start_auto_gc(pd_client.clone(), raft_router, cfg.storage.gc_check_interval)
+            .unwrap_or_else(|err| exit_with_err(format!("{:?}", err)));
+    }
 
     // Run server.
-    server.start(&cfg).unwrap_or_else(|err| exit_with_err(format!("{:?}", err)));
-    signal_handler::handle_signal(engine, backup_path);
+    server.start(&cfg, pd_client).unwrap_or_else(|err| exit_with_err(format!("{:?}", err)));
+    signal_handler::handle_signal(engine, &storage, backup_path);
 
     // Stop.
     server.stop().unwrap_or_else(|err| exit_with_err(format!("{:?}", err)));
@@ -0,0 +1,447 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+
+use kvproto::raft_cmdpb::{CmdType, Request};
+
+use storage::{Key, Value, CF_DEFAULT, CF_LOCK, CF_WRITE};
+use storage::mvcc::{Lock, LockType, Write, WriteType};
+use util::collections::HashMap;
+use util::escape;
+use super::{Event, EventSender, RowChange, RowOp, RowType};
+
+pub struct Downstream {
+    pub id: u64,
+    sink: EventSender,
+}
+
+impl Downstream {
+    pub fn new(id: u64, sink: EventSender) -> Downstream {
+        Downstream { id: id, sink: sink }
+    }
+
+    /// Returns false if the subscriber has gone.
+    pub fn send(&self, event: Event) -> bool {
+        self.sink.unbounded_send(event).is_ok()
+    }
+}
+
+pub fn lock_row_op(lock_type: LockType) -> Option<RowOp> {
+    match lock_type {
+        LockType::Put => Some(RowOp::Put),
+        LockType::Delete => Some(RowOp::Delete),
+        LockType::Lock => Some(RowOp::Lock),
+        LockType::Pessimistic => None,
+    }
+}
+
+pub fn write_row_op(write_type: WriteType) -> Option<RowOp> {
+    match write_type {
+        WriteType::Put => Some(RowOp::Put),
+        WriteType::Delete => Some(RowOp::Delete),
+        WriteType::Lock => Some(RowOp::Lock),
+        WriteType::Rollback => None,
+    }
+}
+
+/// Tracks the subscribers and the pending locks of a region.
+pub struct Delegate {
+    pub region_id: u64,
+    downstreams: Vec<Downstream>,
+    // encoded key -> start ts of the lock
+    locks: HashMap<Vec<u8>, u64>,
+    resolved_ts: u64,
+}
+
+impl Delegate {
+    pub fn new(region_id: u64) -> Delegate {
+        Delegate {
+            region_id: region_id,
+            downstreams: vec![],
+            locks: HashMap::default(),
+            resolved_ts: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.downstreams.is_empty()
+    }
+
+    /// Adds a subscriber whose incremental scan is done, with the pending locks it found and
+    /// the resolved ts of its snapshot.
+    pub fn subscribe(&mut self,
+                     downstream: Downstream,
+                     locks: HashMap<Vec<u8>, u64>,
+                     resolved_ts: u64) {
+        self.locks.extend(locks);
+        let region_id = self.region_id;
+        let mut alive = downstream.send(Event::Initialized { region_id: region_id });
+        if alive && resolved_ts > 0 {
+            alive = downstream.send(Event::ResolvedTs {
+                region_id: region_id,
+                ts: resolved_ts,
+            });
+        }
+        if alive {
+            self.downstreams.push(downstream);
+        }
+    }
+
+    pub fn unsubscribe(&mut self, id: u64) {
+        self.downstreams.retain(|d| d.id != id);
+    }
+
+    /// Closes all the subscriptions with an error.
+    pub fn stop(&mut self, msg: String) {
+        let region_id = self.region_id;
+        for d in self.downstreams.drain(..) {
+            d.send(Event::Error {
+                region_id: region_id,
+                msg: msg.clone(),
+            });
+        }
+    }
+
+    /// Handles the requests applied at `index`.
+    pub fn on_apply(&mut self, index: u64, reqs: &[Request]) {
+        // The rows written by them can't be decoded from the requests, the subscribers have to
+        // subscribe again to get them by an incremental scan.
+        for req in reqs {
+            match req.get_cmd_type() {
+                CmdType::DeleteRange => {
+                    self.stop(format!("range is deleted at index {}", index));
+                    return;
+                }
+                CmdType::IngestSST => {
+                    self.stop(format!("sst is ingested at index {}", index));
+                    return;
+                }
+                _ => {}
+            }
+        }
+        let rows = self.decode_rows(reqs);
+        if !rows.is_empty() {
+            self.broadcast(Event::Rows {
+                region_id: self.region_id,
+                index: index,
+                rows: rows,
+            });
+        }
+    }
+
+    /// Advances the resolved ts with `min_ts`, a ts got from PD after all the requests handled
+    /// so far are applied.
+    pub fn on_min_ts(&mut self, min_ts: u64) {
+        let resolved_ts = resolve(&self.locks, min_ts);
+        if resolved_ts > self.resolved_ts {
+            self.resolved_ts = resolved_ts;
+            self.broadcast(Event::ResolvedTs {
+                region_id: self.region_id,
+                ts: resolved_ts,
+            });
+        }
+    }
+
+    fn broadcast(&mut self, event: Event) {
+        let region_id = self.region_id;
+        self.downstreams.retain(|d| {
+            let alive = d.send(event.clone());
+            if !alive {
+                info!("[region {}] cdc subscriber {} has gone", region_id, d.id);
+            }
+            alive
+        });
+    }
+
+    fn decode_rows(&mut self, reqs: &[Request]) -> Vec<RowChange> {
+        // Values that are too long to be inlined are put to CF_DEFAULT in the same command.
+        let mut values: HashMap<Vec<u8>, Value> = HashMap::default();
+        for req in reqs {
+            if req.get_cmd_type() == CmdType::Put && cf_of(req.get_put().get_cf()) == CF_DEFAULT {
+                values.insert(req.get_put().get_key().to_vec(),
+                              req.get_put().get_value().to_vec());
+            }
+        }
+
+        let mut rows = vec![];
+        for req in reqs {
+            match req.get_cmd_type() {
+                CmdType::Put => {
+                    let put = req.get_put();
+                    let res = match cf_of(put.get_cf()) {
+                        CF_LOCK => self.decode_lock(put.get_key(), put.get_value(), &mut values),
+                        CF_WRITE => self.decode_write(put.get_key(), put.get_value(), &mut values),
+                        _ => Ok(None),
+                    };
+                    match res {
+                        Ok(Some(row)) => rows.push(row),
+                        Ok(None) => {}
+                        Err(e) => {
+                            warn!("[region {}] cdc failed to decode {}: {}",
+                                  self.region_id,
+                                  escape(put.get_key()),
+                                  e)
+                        }
+                    }
+                }
+                CmdType::Delete => {
+                    if cf_of(req.get_delete().get_cf()) == CF_LOCK {
+                        self.locks.remove(req.get_delete().get_key());
+                    }
+                }
+                _ => {}
+            }
+        }
+        rows
+    }
+
+    fn decode_lock(&mut self,
+                   key: &[u8],
+                   value: &[u8],
+                   values: &mut HashMap<Vec<u8>, Value>)
+                   -> ::storage::mvcc::Result<Option<RowChange>> {
+        let lock = try!(Lock::parse(value));
+        let op = match lock_row_op(lock.lock_type) {
+            Some(op) => op,
+            // Pessimistic locks are not written by prewrites.
+            None => return Ok(None),
+        };
+        self.locks.insert(key.to_vec(), lock.ts);
+        let key = Key::from_encoded(key.to_vec());
+        let value = match lock.short_value {
+            Some(v) => v,
+            None => values.remove(key.append_ts(lock.ts).encoded()).unwrap_or_default(),
+        };
+        Ok(Some(RowChange {
+            row_type: RowType::Prewrite,
+            op: op,
+            key: try!(key.raw()),
+            value: value,
+            start_ts: lock.ts,
+            commit_ts: 0,
+        }))
+    }
+
+    fn decode_write(&mut self,
+                    key: &[u8],
+                    value: &[u8],
+                    values: &mut HashMap<Vec<u8>, Value>)
+                    -> ::storage::mvcc::Result<Option<RowChange>> {
+        let write = try!(Write::parse(value));
+        let key = Key::from_encoded(key.to_vec());
+        let commit_ts = try!(key.decode_ts());
+        let key = try!(key.truncate_ts());
+        let (row_type, op, commit_ts) = match write_row_op(write.write_type) {
+            Some(op) => (RowType::Commit, op, commit_ts),
+            // Rollbacks are written at the start ts.
+            None => (RowType::Rollback, RowOp::Delete, 0),
+        };
+        // Commits of one phase commits and imports come without prewrites, so they carry their
+        // values.
+        let value = match write.short_value {
+            Some(v) => v,
+            None => values.remove(key.append_ts(write.start_ts).encoded()).unwrap_or_default(),
+        };
+        Ok(Some(RowChange {
+            row_type: row_type,
+            op: op,
+            key: try!(key.raw()),
+            value: value,
+            start_ts: write.start_ts,
+            commit_ts: commit_ts,
+        }))
+    }
+}
+
+/// Returns the resolved ts for the pending `locks` (encoded key -> start ts) and `min_ts`, a ts
+/// got from PD before the locks are collected.
+///
+/// A commit in the future either commits a pending lock, whose commit ts is larger than its start
+/// ts, or a transaction that is prewritten after `min_ts` is got, whose commit ts is got even
+/// later and so is larger than `min_ts`.
+pub fn resolve(locks: &HashMap<Vec<u8>, u64>, min_ts: u64) -> u64 {
+    match locks.values().min() {
+        Some(&ts) => cmp::min(ts - 1, min_ts),
+        None => min_ts,
+    }
+}
+
+fn cf_of(cf: &str) -> &str {
+    if cf.is_empty() { CF_DEFAULT } else { cf }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Future, Stream};
+    use futures::sync::mpsc::{unbounded, UnboundedReceiver};
+    use kvproto::raft_cmdpb::{CmdType, Request};
+
+    use storage::{make_key, CF_DEFAULT, CF_LOCK, CF_WRITE, SHORT_VALUE_MAX_LEN};
+    use storage::mvcc::{Lock, LockType, Write, WriteType};
+    use util::collections::HashMap;
+    use super::*;
+    use super::super::{Event, RowChange, RowOp, RowType};
+
+    fn put(cf: &str, key: Vec<u8>, value: Vec<u8>) -> Request {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Put);
+        req.mut_put().set_cf(cf.to_owned());
+        req.mut_put().set_key(key);
+        req.mut_put().set_value(value);
+        req
+    }
+
+    fn delete(cf: &str, key: Vec<u8>) -> Request {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Delete);
+        req.mut_delete().set_cf(cf.to_owned());
+        req.mut_delete().set_key(key);
+        req
+    }
+
+    fn prewrite(key: &[u8], value: Vec<u8>, start_ts: u64) -> Vec<Request> {
+        let key = make_key(key);
+        let mut reqs = vec![];
+        let short_value = if value.len() <= SHORT_VALUE_MAX_LEN {
+            Some(value)
+        } else {
+            reqs.push(put(CF_DEFAULT, key.append_ts(start_ts).into_encoded(), value));
+            None
+        };
+        let lock = Lock::new(LockType::Put, b"pk".to_vec(), start_ts, 0, short_value);
+        reqs.push(put(CF_LOCK, key.into_encoded(), lock.to_bytes()));
+        reqs
+    }
+
+    fn commit(key: &[u8], start_ts: u64, commit_ts: u64) -> Vec<Request> {
+        let key = make_key(key);
+        let write = Write::new(WriteType::Put, start_ts, None);
+        vec![delete(CF_LOCK, key.encoded().to_owned()),
+             put(CF_WRITE, key.append_ts(commit_ts).into_encoded(), write.to_bytes())]
+    }
+
+    fn recv(rx: UnboundedReceiver<Event>) -> (Event, UnboundedReceiver<Event>) {
+        match rx.into_future().wait() {
+            Ok((Some(e), rx)) => (e, rx),
+            _ => panic!("no event"),
+        }
+    }
+
+    fn row(row_type: RowType,
+           key: &[u8],
+           value: &[u8],
+           start_ts: u64,
+           commit_ts: u64)
+           -> RowChange {
+        RowChange {
+            row_type: row_type,
+            op: RowOp::Put,
+            key: key.to_vec(),
+            value: value.to_vec(),
+            start_ts: start_ts,
+            commit_ts: commit_ts,
+        }
+    }
+
+    #[test]
+    fn test_delegate() {
+        let mut delegate = Delegate::new(1);
+        let (tx, rx) = unbounded();
+        delegate.subscribe(Downstream::new(1, tx), HashMap::default(), 0);
+        let (e, rx) = recv(rx);
+        assert_eq!(e, Event::Initialized { region_id: 1 });
+
+        let long_value = vec![b'v'; SHORT_VALUE_MAX_LEN + 1];
+        let mut reqs = prewrite(b"k1", b"v1".to_vec(), 10);
+        reqs.extend(prewrite(b"k2", long_value.clone(), 10));
+        delegate.on_apply(5, &reqs);
+        let (e, rx) = recv(rx);
+        assert_eq!(e,
+                   Event::Rows {
+                       region_id: 1,
+                       index: 5,
+                       rows: vec![row(RowType::Prewrite, b"k1", b"v1", 10, 0),
+                                  row(RowType::Prewrite, b"k2", &long_value, 10, 0)],
+                   });
+        // Resolved ts is blocked by the locks.
+        delegate.on_min_ts(30);
+        let (e, rx) = recv(rx);
+        assert_eq!(e, Event::ResolvedTs { region_id: 1, ts: 9 });
+
+        delegate.on_apply(6, &commit(b"k1", 10, 15));
+        let (e, rx) = recv(rx);
+        assert_eq!(e,
+                   Event::Rows {
+                       region_id: 1,
+                       index: 6,
+                       rows: vec![row(RowType::Commit, b"k1", b"", 10, 15)],
+                   });
+        delegate.on_apply(7, &commit(b"k2", 10, 15));
+        let (_, rx) = recv(rx);
+        // Resolved ts is the min ts without any lock.
+        delegate.on_min_ts(30);
+        let (e, rx) = recv(rx);
+        assert_eq!(e, Event::ResolvedTs { region_id: 1, ts: 30 });
+        // It never goes back.
+        delegate.on_min_ts(20);
+
+        // Rollback.
+        let key = make_key(b"k3");
+        let rollback = Write::new(WriteType::Rollback, 20, None);
+        delegate.on_apply(8,
+                          &[put(CF_WRITE, key.append_ts(20).into_encoded(), rollback.to_bytes())]);
+        let (e, rx) = recv(rx);
+        let mut expect = row(RowType::Rollback, b"k3", b"", 20, 0);
+        expect.op = RowOp::Delete;
+        assert_eq!(e,
+                   Event::Rows {
+                       region_id: 1,
+                       index: 8,
+                       rows: vec![expect],
+                   });
+
+        delegate.stop("region split".to_owned());
+        assert!(delegate.is_empty());
+        let (e, _) = recv(rx);
+        assert_eq!(e,
+                   Event::Error {
+                       region_id: 1,
+                       msg: "region split".to_owned(),
+                   });
+    }
+
+    #[test]
+    fn test_delegate_stop_on_undecodable_requests() {
+        for cmd_type in vec![CmdType::DeleteRange, CmdType::IngestSST] {
+            let mut delegate = Delegate::new(1);
+            let (tx, rx) = unbounded();
+            let mut locks = HashMap::default();
+            locks.insert(make_key(b"k1").into_encoded(), 10);
+            delegate.subscribe(Downstream::new(1, tx), locks, 9);
+            let (e, rx) = recv(rx);
+            assert_eq!(e, Event::Initialized { region_id: 1 });
+            let (e, rx) = recv(rx);
+            assert_eq!(e, Event::ResolvedTs { region_id: 1, ts: 9 });
+
+            let mut req = Request::new();
+            req.set_cmd_type(cmd_type);
+            delegate.on_apply(5, &[req]);
+            assert!(delegate.is_empty());
+            match recv(rx).0 {
+                Event::Error { region_id: 1, .. } => {}
+                e => panic!("unexpected event {:?}", e),
+            }
+        }
+    }
+}
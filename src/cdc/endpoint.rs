@@ -0,0 +1,518 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use futures::Future;
+use kvproto::kvrpcpb::Context;
+use kvproto::raft_cmdpb::Request;
+
+use pd::PdClient;
+use raftstore::store::engine::IterOption;
+use storage::{Engine, Key, ScanMode, Snapshot, Statistics, CF_DEFAULT, CF_LOCK, CF_WRITE};
+use storage::mvcc::{Lock, Write};
+use storage::txn::Result;
+use util::collections::HashMap;
+use util::worker::{Runnable, Scheduler};
+use super::delegate::{self, lock_row_op, write_row_op, Delegate, Downstream};
+use super::observer::ObservedRegions;
+use super::{Event, EventSender, RowChange, RowOp, RowType};
+
+// The number of rows sent in one event by an incremental scan.
+const INCREMENTAL_SCAN_BATCH_SIZE: usize = 1024;
+
+pub enum Task {
+    /// Subscribes to the changes of a region committed after `checkpoint_ts`.
+    Register {
+        ctx: Context,
+        checkpoint_ts: u64,
+        sink: EventSender,
+    },
+    /// The requests applied to an observed region at `index`.
+    Apply {
+        region_id: u64,
+        index: u64,
+        reqs: Vec<Request>,
+    },
+    /// The region is changed by an admin command and its subscriptions must be closed.
+    RegionChanged { region_id: u64, msg: String },
+    /// A ts got from PD after the tasks before it are scheduled, to advance the resolved ts.
+    MinTs { ts: u64 },
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Task::Register { ref ctx, checkpoint_ts, .. } => {
+                write!(f,
+                       "[region {}] register from checkpoint ts {}",
+                       ctx.get_region_id(),
+                       checkpoint_ts)
+            }
+            Task::Apply { region_id, index, ref reqs } => {
+                write!(f,
+                       "[region {}] {} requests applied at index {}",
+                       region_id,
+                       reqs.len(),
+                       index)
+            }
+            Task::RegionChanged { region_id, ref msg } => {
+                write!(f, "[region {}] changed: {}", region_id, msg)
+            }
+            Task::MinTs { ts } => write!(f, "min ts {}", ts),
+        }
+    }
+}
+
+pub struct Runner<C: PdClient> {
+    engine: Box<Engine>,
+    pd_client: Arc<C>,
+    observed: ObservedRegions,
+    delegates: HashMap<u64, Delegate>,
+    next_downstream_id: u64,
+}
+
+impl<C: PdClient> Runner<C> {
+    pub fn new(engine: Box<Engine>, pd_client: Arc<C>, observed: ObservedRegions) -> Runner<C> {
+        Runner {
+            engine: engine,
+            pd_client: pd_client,
+            observed: observed,
+            delegates: HashMap::default(),
+            next_downstream_id: 1,
+        }
+    }
+
+    fn on_register(&mut self, ctx: Context, checkpoint_ts: u64, sink: EventSender) {
+        let region_id = ctx.get_region_id();
+        let downstream = Downstream::new(self.next_downstream_id, sink);
+        self.next_downstream_id += 1;
+        // The region must be observed before the snapshot is taken, otherwise the commands
+        // applied in between are missed.
+        self.observed.write().unwrap().insert(region_id);
+        match self.incremental_scan(&ctx, checkpoint_ts, &downstream) {
+            Ok(Some((locks, resolved_ts))) => {
+                info!("[region {}] cdc subscriber {} registered from ts {} with {} locks",
+                      region_id,
+                      downstream.id,
+                      checkpoint_ts,
+                      locks.len());
+                self.delegates
+                    .entry(region_id)
+                    .or_insert_with(|| Delegate::new(region_id))
+                    .subscribe(downstream, locks, resolved_ts);
+            }
+            Ok(None) => {
+                info!("[region {}] cdc subscriber {} has gone during the incremental scan",
+                      region_id,
+                      downstream.id);
+            }
+            Err(e) => {
+                error!("[region {}] cdc incremental scan failed: {:?}", region_id, e);
+                downstream.send(Event::Error {
+                    region_id: region_id,
+                    msg: format!("{:?}", e),
+                });
+            }
+        }
+        self.remove_if_empty(region_id);
+    }
+
+    /// Sends the rows committed after `checkpoint_ts` and then the pending locks of the region
+    /// to `downstream` in batches, in the order of keys.
+    ///
+    /// Returns the pending locks and the resolved ts of the snapshot, or `None` if the
+    /// subscriber has gone.
+    fn incremental_scan(&self,
+                        ctx: &Context,
+                        checkpoint_ts: u64,
+                        downstream: &Downstream)
+                        -> Result<Option<(HashMap<Vec<u8>, u64>, u64)>> {
+        // The ts must be got before the locks are scanned, see `delegate::resolve`.
+        let min_ts = box_try!(self.pd_client.get_tso().wait());
+        let snapshot = try!(self.engine.snapshot(ctx));
+        let mut statistics = Statistics::default();
+        let mut batch = Batch::new(ctx.get_region_id(), downstream);
+
+        let mut cursor = try!(snapshot.iter_cf(CF_WRITE,
+                                               IterOption::new(None, false),
+                                               ScanMode::Forward));
+        let mut valid = cursor.seek_to_first(&mut statistics);
+        while valid {
+            let key = Key::from_encoded(cursor.key().to_vec());
+            let commit_ts = try!(key.decode_ts());
+            if commit_ts > checkpoint_ts {
+                let write = try!(Write::parse(cursor.value()));
+                if let Some(op) = write_row_op(write.write_type) {
+                    let key = try!(key.truncate_ts());
+                    let value = try!(load_value(snapshot.as_ref(),
+                                                &key,
+                                                op,
+                                                write.start_ts,
+                                                write.short_value));
+                    let row = RowChange {
+                        row_type: RowType::Committed,
+                        op: op,
+                        key: try!(key.raw()),
+                        value: value,
+                        start_ts: write.start_ts,
+                        commit_ts: commit_ts,
+                    };
+                    if !batch.push(row) {
+                        return Ok(None);
+                    }
+                }
+            }
+            valid = cursor.next(&mut statistics);
+        }
+
+        let mut locks = HashMap::default();
+        let mut cursor = try!(snapshot.iter_cf(CF_LOCK,
+                                               IterOption::new(None, false),
+                                               ScanMode::Forward));
+        let mut valid = cursor.seek_to_first(&mut statistics);
+        while valid {
+            let lock = try!(Lock::parse(cursor.value()));
+            if let Some(op) = lock_row_op(lock.lock_type) {
+                locks.insert(cursor.key().to_vec(), lock.ts);
+                let key = Key::from_encoded(cursor.key().to_vec());
+                let value =
+                    try!(load_value(snapshot.as_ref(), &key, op, lock.ts, lock.short_value));
+                let row = RowChange {
+                    row_type: RowType::Prewrite,
+                    op: op,
+                    key: try!(key.raw()),
+                    value: value,
+                    start_ts: lock.ts,
+                    commit_ts: 0,
+                };
+                if !batch.push(row) {
+                    return Ok(None);
+                }
+            }
+            valid = cursor.next(&mut statistics);
+        }
+        if !batch.flush() {
+            return Ok(None);
+        }
+        let resolved_ts = delegate::resolve(&locks, min_ts);
+        Ok(Some((locks, resolved_ts)))
+    }
+
+    fn on_apply(&mut self, region_id: u64, index: u64, reqs: Vec<Request>) {
+        if let Some(delegate) = self.delegates.get_mut(&region_id) {
+            delegate.on_apply(index, &reqs);
+        }
+        self.remove_if_empty(region_id);
+    }
+
+    fn on_min_ts(&mut self, ts: u64) {
+        for delegate in self.delegates.values_mut() {
+            delegate.on_min_ts(ts);
+        }
+    }
+
+    fn on_region_changed(&mut self, region_id: u64, msg: String) {
+        if let Some(mut delegate) = self.delegates.remove(&region_id) {
+            info!("[region {}] cdc subscriptions closed: {}", region_id, msg);
+            delegate.stop(msg);
+        }
+        self.observed.write().unwrap().remove(&region_id);
+    }
+
+    fn remove_if_empty(&mut self, region_id: u64) {
+        let empty = self.delegates.get(&region_id).map_or(true, |d| d.is_empty());
+        if empty {
+            self.delegates.remove(&region_id);
+            self.observed.write().unwrap().remove(&region_id);
+        }
+    }
+}
+
+/// Buffers the rows of an incremental scan and sends them in batches.
+struct Batch<'a> {
+    region_id: u64,
+    downstream: &'a Downstream,
+    rows: Vec<RowChange>,
+}
+
+impl<'a> Batch<'a> {
+    fn new(region_id: u64, downstream: &'a Downstream) -> Batch<'a> {
+        Batch {
+            region_id: region_id,
+            downstream: downstream,
+            rows: Vec::with_capacity(INCREMENTAL_SCAN_BATCH_SIZE),
+        }
+    }
+
+    /// Returns false if the subscriber has gone.
+    fn push(&mut self, row: RowChange) -> bool {
+        self.rows.push(row);
+        self.rows.len() < INCREMENTAL_SCAN_BATCH_SIZE || self.flush()
+    }
+
+    /// Returns false if the subscriber has gone.
+    fn flush(&mut self) -> bool {
+        if self.rows.is_empty() {
+            return true;
+        }
+        let rows = mem::replace(&mut self.rows, Vec::with_capacity(INCREMENTAL_SCAN_BATCH_SIZE));
+        self.downstream.send(Event::Rows {
+            region_id: self.region_id,
+            index: 0,
+            rows: rows,
+        })
+    }
+}
+
+fn load_value(snapshot: &Snapshot,
+              key: &Key,
+              op: RowOp,
+              start_ts: u64,
+              short_value: Option<Vec<u8>>)
+              -> Result<Vec<u8>> {
+    if op != RowOp::Put {
+        return Ok(vec![]);
+    }
+    if let Some(v) = short_value {
+        return Ok(v);
+    }
+    let value = try!(snapshot.get_cf(CF_DEFAULT, &key.append_ts(start_ts)));
+    Ok(value.unwrap_or_default())
+}
+
+impl<C: PdClient> Runnable<Task> for Runner<C> {
+    fn run(&mut self, task: Task) {
+        match task {
+            Task::Register { ctx, checkpoint_ts, sink } => {
+                self.on_register(ctx, checkpoint_ts, sink)
+            }
+            Task::Apply { region_id, index, reqs } => self.on_apply(region_id, index, reqs),
+            Task::RegionChanged { region_id, msg } => self.on_region_changed(region_id, msg),
+            Task::MinTs { ts } => self.on_min_ts(ts),
+        }
+    }
+}
+
+/// Gets a ts from PD every `interval` and schedules it to the cdc worker to advance the
+/// resolved ts of the subscribed regions, until `stop` is signaled.
+///
+/// The ts is got before it's scheduled, so the commands applied before are handled by the
+/// worker before it, see `delegate::resolve`.
+pub fn run_min_ts_ticker<C: PdClient>(pd_client: Arc<C>,
+                                      scheduler: Scheduler<Task>,
+                                      interval: Duration,
+                                      stop: Receiver<()>) {
+    loop {
+        match stop.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => return,
+        }
+        let ts = match pd_client.get_tso().wait() {
+            Ok(ts) => ts,
+            Err(e) => {
+                warn!("cdc failed to get ts from pd: {:?}", e);
+                continue;
+            }
+        };
+        if let Err(e) = scheduler.schedule(Task::MinTs { ts: ts }) {
+            error!("cdc failed to schedule min ts {}: {:?}", ts, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::{Future, Stream};
+    use futures::future::ok;
+    use futures::sync::mpsc::unbounded;
+    use kvproto::kvrpcpb::{Context, IsolationLevel};
+    use kvproto::{metapb, pdpb};
+
+    use storage::{make_key, new_local_engine, Engine, Mutation, Options, Statistics, ALL_CFS,
+                  TEMP_DIR};
+    use pd::{PdClient, PdFuture, RegionStat, Result as PdResult};
+    use storage::mvcc::MvccTxn;
+    use util::worker::Runnable;
+    use super::*;
+    use super::super::{Event, RowType};
+
+    struct MockPdClient {
+        ts: u64,
+    }
+
+    impl PdClient for MockPdClient {
+        fn get_cluster_id(&self) -> PdResult<u64> {
+            unimplemented!();
+        }
+        fn bootstrap_cluster(&self, _: metapb::Store, _: metapb::Region) -> PdResult<()> {
+            unimplemented!();
+        }
+        fn is_cluster_bootstrapped(&self) -> PdResult<bool> {
+            unimplemented!();
+        }
+        fn alloc_id(&self) -> PdResult<u64> {
+            unimplemented!();
+        }
+        fn put_store(&self, _: metapb::Store) -> PdResult<()> {
+            unimplemented!();
+        }
+        fn get_store(&self, _: u64) -> PdResult<metapb::Store> {
+            unimplemented!();
+        }
+        fn get_cluster_config(&self) -> PdResult<metapb::Cluster> {
+            unimplemented!();
+        }
+        fn get_region(&self, _: &[u8]) -> PdResult<metapb::Region> {
+            unimplemented!();
+        }
+        fn get_region_by_id(&self, _: u64) -> PdFuture<Option<metapb::Region>> {
+            unimplemented!();
+        }
+        fn region_heartbeat(&self,
+                            _: metapb::Region,
+                            _: metapb::Peer,
+                            _: RegionStat)
+                            -> PdFuture<()> {
+            unimplemented!();
+        }
+        fn handle_region_heartbeat_response<F>(&self, _: u64, _: F) -> PdFuture<()>
+            where F: Fn(pdpb::RegionHeartbeatResponse) + Send + 'static
+        {
+            unimplemented!()
+        }
+        fn ask_split(&self, _: metapb::Region) -> PdFuture<pdpb::AskSplitResponse> {
+            unimplemented!();
+        }
+        fn store_heartbeat(&self, _: pdpb::StoreStats) -> PdFuture<()> {
+            unimplemented!();
+        }
+        fn report_split(&self, _: metapb::Region, _: metapb::Region) -> PdFuture<()> {
+            unimplemented!();
+        }
+        fn get_gc_safe_point(&self) -> PdFuture<u64> {
+            unimplemented!();
+        }
+        fn get_tso(&self) -> PdFuture<u64> {
+            ok(self.ts).boxed()
+        }
+    }
+
+    fn must_prewrite_put(engine: &Engine, key: &[u8], value: &[u8], ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   ts,
+                                   None,
+                                   IsolationLevel::SI);
+        txn.prewrite(Mutation::Put((make_key(key), value.to_vec())),
+                      key,
+                      &Options::default())
+            .unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_commit(engine: &Engine, key: &[u8], start_ts: u64, commit_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   start_ts,
+                                   None,
+                                   IsolationLevel::SI);
+        txn.commit(&make_key(key), commit_ts).unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    #[test]
+    fn test_incremental_scan() {
+        let engine = new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        must_prewrite_put(engine.as_ref(), b"k1", b"v0", 2);
+        must_commit(engine.as_ref(), b"k1", 2, 3);
+        must_prewrite_put(engine.as_ref(), b"k1", b"v1", 5);
+        must_commit(engine.as_ref(), b"k1", 5, 8);
+        must_prewrite_put(engine.as_ref(), b"k2", b"v2", 10);
+
+        let observed = ObservedRegions::default();
+        let pd_client = Arc::new(MockPdClient { ts: 20 });
+        let mut runner = Runner::new(engine.clone(), pd_client, observed.clone());
+        let (tx, rx) = unbounded();
+        runner.run(Task::Register {
+            ctx: Context::new(),
+            checkpoint_ts: 6,
+            sink: tx,
+        });
+        assert!(observed.read().unwrap().contains(&0));
+        let events: Vec<_> = rx.take(3).collect().wait().unwrap();
+        match events[0] {
+            Event::Rows { ref rows, .. } => {
+                // The version committed before the checkpoint is skipped.
+                assert_eq!(rows.len(), 2);
+                assert_eq!(rows[0].row_type, RowType::Committed);
+                assert_eq!((rows[0].value.as_slice(), rows[0].commit_ts), (&b"v1"[..], 8));
+                assert_eq!(rows[1].row_type, RowType::Prewrite);
+                assert_eq!((rows[1].value.as_slice(), rows[1].start_ts), (&b"v2"[..], 10));
+            }
+            ref e => panic!("unexpected event {:?}", e),
+        }
+        assert_eq!(events[1], Event::Initialized { region_id: 0 });
+        // Blocked by the lock of k2.
+        assert_eq!(events[2], Event::ResolvedTs { region_id: 0, ts: 9 });
+
+        runner.run(Task::RegionChanged {
+            region_id: 0,
+            msg: "split".to_owned(),
+        });
+        assert!(observed.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_incremental_scan_in_batches() {
+        let engine = new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        for i in 0..INCREMENTAL_SCAN_BATCH_SIZE + 1 {
+            let key = format!("k{:05}", i).into_bytes();
+            must_prewrite_put(engine.as_ref(), &key, b"v", 2);
+            must_commit(engine.as_ref(), &key, 2, 3);
+        }
+
+        let pd_client = Arc::new(MockPdClient { ts: 20 });
+        let mut runner = Runner::new(engine.clone(), pd_client, ObservedRegions::default());
+        let (tx, rx) = unbounded();
+        runner.run(Task::Register {
+            ctx: Context::new(),
+            checkpoint_ts: 1,
+            sink: tx,
+        });
+        let events: Vec<_> = rx.take(4).collect().wait().unwrap();
+        let sizes: Vec<_> = events[..2]
+            .iter()
+            .map(|e| match *e {
+                Event::Rows { ref rows, .. } => rows.len(),
+                ref e => panic!("unexpected event {:?}", e),
+            })
+            .collect();
+        assert_eq!(sizes, vec![INCREMENTAL_SCAN_BATCH_SIZE, 1]);
+        assert_eq!(events[2], Event::Initialized { region_id: 0 });
+        // Resolved to the ts got from PD if there is no lock.
+        assert_eq!(events[3], Event::ResolvedTs { region_id: 0, ts: 20 });
+    }
+}
@@ -0,0 +1,165 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Change data capture.
+//!
+//! `CdcObserver` is registered to the raftstore coprocessor host and forwards the commands
+//! applied to the observed regions to the cdc worker. The worker decodes their effects on
+//! CF_LOCK, CF_WRITE and CF_DEFAULT into row changes and streams them to the subscribers of the
+//! region. A subscriber first gets the rows committed after its checkpoint and the pending
+//! locks by an incremental scan, then the rows of the applied commands, and resolved ts
+//! watermarks in between. The resolved ts is advanced by the ts got from PD periodically.
+//!
+//! Ranges deleted and SST files ingested can't be decoded into rows, they close the
+//! subscriptions of the region so the subscribers scan the region again.
+//!
+//! Delivery is at least once: rows applied during the incremental scan may be sent twice.
+
+mod delegate;
+mod endpoint;
+mod observer;
+
+use std::fmt::{self, Display, Formatter};
+
+use futures::sync::mpsc::UnboundedSender;
+use kvproto::cdcpb::{ChangeDataEvent, Event as EventPb, Event_Row, Event_Row_OpType, EventLogType};
+use kvproto::errorpb;
+use protobuf::RepeatedField;
+
+pub use self::endpoint::{Runner, Task, run_min_ts_ticker};
+pub use self::observer::{CdcObserver, ObservedRegions};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowType {
+    /// A lock is written by a prewrite.
+    Prewrite,
+    /// A transaction is committed.
+    Commit,
+    /// A transaction is rolled back.
+    Rollback,
+    /// A committed row found by the incremental scan.
+    Committed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowOp {
+    Put,
+    Delete,
+    Lock,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowChange {
+    pub row_type: RowType,
+    pub op: RowOp,
+    pub key: Vec<u8>,
+    // Only set for puts, and may be empty for commits whose value is in the prewrite.
+    pub value: Vec<u8>,
+    pub start_ts: u64,
+    // 0 for prewrites and rollbacks.
+    pub commit_ts: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// Rows changed by the command applied at `index`, or by the incremental scan if `index`
+    /// is 0.
+    Rows {
+        region_id: u64,
+        index: u64,
+        rows: Vec<RowChange>,
+    },
+    /// The incremental scan is done.
+    Initialized { region_id: u64 },
+    /// No row with a commit ts less than or equal to `ts` will be sent any more.
+    ResolvedTs { region_id: u64, ts: u64 },
+    /// The subscription is closed, e.g. the region is split. The subscriber should subscribe to
+    /// the new regions from its resolved ts.
+    Error { region_id: u64, msg: String },
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Event::Rows { region_id, index, ref rows } => {
+                write!(f,
+                       "[region {}] {} rows at index {}",
+                       region_id,
+                       rows.len(),
+                       index)
+            }
+            Event::Initialized { region_id } => write!(f, "[region {}] initialized", region_id),
+            Event::ResolvedTs { region_id, ts } => {
+                write!(f, "[region {}] resolved ts {}", region_id, ts)
+            }
+            Event::Error { region_id, ref msg } => {
+                write!(f, "[region {}] error {}", region_id, msg)
+            }
+        }
+    }
+}
+
+pub type EventSender = UnboundedSender<Event>;
+
+fn encode_row(row: RowChange) -> Event_Row {
+    let mut pb = Event_Row::new();
+    pb.set_field_type(match row.row_type {
+        RowType::Prewrite => EventLogType::Prewrite,
+        RowType::Commit => EventLogType::Commit,
+        RowType::Rollback => EventLogType::Rollback,
+        RowType::Committed => EventLogType::Committed,
+    });
+    pb.set_op_type(match row.op {
+        RowOp::Put => Event_Row_OpType::Put,
+        RowOp::Delete => Event_Row_OpType::Delete,
+        // Rows that only lock the key.
+        RowOp::Lock => Event_Row_OpType::Unknown,
+    });
+    pb.set_key(row.key);
+    pb.set_value(row.value);
+    pb.set_start_ts(row.start_ts);
+    pb.set_commit_ts(row.commit_ts);
+    pb
+}
+
+/// Converts an event to its protobuf message sent to the subscriber.
+pub fn encode_event(event: Event) -> ChangeDataEvent {
+    let mut pb = EventPb::new();
+    match event {
+        Event::Rows { region_id, index, rows } => {
+            pb.set_region_id(region_id);
+            pb.set_index(index);
+            let rows = rows.into_iter().map(encode_row).collect();
+            pb.mut_entries().set_entries(RepeatedField::from_vec(rows));
+        }
+        Event::Initialized { region_id } => {
+            pb.set_region_id(region_id);
+            let mut row = Event_Row::new();
+            row.set_field_type(EventLogType::Initialized);
+            pb.mut_entries().mut_entries().push(row);
+        }
+        Event::ResolvedTs { region_id, ts } => {
+            pb.set_region_id(region_id);
+            pb.set_resolved_ts(ts);
+        }
+        Event::Error { region_id, msg } => {
+            pb.set_region_id(region_id);
+            let mut err = errorpb::Error::new();
+            err.set_message(msg);
+            pb.set_error(err);
+        }
+    }
+    let mut resp = ChangeDataEvent::new();
+    resp.mut_events().push(pb);
+    resp
+}
@@ -0,0 +1,81 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use kvproto::raft_cmdpb::{AdminCmdType, AdminRequest, Request};
+
+use raftstore::coprocessor::{Coprocessor, ObserverContext, RegionObserver};
+use util::collections::HashSet;
+use util::worker::Scheduler;
+use super::Task;
+
+/// Ids of the regions that have subscribers.
+pub type ObservedRegions = Arc<RwLock<HashSet<u64>>>;
+
+/// Forwards the commands applied to the observed regions to the cdc worker.
+pub struct CdcObserver {
+    // `Scheduler` is not `Sync`.
+    scheduler: Mutex<Scheduler<Task>>,
+    observed: ObservedRegions,
+}
+
+impl CdcObserver {
+    pub fn new(scheduler: Scheduler<Task>, observed: ObservedRegions) -> CdcObserver {
+        CdcObserver {
+            scheduler: Mutex::new(scheduler),
+            observed: observed,
+        }
+    }
+
+    fn is_observed(&self, region_id: u64) -> bool {
+        self.observed.read().unwrap().contains(&region_id)
+    }
+
+    fn schedule(&self, task: Task) {
+        if let Err(e) = self.scheduler.lock().unwrap().schedule(task) {
+            error!("failed to schedule cdc task: {:?}", e);
+        }
+    }
+}
+
+impl Coprocessor for CdcObserver {}
+
+impl RegionObserver for CdcObserver {
+    fn post_apply_query(&self, ctx: &mut ObserverContext, index: u64, reqs: &[Request]) {
+        let region_id = ctx.region().get_id();
+        if !self.is_observed(region_id) {
+            return;
+        }
+        self.schedule(Task::Apply {
+            region_id: region_id,
+            index: index,
+            reqs: reqs.to_vec(),
+        });
+    }
+
+    fn post_apply_admin(&self, ctx: &mut ObserverContext, req: &AdminRequest) {
+        let region_id = ctx.region().get_id();
+        if !self.is_observed(region_id) {
+            return;
+        }
+        // The key range of the region is changed, subscribers need to subscribe to the new
+        // regions.
+        if req.get_cmd_type() == AdminCmdType::Split {
+            self.schedule(Task::RegionChanged {
+                region_id: region_id,
+                msg: "region split".to_owned(),
+            });
+        }
+    }
+}
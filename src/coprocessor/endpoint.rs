@@ -11,7 +11,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::u64;
 use std::usize;
+use std::mem;
 use std::collections::BinaryHeap;
 use std::time::{Instant, Duration};
 use std::rc::Rc;
@@ -26,7 +28,8 @@ use byteorder::{BigEndian, ReadBytesExt};
 use kvproto::coprocessor::{Request, Response, KeyRange};
 use kvproto::errorpb::{self, ServerIsBusy};
 
-use storage::{self, Engine, SnapshotStore, engine, Snapshot, Key, ScanMode, Statistics};
+use storage::{self, Engine, SnapshotStore, engine, Snapshot, Key, ScanMode, Statistics,
+              FlowStatistics, MaxReadTs};
 use util::codec::table::{RowColsDict, TableDecoder};
 use util::codec::number::NumberDecoder;
 use util::codec::{Datum, table, datum, mysql};
@@ -64,17 +67,24 @@ const OUTDATED_ERROR_MSG: &'static str = "request outdated.";
 
 const ENDPOINT_IS_BUSY: &'static str = "endpoint is busy";
 
+// The interval the worker of `Host` should tick at, the read flow is reported on every tick.
+pub const READ_FLOW_REPORT_INTERVAL_MS: u64 = 1000;
+
 pub struct Host {
     engine: Box<Engine>,
+    max_read_ts: MaxReadTs,
     sched: Scheduler<Task>,
     reqs: HashMap<u64, Vec<RequestTask>>,
     last_req_id: u64,
     pool: ThreadPool<SmallGroupFirstQueue<u64>, u64>,
     max_running_task_count: usize,
+    // region id -> flow read since the last tick
+    read_flow: HashMap<u64, FlowStatistics>,
 }
 
 impl Host {
     pub fn new(engine: Box<Engine>,
+               max_read_ts: MaxReadTs,
                scheduler: Scheduler<Task>,
                concurrency: usize,
                txn_concurrency_on_busy: usize,
@@ -83,18 +93,27 @@ impl Host {
         let queue = SmallGroupFirstQueue::new(txn_concurrency_on_busy, small_txn_tasks_limit);
         Host {
             engine: engine,
+            max_read_ts: max_read_ts,
             sched: scheduler,
             reqs: HashMap::default(),
             last_req_id: 0,
             max_running_task_count: DEFAULT_MAX_RUNNING_TASK_COUNT,
             pool: ThreadPool::new(thd_name!("endpoint-pool"), concurrency, queue),
+            read_flow: HashMap::default(),
         }
     }
+
+    /// Aggregates the flow read from a region until the next tick.
+    fn add_read_flow(&mut self, region_id: u64, read_flow: FlowStatistics) {
+        self.read_flow.entry(region_id).or_insert_with(FlowStatistics::default).add(&read_flow);
+    }
 }
 
 pub enum Task {
     Request(RequestTask),
     SnapRes(u64, engine::Result<Box<Snapshot>>),
+    // The flow read from a region by a finished request.
+    ReadFlow(u64, FlowStatistics),
 }
 
 impl Display for Task {
@@ -102,6 +121,7 @@ impl Display for Task {
         match *self {
             Task::Request(ref req) => write!(f, "{}", req),
             Task::SnapRes(req_id, _) => write!(f, "snapres [{}]", req_id),
+            Task::ReadFlow(region_id, _) => write!(f, "read flow [region {}]", region_id),
         }
     }
 }
@@ -223,7 +243,8 @@ impl BatchRunnable<Task> for Host {
                          ctx.get_region_epoch().get_conf_ver(),
                          ctx.get_region_epoch().get_version(),
                          ctx.get_peer().get_id(),
-                         ctx.get_peer().get_store_id())
+                         ctx.get_peer().get_store_id(),
+                         ctx.get_replica_read())
                     };
                     let mut group = grouped_reqs.entry(key).or_insert_with(Vec::new);
                     group.push(req);
@@ -247,23 +268,45 @@ impl BatchRunnable<Task> for Host {
                     for req in reqs {
                         let end_point = TiDbEndPoint::new(snap.clone());
                         let txn_id = req.start_ts.unwrap_or_default();
+                        let region_id = req.req.get_context().get_region_id();
+                        let sched = self.sched.clone();
                         self.pool.execute(txn_id, move || {
-                            end_point.handle_request(req);
+                            let read_flow = end_point.handle_request(req);
                             COPR_PENDING_REQS.with_label_values(&["select"]).sub(1.0);
+                            if read_flow.read_keys > 0 {
+                                let _ = sched.schedule(Task::ReadFlow(region_id, read_flow));
+                            }
                         });
                     }
                 }
+                Task::ReadFlow(region_id, read_flow) => self.add_read_flow(region_id, read_flow),
             }
         }
         for (_, reqs) in grouped_reqs {
             self.last_req_id += 1;
             let id = self.last_req_id;
             let sched = self.sched.clone();
-            if let Err(e) = self.engine.async_snapshot(reqs[0].req.get_context(),
-                                                       box move |(_, res)| {
-                                                           sched.schedule(Task::SnapRes(id, res))
-                                                               .unwrap()
-                                                       }) {
+            // The snapshot is shared by the requests, so it can only be taken at a safe ts
+            // if all of them have a start ts.
+            let read_ts = if reqs.iter().all(|r| r.start_ts.is_some()) {
+                reqs.iter().filter_map(|r| r.start_ts).max().unwrap_or(0)
+            } else {
+                0
+            };
+            // Record the read ts before taking the snapshot, see `MaxReadTs`.
+            let region_id = reqs[0].req.get_context().get_region_id();
+            for ts in reqs.iter().filter_map(|r| r.start_ts) {
+                if ts != u64::MAX {
+                    self.max_read_ts.update(region_id, ts);
+                }
+            }
+            if let Err(e) = self.engine.async_snapshot_at(reqs[0].req.get_context(),
+                                                          read_ts,
+                                                          box move |(_, res)| {
+                                                              sched.schedule(Task::SnapRes(id,
+                                                                                           res))
+                                                                  .unwrap()
+                                                          }) {
                 notify_batch_failed(e, reqs);
                 continue;
             }
@@ -271,6 +314,14 @@ impl BatchRunnable<Task> for Host {
         }
     }
 
+    /// Reports the flow read from all the regions to the engine.
+    fn on_tick(&mut self) {
+        if !self.read_flow.is_empty() {
+            let read_flow = mem::replace(&mut self.read_flow, HashMap::default());
+            self.engine.report_read_flow(read_flow);
+        }
+    }
+
     fn shutdown(&mut self) {
         if let Err(e) = self.pool.stop() {
             warn!("Stop threadpool failed with {:?}", e);
@@ -354,16 +405,20 @@ impl TiDbEndPoint {
 }
 
 impl TiDbEndPoint {
-    fn handle_request(&self, mut t: RequestTask) {
+    /// Handles a request and returns the flow it reads.
+    fn handle_request(&self, mut t: RequestTask) -> FlowStatistics {
         t.stop_record_waiting();
         if let Err(e) = t.check_outdated() {
             on_error(e, t);
-            return;
+            return FlowStatistics::default();
         }
-        match self.handle_select(&mut t) {
+        let res = self.handle_select(&mut t);
+        let read_flow = t.statistics.flow_stats.clone();
+        match res {
             Ok(r) => respond(r, t),
             Err(e) => on_error(e, t),
         }
+        read_flow
     }
 
     pub fn handle_select(&self, t: &mut RequestTask) -> Result<Response> {
@@ -1027,6 +1082,7 @@ impl<'a> SelectContext<'a> {
                                                          ScanMode::Forward
                                                      },
                                                      self.key_only(),
+                                                     None,
                                                      upper_bound,
                                                      self.statistics));
             while self.core.limit > row_count {
@@ -1101,6 +1157,7 @@ impl<'a> SelectContext<'a> {
                                                      ScanMode::Forward
                                                  },
                                                  self.key_only(),
+                                                 None,
                                                  upper_bound,
                                                  self.statistics));
         while row_cnt < self.core.limit {
@@ -1191,7 +1248,7 @@ mod tests {
     fn test_req_outdated() {
         let mut worker = Worker::new("test-endpoint");
         let engine = engine::new_local_engine(TEMP_DIR, &[]).unwrap();
-        let end_point = Host::new(engine, worker.scheduler(), 1, 1, 1);
+        let end_point = Host::new(engine, MaxReadTs::new(), worker.scheduler(), 1, 1, 1);
         worker.start_batch(end_point, 30).unwrap();
         let (tx, rx) = mpsc::channel();
         let mut task = RequestTask::new(Request::new(),
@@ -1209,7 +1266,7 @@ mod tests {
     fn test_too_many_reqs() {
         let mut worker = Worker::new("test-endpoint");
         let engine = engine::new_local_engine(TEMP_DIR, &[]).unwrap();
-        let mut end_point = Host::new(engine, worker.scheduler(), 1, 1, 1);
+        let mut end_point = Host::new(engine, MaxReadTs::new(), worker.scheduler(), 1, 1, 1);
         end_point.max_running_task_count = 3;
         worker.start_batch(end_point, 30).unwrap();
         let (tx, rx) = mpsc::channel();
@@ -79,4 +79,5 @@ impl From<txn::Error> for Error {
 }
 
 pub use self::endpoint::{Host as EndPointHost, RequestTask, SelectContext, SINGLE_GROUP,
-                         REQ_TYPE_SELECT, REQ_TYPE_INDEX, Task as EndPointTask};
+                         REQ_TYPE_SELECT, REQ_TYPE_INDEX, READ_FLOW_REPORT_INTERVAL_MS,
+                         Task as EndPointTask};
@@ -0,0 +1,170 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crc::crc32;
+use kvproto::import_sstpb::SSTMeta;
+use rocksdb::{DB, IngestExternalFileOptions};
+
+use util::rocksdb;
+use super::{Error, Result};
+
+const SST_SUFFIX: &'static str = "sst";
+const TMP_SUFFIX: &'static str = "tmp";
+
+/// Sends SST files to the other stores out of band, so that the raft log only needs to carry
+/// their metadata.
+pub trait SSTSender: Send + Sync {
+    /// Sends the file `data` described by `meta` to the importer of the store.
+    fn send(&self, store_id: u64, meta: &SSTMeta, data: &[u8]) -> Result<()>;
+}
+
+/// Keeps the SST files received by this store until the apply state of the raft command
+/// ingesting them is persisted, so the command can be applied again after a restart.
+///
+/// Files of the imports that fail before they are proposed are left in the directory.
+#[derive(Debug)]
+pub struct SSTImporter {
+    dir: PathBuf,
+}
+
+impl SSTImporter {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<SSTImporter> {
+        try!(fs::create_dir_all(dir.as_ref()));
+        Ok(SSTImporter { dir: dir.as_ref().to_path_buf() })
+    }
+
+    fn path(&self, meta: &SSTMeta) -> PathBuf {
+        let uuid: Vec<_> = meta.get_uuid().iter().map(|b| format!("{:02x}", b)).collect();
+        let name = format!("{}_{}_{}", uuid.concat(), meta.get_region_id(), meta.get_cf_name());
+        self.dir.join(name).with_extension(SST_SUFFIX)
+    }
+
+    /// Verifies and saves the file `data` described by `meta`.
+    pub fn save(&self, meta: &SSTMeta, data: &[u8]) -> Result<()> {
+        try!(check_file(meta, data));
+        let path = self.path(meta);
+        let tmp_path = path.with_extension(TMP_SUFFIX);
+        {
+            let mut f = try!(File::create(&tmp_path));
+            try!(f.write_all(data));
+            try!(f.sync_all());
+        }
+        try!(fs::rename(&tmp_path, &path));
+        Ok(())
+    }
+
+    pub fn exists(&self, meta: &SSTMeta) -> bool {
+        self.path(meta).exists()
+    }
+
+    /// Verifies the file saved for `meta` again, it must be done before the file is ingested.
+    pub fn verify(&self, meta: &SSTMeta) -> Result<()> {
+        let path = self.path(meta);
+        let mut data = vec![];
+        match File::open(&path) {
+            Ok(mut f) => try!(f.read_to_end(&mut data)),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {
+                return Err(Error::InvalidInput(format!("sst file {} not found", path.display())))
+            }
+            Err(e) => return Err(Error::Io(e)),
+        };
+        check_file(meta, &data)
+    }
+
+    /// Ingests the file saved for `meta` into `db`. The file is copied, so it's still kept
+    /// after being ingested.
+    pub fn ingest(&self, meta: &SSTMeta, db: &DB) -> Result<()> {
+        let path = self.path(meta);
+        let handle = try!(rocksdb::get_cf_handle(db, meta.get_cf_name()));
+        let opt = IngestExternalFileOptions::new();
+        try!(db.ingest_external_file_cf(handle, &opt, &[path.to_str().unwrap()]));
+        Ok(())
+    }
+
+    /// Deletes the file saved for `meta`, it's ok if the file doesn't exist.
+    pub fn delete(&self, meta: &SSTMeta) -> Result<()> {
+        match fs::remove_file(self.path(meta)) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            res => res.map_err(Error::Io),
+        }
+    }
+}
+
+fn check_file(meta: &SSTMeta, data: &[u8]) -> Result<()> {
+    if data.len() as u64 != meta.get_length() {
+        return Err(Error::FileCorrupted(format!("length mismatch, expect {}, got {}",
+                                                meta.get_length(),
+                                                data.len())));
+    }
+    let checksum = crc32::checksum_ieee(data);
+    if checksum != meta.get_crc32() {
+        return Err(Error::FileCorrupted(format!("crc32 mismatch, expect {}, got {}",
+                                                meta.get_crc32(),
+                                                checksum)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use kvproto::import_sstpb::SSTMeta;
+    use tempdir::TempDir;
+
+    use raftstore::store::engine::Peekable;
+    use storage::{ALL_CFS, CF_DEFAULT};
+    use util::rocksdb;
+    use super::*;
+    use super::super::build_sst;
+
+    #[test]
+    fn test_sst_importer() {
+        let path = TempDir::new("test-sst-importer").unwrap();
+        let db = rocksdb::new_engine(path.path().join("db").to_str().unwrap(), ALL_CFS).unwrap();
+        let importer = SSTImporter::new(path.path().join("import")).unwrap();
+
+        let sst = build_sst(vec![(b"k1".to_vec(), b"v1".to_vec())]).unwrap();
+        let mut meta = SSTMeta::new();
+        meta.set_uuid(vec![1; 16]);
+        meta.set_region_id(1);
+        meta.set_cf_name(CF_DEFAULT.to_owned());
+        meta.set_crc32(sst.crc32);
+        meta.set_length(sst.data.len() as u64);
+
+        // The file is not uploaded yet.
+        assert!(importer.verify(&meta).is_err());
+
+        // A corrupted file is rejected.
+        let mut data = sst.data.clone();
+        data[0] ^= 1;
+        assert!(importer.save(&meta, &data).is_err());
+        assert!(importer.save(&meta, &sst.data[1..]).is_err());
+        assert!(!importer.exists(&meta));
+
+        importer.save(&meta, &sst.data).unwrap();
+        assert!(importer.exists(&meta));
+        importer.verify(&meta).unwrap();
+        importer.ingest(&meta, &db).unwrap();
+        assert_eq!(&*db.get_value(b"k1").unwrap().unwrap(), b"v1");
+        // The file can be ingested again.
+        assert!(importer.exists(&meta));
+        importer.ingest(&meta, &db).unwrap();
+
+        importer.delete(&meta).unwrap();
+        assert!(!importer.exists(&meta));
+        importer.delete(&meta).unwrap();
+    }
+}
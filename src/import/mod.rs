@@ -0,0 +1,58 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bulk loading of data. Sorted key-value pairs are built into SST files, which are ingested
+//! into RocksDB directly instead of being written one by one. The files are sent to the
+//! replicas of a region out of band, and only their metadata goes through the raft log.
+
+mod importer;
+mod sst;
+
+use std::error;
+use std::io::Error as IoError;
+use std::result;
+
+pub use self::importer::{SSTImporter, SSTSender};
+pub use self::sst::{SSTFile, build_sst, ingest_sst};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Io(err: IoError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        RocksDb(msg: String) {
+            from()
+            description("RocksDb error")
+            display("RocksDb {}", msg)
+        }
+        InvalidInput(msg: String) {
+            description("invalid import input")
+            display("invalid import input: {}", msg)
+        }
+        FileCorrupted(msg: String) {
+            description("sst file corrupted")
+            display("sst file corrupted: {}", msg)
+        }
+        Other(err: Box<error::Error + Sync + Send>) {
+            from()
+            cause(err.as_ref())
+            description(err.description())
+            display("{:?}", err)
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
@@ -0,0 +1,126 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::io::{Read, Write};
+
+use crc::crc32;
+use rocksdb::{DB, EnvOptions, Options, SstFileWriter, IngestExternalFileOptions};
+use tempdir::TempDir;
+
+use util::escape;
+use util::rocksdb;
+use super::{Error, Result};
+
+const SST_FILE_NAME: &'static str = "import.sst";
+
+/// An SST file built in memory, ready to be sent to the replicas of a region.
+pub struct SSTFile {
+    pub data: Vec<u8>,
+    pub crc32: u32,
+    /// The first and the last key in the file.
+    pub range: (Vec<u8>, Vec<u8>),
+}
+
+/// Builds an SST file from `kvs`. The keys must be sorted and unique.
+pub fn build_sst<I>(kvs: I) -> Result<SSTFile>
+    where I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>
+{
+    let dir = try!(TempDir::new("import-sst"));
+    let path = dir.path().join(SST_FILE_NAME);
+    let mut writer = SstFileWriter::new(EnvOptions::new(), Options::new());
+    try!(writer.open(path.to_str().unwrap()));
+
+    let mut range: Option<(Vec<u8>, Vec<u8>)> = None;
+    for (k, v) in kvs {
+        if let Some((_, ref last)) = range {
+            if k <= *last {
+                return Err(Error::InvalidInput(format!("key {} is not greater than {}",
+                                                       escape(&k),
+                                                       escape(last))));
+            }
+        }
+        try!(writer.add(&k, &v));
+        range = match range {
+            Some((first, _)) => Some((first, k)),
+            None => Some((k.clone(), k)),
+        };
+    }
+    let range = match range {
+        Some(range) => range,
+        None => return Err(Error::InvalidInput("no key to import".to_owned())),
+    };
+    try!(writer.finish());
+
+    let mut data = vec![];
+    try!(try!(File::open(&path)).read_to_end(&mut data));
+    Ok(SSTFile {
+        crc32: crc32::checksum_ieee(&data),
+        data: data,
+        range: range,
+    })
+}
+
+/// Verifies the checksum of the SST file `data` and ingests it into `cf` of `db`.
+pub fn ingest_sst(db: &DB, cf: &str, data: &[u8], crc32: u32) -> Result<()> {
+    let checksum = crc32::checksum_ieee(data);
+    if checksum != crc32 {
+        return Err(Error::FileCorrupted(format!("crc32 mismatch, expect {}, got {}",
+                                                crc32,
+                                                checksum)));
+    }
+
+    let dir = try!(TempDir::new("ingest-sst"));
+    let path = dir.path().join(SST_FILE_NAME);
+    try!(try!(File::create(&path)).write_all(data));
+
+    let handle = try!(rocksdb::get_cf_handle(db, cf));
+    let opt = IngestExternalFileOptions::new();
+    try!(db.ingest_external_file_cf(handle, &opt, &[path.to_str().unwrap()]));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use raftstore::store::engine::Peekable;
+    use storage::{ALL_CFS, CF_WRITE};
+    use util::rocksdb;
+    use super::*;
+
+    #[test]
+    fn test_build_and_ingest_sst() {
+        let path = TempDir::new("test-import-sst").unwrap();
+        let db = rocksdb::new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap();
+
+        let kvs: Vec<_> = (0..10)
+            .map(|i| (format!("k{}", i).into_bytes(), format!("v{}", i).into_bytes()))
+            .collect();
+        let sst = build_sst(kvs.clone()).unwrap();
+        assert_eq!(sst.range, (b"k0".to_vec(), b"k9".to_vec()));
+
+        // A corrupted file is rejected.
+        assert!(ingest_sst(&db, CF_WRITE, &sst.data, sst.crc32 + 1).is_err());
+        assert!(db.get_value_cf(CF_WRITE, b"k0").unwrap().is_none());
+
+        ingest_sst(&db, CF_WRITE, &sst.data, sst.crc32).unwrap();
+        for (k, v) in kvs {
+            assert_eq!(&*db.get_value_cf(CF_WRITE, &k).unwrap().unwrap(), &*v);
+        }
+
+        // Keys must be sorted and not empty.
+        assert!(build_sst(vec![(b"k2".to_vec(), vec![]), (b"k1".to_vec(), vec![])]).is_err());
+        assert!(build_sst(vec![]).is_err());
+    }
+}
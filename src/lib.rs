@@ -78,3 +78,6 @@ pub mod raftstore;
 pub mod pd;
 pub mod server;
 pub mod coprocessor;
+pub mod import;
+pub mod cdc;
+pub mod backup;
@@ -21,6 +21,7 @@ use grpc::{EnvBuilder, WriteFlags};
 use kvproto::metapb;
 use kvproto::pdpb::{self, Member};
 
+use storage::mvcc::compose_ts;
 use util::{Either, HandyRwLock};
 use pd::PdFuture;
 use super::super::{Result, Error, PdClient, RegionStat};
@@ -205,6 +206,8 @@ impl PdClient for RpcClient {
         req.set_pending_peers(RepeatedField::from_vec(region_stat.pending_peers));
         req.set_bytes_written(region_stat.written_bytes);
         req.set_keys_written(region_stat.written_keys);
+        req.set_bytes_read(region_stat.read_bytes);
+        req.set_keys_read(region_stat.read_keys);
 
         let executor = |client: &RwLock<Inner>, req: pdpb::RegionHeartbeatRequest| {
             let mut inner = client.wl();
@@ -305,4 +308,58 @@ impl PdClient for RpcClient {
             .request(req, executor, LEADER_CHANGE_RETRY)
             .execute()
     }
+
+    fn get_gc_safe_point(&self) -> PdFuture<u64> {
+        let mut req = pdpb::GetGCSafePointRequest::new();
+        req.set_header(self.header());
+
+        let executor = |client: &RwLock<Inner>, req: pdpb::GetGCSafePointRequest| {
+            let handler = client.rl().client.get_gc_safe_point_async(req);
+            handler.map_err(Error::Grpc)
+                .and_then(|resp| {
+                    try!(check_resp_header(resp.get_header()));
+                    Ok(resp.get_safe_point())
+                })
+                .boxed()
+        };
+
+        self.leader_client
+            .request(req, executor, LEADER_CHANGE_RETRY)
+            .execute()
+    }
+
+    fn get_tso(&self) -> PdFuture<u64> {
+        let mut req = pdpb::TsoRequest::new();
+        req.set_header(self.header());
+        req.set_count(1);
+
+        let executor = |client: &RwLock<Inner>, req: pdpb::TsoRequest| {
+            let (sink, receiver) = client.rl().client.tso();
+            sink.send((req, WriteFlags::default()))
+                .and_then(|sink| {
+                    receiver.into_future()
+                        .map(move |(resp, _)| {
+                            // Closes the request stream after the response is received.
+                            drop(sink);
+                            resp
+                        })
+                        .map_err(|(e, _)| e)
+                })
+                .map_err(Error::Grpc)
+                .and_then(|resp| {
+                    let resp = match resp {
+                        Some(resp) => resp,
+                        None => return Err(box_err!("tso stream is closed")),
+                    };
+                    try!(check_resp_header(resp.get_header()));
+                    let ts = resp.get_timestamp();
+                    Ok(compose_ts(ts.get_physical() as u64, ts.get_logical() as u64))
+                })
+                .boxed()
+        };
+
+        self.leader_client
+            .request(req, executor, LEADER_CHANGE_RETRY)
+            .execute()
+    }
 }
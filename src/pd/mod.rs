@@ -35,19 +35,25 @@ pub struct RegionStat {
     pub pending_peers: Vec<metapb::Peer>,
     pub written_bytes: u64,
     pub written_keys: u64,
+    pub read_bytes: u64,
+    pub read_keys: u64,
 }
 
 impl RegionStat {
     pub fn new(down_peers: Vec<pdpb::PeerStats>,
                pending_peers: Vec<metapb::Peer>,
                written_bytes: u64,
-               written_keys: u64)
+               written_keys: u64,
+               read_bytes: u64,
+               read_keys: u64)
                -> RegionStat {
         RegionStat {
             down_peers: down_peers,
             pending_peers: pending_peers,
             written_bytes: written_bytes,
             written_keys: written_keys,
+            read_bytes: read_bytes,
+            read_keys: read_keys,
         }
     }
 }
@@ -131,4 +137,11 @@ pub trait PdClient: Send + Sync {
 
     // Report pd the split region.
     fn report_split(&self, left: metapb::Region, right: metapb::Region) -> PdFuture<()>;
+
+    // Get the safe point of the cluster, the versions that are not visible to it can be
+    // collected by the stores.
+    fn get_gc_safe_point(&self) -> PdFuture<u64>;
+
+    // Get a timestamp from the TSO, it's larger than any timestamp got before.
+    fn get_tso(&self) -> PdFuture<u64>;
 }
@@ -39,6 +39,11 @@ pub struct Unstable {
     // all entries that have not yet been written to storage.
     pub entries: Vec<Entry>,
     pub offset: u64,
+    // entries before in_progress are being persisted asynchronously, they are not
+    // returned by entries_to_persist again.
+    pub in_progress: u64,
+    // whether the snapshot is being persisted asynchronously.
+    pub snapshot_in_progress: bool,
 
     pub tag: String,
 }
@@ -50,6 +55,8 @@ impl Unstable {
             offset: offset,
             snapshot: None,
             entries: vec![],
+            in_progress: 0,
+            snapshot_in_progress: false,
             tag: tag,
         }
     }
@@ -96,17 +103,20 @@ impl Unstable {
         })
     }
 
-    pub fn stable_to(&mut self, idx: u64, term: u64) {
+    // stable_to returns true if the entries up to idx are removed.
+    pub fn stable_to(&mut self, idx: u64, term: u64) -> bool {
         let t = self.maybe_term(idx);
         if t.is_none() {
-            return;
+            return false;
         }
 
         if t.unwrap() == term && idx >= self.offset {
             let start = idx + 1 - self.offset;
             self.entries.drain(..start as usize);
             self.offset = idx + 1;
+            return true;
         }
+        false
     }
 
     pub fn stable_snap_to(&mut self, idx: u64) {
@@ -115,18 +125,54 @@ impl Unstable {
         }
         if idx == self.snapshot.as_ref().unwrap().get_metadata().get_index() {
             self.snapshot = None;
+            self.snapshot_in_progress = false;
         }
     }
 
     pub fn restore(&mut self, snap: Snapshot) {
         self.entries.clear();
         self.offset = snap.get_metadata().get_index() + 1;
+        self.in_progress = 0;
         self.snapshot = Some(snap);
+        self.snapshot_in_progress = false;
+    }
+
+    // entries_to_persist returns the entries that haven't been handed to the
+    // application for persisting.
+    pub fn entries_to_persist(&self) -> &[Entry] {
+        if self.in_progress <= self.offset {
+            return &self.entries;
+        }
+        &self.entries[(self.in_progress - self.offset) as usize..]
+    }
+
+    // snapshot_to_persist returns the snapshot if it hasn't been handed to the
+    // application for persisting.
+    pub fn snapshot_to_persist(&self) -> Option<&Snapshot> {
+        if self.snapshot_in_progress {
+            return None;
+        }
+        self.snapshot.as_ref()
+    }
+
+    // accept_in_progress marks all the unstable entries and snapshot as being
+    // persisted asynchronously.
+    pub fn accept_in_progress(&mut self) {
+        if !self.entries.is_empty() {
+            self.in_progress = self.offset + self.entries.len() as u64;
+        }
+        if self.snapshot.is_some() {
+            self.snapshot_in_progress = true;
+        }
     }
 
     // append entries to unstable, truncate local block first if overlapped.
     pub fn truncate_and_append(&mut self, ents: &[Entry]) {
         let after = ents[0].get_index();
+        // The truncated entries need to be persisted again.
+        if after < self.in_progress {
+            self.in_progress = after;
+        }
         if after == self.offset + self.entries.len() as u64 {
             // after is the next index in the self.entries, append directly
             self.entries.extend_from_slice(ents);
@@ -377,4 +423,35 @@ mod test {
             assert_eq!(u.entries, wentries);
         }
     }
+
+    #[test]
+    fn test_accept_in_progress() {
+        let mut u = Unstable {
+            entries: vec![new_entry(5, 1), new_entry(6, 1)],
+            offset: 5,
+            snapshot: Some(new_snapshot(4, 1)),
+            ..Default::default()
+        };
+        assert_eq!(u.entries_to_persist().len(), 2);
+        assert!(u.snapshot_to_persist().is_some());
+        u.accept_in_progress();
+        assert!(u.entries_to_persist().is_empty());
+        assert!(u.snapshot_to_persist().is_none());
+
+        u.truncate_and_append(&[new_entry(7, 1), new_entry(8, 1)]);
+        assert_eq!(u.entries_to_persist(), &[new_entry(7, 1), new_entry(8, 1)]);
+        u.accept_in_progress();
+
+        // The truncated entries should be persisted again.
+        u.truncate_and_append(&[new_entry(6, 2)]);
+        assert_eq!(u.entries_to_persist(), &[new_entry(6, 2)]);
+
+        u.stable_snap_to(4);
+        assert!(u.stable_to(5, 1));
+        assert_eq!(u.entries_to_persist(), &[new_entry(6, 2)]);
+        u.accept_in_progress();
+        assert!(u.stable_to(6, 2));
+        assert!(u.entries_to_persist().is_empty());
+        assert!(!u.stable_to(6, 1));
+    }
 }
@@ -79,6 +79,11 @@ pub struct Progress {
     // When a leader receives a reply, the previous inflights should
     // be freed by calling inflights.freeTo.
     pub ins: Inflights,
+
+    // is_learner is true if the progress is for a learner. A learner receives
+    // entries and snapshots like a follower, but it is not counted in the
+    // quorum and never votes or campaigns.
+    pub is_learner: bool,
 }
 
 
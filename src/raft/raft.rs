@@ -30,8 +30,9 @@ use std::cmp;
 use std::boxed::Box;
 
 use rand::{self, Rng};
-use kvproto::eraftpb::{HardState, Entry, EntryType, Message, Snapshot, MessageType};
-use protobuf::repeated::RepeatedField;
+use kvproto::eraftpb::{HardState, Entry, EntryType, Message, Snapshot, MessageType, ConfState,
+                       ConfChange, ConfChangeType};
+use protobuf::{self, RepeatedField};
 
 use raft::storage::Storage;
 use raft::progress::{Progress, Inflights, ProgressState};
@@ -83,6 +84,12 @@ pub struct Config {
     /// peer is private and only used for testing right now.
     pub peers: Vec<u64>,
 
+    /// learners contains the IDs of all learner nodes (including self if the
+    /// local node is a learner) in the raft cluster. Learners only receive
+    /// entries from the leader node, they don't vote or promote themselves.
+    /// Like peers, it should only be set when starting a new raft cluster.
+    pub learners: Vec<u64>,
+
     /// ElectionTick is the number of node.tick invocations that must pass between
     /// elections. That is, if a follower does not receive any message from the
     /// leader of current term before ElectionTick has elapsed, it will become
@@ -109,7 +116,7 @@ pub struct Config {
     /// max_inflight_msgs limits the max number of in-flight append messages during optimistic
     /// replication phase. The application transportation layer usually has its own sending
     /// buffer over TCP/UDP. Setting MaxInflightMsgs to avoid overflowing that sending buffer.
-    /// TODO: feedback to application to limit the proposal rate?
+    /// The application can limit the proposal rate by `RawNode::should_throttle_proposal`.
     pub max_inflight_msgs: usize,
 
     /// check_quorum specifies if the leader should check quorum activity. Leader steps down when
@@ -129,6 +136,12 @@ pub struct Config {
     // May affect proposal forwarding and follower read.
     pub skip_bcast_commit: bool,
 
+    /// async_persist specifies that the application persists the entries and the hard state
+    /// of a Ready asynchronously and reports them by `RawNode::on_persist_ready`. The leader
+    /// counts itself in the quorum of an entry only after the entry is persisted locally, and
+    /// the messages of the other roles are held until the Ready is persisted.
+    pub async_persist: bool,
+
     /// tag is only used for logging
     pub tag: String,
 }
@@ -197,6 +210,11 @@ pub struct Raft<T: Storage> {
     /// New configuration is ignored if there exists unapplied configuration.
     pub pending_conf: bool,
 
+    /// The configuration being switched to in a joint consensus (C_old,new). The nodes only
+    /// in it are tracked as learners in `prs` until the change is finalized, and both the
+    /// voters in `prs` and the voters in it must form a quorum to commit or elect.
+    pub next_conf: Option<ConfState>,
+
     pub read_only: ReadOnly,
 
     /// number of ticks since it reached last electionTimeout when it is leader
@@ -209,6 +227,12 @@ pub struct Raft<T: Storage> {
     /// only leader keeps heartbeatElapsed.
     heartbeat_elapsed: usize,
 
+    /// number of ticks the leader's appended entries have been waiting to be
+    /// persisted without any progress.
+    append_stall_elapsed: usize,
+    /// the persisted index when append_stall_elapsed was last reset.
+    stall_persisted: u64,
+
     pub check_quorum: bool,
     pre_vote: bool,
     skip_bcast_commit: bool,
@@ -265,17 +289,20 @@ impl<T: Storage> Raft<T> {
     pub fn new(c: &Config, store: T) -> Raft<T> {
         c.validate().expect("configuration is invalid");
         let rs = store.initial_state().expect("");
-        let raft_log = RaftLog::new(store, c.tag.clone());
+        let mut raft_log = RaftLog::new(store, c.tag.clone());
+        raft_log.async_persist = c.async_persist;
         let mut peers: &[u64] = &c.peers;
-        if !rs.conf_state.get_nodes().is_empty() {
-            if !peers.is_empty() {
+        let mut learners: &[u64] = &c.learners;
+        if !rs.conf_state.get_nodes().is_empty() || !rs.conf_state.get_learners().is_empty() {
+            if !peers.is_empty() || !learners.is_empty() {
                 // TODO: the peers argument is always nil except in
                 // tests; the argument should be removed and these tests should be
                 // updated to specify their nodes through a snap
-                panic!("{} cannot specify both new(peers) and ConfState.Nodes",
+                panic!("{} cannot specify both new(peers/learners) and ConfState.(Nodes/Learners)",
                        c.tag)
             }
             peers = rs.conf_state.get_nodes();
+            learners = rs.conf_state.get_learners();
         }
         let mut r = Raft {
             id: c.id,
@@ -283,7 +310,7 @@ impl<T: Storage> Raft<T> {
             raft_log: raft_log,
             max_inflight: c.max_inflight_msgs,
             max_msg_size: c.max_size_per_msg,
-            prs: FlatMap::with_capacity(peers.len()),
+            prs: FlatMap::with_capacity(peers.len() + learners.len()),
             state: StateRole::Follower,
             check_quorum: c.check_quorum,
             pre_vote: c.pre_vote,
@@ -297,9 +324,12 @@ impl<T: Storage> Raft<T> {
             term: Default::default(),
             election_elapsed: Default::default(),
             pending_conf: Default::default(),
+            next_conf: None,
             before_step_state: None,
             vote: Default::default(),
             heartbeat_elapsed: Default::default(),
+            append_stall_elapsed: 0,
+            stall_persisted: 0,
             randomized_election_timeout: 0,
             skip_bcast_commit: c.skip_bcast_commit,
             tag: c.tag.to_owned(),
@@ -307,6 +337,18 @@ impl<T: Storage> Raft<T> {
         for p in peers {
             r.prs.insert(*p, new_progress(1, r.max_inflight));
         }
+        for l in learners {
+            if r.prs.contains_key(l) {
+                panic!("{} node {} is in both learner and peer list", r.tag, l);
+            }
+            let mut pr = new_progress(1, r.max_inflight);
+            pr.is_learner = true;
+            r.prs.insert(*l, pr);
+        }
+        if let Some(conf) = rs.next_conf_state {
+            // The node restarts in a joint consensus.
+            r.set_next_conf(conf);
+        }
         if rs.hard_state != HardState::new() {
             r.load_state(rs.hard_state);
         }
@@ -315,10 +357,11 @@ impl<T: Storage> Raft<T> {
         }
         let term = r.term;
         r.become_follower(term, INVALID_ID);
-        info!("{} newRaft [peers: {:?}, term: {:?}, commit: {}, applied: {}, last_index: {}, \
-               last_term: {}]",
+        info!("{} newRaft [peers: {:?}, learners: {:?}, term: {:?}, commit: {}, applied: {}, \
+               last_index: {}, last_term: {}]",
               r.tag,
               r.nodes(),
+              r.learner_nodes(),
               r.term,
               r.raft_log.committed,
               r.raft_log.get_applied(),
@@ -371,8 +414,9 @@ impl<T: Storage> Raft<T> {
         self.state == StateRole::Leader && self.check_quorum
     }
 
+    // Learners are not counted in the quorum.
     fn quorum(&self) -> usize {
-        quorum(self.prs.len())
+        quorum(self.prs.values().filter(|p| !p.is_learner).count())
     }
 
     // for testing leader lease
@@ -392,13 +436,59 @@ impl<T: Storage> Raft<T> {
         self.randomized_election_timeout
     }
 
+    /// Returns the ids of the voters.
     pub fn nodes(&self) -> Vec<u64> {
         let mut nodes = Vec::with_capacity(self.prs.len());
-        nodes.extend(self.prs.keys());
+        nodes.extend(self.prs.iter().filter(|&(_, p)| !p.is_learner).map(|(id, _)| *id));
         nodes.sort();
         nodes
     }
 
+    /// Returns the ids of the learners.
+    pub fn learner_nodes(&self) -> Vec<u64> {
+        let mut nodes = vec![];
+        nodes.extend(self.prs.iter().filter(|&(_, p)| p.is_learner).map(|(id, _)| *id));
+        nodes.sort();
+        nodes
+    }
+
+    /// Returns true if the local node is a learner.
+    pub fn is_learner(&self) -> bool {
+        self.prs.contains_key(&self.id) && !self.is_voter(self.id)
+    }
+
+    /// Returns true if the node votes in the current configuration, or in the next one
+    /// during a joint consensus.
+    pub fn is_voter(&self, id: u64) -> bool {
+        if self.in_current_conf(id) {
+            return true;
+        }
+        self.next_conf.as_ref().map_or(false, |conf| conf.get_nodes().contains(&id))
+    }
+
+    // Returns true if the node votes in the current configuration. Nodes which are only in the
+    // next configuration of a joint consensus are learners in `prs`.
+    fn in_current_conf(&self, id: u64) -> bool {
+        self.prs.get(&id).map_or(false, |p| !p.is_learner)
+    }
+
+    /// Returns true if the raft is in a joint consensus.
+    pub fn in_joint_consensus(&self) -> bool {
+        self.next_conf.is_some()
+    }
+
+    // In a joint consensus, returns true if the nodes satisfying `f` also form a quorum of
+    // the next configuration. Returns true if the raft isn't in a joint consensus.
+    fn next_conf_quorum<F: Fn(u64) -> bool>(&self, f: F) -> bool {
+        match self.next_conf {
+            Some(ref conf) => {
+                let n = conf.get_nodes().iter().filter(|&&id| f(id)).count();
+                n >= quorum(conf.get_nodes().len())
+            }
+            None => true,
+        }
+    }
+
     // send persists state to stable storage and then sends to its mailbox.
     fn send(&mut self, mut m: Message) {
         m.set_from(self.id);
@@ -577,12 +667,21 @@ impl<T: Storage> Raft<T> {
     pub fn maybe_commit(&mut self) -> bool {
         // TODO: optimize
         let mut mis = Vec::with_capacity(self.prs.len());
-        for p in self.prs.values() {
+        for p in self.prs.values().filter(|p| !p.is_learner) {
             mis.push(p.matched);
         }
         // reverse sort
         mis.sort_by(|a, b| b.cmp(a));
-        let mci = mis[self.quorum() - 1];
+        let mut mci = mis[self.quorum() - 1];
+        if let Some(ref conf) = self.next_conf {
+            // An entry is committed only if it's committed by both configurations.
+            let mut mis: Vec<_> = conf.get_nodes()
+                .iter()
+                .map(|id| self.prs.get(id).map_or(0, |p| p.matched))
+                .collect();
+            mis.sort_by(|a, b| b.cmp(a));
+            mci = cmp::min(mci, mis[quorum(mis.len()) - 1]);
+        }
         let term = self.term;
         self.raft_log.maybe_commit(mci, term)
     }
@@ -596,16 +695,25 @@ impl<T: Storage> Raft<T> {
         self.reset_randomized_election_timeout();
         self.election_elapsed = 0;
         self.heartbeat_elapsed = 0;
+        self.append_stall_elapsed = 0;
+        self.stall_persisted = self.raft_log.persisted;
 
         self.abort_leader_transfer();
 
         self.votes = FlatMap::default();
         let (last_index, max_inflight) = (self.raft_log.last_index(), self.max_inflight);
+        let self_matched = if self.raft_log.async_persist {
+            cmp::min(last_index, self.raft_log.persisted)
+        } else {
+            last_index
+        };
         let self_id = self.id;
         for (id, p) in &mut self.prs {
+            let is_learner = p.is_learner;
             *p = new_progress(last_index + 1, max_inflight);
+            p.is_learner = is_learner;
             if id == &self_id {
-                p.matched = last_index;
+                p.matched = self_matched;
             }
         }
         self.pending_conf = false;
@@ -619,11 +727,39 @@ impl<T: Storage> Raft<T> {
             e.set_index(li + 1 + i as u64);
         }
         self.raft_log.append(es);
+        if self.raft_log.async_persist {
+            // The entries are acknowledged by on_persist_entries.
+            return;
+        }
         self.prs.get_mut(&self.id).unwrap().maybe_update(self.raft_log.last_index());
         // Regardless of maybe_commit's return, our caller will call bcastAppend.
         self.maybe_commit();
     }
 
+    /// Notifies that the entries up to `index` at `term` are persisted asynchronously.
+    pub fn on_persist_entries(&mut self, index: u64, term: u64) {
+        // The entries may have been overwritten by a new leader before persisted.
+        if index <= self.raft_log.persisted || self.raft_log.term(index).ok() != Some(term) {
+            return;
+        }
+        self.raft_log.stable_to(index, term);
+        if self.state != StateRole::Leader {
+            return;
+        }
+        let updated = match self.prs.get_mut(&self.id) {
+            Some(pr) => pr.maybe_update(index),
+            None => false,
+        };
+        if updated && self.maybe_commit() && !self.skip_bcast_commit {
+            self.bcast_append();
+        }
+    }
+
+    /// Notifies that the snapshot at `index` is persisted asynchronously.
+    pub fn on_persist_snap(&mut self, index: u64) {
+        self.raft_log.stable_snap_to(index);
+    }
+
     /// Returns true to indicate that there will probably be some readiness need to be handled.
     pub fn tick(&mut self) -> bool {
         match self.state {
@@ -672,6 +808,9 @@ impl<T: Storage> Raft<T> {
             return has_ready;
         }
 
+        // Heartbeats don't touch the storage, so the stalled appends are tracked separately.
+        self.tick_append_stall();
+
         if self.heartbeat_elapsed >= self.heartbeat_timeout {
             self.heartbeat_elapsed = 0;
             has_ready = true;
@@ -681,6 +820,22 @@ impl<T: Storage> Raft<T> {
         has_ready
     }
 
+    fn tick_append_stall(&mut self) {
+        let persisted = self.raft_log.persisted;
+        if persisted >= self.raft_log.last_index() || persisted != self.stall_persisted {
+            self.stall_persisted = persisted;
+            self.append_stall_elapsed = 0;
+            return;
+        }
+        self.append_stall_elapsed += 1;
+    }
+
+    /// Returns the number of ticks the leader's appended entries have been waiting to be
+    /// persisted without any progress.
+    pub fn append_stall_ticks(&self) -> usize {
+        self.append_stall_elapsed
+    }
+
     pub fn become_follower(&mut self, term: u64, leader_id: u64) {
         self.reset(term);
         self.leader_id = leader_id;
@@ -734,6 +889,10 @@ impl<T: Storage> Raft<T> {
             self.pending_conf = true;
         }
         self.append_entry(&mut [Entry::new()]);
+        if self.next_conf.is_some() && !self.pending_conf {
+            // The previous leader didn't finalize the joint consensus.
+            self.append_finalize_membership_change();
+        }
         info!("{} became leader at term {}", self.tag, self.term);
     }
 
@@ -751,7 +910,8 @@ impl<T: Storage> Raft<T> {
             (MessageType::MsgRequestVote, self.term)
         };
         let id = self.id;
-        if self.quorum() == self.poll(id, vote_resp_msg_type(vote_msg), true) {
+        self.poll(id, vote_resp_msg_type(vote_msg), true);
+        if self.vote_result() == Some(true) {
             // We won the election after voting for ourselves (which must mean that
             // this is a single-node cluster). Advance to the next state.
             if campaign_type == CAMPAIGN_PRE_ELECTION {
@@ -761,7 +921,12 @@ impl<T: Storage> Raft<T> {
             }
             return;
         }
-        let ids: Vec<_> = self.prs.keys().cloned().collect();
+        // Learners don't vote, while the voters of both configurations vote in a joint
+        // consensus.
+        let mut ids = self.nodes();
+        if let Some(ref conf) = self.next_conf {
+            ids.extend(conf.get_nodes().iter().filter(|id| self.prs[id].is_learner));
+        }
         for id in ids {
             if id == self.id {
                 continue;
@@ -802,6 +967,32 @@ impl<T: Storage> Raft<T> {
         self.votes.values().filter(|x| **x).count()
     }
 
+    // Returns Some(true) if the election is won, Some(false) if it is lost, or None if
+    // it is undecided yet. Both configurations must grant the votes in a joint consensus.
+    fn vote_result(&self) -> Option<bool> {
+        let (mut granted, mut rejected) = (0, 0);
+        for (&id, &v) in &self.votes {
+            if !self.in_current_conf(id) {
+                continue;
+            }
+            if v {
+                granted += 1;
+            } else {
+                rejected += 1;
+            }
+        }
+        if granted >= self.quorum() &&
+           self.next_conf_quorum(|id| self.votes.get(&id) == Some(&true)) {
+            return Some(true);
+        }
+        if rejected >= self.quorum() ||
+           (self.in_joint_consensus() &&
+            self.next_conf_quorum(|id| self.votes.get(&id) == Some(&false))) {
+            return Some(false);
+        }
+        None
+    }
+
     pub fn step(&mut self, m: Message) -> Result<()> {
         // Handle the message term, which may result in our stepping down to a follower.
 
@@ -898,6 +1089,10 @@ impl<T: Storage> Raft<T> {
 
         match m.get_msg_type() {
             MessageType::MsgHup => {
+                if self.is_learner() {
+                    debug!("{} ignoring MsgHup because it is a learner", self.tag);
+                    return Ok(());
+                }
                 if self.state != StateRole::Leader {
                     let ents = self.raft_log
                         .slice(self.raft_log.applied + 1,
@@ -1078,6 +1273,12 @@ impl<T: Storage> Raft<T> {
                    self.tag);
             return;
         }
+        if !self.is_voter(lead_transferee) {
+            debug!("{} ignored transferring leadership to learner {}",
+                   self.tag,
+                   lead_transferee);
+            return;
+        }
         // Transfer leadership to third party.
         info!("{} [term {}] starts to transfer leadership to {}",
               self.tag,
@@ -1150,12 +1351,26 @@ impl<T: Storage> Raft<T> {
                     }
                 }
 
-                if self.read_only.option != ReadOnlyOption::Safe || m.get_context().is_empty() {
+                // Learners don't acknowledge read only requests.
+                if self.read_only.option != ReadOnlyOption::Safe || m.get_context().is_empty() ||
+                   !self.is_voter(m.get_from()) {
                     return;
                 }
 
-                let ack_count = self.read_only.recv_ack(m);
-                if ack_count < self.quorum() {
+                self.read_only.recv_ack(m);
+                let self_id = self.id;
+                let acked = match self.read_only.pending_read_index.get(m.get_context()) {
+                    Some(rs) => {
+                        let acked = |id: u64| id == self_id || rs.acks.contains(&id);
+                        let ack_count = self.prs
+                            .iter()
+                            .filter(|&(id, p)| !p.is_learner && acked(*id))
+                            .count();
+                        ack_count >= self.quorum() && self.next_conf_quorum(acked)
+                    }
+                    None => false,
+                };
+                if !acked {
                     return;
                 }
 
@@ -1262,7 +1477,7 @@ impl<T: Storage> Raft<T> {
                     return;
                 }
 
-                if self.quorum() > 1 {
+                if self.quorum() > 1 || self.in_joint_consensus() {
                     // thinking: use an interally defined context instead of the user given context.
                     // We can express this in terms of the term and index instead of
                     // a user-supplied value.
@@ -1376,15 +1591,17 @@ impl<T: Storage> Raft<T> {
                       gr,
                       m.get_msg_type(),
                       self.votes.len() - gr);
-                if self.quorum() == gr {
-                    if self.state == StateRole::PreCandidate {
-                        self.campaign(CAMPAIGN_ELECTION);
-                    } else {
-                        self.become_leader();
-                        self.bcast_append();
+                match self.vote_result() {
+                    Some(true) => {
+                        if self.state == StateRole::PreCandidate {
+                            self.campaign(CAMPAIGN_ELECTION);
+                        } else {
+                            self.become_leader();
+                            self.bcast_append();
+                        }
                     }
-                } else if self.quorum() == self.votes.len() - gr {
-                    self.become_follower(term, INVALID_ID);
+                    Some(false) => self.become_follower(term, INVALID_ID),
+                    None => {}
                 }
             }
             MessageType::MsgTimeoutNow => {
@@ -1459,6 +1676,8 @@ impl<T: Storage> Raft<T> {
                           self.term);
                     return;
                 }
+                let ctx = m.get_entries()[0].get_data().to_vec();
+                self.read_only.add_forwarded_request(ctx);
                 m.set_to(self.leader_id);
                 self.send(m);
             }
@@ -1470,6 +1689,12 @@ impl<T: Storage> Raft<T> {
                            m.get_entries().len());
                     return;
                 }
+                if !self.read_only.recv_forwarded_resp(m.get_entries()[0].get_data()) {
+                    debug!("{} ignores outdated MsgReadIndexResp from {}",
+                           self.tag,
+                           m.get_from());
+                    return;
+                }
                 let rs = ReadState {
                     index: m.get_index(),
                     request_ctx: m.take_entries()[0].take_data(),
@@ -1579,16 +1804,25 @@ impl<T: Storage> Raft<T> {
               self.raft_log.last_term(),
               meta.get_index(),
               meta.get_term());
-        self.prs = FlatMap::with_capacity(meta.get_conf_state().get_nodes().len());
-        for &n in meta.get_conf_state().get_nodes() {
+        self.next_conf = None;
+        let conf_state = meta.get_conf_state();
+        let mut nodes: Vec<_> = conf_state.get_nodes().iter().map(|&n| (n, false)).collect();
+        nodes.extend(conf_state.get_learners().iter().map(|&n| (n, true)));
+        self.prs = FlatMap::with_capacity(nodes.len());
+        for (n, is_learner) in nodes {
             let next_idx = self.raft_log.last_index() + 1;
             let matched = if n == self.id { next_idx - 1 } else { 0 };
             self.set_progress(n, matched, next_idx);
+            self.prs.get_mut(&n).unwrap().is_learner = is_learner;
             info!("{} restored progress of {} [{:?}]",
                   self.tag,
                   n,
                   self.prs[&n]);
         }
+        if meta.has_next_conf_state() {
+            // The snapshot is taken in a joint consensus.
+            self.set_next_conf(meta.get_next_conf_state().clone());
+        }
         None
     }
 
@@ -1606,14 +1840,19 @@ impl<T: Storage> Raft<T> {
     }
 
     // promotable indicates whether state machine can be promoted to leader,
-    // which is true when its own id is in progress list.
+    // which is true when it's a voter of the current or the next configuration.
     pub fn promotable(&self) -> bool {
-        self.prs.contains_key(&self.id)
+        self.is_voter(self.id)
     }
 
+    /// Adds a voter, or promotes the node to a voter if it is a learner.
     pub fn add_node(&mut self, id: u64) {
         self.pending_conf = false;
-        if self.prs.contains_key(&id) {
+        if let Some(pr) = self.prs.get_mut(&id) {
+            if pr.is_learner {
+                info!("{} promotes learner {} to voter", self.tag, id);
+                pr.is_learner = false;
+            }
             // Ignore any redundant addNode calls (which can happen because the
             // initial bootstrapping entries are applied twice).
             return;
@@ -1622,6 +1861,20 @@ impl<T: Storage> Raft<T> {
         self.set_progress(id, 0, last_index + 1);
     }
 
+    /// Adds a learner, a voter can't be demoted to a learner.
+    pub fn add_learner(&mut self, id: u64) {
+        self.pending_conf = false;
+        if let Some(pr) = self.prs.get(&id) {
+            if !pr.is_learner {
+                warn!("{} ignored adding voter {} as learner", self.tag, id);
+            }
+            return;
+        }
+        let last_index = self.raft_log.last_index();
+        self.set_progress(id, 0, last_index + 1);
+        self.prs.get_mut(&id).unwrap().is_learner = true;
+    }
+
     pub fn remove_node(&mut self, id: u64) {
         self.del_progress(id);
         self.pending_conf = false;
@@ -1642,6 +1895,99 @@ impl<T: Storage> Raft<T> {
         }
     }
 
+    /// Enters the joint consensus of the current configuration and `conf`, the leader
+    /// proposes to finalize the change at once.
+    pub fn begin_membership_change(&mut self, conf: &ConfState) {
+        self.pending_conf = false;
+        if self.next_conf.is_some() {
+            warn!("{} ignored membership change to {:?} since it's in a joint consensus",
+                  self.tag,
+                  conf);
+            return;
+        }
+        if conf.get_nodes().is_empty() {
+            warn!("{} ignored membership change to {:?} without voters",
+                  self.tag,
+                  conf);
+            return;
+        }
+        info!("{} begins membership change from [voters: {:?}, learners: {:?}] to {:?}",
+              self.tag,
+              self.nodes(),
+              self.learner_nodes(),
+              conf);
+        self.set_next_conf(conf.clone());
+        if self.state == StateRole::Leader {
+            self.append_finalize_membership_change();
+        }
+    }
+
+    fn set_next_conf(&mut self, conf: ConfState) {
+        // The new nodes only replicate the log as learners in the current configuration.
+        let last_index = self.raft_log.last_index();
+        for &id in conf.get_nodes().iter().chain(conf.get_learners()) {
+            if !self.prs.contains_key(&id) {
+                self.set_progress(id, 0, last_index + 1);
+                self.prs.get_mut(&id).unwrap().is_learner = true;
+            }
+        }
+        self.next_conf = Some(conf);
+    }
+
+    fn append_finalize_membership_change(&mut self) {
+        let mut cc = ConfChange::new();
+        cc.set_change_type(ConfChangeType::FinalizeMembershipChange);
+        let data = protobuf::Message::write_to_bytes(&cc).expect("unexpected marshal error");
+        let mut e = Entry::new();
+        e.set_entry_type(EntryType::EntryConfChange);
+        e.set_data(data);
+        self.pending_conf = true;
+        self.append_entry(&mut [e]);
+        self.bcast_append();
+    }
+
+    /// Leaves the joint consensus, the next configuration becomes the current one.
+    pub fn finalize_membership_change(&mut self) {
+        self.pending_conf = false;
+        let conf = match self.next_conf.take() {
+            Some(conf) => conf,
+            None => {
+                warn!("{} ignored finalizing membership change since it's not in a joint \
+                       consensus",
+                      self.tag);
+                return;
+            }
+        };
+        info!("{} finalizes membership change to {:?}", self.tag, conf);
+        let ids: Vec<_> = self.prs.keys().cloned().collect();
+        for id in ids {
+            if conf.get_nodes().contains(&id) {
+                self.prs.get_mut(&id).unwrap().is_learner = false;
+            } else if conf.get_learners().contains(&id) {
+                self.prs.get_mut(&id).unwrap().is_learner = true;
+            } else {
+                self.del_progress(id);
+            }
+        }
+
+        if self.state != StateRole::Leader {
+            return;
+        }
+        if !self.promotable() {
+            // The leader has been removed or demoted by the change.
+            let term = self.term;
+            self.become_follower(term, INVALID_ID);
+            return;
+        }
+        // The quorum has changed, see if any pending entries can be committed.
+        if self.maybe_commit() && !self.skip_bcast_commit {
+            self.bcast_append();
+        }
+        if self.lead_transferee.map_or(false, |id| !self.is_voter(id)) {
+            self.abort_leader_transfer();
+        }
+    }
+
     pub fn reset_pending_conf(&mut self) {
         self.pending_conf = false;
     }
@@ -1695,8 +2041,10 @@ impl<T: Storage> Raft<T> {
     // false.
     // check_quorum_active also resets all recent_active to false.
     fn check_quorum_active(&mut self) -> bool {
-        let mut act = 0;
         let self_id = self.id;
+        let next_active =
+            self.next_conf_quorum(|id| id == self_id || self.prs[&id].recent_active);
+        let mut act = 0;
         for (id, p) in &mut self.prs {
             if id == &self_id {
                 // self is always active
@@ -1704,13 +2052,13 @@ impl<T: Storage> Raft<T> {
                 continue;
             }
 
-            if p.recent_active {
+            if p.recent_active && !p.is_learner {
                 act += 1;
             }
 
             p.recent_active = false;
         }
-        act >= self.quorum()
+        act >= self.quorum() && next_active
     }
 
     pub fn send_timeout_now(&mut self, to: u64) {
@@ -1721,4 +2069,64 @@ impl<T: Storage> Raft<T> {
     pub fn abort_leader_transfer(&mut self) {
         self.lead_transferee = None;
     }
+
+    /// Returns the followers which can't accept more append messages for now, because their
+    /// in-flight appends reach max_inflight_msgs, or they are being probed or sent a snapshot.
+    pub fn saturated_followers(&self) -> Vec<u64> {
+        if self.state != StateRole::Leader {
+            return vec![];
+        }
+        let mut ids: Vec<_> = self.prs
+            .iter()
+            .filter(|&(id, pr)| *id != self.id && pr.is_paused())
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Returns true if the voters which can accept more append messages can't form a quorum,
+    /// in which case new proposals can't be committed in time.
+    pub fn should_throttle_proposal(&self) -> bool {
+        if self.state != StateRole::Leader {
+            return false;
+        }
+        let available = |id: u64| {
+            id == self.id || self.prs.get(&id).map_or(false, |pr| !pr.is_paused())
+        };
+        let count = self.nodes().into_iter().filter(|&id| available(id)).count();
+        count < self.quorum() || !self.next_conf_quorum(available)
+    }
+
+    /// Returns the recently active voter with the most up-to-date log except self.
+    pub fn most_up_to_date_follower(&self) -> Option<u64> {
+        self.prs
+            .iter()
+            .filter(|&(id, pr)| *id != self.id && pr.recent_active && self.is_voter(*id))
+            .max_by_key(|&(id, pr)| (pr.matched, *id))
+            .map(|(id, _)| *id)
+    }
+
+    /// Transfers the leadership to the most up-to-date healthy follower, it's used by a
+    /// leader which can't make progress itself. Returns the transferee if the transfer
+    /// is started.
+    pub fn transfer_leader_to_healthy_follower(&mut self) -> Option<u64> {
+        if self.state != StateRole::Leader || self.lead_transferee.is_some() {
+            return None;
+        }
+        let transferee = match self.most_up_to_date_follower() {
+            Some(id) => id,
+            None => {
+                warn!("{} [term {}] no healthy follower to transfer leadership to",
+                      self.tag,
+                      self.term);
+                return None;
+            }
+        };
+        let mut m = Message::new();
+        m.set_msg_type(MessageType::MsgTransferLeader);
+        m.set_from(transferee);
+        self.handle_transfer_leader(&m);
+        self.lead_transferee
+    }
 }
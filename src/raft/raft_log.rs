@@ -54,6 +54,14 @@ pub struct RaftLog<T: Storage> {
     // Invariant: applied <= committed
     pub applied: u64,
 
+    // persisted is the highest log position that is known to be in
+    // stable storage on this node.
+    pub persisted: u64,
+
+    // async_persist indicates the application persists the log asynchronously,
+    // so only the committed entries that are persisted can be applied.
+    pub async_persist: bool,
+
     pub tag: String,
 }
 
@@ -79,6 +87,8 @@ impl<T: Storage> RaftLog<T> {
             store: storage,
             committed: first_index - 1,
             applied: first_index - 1,
+            persisted: last_index,
+            async_persist: false,
             unstable: Unstable::new(last_index + 1, tag.clone()),
             tag: tag,
         }
@@ -233,10 +243,15 @@ impl<T: Storage> RaftLog<T> {
     }
 
     pub fn stable_to(&mut self, idx: u64, term: u64) {
-        self.unstable.stable_to(idx, term)
+        if self.unstable.stable_to(idx, term) && idx > self.persisted {
+            self.persisted = idx;
+        }
     }
 
     pub fn stable_snap_to(&mut self, idx: u64) {
+        if self.unstable.snapshot.as_ref().map_or(false, |s| s.get_metadata().get_index() == idx) {
+            self.persisted = idx;
+        }
         self.unstable.stable_snap_to(idx)
     }
 
@@ -256,15 +271,22 @@ impl<T: Storage> RaftLog<T> {
                    after,
                    self.committed)
         }
+        if after < self.persisted {
+            // The persisted entries after `after` are overwritten.
+            self.persisted = after;
+        }
         self.unstable.truncate_and_append(ents);
         self.last_index()
     }
 
+    // unstable_entries returns the unstable entries that haven't been handed to
+    // the application for persisting.
     pub fn unstable_entries(&self) -> Option<&[Entry]> {
-        if self.unstable.entries.is_empty() {
+        let ents = self.unstable.entries_to_persist();
+        if ents.is_empty() {
             return None;
         }
-        Some(&self.unstable.entries)
+        Some(ents)
     }
 
     pub fn entries(&self, idx: u64, max_size: u64) -> Result<Vec<Entry>> {
@@ -299,9 +321,18 @@ impl<T: Storage> RaftLog<T> {
         term > self.last_term() || (term == self.last_term() && last_index >= self.last_index())
     }
 
+    // max_apply_index returns the highest log position that can be applied.
+    fn max_apply_index(&self) -> u64 {
+        if self.async_persist {
+            cmp::min(self.committed, self.persisted)
+        } else {
+            self.committed
+        }
+    }
+
     pub fn next_entries_since(&self, since_idx: u64) -> Option<Vec<Entry>> {
         let offset = cmp::max(since_idx + 1, self.first_index());
-        let committed = self.committed;
+        let committed = self.max_apply_index();
         if committed + 1 > offset {
             match self.slice(offset, committed + 1, NO_LIMIT) {
                 Ok(vec) => return Some(vec),
@@ -320,7 +351,7 @@ impl<T: Storage> RaftLog<T> {
 
     pub fn has_next_entries_since(&self, since_idx: u64) -> bool {
         let offset = cmp::max(since_idx + 1, self.first_index());
-        self.committed + 1 > offset
+        self.max_apply_index() + 1 > offset
     }
 
     pub fn has_next_entries(&self) -> bool {
@@ -410,7 +441,10 @@ impl<T: Storage> RaftLog<T> {
               self.to_string(),
               snapshot.get_metadata().get_index(),
               snapshot.get_metadata().get_term());
-        self.committed = snapshot.get_metadata().get_index();
+        let index = snapshot.get_metadata().get_index();
+        self.committed = index;
+        // The log is replaced by the snapshot, it's persisted along with the snapshot.
+        self.persisted = cmp::min(self.persisted, index - 1);
         self.unstable.restore(snapshot);
     }
 }
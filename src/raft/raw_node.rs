@@ -26,12 +26,14 @@
 // limitations under the License.
 
 
+use std::collections::VecDeque;
+
 use raft::errors::{Result, Error};
 use raft::Storage;
 use protobuf::{self, RepeatedField};
 use kvproto::eraftpb::{HardState, Entry, EntryType, Message, Snapshot, MessageType, ConfChange,
                        ConfChangeType, ConfState};
-use raft::raft::{Config, Raft, SoftState, INVALID_ID};
+use raft::raft::{Config, Raft, SoftState, StateRole, INVALID_ID};
 use raft::Status;
 use raft::read_only::ReadState;
 
@@ -110,7 +112,17 @@ pub struct Ready {
     // committed to stable storage.
     // If it contains a MsgSnap message, the application MUST report back to raft
     // when the snapshot has been received or has failed by calling ReportSnapshot.
+    // When the log is persisted asynchronously, they can be sent immediately.
     pub messages: Vec<Message>,
+
+    // PersistedMessages specifies outbound messages to be sent AFTER the
+    // Entries, HardState and Snapshot of this Ready are persisted. It's only
+    // used when the log is persisted asynchronously.
+    pub persisted_messages: Vec<Message>,
+
+    // Number identifies the Ready for RawNode.on_persist_ready when the log is
+    // persisted asynchronously.
+    pub number: u64,
 }
 
 impl Ready {
@@ -121,9 +133,15 @@ impl Ready {
                        -> Ready {
         let mut rd = Ready {
             entries: raft.raft_log.unstable_entries().unwrap_or(&[]).to_vec(),
-            messages: raft.msgs.drain(..).collect(),
             ..Default::default()
         };
+        if raft.raft_log.async_persist && raft.state != StateRole::Leader {
+            // The responses and votes of other roles depend on the log and the hard state,
+            // so they can't be sent until this Ready is persisted.
+            rd.persisted_messages = raft.msgs.drain(..).collect();
+        } else {
+            rd.messages = raft.msgs.drain(..).collect();
+        }
         rd.committed_entries = Some((match since_idx {
                 None => raft.raft_log.next_entries(),
                 Some(idx) => raft.raft_log.next_entries_since(idx),
@@ -137,8 +155,8 @@ impl Ready {
         if &hs != prev_hs {
             rd.hs = Some(hs);
         }
-        if raft.raft_log.get_unstable().snapshot.is_some() {
-            rd.snapshot = raft.raft_log.get_unstable().snapshot.clone().unwrap();
+        if let Some(snap) = raft.raft_log.get_unstable().snapshot_to_persist() {
+            rd.snapshot = snap.clone();
         }
         if !raft.read_states.is_empty() {
             rd.read_states = raft.read_states.clone();
@@ -147,6 +165,15 @@ impl Ready {
     }
 }
 
+// ReadyRecord is what to acknowledge when a Ready is persisted asynchronously.
+struct ReadyRecord {
+    number: u64,
+    // (index, term) of the last entry in the Ready.
+    last_entry: Option<(u64, u64)>,
+    // index of the snapshot in the Ready.
+    snapshot: Option<u64>,
+}
+
 // RawNode is a thread-unsafe Node.
 // The methods of this struct correspond to the methods of Node and are described
 // more fully there.
@@ -154,6 +181,10 @@ pub struct RawNode<T: Storage> {
     pub raft: Raft<T>,
     prev_ss: SoftState,
     prev_hs: HardState,
+    // the number of the last Ready.
+    max_number: u64,
+    // the Readies being persisted asynchronously.
+    records: VecDeque<ReadyRecord>,
 }
 
 impl<T: Storage> RawNode<T> {
@@ -165,6 +196,8 @@ impl<T: Storage> RawNode<T> {
             raft: r,
             prev_hs: Default::default(),
             prev_ss: Default::default(),
+            max_number: 0,
+            records: VecDeque::new(),
         };
         let last_index = rn.raft.get_store().last_index().expect("");
         if last_index == 0 {
@@ -263,21 +296,25 @@ impl<T: Storage> RawNode<T> {
         self.raft.step(m)
     }
 
+    // ApplyConfChange applies a config change to the local node. During a joint consensus,
+    // the returned ConfState is still the old configuration.
     pub fn apply_conf_change(&mut self, cc: &ConfChange) -> ConfState {
-        if cc.get_node_id() == INVALID_ID {
-            self.raft.reset_pending_conf();
-            let mut cs = ConfState::new();
-            cs.set_nodes(self.raft.nodes());
-            return cs;
-        }
         let nid = cc.get_node_id();
-        assert!(cc.has_change_type(), "unexpected conf type");
+        assert!(nid == INVALID_ID || cc.has_change_type(), "unexpected conf type");
         match cc.get_change_type() {
+            ConfChangeType::BeginMembershipChange => {
+                self.raft.begin_membership_change(cc.get_configuration())
+            }
+            ConfChangeType::FinalizeMembershipChange => self.raft.finalize_membership_change(),
+            _ if nid == INVALID_ID => self.raft.reset_pending_conf(),
+            // Adding an existing learner as a node promotes it to a voter.
             ConfChangeType::AddNode => self.raft.add_node(nid),
+            ConfChangeType::AddLearnerNode => self.raft.add_learner(nid),
             ConfChangeType::RemoveNode => self.raft.remove_node(nid),
         }
         let mut cs = ConfState::new();
         cs.set_nodes(self.raft.nodes());
+        cs.set_learners(self.raft.learner_nodes());
         cs
     }
 
@@ -293,16 +330,22 @@ impl<T: Storage> RawNode<T> {
         Err(Error::StepPeerNotFound)
     }
 
+    fn gen_ready(&mut self, since_idx: Option<u64>) -> Ready {
+        let mut rd = Ready::new(&mut self.raft, &self.prev_ss, &self.prev_hs, since_idx);
+        if self.raft.raft_log.async_persist {
+            self.max_number += 1;
+            rd.number = self.max_number;
+        }
+        rd
+    }
+
     pub fn ready_since(&mut self, applied_idx: u64) -> Ready {
-        Ready::new(&mut self.raft,
-                   &self.prev_ss,
-                   &self.prev_hs,
-                   Some(applied_idx))
+        self.gen_ready(Some(applied_idx))
     }
 
     // Ready returns the current point-in-time state of this RawNode.
     pub fn ready(&mut self) -> Ready {
-        Ready::new(&mut self.raft, &self.prev_ss, &self.prev_hs, None)
+        self.gen_ready(None)
     }
 
     pub fn has_ready_since(&self, applied_idx: Option<u64>) -> bool {
@@ -313,7 +356,7 @@ impl<T: Storage> RawNode<T> {
         if !raft.read_states.is_empty() {
             return true;
         }
-        if self.get_snap().map_or(false, |s| !is_empty_snap(s)) {
+        if raft.raft_log.get_unstable().snapshot_to_persist().map_or(false, |s| !is_empty_snap(s)) {
             return true;
         }
         let has_unapplied_entries = match applied_idx {
@@ -372,11 +415,78 @@ impl<T: Storage> RawNode<T> {
         self.commit_apply(applied);
     }
 
+    // AdvanceAppendAsync notifies the RawNode that the application has started to persist
+    // the Entries, HardState and Snapshot of the Ready asynchronously, and has sent its
+    // Messages. The application should send the PersistedMessages and call on_persist_ready
+    // after the Ready is persisted. Readies must be persisted in order.
+    pub fn advance_append_async(&mut self, rd: &Ready) {
+        assert!(self.raft.raft_log.async_persist,
+                "{} async persistence is not enabled",
+                self.raft.tag);
+        if let Some(ref ss) = rd.ss {
+            self.prev_ss = ss.clone();
+        }
+        if let Some(ref hs) = rd.hs {
+            if *hs != HardState::new() {
+                self.prev_hs = hs.clone();
+            }
+        }
+        if !rd.read_states.is_empty() {
+            self.raft.read_states.clear();
+        }
+        let last_entry = rd.entries.last().map(|e| (e.get_index(), e.get_term()));
+        let snapshot = if rd.snapshot != Snapshot::new() {
+            Some(rd.snapshot.get_metadata().get_index())
+        } else {
+            None
+        };
+        self.raft.raft_log.unstable.accept_in_progress();
+        if last_entry.is_some() || snapshot.is_some() {
+            self.records.push_back(ReadyRecord {
+                number: rd.number,
+                last_entry: last_entry,
+                snapshot: snapshot,
+            });
+        }
+    }
+
+    // OnPersistReady notifies the RawNode that the Readies up to `number` are persisted.
+    pub fn on_persist_ready(&mut self, number: u64) {
+        let (mut last_entry, mut snapshot) = (None, None);
+        while self.records.front().map_or(false, |r| r.number <= number) {
+            let record = self.records.pop_front().unwrap();
+            if record.last_entry.is_some() {
+                last_entry = record.last_entry;
+            }
+            if record.snapshot.is_some() {
+                snapshot = record.snapshot;
+            }
+        }
+        if let Some(index) = snapshot {
+            self.raft.on_persist_snap(index);
+        }
+        if let Some((index, term)) = last_entry {
+            self.raft.on_persist_entries(index, term);
+        }
+    }
+
     // Status returns the current status of the given group.
     pub fn status(&self) -> Status {
         Status::new(&self.raft)
     }
 
+    // SaturatedFollowers returns the followers which can't accept more append messages
+    // for now.
+    pub fn saturated_followers(&self) -> Vec<u64> {
+        self.raft.saturated_followers()
+    }
+
+    // ShouldThrottleProposal reports whether the followers are so saturated that new
+    // proposals can't be committed in time, the application should slow down proposing.
+    pub fn should_throttle_proposal(&self) -> bool {
+        self.raft.should_throttle_proposal()
+    }
+
     // ReportUnreachable reports the given node is not reachable for the last send.
     pub fn report_unreachable(&mut self, id: u64) {
         let mut m = Message::new();
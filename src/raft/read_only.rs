@@ -73,6 +73,9 @@ pub struct ReadOnly {
     pub option: ReadOnlyOption,
     pub pending_read_index: HashMap<Vec<u8>, ReadIndexStatus>,
     pub read_index_queue: VecDeque<Vec<u8>>,
+    /// Contexts of the read only requests a follower forwarded to the leader, which
+    /// are waiting for MsgReadIndexResp.
+    pub forwarded_read_index: VecDeque<Vec<u8>>,
 }
 
 impl ReadOnly {
@@ -81,6 +84,7 @@ impl ReadOnly {
             option: option,
             pending_read_index: HashMap::default(),
             read_index_queue: VecDeque::new(),
+            forwarded_read_index: VecDeque::new(),
         }
     }
 
@@ -142,8 +146,27 @@ impl ReadOnly {
         self.read_index_queue.back().cloned()
     }
 
+    /// add_forwarded_request records a read only request that is forwarded to the leader.
+    pub fn add_forwarded_request(&mut self, ctx: Vec<u8>) {
+        self.forwarded_read_index.push_back(ctx);
+    }
+
+    /// recv_forwarded_resp dequeues the forwarded requests until it finds the one that
+    /// has the same context as the given `ctx`. The leader handles the requests in order,
+    /// so the requests before it have been dropped by the leader. It returns false if
+    /// `ctx` is not forwarded by this node, e.g. the response is outdated.
+    pub fn recv_forwarded_resp(&mut self, ctx: &[u8]) -> bool {
+        match self.forwarded_read_index.iter().position(|x| x.as_slice() == ctx) {
+            Some(i) => {
+                self.forwarded_read_index.drain(..i + 1);
+                true
+            }
+            None => false,
+        }
+    }
+
     #[inline]
     pub fn pending_read_count(&self) -> usize {
-        self.read_index_queue.len()
+        self.read_index_queue.len() + self.forwarded_read_index.len()
     }
 }
@@ -36,6 +36,8 @@ use util::{self, HandyRwLock};
 pub struct RaftState {
     pub hard_state: HardState,
     pub conf_state: ConfState,
+    /// The configuration being switched to if the node is in a joint consensus.
+    pub next_conf_state: Option<ConfState>,
 }
 
 /// Storage is an trait that may be implemented by the application
@@ -230,9 +232,16 @@ impl Storage for MemStorage {
     /// initial_state implements the Storage trait.
     fn initial_state(&self) -> Result<RaftState> {
         let core = self.rl();
+        let meta = core.snapshot.get_metadata();
+        let next_conf_state = if meta.has_next_conf_state() {
+            Some(meta.get_next_conf_state().clone())
+        } else {
+            None
+        };
         Ok(RaftState {
             hard_state: core.hard_state.clone(),
-            conf_state: core.snapshot.get_metadata().get_conf_state().clone(),
+            conf_state: meta.get_conf_state().clone(),
+            next_conf_state: next_conf_state,
         })
     }
 
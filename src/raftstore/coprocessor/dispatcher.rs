@@ -94,6 +94,21 @@ impl CoprocessorHost {
         }
     }
 
+    /// Call all post apply hook until bypass is set to true.
+    pub fn post_apply(&self, region: &Region, index: u64, req: &RaftCmdRequest) {
+        let mut ctx = ObserverContext::new(region);
+        for entry in &self.registry.observers {
+            if req.has_admin_request() {
+                entry.observer.post_apply_admin(&mut ctx, req.get_admin_request());
+            } else {
+                entry.observer.post_apply_query(&mut ctx, index, req.get_requests());
+            }
+            if ctx.bypass {
+                break;
+            }
+        }
+    }
+
     pub fn shutdown(&self) {
         for entry in &self.registry.observers {
             entry.observer.stop();
@@ -165,6 +180,16 @@ mod test {
             self.called.fetch_add(3, Ordering::SeqCst);
             ctx.bypass = self.bypass.load(Ordering::SeqCst);
         }
+
+        fn post_apply_query(&self, ctx: &mut ObserverContext, _: u64, _: &[Request]) {
+            self.called.fetch_add(4, Ordering::SeqCst);
+            ctx.bypass = self.bypass.load(Ordering::SeqCst);
+        }
+
+        fn post_apply_admin(&self, ctx: &mut ObserverContext, _: &AdminRequest) {
+            self.called.fetch_add(5, Ordering::SeqCst);
+            ctx.bypass = self.bypass.load(Ordering::SeqCst);
+        }
     }
 
     fn share_bool() -> Arc<AtomicBool> {
@@ -246,5 +271,11 @@ mod test {
         set_all!(&[&r2], true);
         assert!(host.pre_propose(&region, &mut admin_req).is_err());
         assert_all!(&[&called1, &called2], &[0, 1]);
+
+        set_all!(&[&called1, &called2], 0);
+        host.post_apply(&region, 1, &query_req);
+        assert_all!(&[&called1, &called2], &[4, 4]);
+        host.post_apply(&region, 2, &admin_req);
+        assert_all!(&[&called1, &called2], &[9, 9]);
     }
 }
@@ -69,4 +69,12 @@ pub trait RegionObserver: Coprocessor {
     ///
     /// Please note that improper implementation can lead to data inconsistency.
     fn pre_apply_query(&self, _: &mut ObserverContext, _: &mut RepeatedField<Request>) {}
+
+    /// Hook to call after read/write request is applied successfully at the given index.
+    ///
+    /// Please note that the write batch of the request may not be persisted yet.
+    fn post_apply_query(&self, _: &mut ObserverContext, _: u64, _: &[Request]) {}
+
+    /// Hook to call after admin request is applied successfully.
+    fn post_apply_admin(&self, _: &mut ObserverContext, _: &AdminRequest) {}
 }
@@ -24,6 +24,7 @@ const RAFT_ELECTION_TIMEOUT_TICKS: usize = 10;
 const RAFT_MAX_SIZE_PER_MSG: u64 = 1024 * 1024;
 const RAFT_MAX_INFLIGHT_MSGS: usize = 256;
 const RAFT_ENTRY_MAX_SIZE: u64 = 8 * 1024 * 1024;
+const MAX_PROPOSAL_BATCH_SIZE: u64 = 0; // disable proposal batching by default.
 const RAFT_LOG_GC_INTERVAL: u64 = 10000;
 const RAFT_LOG_GC_THRESHOLD: u64 = 50;
 // Assume the average size of entries is 1k.
@@ -45,6 +46,7 @@ const DEFAULT_MGR_GC_TICK_INTERVAL: u64 = 60000;
 const DEFAULT_SNAP_GC_TIMEOUT_SECS: u64 = 4 * 60 * 60; // 4 hours
 const DEFAULT_MESSAGES_PER_TICK: usize = 4096;
 const DEFAULT_MAX_PEER_DOWN_SECS: u64 = 300;
+const DEFAULT_MAX_LEADER_STALL_SECS: u64 = 0; // disable leader stall check by default.
 const DEFAULT_LOCK_CF_COMPACT_INTERVAL: u64 = 10 * 60 * 1000; // 10 min
 const DEFAULT_LOCK_CF_COMPACT_BYTES_THRESHOLD: u64 = 256 * 1024 * 1024; // 256 MB
 // If the leader missing for over 2 hours,
@@ -57,6 +59,8 @@ const DEFAULT_CONSISTENCY_CHECK_INTERVAL: u64 = 0;
 
 const DEFAULT_REPORT_REGION_FLOW_INTERVAL: u64 = 60000; // 60 seconds
 
+const DEFAULT_SAFE_TS_TICK_INTERVAL: u64 = 1000; // 1 second
+
 const DEFAULT_RAFT_STORE_LEASE_SEC: i64 = 9; // 9 seconds
 
 const DEFAULT_USE_SST_FILE_SNAPSHOT: bool = true;
@@ -65,6 +69,10 @@ const DEFAULT_USE_SST_FILE_SNAPSHOT: bool = true;
 pub struct Config {
     // true for high reliability, prevent data loss when power failure.
     pub sync_log: bool,
+    // Persists the raft log in the raft log write worker instead of the raftstore thread,
+    // so that the leader replicates the entries in parallel with its own fsync and the
+    // raftstore thread keeps running while the disk is slow.
+    pub raft_async_persist: bool,
 
     // store capacity.
     // TODO: if not set, we will use disk capacity instead.
@@ -79,6 +87,11 @@ pub struct Config {
     pub raft_max_inflight_msgs: usize,
     // When the entry exceed the max size, reject to propose it.
     pub raft_entry_max_size: u64,
+    // The small write commands proposed in one loop are batched into one entry
+    // until the batch exceeds the size, 0 disables batching. The batch keeps
+    // growing while the followers can't accept more entries. The commands in a
+    // batch are applied together, so an error fails all of them.
+    pub max_proposal_batch_size: u64,
 
     // Interval to gc unnecessary raft log (ms).
     pub raft_log_gc_tick_interval: u64,
@@ -119,6 +132,12 @@ pub struct Config {
     /// the peer is considered to be down and is reported to PD.
     pub max_peer_down_duration: Duration,
 
+    /// If the appending or applying entries of a leader make no progress for longer than
+    /// max_leader_stall_duration, the leader transfers its leadership to the most up-to-date
+    /// healthy follower. Zero disables it. The appending can only stall when
+    /// raft_async_persist is on, otherwise the raftstore thread waits for the disk as well.
+    pub max_leader_stall_duration: Duration,
+
     /// If the leader of a peer is missing for longer than max_leader_missing_duration,
     /// the peer would ask pd to confirm whether it is valid in any region.
     /// If the peer is stale and is not valid in any region, it will destroy itself.
@@ -131,6 +150,10 @@ pub struct Config {
 
     pub report_region_flow_interval: u64,
 
+    // Interval (ms) to get a timestamp from PD, which followers use to advance their safe ts
+    // for replica reads. Zero disables it, then followers serve no reads without read index.
+    pub safe_ts_tick_interval: u64,
+
     // The lease provided by a successfully proposed and applied entry.
     pub raft_store_max_leader_lease: TimeDuration,
 
@@ -144,6 +167,7 @@ impl Default for Config {
     fn default() -> Config {
         Config {
             sync_log: true,
+            raft_async_persist: false,
             capacity: STORE_CAPACITY,
             raft_base_tick_interval: RAFT_BASE_TICK_INTERVAL,
             raft_heartbeat_ticks: RAFT_HEARTBEAT_TICKS,
@@ -151,6 +175,7 @@ impl Default for Config {
             raft_max_size_per_msg: RAFT_MAX_SIZE_PER_MSG,
             raft_max_inflight_msgs: RAFT_MAX_INFLIGHT_MSGS,
             raft_entry_max_size: RAFT_ENTRY_MAX_SIZE,
+            max_proposal_batch_size: MAX_PROPOSAL_BATCH_SIZE,
             raft_log_gc_tick_interval: RAFT_LOG_GC_INTERVAL,
             raft_log_gc_threshold: RAFT_LOG_GC_THRESHOLD,
             raft_log_gc_count_limit: RAFT_LOG_GC_COUNT_LIMIT,
@@ -168,12 +193,14 @@ impl Default for Config {
             snap_gc_timeout: DEFAULT_SNAP_GC_TIMEOUT_SECS,
             messages_per_tick: DEFAULT_MESSAGES_PER_TICK,
             max_peer_down_duration: Duration::from_secs(DEFAULT_MAX_PEER_DOWN_SECS),
+            max_leader_stall_duration: Duration::from_secs(DEFAULT_MAX_LEADER_STALL_SECS),
             max_leader_missing_duration: Duration::from_secs(DEFAULT_MAX_LEADER_MISSING_SECS),
             snap_apply_batch_size: DEFAULT_SNAPSHOT_APPLY_BATCH_SIZE,
             lock_cf_compact_interval: DEFAULT_LOCK_CF_COMPACT_INTERVAL,
             lock_cf_compact_bytes_threshold: DEFAULT_LOCK_CF_COMPACT_BYTES_THRESHOLD,
             consistency_check_tick_interval: DEFAULT_CONSISTENCY_CHECK_INTERVAL,
             report_region_flow_interval: DEFAULT_REPORT_REGION_FLOW_INTERVAL,
+            safe_ts_tick_interval: DEFAULT_SAFE_TS_TICK_INTERVAL,
             raft_store_max_leader_lease: TimeDuration::seconds(DEFAULT_RAFT_STORE_LEASE_SEC),
             use_sst_file_snapshot: DEFAULT_USE_SST_FILE_SNAPSHOT,
             right_derive_when_split: true,
@@ -204,6 +231,12 @@ impl Config {
             return Err(box_err!("raft log gc size limit should large than 0."));
         }
 
+        if self.max_proposal_batch_size > self.raft_entry_max_size {
+            return Err(box_err!("max proposal batch size {} must <= raft entry max size {}",
+                                self.max_proposal_batch_size,
+                                self.raft_entry_max_size));
+        }
+
         if self.region_max_size < self.region_split_size {
             return Err(box_err!("region max size {} must >= split size {}",
                                 self.region_max_size,
@@ -252,6 +285,10 @@ mod tests {
         cfg.raft_log_gc_size_limit = 0;
         assert!(cfg.validate().is_err());
 
+        cfg = Config::new();
+        cfg.max_proposal_batch_size = cfg.raft_entry_max_size + 1;
+        assert!(cfg.validate().is_err());
+
         cfg = Config::new();
         cfg.region_max_size = 10;
         cfg.region_split_size = 20;
@@ -11,6 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
 use std::sync::Arc;
 use prometheus::{Gauge, GaugeVec};
 use rocksdb::{DB, DBStatisticsTickerType as TickerType, DBStatisticsHistogramType as HistType,
@@ -24,6 +25,8 @@ pub const ROCKSDB_CUR_SIZE_ALL_MEM_TABLES: &'static str = "rocksdb.cur-size-all-
 pub const ROCKSDB_ESTIMATE_NUM_KEYS: &'static str = "rocksdb.estimate-num-keys";
 pub const ROCKSDB_ESTIMATE_PENDING_COMPACTION_BYTES: &'static str = "rocksdb.\
                                                          estimate-pending-compaction-bytes";
+pub const ROCKSDB_NUM_FILES_AT_LEVEL0: &'static str = "rocksdb.num-files-at-level0";
+pub const ROCKSDB_NUM_IMMUTABLE_MEM_TABLE: &'static str = "rocksdb.num-immutable-mem-table";
 pub const ENGINE_TICKER_TYPES: &'static [TickerType] = &[TickerType::BlockCacheMiss,
                                                          TickerType::BlockCacheHit,
                                                          TickerType::MemtableHit,
@@ -196,10 +199,57 @@ pub fn flush_engine_properties_and_get_used_size(engine: Arc<DB>) -> u64 {
             STORE_ENGINE_PENDING_COMACTION_BYTES_VEC.with_label_values(&[cf])
                 .set(pending_compaction_bytes as f64);
         }
+
+        // Level 0 files and immutable memtables, RocksDB stalls writes when they pile up.
+        if let Some(l0_files) = engine.get_property_int_cf(handle, ROCKSDB_NUM_FILES_AT_LEVEL0) {
+            STORE_ENGINE_NUM_FILES_AT_LEVEL0_VEC.with_label_values(&[cf]).set(l0_files as f64);
+        }
+        if let Some(immutable_mem_tables) =
+               engine.get_property_int_cf(handle, ROCKSDB_NUM_IMMUTABLE_MEM_TABLE) {
+            STORE_ENGINE_NUM_IMMUTABLE_MEM_TABLE_VEC.with_label_values(&[cf])
+                .set(immutable_mem_tables as f64);
+        }
     }
     used_size
 }
 
+/// Signals that RocksDB uses to decide whether to slow down or stop writes. Each signal is the
+/// maximum one among all column families.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EngineStallSignals {
+    pub pending_compaction_bytes: u64,
+    pub l0_files: u64,
+    pub immutable_mem_tables: u64,
+}
+
+impl EngineStallSignals {
+    /// Returns true if any signal reaches its limit in `limits`. A limit of 0 is ignored.
+    pub fn exceeds(&self, limits: &EngineStallSignals) -> bool {
+        let reach = |v: u64, limit: u64| limit > 0 && v >= limit;
+        reach(self.pending_compaction_bytes, limits.pending_compaction_bytes) ||
+        reach(self.l0_files, limits.l0_files) ||
+        reach(self.immutable_mem_tables, limits.immutable_mem_tables)
+    }
+}
+
+pub fn get_engine_stall_signals(engine: &DB) -> EngineStallSignals {
+    let mut signals = EngineStallSignals::default();
+    for cf in ALL_CFS {
+        let handle = rocksdb::get_cf_handle(engine, cf).unwrap();
+        if let Some(v) = engine.get_property_int_cf(handle,
+                                                    ROCKSDB_ESTIMATE_PENDING_COMPACTION_BYTES) {
+            signals.pending_compaction_bytes = cmp::max(signals.pending_compaction_bytes, v);
+        }
+        if let Some(v) = engine.get_property_int_cf(handle, ROCKSDB_NUM_FILES_AT_LEVEL0) {
+            signals.l0_files = cmp::max(signals.l0_files, v);
+        }
+        if let Some(v) = engine.get_property_int_cf(handle, ROCKSDB_NUM_IMMUTABLE_MEM_TABLE) {
+            signals.immutable_mem_tables = cmp::max(signals.immutable_mem_tables, v);
+        }
+    }
+    signals
+}
+
 lazy_static!{
     pub static ref STORE_ENGINE_SIZE_GAUGE_VEC: GaugeVec =
         register_gauge_vec!(
@@ -298,6 +348,20 @@ lazy_static!{
             &["cf"]
         ).unwrap();
 
+    pub static ref STORE_ENGINE_NUM_FILES_AT_LEVEL0_VEC: GaugeVec =
+        register_gauge_vec!(
+            "tikv_engine_num_files_at_level0",
+            "Number of files at level 0.",
+            &["cf"]
+        ).unwrap();
+
+    pub static ref STORE_ENGINE_NUM_IMMUTABLE_MEM_TABLE_VEC: GaugeVec =
+        register_gauge_vec!(
+            "tikv_engine_num_immutable_mem_table",
+            "Number of immutable mem tables.",
+            &["cf"]
+        ).unwrap();
+
     pub static ref STORE_ENGINE_COMPACTION_FLOW_VEC: GaugeVec =
         register_gauge_vec!(
             "tikv_engine_compaction_flow_bytes",
@@ -305,3 +369,28 @@ lazy_static!{
             &["type"]
         ).unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stall_signals_exceeds() {
+        let limits = EngineStallSignals {
+            pending_compaction_bytes: 1024,
+            l0_files: 10,
+            immutable_mem_tables: 0,
+        };
+        let mut signals = EngineStallSignals::default();
+        assert!(!signals.exceeds(&limits));
+        signals.l0_files = 10;
+        assert!(signals.exceeds(&limits));
+        signals.l0_files = 9;
+        signals.pending_compaction_bytes = 2048;
+        assert!(signals.exceeds(&limits));
+        // A zero limit never triggers.
+        signals.pending_compaction_bytes = 0;
+        signals.immutable_mem_tables = 100;
+        assert!(!signals.exceeds(&limits));
+    }
+}
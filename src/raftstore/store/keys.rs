@@ -38,6 +38,8 @@ pub const DATA_MAX_KEY: &'static [u8] = &[DATA_PREFIX + 1];
 // Following keys are all local keys, so the first byte must be 0x01.
 pub const STORE_IDENT_KEY: &'static [u8] = &[LOCAL_PREFIX, 0x01];
 pub const PREPARE_BOOTSTRAP_KEY: &'static [u8] = &[LOCAL_PREFIX, 0x02];
+// Whether the raw data of the store is written with TTL enabled.
+pub const ENABLE_TTL_KEY: &'static [u8] = &[LOCAL_PREFIX, 0x04];
 // We save two types region data in DB, for raft and other meta data.
 // When the store starts, we should iterate all region meta data to
 // construct peer, no need to travel large raft data, so we separate them
@@ -66,6 +68,10 @@ pub fn prepare_bootstrap_key() -> Vec<u8> {
     PREPARE_BOOTSTRAP_KEY.to_vec()
 }
 
+pub fn enable_ttl_key() -> Vec<u8> {
+    ENABLE_TTL_KEY.to_vec()
+}
+
 fn make_region_id_key(region_id: u64, suffix: u8, extra_cap: usize) -> Vec<u8> {
     let mut key = Vec::with_capacity(REGION_RAFT_PREFIX_KEY.len() + mem::size_of::<u64>() +
                                      mem::size_of::<u8>() +
@@ -211,6 +217,12 @@ pub fn data_end_key(region_end_key: &[u8]) -> Vec<u8> {
     }
 }
 
+/// Check whether the encoded range [`start_key`, `end_key`) only covers data keys, so
+/// destroying it can't touch any local or system key.
+pub fn is_data_range(start_key: &[u8], end_key: &[u8]) -> bool {
+    start_key < end_key && start_key >= DATA_MIN_KEY && end_key <= DATA_MAX_KEY
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -218,6 +230,18 @@ mod tests {
     use kvproto::metapb::{Region, Peer};
     use std::cmp::Ordering;
 
+    #[test]
+    fn test_data_range() {
+        assert!(is_data_range(DATA_MIN_KEY, DATA_MAX_KEY));
+        assert!(is_data_range(&data_key(b"a"), &data_key(b"b")));
+        assert!(is_data_range(&data_key(b"a"), &data_end_key(b"")));
+        assert!(!is_data_range(&data_key(b"b"), &data_key(b"a")));
+        assert!(!is_data_range(&data_key(b"a"), &data_key(b"a")));
+        assert!(!is_data_range(LOCAL_MIN_KEY, &data_key(b"a")));
+        assert!(!is_data_range(&store_ident_key(), DATA_MAX_KEY));
+        assert!(!is_data_range(&data_key(b"a"), MAX_KEY));
+    }
+
     #[test]
     fn test_region_id_key() {
         let region_ids = vec![0, 1, 1024, ::std::u64::MAX];
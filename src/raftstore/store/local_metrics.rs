@@ -151,7 +151,9 @@ pub struct RaftProposeMetrics {
     pub all: u64,
     pub local_read: u64,
     pub read_index: u64,
+    pub stale_read: u64,
     pub normal: u64,
+    pub batched: u64,
     pub transfer_leader: u64,
     pub conf_change: u64,
     pub request_wait_time: LocalHistogram,
@@ -163,7 +165,9 @@ impl Default for RaftProposeMetrics {
             all: 0,
             local_read: 0,
             read_index: 0,
+            stale_read: 0,
             normal: 0,
+            batched: 0,
             transfer_leader: 0,
             conf_change: 0,
             request_wait_time: REQUEST_WAIT_TIME_HISTOGRAM.local(),
@@ -193,12 +197,24 @@ impl RaftProposeMetrics {
                 .unwrap();
             self.read_index = 0;
         }
+        if self.stale_read > 0 {
+            PEER_PROPOSAL_COUNTER_VEC.with_label_values(&["stale_read"])
+                .inc_by(self.stale_read as f64)
+                .unwrap();
+            self.stale_read = 0;
+        }
         if self.normal > 0 {
             PEER_PROPOSAL_COUNTER_VEC.with_label_values(&["normal"])
                 .inc_by(self.normal as f64)
                 .unwrap();
             self.normal = 0;
         }
+        if self.batched > 0 {
+            PEER_PROPOSAL_COUNTER_VEC.with_label_values(&["batched"])
+                .inc_by(self.batched as f64)
+                .unwrap();
+            self.batched = 0;
+        }
         if self.transfer_leader > 0 {
             PEER_PROPOSAL_COUNTER_VEC.with_label_values(&["transfer_leader"])
                 .inc_by(self.transfer_leader as f64)
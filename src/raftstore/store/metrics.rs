@@ -129,6 +129,20 @@ lazy_static! {
              exponential_buckets(1.0, 2.0, 20).unwrap()
         ).unwrap();
 
+    pub static ref REGION_READ_BYTES_HISTOGRAM: Histogram =
+        register_histogram!(
+            "tikv_region_read_bytes",
+            "Histogram of bytes read for regions",
+             exponential_buckets(256.0, 2.0, 20).unwrap()
+        ).unwrap();
+
+    pub static ref REGION_READ_KEYS_HISTOGRAM: Histogram =
+        register_histogram!(
+            "tikv_region_read_keys",
+            "Histogram of keys read for regions",
+             exponential_buckets(1.0, 2.0, 20).unwrap()
+        ).unwrap();
+
     pub static ref REQUEST_WAIT_TIME_HISTOGRAM: Histogram =
         register_histogram!(
             "tikv_raftstore_request_wait_time_duration_secs",
@@ -179,6 +193,13 @@ lazy_static! {
              exponential_buckets(1024.0, 2.0, 22).unwrap() // 1024,1024*2^1,..,4G
         ).unwrap();
 
+    pub static ref LEADER_STALL_TRANSFER_COUNTER_VEC: CounterVec =
+        register_counter_vec!(
+            "tikv_raftstore_leader_stall_transfer_total",
+            "Total number of leader transfers caused by stalls.",
+            &["reason"]
+        ).unwrap();
+
     pub static ref RAFT_ENTRY_FETCHES: CounterVec =
         register_counter_vec!(
             "tikv_raftstore_entry_fetches",
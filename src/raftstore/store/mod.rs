@@ -29,7 +29,7 @@ mod metrics;
 mod engine_metrics;
 mod local_metrics;
 
-pub use self::msg::{Msg, Callback, Tick, SnapshotStatusMsg};
+pub use self::msg::{Msg, Callback, LeaderRegionsCallback, Tick, SnapshotStatusMsg};
 pub use self::store::{StoreChannel, Store, create_event_loop};
 pub use self::config::Config;
 pub use self::transport::Transport;
@@ -41,3 +41,4 @@ pub use self::peer_storage::{PeerStorage, do_snapshot, SnapState, RAFT_INIT_LOG_
                              RAFT_INIT_LOG_INDEX, CacheQueryStats};
 pub use self::snap::{SnapKey, Snapshot, SnapshotDeleter, SnapshotStatistics, ApplyOptions,
                      SnapEntry, SnapManager, check_abort, copy_snapshot};
+pub use self::engine_metrics::{EngineStallSignals, get_engine_stall_signals};
@@ -17,12 +17,16 @@ use std::fmt;
 
 use kvproto::raft_serverpb::RaftMessage;
 use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse};
-use kvproto::metapb::RegionEpoch;
+use kvproto::metapb::{Peer, Region, RegionEpoch};
 use raft::SnapshotStatus;
 
 use util::escape;
+use util::collections::HashMap;
+use storage::FlowStatistics;
+use super::worker::PersistedReady;
 
 pub type Callback = Box<FnBox(RaftCmdResponse) + Send>;
+pub type LeaderRegionsCallback = Box<FnBox(Vec<(Region, Peer)>) + Send>;
 
 #[derive(Debug, Clone, Copy)]
 pub enum Tick {
@@ -36,6 +40,7 @@ pub enum Tick {
     CompactLockCf,
     ConsistencyCheck,
     ReportRegionFlow,
+    SafeTs,
 }
 
 pub struct SnapshotStatusMsg {
@@ -74,6 +79,15 @@ pub enum Msg {
         index: u64,
         hash: Vec<u8>,
     },
+
+    // Gets the regions led by the store, with the leader peers.
+    GetLeaderRegions { callback: LeaderRegionsCallback },
+
+    // The flow read from regions by storage and coprocessor, keyed by region id.
+    ReadFlow { read_flow: HashMap<u64, FlowStatistics> },
+
+    // The raft log of the readies is persisted by the raft log write worker.
+    RaftLogPersisted { readies: Vec<PersistedReady> },
 }
 
 impl fmt::Debug for Msg {
@@ -97,6 +111,13 @@ impl fmt::Debug for Msg {
                        index,
                        escape(hash))
             }
+            Msg::GetLeaderRegions { .. } => write!(fmt, "Get Leader Regions"),
+            Msg::ReadFlow { ref read_flow } => {
+                write!(fmt, "Read flow of {} regions", read_flow.len())
+            }
+            Msg::RaftLogPersisted { ref readies } => {
+                write!(fmt, "Raft log persisted of {} readies", readies.len())
+            }
         }
     }
 }
@@ -12,10 +12,11 @@
 // limitations under the License.
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::collections::VecDeque;
-use std::{mem, slice};
+use std::{cmp, mem, slice};
 use std::time::{Instant, Duration};
 
 use time::Timespec;
@@ -28,7 +29,8 @@ use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse, CmdType, AdminCmdType
 use kvproto::raft_serverpb::{RaftMessage, PeerState};
 use kvproto::pdpb::PeerStats;
 
-use raft::{self, RawNode, StateRole, SnapshotStatus, Ready, ProgressState, Progress, INVALID_INDEX};
+use raft::{self, RawNode, StateRole, SnapshotStatus, Ready, ReadState, ProgressState, Progress,
+           INVALID_INDEX};
 use raftstore::{Result, Error};
 use raftstore::coprocessor::CoprocessorHost;
 use raftstore::store::Config;
@@ -37,10 +39,12 @@ use raftstore::store::worker::apply::ExecResult;
 
 use util::worker::{FutureWorker, Scheduler, Worker};
 use raftstore::store::worker::{ApplyTask, ApplyRes, Apply};
-use util::{clocktime, Either};
+use util::{clocktime, duration_to_ms, Either};
+use util::codec::number::{NumberEncoder, NumberDecoder};
 use util::collections::{HashSet, FlatMap, FlatMapValues as Values};
 
 use pd::INVALID_ID;
+use storage::MaxReadTs;
 
 use super::store::Store;
 use super::peer_storage::{PeerStorage, ApplySnapResult, write_peer_state, InvokeContext};
@@ -59,6 +63,12 @@ struct ReadIndexRequest {
     id: u64,
     cmds: Vec<(RaftCmdRequest, Callback)>,
     renew_lease_time: Timespec,
+    // The read index returned by the leader, followers need to wait until it's applied.
+    read_index: Option<u64>,
+    // The timestamp got from PD before a follower requests the read index. Any transaction
+    // committed before it has been proposed before the read index, so it's safe once the read
+    // index is applied.
+    safe_ts: u64,
 }
 
 impl ReadIndexRequest {
@@ -98,6 +108,28 @@ impl ReadIndexQueue {
             }
         }
     }
+
+    /// Marks the read of `state` as ready and returns its renew lease time. The uncommitted
+    /// reads before it are dropped by the leader silently, so they are notified as stale.
+    fn mark_ready(&mut self, state: &ReadState, term: u64) -> Option<Timespec> {
+        let pos = match self.reads
+            .iter()
+            .skip(self.ready_cnt)
+            .position(|read| state.request_ctx.starts_with(read.binary_id())) {
+            Some(pos) => self.ready_cnt + pos,
+            // The read has been cleared.
+            None => return None,
+        };
+        for mut read in self.reads.drain(self.ready_cnt..pos) {
+            for (_, cb) in read.cmds.drain(..) {
+                apply::notify_stale_req(term, cb);
+            }
+        }
+        let read = &mut self.reads[self.ready_cnt];
+        read.read_index = Some(state.index);
+        self.ready_cnt += 1;
+        Some(read.renew_lease_time)
+    }
 }
 
 /// The returned states of the peer after checking whether it is stale
@@ -178,17 +210,90 @@ enum RequestPolicy {
     ReadLocal,
     // Handle the read request via raft's SafeReadIndex mechanism.
     ReadIndex,
+    // Handle the read request on a follower directly, because all the changes committed
+    // before the read ts have been applied.
+    ReadStale,
     ProposeNormal,
     ProposeTransferLeader,
     ProposeConfChange,
 }
 
+/// Small write commands which are proposed in one raft entry.
+struct ProposalBatch {
+    req: RaftCmdRequest,
+    // The callbacks of the commands and the numbers of their requests.
+    cbs: Vec<(Callback, usize)>,
+    err_resp: RaftCmdResponse,
+    size: u64,
+}
+
+impl ProposalBatch {
+    fn new(req: RaftCmdRequest,
+           cb: Callback,
+           err_resp: RaftCmdResponse,
+           size: u64)
+           -> ProposalBatch {
+        let cnt = req.get_requests().len();
+        ProposalBatch {
+            req: req,
+            cbs: vec![(cb, cnt)],
+            err_resp: err_resp,
+            size: size,
+        }
+    }
+
+    fn can_push(&self, req: &RaftCmdRequest, size: u64, limit: u64) -> bool {
+        if self.size + size > limit {
+            return false;
+        }
+        // Only the commands with the same header except the uuid can be batched.
+        let mut header = req.get_header().clone();
+        header.set_uuid(self.req.get_header().get_uuid().to_vec());
+        header == *self.req.get_header()
+    }
+
+    fn push(&mut self, mut req: RaftCmdRequest, cb: Callback, size: u64) {
+        let requests = req.take_requests().into_vec();
+        self.cbs.push((cb, requests.len()));
+        for r in requests {
+            self.req.mut_requests().push(r);
+        }
+        self.size += size;
+    }
+
+    /// Returns the batched request, the callback that splits the response for every command,
+    /// and the response to bind error to.
+    fn into_parts(self) -> (RaftCmdRequest, Callback, RaftCmdResponse) {
+        let ProposalBatch { req, mut cbs, err_resp, .. } = self;
+        if cbs.len() == 1 {
+            return (req, cbs.pop().unwrap().0, err_resp);
+        }
+        let cb: Callback = box move |mut resp: RaftCmdResponse| {
+            let mut responses = resp.take_responses().into_iter();
+            for (cb, cnt) in cbs {
+                let mut r = RaftCmdResponse::new();
+                r.set_header(resp.get_header().clone());
+                if !resp.get_header().has_error() {
+                    let rs = responses.by_ref().take(cnt).collect();
+                    r.set_responses(protobuf::RepeatedField::from_vec(rs));
+                }
+                cb(r);
+            }
+        };
+        (req, cb, err_resp)
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct PeerStat {
     pub written_bytes: u64,
     pub written_keys: u64,
     pub last_written_bytes: u64,
     pub last_written_keys: u64,
+    pub read_bytes: u64,
+    pub read_keys: u64,
+    pub last_read_bytes: u64,
+    pub last_read_keys: u64,
 }
 
 pub struct Peer {
@@ -201,6 +306,17 @@ pub struct Peer {
     proposals: ProposalQueue,
     apply_proposals: Vec<Proposal>,
     pending_reads: ReadIndexQueue,
+    // The messages held until the readies are persisted, keyed by the ready numbers. It's
+    // only used when the raft log is persisted asynchronously.
+    persisted_msgs: VecDeque<(u64, Vec<eraftpb::Message>)>,
+    // The write commands to be proposed in one entry.
+    proposal_batch: Option<ProposalBatch>,
+    // All the changes committed before `safe_ts` have been applied, so reads with smaller
+    // timestamps can be served by this peer even if it's not the leader.
+    safe_ts: u64,
+    // The latest timestamp got from PD by the store.
+    tso: Arc<AtomicUsize>,
+    max_read_ts: MaxReadTs,
     // Record the last instant of each peer's heartbeat response.
     pub peer_heartbeats: FlatMap<u64, Instant>,
     coprocessor_host: Arc<CoprocessorHost>,
@@ -229,6 +345,11 @@ pub struct Peer {
 
     leader_missing_time: Option<Instant>,
 
+    // The time since the applying entries have made no progress, and the applied index
+    // at that time.
+    apply_stall_time: Option<Instant>,
+    stall_applied_idx: u64,
+
     // `leader_lease_expired_time` contains either timestamps of
     //   1. Either::Left<Timespec>
     //      A safe leader lease expired time, which marks the leader holds the lease for now.
@@ -256,7 +377,11 @@ impl Peer {
     // for this store.
     pub fn create<T, C>(store: &mut Store<T, C>, region: &metapb::Region) -> Result<Peer> {
         let store_id = store.store_id();
-        let peer_id = match util::find_peer(region, store_id) {
+        // A peer being removed by a membership change is only in the previous configuration.
+        let peer = util::find_peer(region, store_id).or_else(|| {
+            region.get_prev_peers().iter().find(|p| p.get_store_id() == store_id)
+        });
+        let peer_id = match peer {
             None => {
                 return Err(box_err!("find no peer for store {} in region {:?}", store_id, region))
             }
@@ -312,6 +437,7 @@ impl Peer {
             check_quorum: true,
             tag: tag.clone(),
             skip_bcast_commit: true,
+            async_persist: cfg.raft_async_persist,
             ..Default::default()
         };
 
@@ -325,6 +451,11 @@ impl Peer {
             proposals: Default::default(),
             apply_proposals: vec![],
             pending_reads: Default::default(),
+            persisted_msgs: VecDeque::new(),
+            proposal_batch: None,
+            safe_ts: 0,
+            tso: store.tso.clone(),
+            max_read_ts: store.max_read_ts.clone(),
             peer_cache: RefCell::new(peer_cache),
             peer_heartbeats: FlatMap::default(),
             coprocessor_host: store.coprocessor_host.clone(),
@@ -334,6 +465,8 @@ impl Peer {
             pending_remove: false,
             marked_to_be_checked: false,
             leader_missing_time: Some(Instant::now()),
+            apply_stall_time: None,
+            stall_applied_idx: applied_index,
             tag: tag,
             last_applying_idx: applied_index,
             last_compacted_idx: 0,
@@ -399,6 +532,11 @@ impl Peer {
             apply::notify_req_region_removed(region.get_id(), proposal.cb);
         }
 
+        if let Some(batch) = self.proposal_batch.take() {
+            let (_, cb, _) = batch.into_parts();
+            apply::notify_req_region_removed(region.get_id(), cb);
+        }
+
         info!("{} destroy itself, takes {:?}", self.tag, t.elapsed());
 
         Ok(())
@@ -503,6 +641,17 @@ impl Peer {
         if self.is_leader() && m.get_from() != INVALID_ID {
             self.peer_heartbeats.insert(m.get_from(), Instant::now());
         }
+        if self.is_leader() && m.get_msg_type() == MessageType::MsgReadIndex {
+            // A follower serves replica reads at the ts after the read index is returned, it
+            // must be recorded before the read index is decided, see `MaxReadTs`.
+            let data = m.get_entries().first().map_or(&[][..], |e| e.get_data());
+            if data.len() > mem::size_of::<u64>() {
+                let mut ctx = &data[mem::size_of::<u64>()..];
+                if let Ok(ts) = ctx.decode_u64() {
+                    self.max_read_ts.update(self.region_id, ts);
+                }
+            }
+        }
         try!(self.raft_group.step(m));
         Ok(())
     }
@@ -588,6 +737,49 @@ impl Peer {
         StaleState::Valid
     }
 
+    /// Checks whether the leader's appends or applies have made no progress for longer than
+    /// `d`, and transfers the leadership to a healthy follower if so. Returns the reason of
+    /// the transfer.
+    pub fn check_leader_stall(&mut self, d: Duration) -> Option<&'static str> {
+        // Updates the `apply_stall_time` according to the apply progress.
+        let applied_idx = self.get_store().applied_index();
+        if !self.is_leader() || applied_idx >= self.last_applying_idx {
+            self.apply_stall_time = None;
+        } else if self.apply_stall_time.is_none() || applied_idx != self.stall_applied_idx {
+            self.apply_stall_time = Some(Instant::now());
+        }
+        self.stall_applied_idx = applied_idx;
+
+        if !self.is_leader() || d == Duration::new(0, 0) {
+            return None;
+        }
+        let append_stall = self.raft_group.raft.append_stall_ticks() as u64 *
+                           self.cfg.raft_base_tick_interval;
+        let reason = if append_stall >= duration_to_ms(d) {
+            "append"
+        } else if self.apply_stall_time.map_or(false, |t| t.elapsed() >= d) {
+            "apply"
+        } else {
+            return None;
+        };
+        let transferee = match self.raft_group.raft.transfer_leader_to_healthy_follower() {
+            Some(id) => id,
+            None => return None,
+        };
+        warn!("{} transfers leader to peer {} since {} stalls [last_index: {}, \
+               persisted: {}, applied_index: {}, last_applying_idx: {}]",
+              self.tag,
+              transferee,
+              reason,
+              self.raft_group.raft.raft_log.last_index(),
+              self.raft_group.raft.raft_log.persisted,
+              applied_idx,
+              self.last_applying_idx);
+        // Waits for another stall period before the next transfer.
+        self.apply_stall_time = None;
+        Some(reason)
+    }
+
     fn next_lease_expired_time(&self, send_to_quorum_ts: Timespec) -> Timespec {
         // The valid leader lease should be
         // "lease = max_lease - (quorum_commit_ts - send_to_quorum_ts)"
@@ -614,6 +806,9 @@ impl Peer {
                     debug!("{} becomes leader and lease expired time is {:?}",
                            self.tag,
                            next_expired_time);
+                    // The old leader may have served reads this peer doesn't know.
+                    let token = self.max_read_ts.reset(self.region_id);
+                    self.init_max_read_ts(token, worker);
                     self.heartbeat_pd(worker)
                 }
                 StateRole::Follower => {
@@ -667,6 +862,8 @@ impl Peer {
                    self.tag);
             return;
         }
+        // Replica reads may be waiting for the snapshot.
+        self.handle_replica_reads();
 
         if self.has_pending_snapshot() && !self.ready_to_handle_pending_snap() {
             debug!("{} [apply_idx: {}, last_applying_idx: {}] is not ready to apply snapshot.",
@@ -728,6 +925,10 @@ impl Peer {
                 warn!("{} follower send messages err {:?}", self.tag, e);
             });
         }
+        if !ready.persisted_messages.is_empty() {
+            let msgs = mem::replace(&mut ready.persisted_messages, vec![]);
+            self.persisted_msgs.push_back((ready.number, msgs));
+        }
 
         if apply_snap_result.is_some() {
             let reg = ApplyTask::register(self);
@@ -737,6 +938,21 @@ impl Peer {
         apply_snap_result
     }
 
+    /// Acknowledges the readies up to `number` which have been persisted by the raft log
+    /// write worker, and sends the messages held for them.
+    pub fn on_persist_ready<T: Transport>(&mut self,
+                                          trans: &T,
+                                          number: u64,
+                                          metrics: &mut RaftMessageMetrics) {
+        self.raft_group.on_persist_ready(number);
+        while self.persisted_msgs.front().map_or(false, |&(n, _)| n <= number) {
+            let (_, msgs) = self.persisted_msgs.pop_front().unwrap();
+            self.send(trans, msgs, metrics).unwrap_or_else(|e| {
+                warn!("{} send persisted messages err {:?}", self.tag, e);
+            });
+        }
+    }
+
     pub fn handle_raft_ready_apply(&mut self, mut ready: Ready, apply_tasks: &mut Vec<Apply>) {
         // Call `handle_raft_committed_entries` directly here may lead to inconsistency.
         // In some cases, there will be some pending committed entries when applying a
@@ -773,7 +989,12 @@ impl Peer {
 
         self.apply_reads(&ready);
 
-        self.raft_group.advance_append(ready);
+        if self.cfg.raft_async_persist {
+            // The ready is acknowledged by `on_persist_ready` after it's persisted.
+            self.raft_group.advance_append_async(&ready);
+        } else {
+            self.raft_group.advance_append(ready);
+        }
         if self.is_applying_snapshot() {
             // Because we only handle raft ready when not applying snapshot, so following
             // line won't be called twice for the same snapshot.
@@ -783,7 +1004,7 @@ impl Peer {
 
     fn apply_reads(&mut self, ready: &Ready) {
         let mut propose_time = None;
-        if self.ready_to_handle_read() {
+        if self.is_leader() && self.ready_to_handle_read() {
             for state in &ready.read_states {
                 let mut read = self.pending_reads.reads.pop_front().unwrap();
                 assert_eq!(state.request_ctx.as_slice(), read.binary_id());
@@ -796,12 +1017,14 @@ impl Peer {
                 propose_time = Some(read.renew_lease_time);
             }
         } else {
+            // Reads forwarded by a follower may be dropped by the leader.
+            let term = self.term();
             for state in &ready.read_states {
-                let read = &self.pending_reads.reads[self.pending_reads.ready_cnt];
-                assert_eq!(state.request_ctx.as_slice(), read.binary_id());
-                self.pending_reads.ready_cnt += 1;
-                propose_time = Some(read.renew_lease_time);
+                if let Some(time) = self.pending_reads.mark_ready(state, term) {
+                    propose_time = Some(time);
+                }
             }
+            self.handle_replica_reads();
         }
 
         // Note that only after handle read_states can we identify what requests are
@@ -812,6 +1035,10 @@ impl Peer {
             self.pending_reads.clear_uncommitted(term);
         }
 
+        if !self.is_leader() {
+            return;
+        }
+
         if let Some(Either::Right(_)) = self.leader_lease_expired_time {
             return;
         }
@@ -821,6 +1048,28 @@ impl Peer {
         }
     }
 
+    /// Handles the ready reads of a follower whose read index has been applied. The safe ts
+    /// is advanced to the PD timestamp recorded before the read index was requested.
+    fn handle_replica_reads(&mut self) {
+        if self.is_leader() || self.is_applying_snapshot() {
+            return;
+        }
+        let applied_index = self.get_store().applied_index();
+        while self.pending_reads.ready_cnt > 0 {
+            if self.pending_reads.reads[0].read_index.unwrap() > applied_index {
+                break;
+            }
+            let mut read = self.pending_reads.reads.pop_front().unwrap();
+            self.pending_reads.ready_cnt -= 1;
+            if read.safe_ts > self.safe_ts {
+                self.safe_ts = read.safe_ts;
+            }
+            for (req, cb) in read.cmds.drain(..) {
+                self.handle_read(req, cb);
+            }
+        }
+    }
+
     pub fn post_apply(&mut self, res: &ApplyRes, groups: &mut HashSet<u64>) {
         if self.is_applying_snapshot() {
             panic!("{} should not applying snapshot.", self.tag);
@@ -851,7 +1100,7 @@ impl Peer {
             self.mark_to_be_checked(groups);
         }
 
-        if self.pending_reads.ready_cnt > 0 && self.ready_to_handle_read() {
+        if self.pending_reads.ready_cnt > 0 && self.is_leader() && self.ready_to_handle_read() {
             for _ in 0..self.pending_reads.ready_cnt {
                 let mut read = self.pending_reads.reads.pop_front().unwrap();
                 for (req, cb) in read.cmds.drain(..) {
@@ -860,6 +1109,7 @@ impl Peer {
             }
             self.pending_reads.ready_cnt = 0;
         }
+        self.handle_replica_reads();
     }
 
     fn update_lease_with(&mut self, propose_time: Timespec) {
@@ -945,7 +1195,7 @@ impl Peer {
     pub fn propose(&mut self,
                    cb: Callback,
                    req: RaftCmdRequest,
-                   mut err_resp: RaftCmdResponse,
+                   err_resp: RaftCmdResponse,
                    metrics: &mut RaftProposeMetrics)
                    -> bool {
         if self.pending_remove {
@@ -956,12 +1206,27 @@ impl Peer {
 
         let mut is_conf_change = false;
 
-        let res = match self.get_handle_policy(&req) {
+        let policy = self.get_handle_policy(&req);
+        if let Ok(RequestPolicy::ProposeNormal) = policy {
+            if self.cfg.max_proposal_batch_size > 0 && !req.has_admin_request() {
+                self.batch_proposal(req, cb, err_resp, metrics);
+                return true;
+            }
+        }
+        // Proposes the batched commands first to keep the order of the commands.
+        self.propose_batch(metrics);
+
+        let res = match policy {
             Ok(RequestPolicy::ReadLocal) => {
                 self.read_local(req, cb, metrics);
                 return false;
             }
             Ok(RequestPolicy::ReadIndex) => return self.read_index(req, cb, metrics),
+            Ok(RequestPolicy::ReadStale) => {
+                metrics.stale_read += 1;
+                self.handle_read(req, cb);
+                return false;
+            }
             Ok(RequestPolicy::ProposeNormal) => self.propose_normal(req, metrics),
             Ok(RequestPolicy::ProposeTransferLeader) => {
                 return self.propose_transfer_leader(req, cb, metrics)
@@ -973,6 +1238,15 @@ impl Peer {
             Err(e) => Err(e),
         };
 
+        self.handle_propose_result(res, is_conf_change, cb, err_resp)
+    }
+
+    fn handle_propose_result(&mut self,
+                             res: Result<u64>,
+                             is_conf_change: bool,
+                             cb: Callback,
+                             mut err_resp: RaftCmdResponse)
+                             -> bool {
         match res {
             Err(e) => {
                 cmd_resp::bind_error(&mut err_resp, e);
@@ -991,6 +1265,42 @@ impl Peer {
         }
     }
 
+    fn batch_proposal(&mut self,
+                      req: RaftCmdRequest,
+                      cb: Callback,
+                      err_resp: RaftCmdResponse,
+                      metrics: &mut RaftProposeMetrics) {
+        let size = req.compute_size() as u64;
+        let limit = self.cfg.max_proposal_batch_size;
+        if self.proposal_batch.as_ref().map_or(false, |b| b.can_push(&req, size, limit)) {
+            metrics.batched += 1;
+            self.proposal_batch.as_mut().unwrap().push(req, cb, size);
+            return;
+        }
+        self.propose_batch(metrics);
+        self.proposal_batch = Some(ProposalBatch::new(req, cb, err_resp, size));
+    }
+
+    /// Proposes the batched write commands unless the followers can't accept more entries,
+    /// in which case the batch keeps growing until it's full or the followers catch up.
+    pub fn maybe_propose_batch(&mut self, metrics: &mut RaftProposeMetrics) {
+        if self.proposal_batch.is_some() && self.raft_group.should_throttle_proposal() {
+            return;
+        }
+        self.propose_batch(metrics);
+    }
+
+    /// Proposes the batched write commands in one entry. Returns true if it's proposed.
+    fn propose_batch(&mut self, metrics: &mut RaftProposeMetrics) -> bool {
+        let batch = match self.proposal_batch.take() {
+            Some(batch) => batch,
+            None => return false,
+        };
+        let (req, cb, err_resp) = batch.into_parts();
+        let res = self.propose_normal(req, metrics);
+        self.handle_propose_result(res, false, cb, err_resp)
+    }
+
     fn post_propose(&mut self, mut meta: ProposalMeta, is_conf_change: bool, cb: Callback) {
         // Try to renew leader lease on every consistent read/write request.
         meta.renew_lease_time = Some(clocktime::raw_now());
@@ -1017,7 +1327,9 @@ impl Peer {
         for r in req.get_requests() {
             match r.get_cmd_type() {
                 CmdType::Get | CmdType::Snap => is_read = true,
-                CmdType::Delete | CmdType::Put => is_write = true,
+                CmdType::Delete | CmdType::Put | CmdType::DeleteRange | CmdType::IngestSST => {
+                    is_write = true
+                }
                 CmdType::Prewrite | CmdType::Invalid => {
                     return Err(box_err!("invalid cmd type {:?}, message maybe currupted",
                                         r.get_cmd_type()));
@@ -1033,6 +1345,16 @@ impl Peer {
             return Ok(RequestPolicy::ProposeNormal);
         }
 
+        if !self.is_leader() {
+            // Only replica reads can reach a follower, serve it locally if the read ts is
+            // safe, otherwise ask the leader for the read index.
+            let read_ts = req.get_header().get_read_ts();
+            if read_ts > 0 && read_ts <= self.safe_ts && !self.is_applying_snapshot() {
+                return Ok(RequestPolicy::ReadStale);
+            }
+            return Ok(RequestPolicy::ReadIndex);
+        }
+
         if (req.has_header() && req.get_header().get_read_quorum()) ||
            !self.raft_group.raft.in_lease() {
             return Ok(RequestPolicy::ReadIndex);
@@ -1066,7 +1388,7 @@ impl Peer {
         Ok(RequestPolicy::ReadIndex)
     }
 
-    /// Count the number of the healthy nodes.
+    /// Count the number of the healthy voters, learners are not counted.
     /// A node is healthy when
     /// 1. it's the leader of the Raft group, which has the latest logs
     /// 2. it's a follower, and it does not lag behind the leader a lot.
@@ -1075,7 +1397,7 @@ impl Peer {
     fn count_healthy_node(&self, progress: Values<u64, Progress>) -> usize {
         let mut healthy = 0;
         for pr in progress {
-            if pr.matched >= self.get_store().truncated_index() {
+            if !pr.is_learner && pr.matched >= self.get_store().truncated_index() {
                 healthy += 1;
             }
         }
@@ -1085,7 +1407,8 @@ impl Peer {
     /// Check whether it's safe to propose the specified conf change request.
     /// It's safe iff at least the quorum of the Raft group is still healthy
     /// right after that conf change is applied.
-    /// Define the total number of nodes in current Raft cluster to be `total`.
+    /// Define the total number of voters in current Raft cluster to be `total`, learners
+    /// are not in the quorum.
     /// To ensure the above safety, if the cmd is
     /// 1. A `AddNode` request
     ///    Then at least '(total + 1)/2 + 1' nodes need to be up to date for now.
@@ -1099,7 +1422,7 @@ impl Peer {
         let peer = change_peer.get_peer();
 
         let mut status = self.raft_group.status();
-        let total = status.progress.len();
+        let total = status.progress.values().filter(|pr| !pr.is_learner).count();
         if total == 1 {
             // It's always safe if there is only one node in the cluster.
             return Ok(());
@@ -1107,7 +1430,10 @@ impl Peer {
 
         match change_type {
             ConfChangeType::AddNode => {
-                status.progress.insert(peer.get_id(), Progress::default());
+                // A promoted learner keeps its progress, but votes from now on.
+                let mut pr = status.progress.remove(&peer.get_id()).unwrap_or_default();
+                pr.is_learner = false;
+                status.progress.insert(peer.get_id(), pr);
             }
             ConfChangeType::RemoveNode => {
                 if status.progress.remove(&peer.get_id()).is_none() {
@@ -1115,9 +1441,41 @@ impl Peer {
                     return Ok(());
                 }
             }
+            // Learners are not counted in the quorum.
+            ConfChangeType::AddLearnerNode => return Ok(()),
+            ConfChangeType::BeginMembershipChange => {
+                // Both configurations vote in the joint consensus, the current one is
+                // checked below.
+                let mut next = FlatMap::default();
+                for p in change_peer.get_peers().iter().filter(|p| !p.get_is_learner()) {
+                    let mut pr = status.progress.get(&p.get_id()).cloned().unwrap_or_default();
+                    pr.is_learner = false;
+                    next.insert(p.get_id(), pr);
+                }
+                let healthy = self.count_healthy_node(next.values());
+                let quorum = raft::quorum(next.len());
+                if healthy < quorum {
+                    PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&["conf_change", "reject_unsafe"])
+                        .inc();
+                    info!("{} rejects unsafe membership change {:?}, healthy {}, quorum {}",
+                          self.tag,
+                          change_peer,
+                          healthy,
+                          quorum);
+                    return Err(box_err!("unsafe to perform membership change {:?}, healthy {}, \
+                                         quorum {}",
+                                        change_peer,
+                                        healthy,
+                                        quorum));
+                }
+            }
+            ConfChangeType::FinalizeMembershipChange => {
+                return Err(box_err!("finalizing membership change is only proposed by raft"));
+            }
         }
         let healthy = self.count_healthy_node(status.progress.values());
-        let quorum_after_change = raft::quorum(status.progress.len());
+        let voters = status.progress.values().filter(|pr| !pr.is_learner).count();
+        let quorum_after_change = raft::quorum(voters);
         if healthy >= quorum_after_change {
             return Ok(());
         }
@@ -1176,20 +1534,38 @@ impl Peer {
         metrics.read_index += 1;
 
         let renew_lease_time = clocktime::raw_now();
+        // A follower can't piggyback the read on a pending one, whose read index may not
+        // cover the changes committed before the read ts.
+        let is_leader = self.is_leader();
         if let Some(read) = self.pending_reads.reads.back_mut() {
-            if read.renew_lease_time + self.cfg.raft_store_max_leader_lease > renew_lease_time {
+            if is_leader &&
+               read.renew_lease_time + self.cfg.raft_store_max_leader_lease > renew_lease_time {
                 read.cmds.push((req, cb));
                 return false;
             }
         }
 
+        // Load the timestamp before the read index is requested.
+        let safe_ts = if is_leader {
+            0
+        } else {
+            self.tso.load(Ordering::SeqCst) as u64
+        };
+
         // Should we call pre_propose here?
         let last_pending_read_count = self.raft_group.raft.pending_read_count();
         let last_ready_read_count = self.raft_group.raft.ready_read_count();
 
         let id = self.pending_reads.next_id();
         let ctx: [u8; 8] = unsafe { mem::transmute(id) };
-        self.raft_group.read_index(ctx.to_vec());
+        let mut ctx = ctx.to_vec();
+        if !is_leader {
+            // The leader records the max ts this peer may read at after the read index is
+            // applied, see `step`.
+            let read_ts = cmp::max(safe_ts, req.get_header().get_read_ts());
+            ctx.encode_u64(read_ts).unwrap();
+        }
+        self.raft_group.read_index(ctx);
 
         let pending_read_count = self.raft_group.raft.pending_read_count();
         let ready_read_count = self.raft_group.raft.ready_read_count();
@@ -1205,6 +1581,8 @@ impl Peer {
             id: id,
             cmds: vec![(req, cb)],
             renew_lease_time: renew_lease_time,
+            read_index: None,
+            safe_ts: safe_ts,
         });
 
         match self.leader_lease_expired_time {
@@ -1308,6 +1686,9 @@ impl Peer {
         let mut cc = eraftpb::ConfChange::new();
         cc.set_change_type(change_peer.get_change_type());
         cc.set_node_id(change_peer.get_peer().get_id());
+        if change_peer.get_change_type() == ConfChangeType::BeginMembershipChange {
+            cc.set_configuration(util::conf_state_from_peers(change_peer.get_peers()));
+        }
         cc.set_context(data);
 
         info!("{} propose conf change {:?} peer {:?}",
@@ -1414,8 +1795,10 @@ impl Peer {
             return Some(peer.clone());
         }
 
-        // Try to find in region, if found, set in cache.
-        for peer in self.get_store().get_region().get_peers() {
+        // Try to find in region, if found, set in cache. The peers being removed by a
+        // membership change are still in the previous configuration.
+        let region = self.get_store().get_region();
+        for peer in region.get_peers().iter().chain(region.get_prev_peers()) {
             if peer.get_id() == peer_id {
                 self.peer_cache.borrow_mut().insert(peer_id, peer.clone());
                 return Some(peer.clone());
@@ -1425,6 +1808,18 @@ impl Peer {
         None
     }
 
+    /// Initializes the max read ts of the region with a timestamp got from PD.
+    pub fn init_max_read_ts(&self, token: u64, worker: &FutureWorker<PdTask>) {
+        let task = PdTask::InitMaxReadTs {
+            region_id: self.region_id,
+            token: token,
+            max_read_ts: self.max_read_ts.clone(),
+        };
+        if let Err(e) = worker.schedule(task) {
+            error!("{} failed to init max read ts: {}", self.tag, e);
+        }
+    }
+
     pub fn heartbeat_pd(&self, worker: &FutureWorker<PdTask>) {
         let task = PdTask::Heartbeat {
             region: self.region().clone(),
@@ -1433,6 +1828,8 @@ impl Peer {
             pending_peers: self.collect_pending_peers(),
             written_bytes: self.peer_stat.last_written_bytes,
             written_keys: self.peer_stat.last_written_keys,
+            read_bytes: self.peer_stat.last_read_bytes,
+            read_keys: self.peer_stat.last_read_keys,
         };
         if let Err(e) = worker.schedule(task) {
             error!("{} failed to notify pd: {}", self.tag, e);
@@ -1517,7 +1914,11 @@ impl Peer {
                 CmdType::Get => try!(apply::do_get(&self.tag, self.region(), &snap, req)),
                 CmdType::Snap => try!(apply::do_snap(self.region().to_owned())),
                 CmdType::Prewrite => unreachable!(),
-                CmdType::Put | CmdType::Delete | CmdType::Invalid => unreachable!(),
+                CmdType::Put |
+                CmdType::Delete |
+                CmdType::DeleteRange |
+                CmdType::IngestSST |
+                CmdType::Invalid => unreachable!(),
             };
 
             resp.set_cmd_type(cmd_type);
@@ -1531,6 +1932,15 @@ impl Peer {
     }
 }
 
+/// Returns true if `msg` only reads data and can be served by a follower.
+pub fn is_replica_read(msg: &RaftCmdRequest) -> bool {
+    msg.get_header().get_replica_read() && !msg.has_admin_request() &&
+    msg.get_requests().iter().all(|r| match r.get_cmd_type() {
+        CmdType::Get | CmdType::Snap => true,
+        _ => false,
+    })
+}
+
 fn get_transfer_leader_cmd(msg: &RaftCmdRequest) -> Option<&TransferLeaderRequest> {
     if !msg.has_admin_request() {
         return None;
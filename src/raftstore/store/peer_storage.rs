@@ -33,6 +33,7 @@ use raft::{self, Storage, RaftState, StorageError, Error as RaftError, Ready};
 use raftstore::{Result, Error};
 use super::worker::RegionTask;
 use super::keys::{self, enc_start_key, enc_end_key};
+use super::util::{conf_state_from_region, next_conf_state_from_region};
 use super::engine::{Snapshot as DbSnapshot, Peekable, Iterable, Mutable};
 use super::peer::ReadyContext;
 use super::metrics::*;
@@ -410,7 +411,6 @@ impl PeerStorage {
 
     pub fn initial_state(&self) -> raft::Result<RaftState> {
         let hard_state = self.raft_state.get_hard_state().clone();
-        let mut conf_state = ConfState::new();
         if hard_state == HardState::new() {
             assert!(!self.is_initialized(),
                     "peer for region {:?} is initialized but local state {:?} has empty hard \
@@ -420,17 +420,15 @@ impl PeerStorage {
 
             return Ok(RaftState {
                 hard_state: hard_state,
-                conf_state: conf_state,
+                conf_state: ConfState::new(),
+                next_conf_state: None,
             });
         }
 
-        for p in self.region.get_peers() {
-            conf_state.mut_nodes().push(p.get_id());
-        }
-
         Ok(RaftState {
             hard_state: hard_state,
-            conf_state: conf_state,
+            conf_state: conf_state_from_region(&self.region),
+            next_conf_state: next_conf_state_from_region(&self.region),
         })
     }
 
@@ -1060,12 +1058,11 @@ pub fn do_snapshot(mgr: SnapManager, snap: &DbSnapshot, region_id: u64) -> raft:
     snapshot.mut_metadata().set_index(key.idx);
     snapshot.mut_metadata().set_term(key.term);
 
-    let mut conf_state = ConfState::new();
-    for p in state.get_region().get_peers() {
-        conf_state.mut_nodes().push(p.get_id());
-    }
-
+    let conf_state = conf_state_from_region(state.get_region());
     snapshot.mut_metadata().set_conf_state(conf_state);
+    if let Some(next_conf_state) = next_conf_state_from_region(state.get_region()) {
+        snapshot.mut_metadata().set_next_conf_state(next_conf_state);
+    }
 
     let mut s = try!(mgr.get_snapshot_for_building(&key, snap));
     // Set snapshot data.
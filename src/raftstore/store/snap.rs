@@ -31,7 +31,7 @@ use kvproto::raft_serverpb::RaftSnapshotData;
 
 use raftstore::Result as RaftStoreResult;
 use raftstore::store::Msg;
-use storage::{CfName, CF_DEFAULT, CF_LOCK, CF_WRITE};
+use storage::{CfName, CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAW_TTL};
 use util::transport::SendCh;
 use util::HandyRwLock;
 use util::collections::{HashMap, HashMapEntry as Entry};
@@ -39,8 +39,9 @@ use util::collections::{HashMap, HashMapEntry as Entry};
 use super::engine::Snapshot as DbSnapshot;
 use super::peer_storage::JOB_STATUS_CANCELLING;
 
-// Data in CF_RAFT should be excluded for a snapshot.
-pub const SNAPSHOT_CFS: &'static [CfName] = &[CF_DEFAULT, CF_LOCK, CF_WRITE];
+// Data in CF_RAFT should be excluded for a snapshot. CF_RAW_TTL is left out if it's empty, so
+// that the nodes without the cf can still apply the snapshot.
+pub const SNAPSHOT_CFS: &'static [CfName] = &[CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAW_TTL];
 
 /// Name prefix for the self-generated snapshot file.
 const SNAP_GEN_PREFIX: &'static str = "gen";
@@ -208,6 +209,7 @@ mod v1 {
     use rocksdb::{Writable, WriteBatch, CFHandle};
     use kvproto::metapb::Region;
     use kvproto::raft_serverpb::RaftSnapshotData;
+    use storage::CF_RAW_TTL;
     use util::{HandyRwLock, rocksdb, duration_to_sec};
     use util::codec::bytes::{BytesEncoder, CompactBytesDecoder};
     use raftstore::{Result as RaftStoreResult, Error as RaftStoreError};
@@ -249,6 +251,23 @@ mod v1 {
         Ok((cf_key_count, cf_size))
     }
 
+    fn is_cf_empty(snap: &DbSnapshot,
+                   cf: &str,
+                   start_key: &[u8],
+                   end_key: &[u8])
+                   -> RaftStoreResult<bool> {
+        let mut empty = true;
+        try!(snap.scan_cf(cf,
+                          start_key,
+                          end_key,
+                          false,
+                          &mut |_, _| {
+                              empty = false;
+                              Ok(false)
+                          }));
+        Ok(empty)
+    }
+
     pub fn apply_plain_cf_file<D: CompactBytesDecoder>(decoder: &mut D,
                                                        options: &ApplyOptions,
                                                        handle: &CFHandle)
@@ -425,6 +444,9 @@ mod v1 {
             let mut snap_key_count = 0;
             let (begin_key, end_key) = (enc_start_key(region), enc_end_key(region));
             for cf in SNAPSHOT_CFS {
+                if *cf == CF_RAW_TTL && try!(is_cf_empty(snap, cf, &begin_key, &end_key)) {
+                    continue;
+                }
                 box_try!(self.encode_compact_bytes(cf.as_bytes()));
                 let (cf_key_count, cf_size) =
                     try!(build_plain_cf_file(self, snap, cf, &begin_key, &end_key));
@@ -717,7 +739,7 @@ mod v2 {
     use kvproto::metapb::Region;
     use kvproto::raft_serverpb::{SnapshotCFFile, SnapshotMeta, RaftSnapshotData};
     use rocksdb::{EnvOptions, SstFileWriter, IngestExternalFileOptions};
-    use storage::{CfName, CF_LOCK};
+    use storage::{CfName, CF_LOCK, CF_RAW_TTL};
     use util::{HandyRwLock, rocksdb, duration_to_sec};
     use util::file::{get_file_size, file_exists, delete_file_if_exist};
     use raftstore::Result as RaftStoreResult;
@@ -765,6 +787,9 @@ mod v2 {
             if SNAPSHOT_CFS.iter().find(|&cf| cf_file.cf == *cf).is_none() {
                 return Err(box_err!("failed to encode invalid snapshot cf {}", cf_file.cf));
             }
+            if cf_file.cf == CF_RAW_TTL && cf_file.size == 0 {
+                continue;
+            }
 
             let mut cf_file_meta = SnapshotCFFile::new();
             cf_file_meta.set_cf(cf_file.cf.to_owned());
@@ -1023,13 +1048,25 @@ mod v2 {
         }
 
         fn set_snapshot_meta(&mut self, snapshot_meta: SnapshotMeta) -> RaftStoreResult<()> {
-            if snapshot_meta.get_cf_files().len() != self.cf_files.len() {
+            let cf_num = snapshot_meta.get_cf_files().len();
+            if cf_num != self.cf_files.len() && cf_num != self.cf_files.len() - 1 {
                 return Err(box_err!("invalid cf number of snapshot meta, expect {}, got {}",
                                     SNAPSHOT_CFS.len(),
-                                    snapshot_meta.get_cf_files().len()));
+                                    cf_num));
             }
             for (i, cf_file) in self.cf_files.iter_mut().enumerate() {
-                let meta = snapshot_meta.get_cf_files().get(i).unwrap();
+                let meta = match snapshot_meta.get_cf_files().get(i) {
+                    Some(meta) => meta,
+                    // The empty CF_RAW_TTL is left out.
+                    None if cf_file.cf == CF_RAW_TTL => {
+                        cf_file.size = 0;
+                        cf_file.checksum = 0;
+                        continue;
+                    }
+                    None => {
+                        return Err(box_err!("cf {} is missing in snapshot meta", cf_file.cf))
+                    }
+                };
                 if meta.get_cf() != cf_file.cf {
                     return Err(box_err!("invalid {} cf in snapshot meta, expect {}, got {}",
                                         i,
@@ -1440,12 +1477,12 @@ mod v2 {
         use std::sync::{Arc, RwLock};
         use std::sync::atomic::AtomicUsize;
         use tempdir::TempDir;
-        use protobuf::Message;
+        use protobuf::{Message, RepeatedField};
         use kvproto::metapb::{Peer, Region};
         use kvproto::raft_serverpb::{SnapshotMeta, RaftSnapshotData};
         use rocksdb::DB;
 
-        use storage::{ALL_CFS, CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAFT};
+        use storage::{ALL_CFS, CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAFT, CF_RAW_TTL};
         use util::{rocksdb, HandyRwLock};
         use raftstore::Result;
         use raftstore::store::keys;
@@ -1576,6 +1613,33 @@ mod v2 {
                            cf_file_meta.get_checksum());
                 }
             }
+
+            // An empty CF_RAW_TTL is left out for the nodes without it.
+            let last = cf_file.len() - 1;
+            assert_eq!(cf_file[last].cf, CF_RAW_TTL);
+            cf_file[last].size = 0;
+            cf_file[last].checksum = 0;
+            let meta = super::gen_snapshot_meta(&cf_file).unwrap();
+            assert_eq!(meta.get_cf_files().len(), last);
+
+            let dir = TempDir::new("test-gen-snapshot-meta").unwrap();
+            let key = SnapKey::new(1, 1, 1);
+            let size_track = Arc::new(RwLock::new(0));
+            let deleter = Box::new(DummyDeleter {});
+            let mut snap = Snap::new_for_receiving(dir.path(),
+                                                   &key,
+                                                   meta.clone(),
+                                                   size_track.clone(),
+                                                   deleter.clone())
+                .unwrap();
+            assert_eq!(snap.cf_files[last].size, 0);
+
+            // Other cfs can't be left out.
+            let mut metas = meta.get_cf_files().to_vec();
+            metas.pop();
+            let mut meta = SnapshotMeta::new();
+            meta.set_cf_files(RepeatedField::from_vec(metas));
+            snap.set_snapshot_meta(meta).unwrap_err();
         }
 
         #[test]
@@ -1680,7 +1744,7 @@ mod v2 {
             let dst_db_dir = TempDir::new("test-snap-file-db-dst").unwrap();
             let dst_db_path = dst_db_dir.path().to_str().unwrap();
             // Change arbitrarily the cf order of ALL_CFS at destination db.
-            let dst_cfs = [CF_WRITE, CF_DEFAULT, CF_LOCK, CF_RAFT];
+            let dst_cfs = [CF_WRITE, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_RAW_TTL];
             let dst_db = Arc::new(rocksdb::new_engine(dst_db_path, &dst_cfs).unwrap());
             let options = ApplyOptions {
                 db: dst_db.clone(),
@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
 use std::sync::mpsc::{self, Receiver as StdReceiver, TryRecvError};
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -22,7 +23,7 @@ use std::time::{Duration, Instant};
 use std::thread;
 use std::u64;
 
-use rocksdb::{DB, DBStatisticsTickerType as TickerType};
+use rocksdb::{DB, WriteBatch, DBStatisticsTickerType as TickerType};
 use rocksdb::rocksdb_options::WriteOptions;
 use mio::{self, EventLoop, EventLoopConfig, Sender};
 use protobuf;
@@ -45,13 +46,14 @@ use util::worker::{Worker, Scheduler, FutureWorker};
 use util::transport::SendCh;
 use util::{rocksdb, RingQueue};
 use util::collections::{HashMap, HashSet};
-use storage::{CF_DEFAULT, CF_LOCK, CF_WRITE};
+use storage::{CF_LOCK, LARGE_CFS, FlowStatistics, MaxReadTs};
 use raftstore::coprocessor::CoprocessorHost;
+use import::SSTImporter;
 use raftstore::coprocessor::split_observer::SplitObserver;
 use super::worker::{SplitCheckRunner, SplitCheckTask, RegionTask, RegionRunner, CompactTask,
                     CompactRunner, RaftlogGcTask, RaftlogGcRunner, PdRunner, PdTask,
                     ConsistencyCheckTask, ConsistencyCheckRunner, ApplyTask, ApplyRunner,
-                    ApplyTaskRes};
+                    ApplyTaskRes, RaftlogWriteTask, RaftlogWriteRunner, PersistedReady};
 use super::worker::apply::{ExecResult, ChangePeer};
 use super::{util, Msg, Tick, SnapshotStatusMsg, SnapManager, SnapshotDeleter};
 use super::keys::{self, enc_start_key, enc_end_key, data_end_key, data_key};
@@ -81,6 +83,8 @@ pub struct StoreChannel {
 pub struct StoreStat {
     pub region_bytes_written: LocalHistogram,
     pub region_keys_written: LocalHistogram,
+    pub region_bytes_read: LocalHistogram,
+    pub region_keys_read: LocalHistogram,
     pub lock_cf_bytes_written: u64,
     pub engine_total_bytes_written: u64,
     pub engine_total_keys_written: u64,
@@ -91,6 +95,8 @@ impl Default for StoreStat {
         StoreStat {
             region_bytes_written: REGION_WRITTEN_BYTES_HISTOGRAM.local(),
             region_keys_written: REGION_WRITTEN_KEYS_HISTOGRAM.local(),
+            region_bytes_read: REGION_READ_BYTES_HISTOGRAM.local(),
+            region_keys_read: REGION_READ_KEYS_HISTOGRAM.local(),
             lock_cf_bytes_written: 0,
             engine_total_bytes_written: 0,
             engine_total_keys_written: 0,
@@ -117,6 +123,7 @@ pub struct Store<T, C: 'static> {
     split_check_worker: Worker<SplitCheckTask>,
     region_worker: Worker<RegionTask>,
     raftlog_gc_worker: Worker<RaftlogGcTask>,
+    raftlog_write_worker: Worker<RaftlogWriteTask>,
     compact_worker: Worker<CompactTask>,
     pd_worker: FutureWorker<PdTask>,
     consistency_check_worker: Worker<ConsistencyCheckTask>,
@@ -130,10 +137,17 @@ pub struct Store<T, C: 'static> {
     pub coprocessor_host: Arc<CoprocessorHost>,
 
     snap_mgr: SnapManager,
+    // For ingesting the SST files received from the other stores.
+    importer: Arc<SSTImporter>,
 
     raft_metrics: RaftMetrics,
     pub entry_cache_metries: Rc<RefCell<CacheQueryStats>>,
 
+    // The latest timestamp got from PD, followers advance their safe ts to it.
+    pub tso: Arc<AtomicUsize>,
+    // Shared with the storage, the leaders update it with the ts of the replica reads.
+    pub max_read_ts: MaxReadTs,
+
     tag: String,
 
     start_time: Timespec,
@@ -171,13 +185,17 @@ pub fn delete_file_in_range(db: &DB, start_key: &[u8], end_key: &[u8]) -> Result
 }
 
 impl<T, C> Store<T, C> {
+    #[allow(too_many_arguments)]
     pub fn new(ch: StoreChannel,
                meta: metapb::Store,
                cfg: Config,
                engine: Arc<DB>,
                trans: T,
                pd_client: Arc<C>,
-               mgr: SnapManager)
+               mgr: SnapManager,
+               mut coprocessor_host: CoprocessorHost,
+               importer: Arc<SSTImporter>,
+               max_read_ts: MaxReadTs)
                -> Result<Store<T, C>> {
         // TODO: we can get cluster meta regularly too later.
         try!(cfg.validate());
@@ -185,7 +203,6 @@ impl<T, C> Store<T, C> {
         let sendch = SendCh::new(ch.sender, "raftstore");
         let tag = format!("[store {}]", meta.get_id());
 
-        let mut coprocessor_host = CoprocessorHost::new();
         // TODO load coprocessors from configuration
         coprocessor_host.registry.register_observer(100, box SplitObserver);
 
@@ -201,6 +218,7 @@ impl<T, C> Store<T, C> {
             split_check_worker: Worker::new("split check worker"),
             region_worker: Worker::new("snapshot worker"),
             raftlog_gc_worker: Worker::new("raft gc worker"),
+            raftlog_write_worker: Worker::new("raft log write worker"),
             compact_worker: Worker::new("compact worker"),
             pd_worker: FutureWorker::new("pd worker"),
             consistency_check_worker: Worker::new("consistency check worker"),
@@ -212,8 +230,11 @@ impl<T, C> Store<T, C> {
             pd_client: pd_client,
             coprocessor_host: Arc::new(coprocessor_host),
             snap_mgr: mgr,
+            importer: importer,
             raft_metrics: RaftMetrics::default(),
             entry_cache_metries: Rc::new(RefCell::new(CacheQueryStats::default())),
+            tso: Arc::new(AtomicUsize::new(0)),
+            max_read_ts: max_read_ts,
             pending_votes: RingQueue::with_capacity(PENDING_VOTES_CAP),
             tag: tag,
             start_time: time::get_time(),
@@ -325,6 +346,10 @@ impl<T, C> Store<T, C> {
         self.apply_worker.scheduler()
     }
 
+    pub fn importer(&self) -> Arc<SSTImporter> {
+        self.importer.clone()
+    }
+
     pub fn engine(&self) -> Arc<DB> {
         self.engine.clone()
     }
@@ -404,6 +429,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         self.register_compact_lock_cf_tick(event_loop);
         self.register_consistency_check_tick(event_loop);
         self.register_report_region_flow_tick(event_loop);
+        self.register_safe_ts_tick(event_loop);
 
         let split_check_runner = SplitCheckRunner::new(self.engine.clone(),
                                                        self.sendch.clone(),
@@ -419,6 +445,10 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         let raftlog_gc_runner = RaftlogGcRunner::new(None);
         box_try!(self.raftlog_gc_worker.start(raftlog_gc_runner));
 
+        let raftlog_write_runner = RaftlogWriteRunner::new(self.engine.clone(),
+                                                           self.sendch.clone());
+        box_try!(self.raftlog_write_worker.start(raftlog_write_runner));
+
         let compact_runner = CompactRunner::new(self.engine.clone());
         box_try!(self.compact_worker.start(compact_runner));
 
@@ -450,6 +480,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         handles.push(self.split_check_worker.stop());
         handles.push(self.region_worker.stop());
         handles.push(self.raftlog_gc_worker.stop());
+        handles.push(self.raftlog_write_worker.stop());
         handles.push(self.compact_worker.stop());
         handles.push(self.pd_worker.stop());
         handles.push(self.consistency_check_worker.stop());
@@ -493,6 +524,13 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 peer.mark_to_be_checked(&mut self.pending_raft_groups);
             }
 
+            // A leader whose disk or apply hangs still sends heartbeats and keeps the region
+            // stalled, so it hands over the leadership proactively.
+            if let Some(reason) = peer.check_leader_stall(self.cfg.max_leader_stall_duration) {
+                LEADER_STALL_TRANSFER_COUNTER_VEC.with_label_values(&[reason]).inc();
+                peer.mark_to_be_checked(&mut self.pending_raft_groups);
+            }
+
             // If this peer detects the leader is missing for a long long time,
             // it should consider itself as a stale peer which is removed from
             // the original cluster.
@@ -890,6 +928,13 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
         self.raft_metrics.ready.pending_region += pending_count as u64;
 
+        // Proposes the write commands batched since the last ready.
+        for region_id in &self.pending_raft_groups {
+            if let Some(peer) = self.region_peers.get_mut(region_id) {
+                peer.maybe_propose_batch(&mut self.raft_metrics.propose);
+            }
+        }
+
         let (wb, append_res) = {
             let mut ctx = ReadyContext::new(&mut self.raft_metrics, &self.trans, pending_count);
             for region_id in self.pending_raft_groups.drain() {
@@ -903,7 +948,29 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
         self.raft_metrics.ready.has_ready_region += append_res.len() as u64;
 
-        if !wb.is_empty() {
+        // The readies to acknowledge after the snapshots in this batch are applied.
+        let mut flushed_readies = vec![];
+        if self.cfg.raft_async_persist {
+            let readies: Vec<_> = append_res.iter()
+                .map(|&(ref ready, ref invoke_ctx)| {
+                    PersistedReady {
+                        region_id: invoke_ctx.region_id,
+                        peer_id: self.region_peers[&invoke_ctx.region_id].peer_id(),
+                        number: ready.number,
+                    }
+                })
+                .collect();
+            if append_res.iter().any(|&(_, ref invoke_ctx)| invoke_ctx.has_snapshot()) {
+                // The snapshot is applied by the region worker after it's persisted, so
+                // the batch must be persisted before `post_raft_ready_append`.
+                self.schedule_raft_log_write(wb, vec![]);
+                self.flush_raft_log();
+                flushed_readies = readies;
+            } else {
+                // The writes still go through the worker to keep them in order.
+                self.schedule_raft_log_write(wb, readies);
+            }
+        } else if !wb.is_empty() {
             let mut write_opts = WriteOptions::new();
             write_opts.set_sync(self.cfg.sync_log);
             self.engine.write_opt(wb, &write_opts).unwrap_or_else(|e| {
@@ -950,6 +1017,9 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             }
         }
         self.apply_worker.schedule(ApplyTask::applies(apply_tasks)).unwrap();
+        for ready in flushed_readies {
+            self.on_ready_persisted(ready);
+        }
 
         let dur = t.elapsed();
         if !self.is_busy {
@@ -968,6 +1038,46 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         slow_log!(t, "{} on {} regions raft ready", self.tag, pending_count);
     }
 
+    fn schedule_raft_log_write(&self, wb: WriteBatch, readies: Vec<PersistedReady>) {
+        let task = RaftlogWriteTask::Write {
+            wb: wb,
+            sync: self.cfg.sync_log,
+            readies: readies,
+        };
+        if let Err(e) = self.raftlog_write_worker.schedule(task) {
+            panic!("{} failed to schedule raft log write: {:?}", self.tag, e);
+        }
+    }
+
+    /// Waits until all the raft log writes scheduled before are persisted.
+    fn flush_raft_log(&self) {
+        let (tx, rx) = mpsc::channel();
+        if let Err(e) = self.raftlog_write_worker.schedule(RaftlogWriteTask::Flush { cb: tx }) {
+            panic!("{} failed to schedule raft log flush: {:?}", self.tag, e);
+        }
+        rx.recv().unwrap();
+    }
+
+    fn on_ready_persisted(&mut self, ready: PersistedReady) {
+        let peer = match self.region_peers.get_mut(&ready.region_id) {
+            Some(peer) => peer,
+            None => return,
+        };
+        // The peer may be destroyed and created again before the notification arrives.
+        if peer.peer_id() != ready.peer_id {
+            return;
+        }
+        peer.on_persist_ready(&self.trans, ready.number, &mut self.raft_metrics.message);
+        peer.mark_to_be_checked(&mut self.pending_raft_groups);
+    }
+
+    fn on_raft_log_persisted(&mut self, readies: Vec<PersistedReady>) {
+        for ready in readies {
+            self.on_ready_persisted(ready);
+        }
+        self.trans.flush();
+    }
+
     fn destroy_peer(&mut self, region_id: u64, peer: metapb::Peer) {
         info!("[region {}] destroy peer {:?}", region_id, peer);
         // TODO: should we check None here?
@@ -977,6 +1087,10 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         assert!(!p.is_applying_snapshot());
 
         let is_initialized = p.is_initialized();
+        if self.cfg.raft_async_persist {
+            // The raft log being written must not be left after the peer is cleaned up.
+            self.flush_raft_log();
+        }
         if let Err(e) = p.destroy() {
             // If not panic here, the peer will be recreated in the next restart,
             // then it will be gc again. But if some overlap region is created
@@ -1000,10 +1114,13 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
     fn on_ready_change_peer(&mut self, region_id: u64, cp: ChangePeer) {
         let my_peer_id;
+        let mut removed_peer = None;
         let change_type = cp.conf_change.get_change_type();
+        let is_membership_change = change_type == ConfChangeType::BeginMembershipChange ||
+                                   change_type == ConfChangeType::FinalizeMembershipChange;
         if let Some(p) = self.region_peers.get_mut(&region_id) {
             p.raft_group.apply_conf_change(&cp.conf_change);
-            if cp.conf_change.get_node_id() == raft::INVALID_ID {
+            if cp.conf_change.get_node_id() == raft::INVALID_ID && !is_membership_change {
                 // Apply failed, skip.
                 return;
             }
@@ -1017,7 +1134,8 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             }
 
             match change_type {
-                ConfChangeType::AddNode => {
+                ConfChangeType::AddNode |
+                ConfChangeType::AddLearnerNode => {
                     // Add this peer to cache.
                     let peer = cp.peer.clone();
                     p.peer_heartbeats.insert(peer.get_id(), Instant::now());
@@ -1028,6 +1146,30 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                     p.peer_heartbeats.remove(&cp.peer.get_id());
                     p.remove_peer_from_cache(cp.peer.get_id());
                 }
+                ConfChangeType::BeginMembershipChange => {
+                    // Add the new peers to cache, the removed ones are kept in cache until the
+                    // change is finalized.
+                    for peer in p.region().get_peers().to_vec() {
+                        if !p.peer_heartbeats.contains_key(&peer.get_id()) {
+                            p.peer_heartbeats.insert(peer.get_id(), Instant::now());
+                            p.insert_peer_cache(peer);
+                        }
+                    }
+                }
+                ConfChangeType::FinalizeMembershipChange => {
+                    let removed: Vec<_> = p.peer_heartbeats
+                        .keys()
+                        .filter(|&&id| p.region().get_peers().iter().all(|x| x.get_id() != id))
+                        .cloned()
+                        .collect();
+                    for id in removed {
+                        p.peer_heartbeats.remove(&id);
+                        p.remove_peer_from_cache(id);
+                    }
+                    if p.region().get_peers().iter().all(|x| x.get_id() != p.peer_id()) {
+                        removed_peer = Some(p.peer.clone());
+                    }
+                }
             }
 
             my_peer_id = p.peer_id();
@@ -1035,6 +1177,12 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             panic!("{} missing region {}", self.tag, region_id);
         }
 
+        if let Some(peer) = removed_peer {
+            // This peer has been removed by the membership change.
+            self.destroy_peer(region_id, peer);
+            return;
+        }
+
         let peer = cp.peer;
 
         // We only care remove itself now.
@@ -1282,7 +1430,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             Some(peer) => peer,
             None => return Err(Error::RegionNotFound(region_id)),
         };
-        if !peer.is_leader() {
+        if !peer.is_leader() && !peer::is_replica_read(msg) {
             return Err(Error::NotLeader(region_id, peer.get_peer_from_cache(peer.leader_id())));
         }
         if peer.peer_id() != peer_id {
@@ -1339,23 +1487,58 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         };
     }
 
+    fn register_safe_ts_tick(&self, event_loop: &mut EventLoop<Self>) {
+        if let Err(e) = register_timer(event_loop, Tick::SafeTs, self.cfg.safe_ts_tick_interval) {
+            error!("{} register safe ts tick err: {:?}", self.tag, e);
+        };
+    }
+
+    fn on_safe_ts_tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        let task = PdTask::UpdateTso { tso: self.tso.clone() };
+        if let Err(e) = self.pd_worker.schedule(task) {
+            error!("{} failed to update tso: {:?}", self.tag, e);
+        }
+
+        // Retry the leaders whose max read ts failed to be initialized.
+        for peer in self.region_peers.values().filter(|p| p.is_leader()) {
+            if let Some(token) = self.max_read_ts.unknown_token(peer.region().get_id()) {
+                peer.init_max_read_ts(token, &self.pd_worker);
+            }
+        }
+
+        self.register_safe_ts_tick(event_loop);
+    }
+
+    fn on_read_flow(&mut self, read_flow: HashMap<u64, FlowStatistics>) {
+        for (region_id, flow) in read_flow {
+            if let Some(peer) = self.region_peers.get_mut(&region_id) {
+                peer.peer_stat.read_bytes += flow.read_bytes as u64;
+                peer.peer_stat.read_keys += flow.read_keys as u64;
+            }
+        }
+    }
+
     fn on_report_region_flow(&mut self, event_loop: &mut EventLoop<Self>) {
         for peer in self.region_peers.values_mut() {
             peer.peer_stat.last_written_bytes = peer.peer_stat.written_bytes;
             peer.peer_stat.last_written_keys = peer.peer_stat.written_keys;
-            if !peer.is_leader() {
-                peer.peer_stat.written_bytes = 0;
-                peer.peer_stat.written_keys = 0;
-                continue;
+            peer.peer_stat.last_read_bytes = peer.peer_stat.read_bytes;
+            peer.peer_stat.last_read_keys = peer.peer_stat.read_keys;
+            if peer.is_leader() {
+                self.store_stat.region_bytes_written.observe(peer.peer_stat.written_bytes as f64);
+                self.store_stat.region_keys_written.observe(peer.peer_stat.written_keys as f64);
+                self.store_stat.region_bytes_read.observe(peer.peer_stat.read_bytes as f64);
+                self.store_stat.region_keys_read.observe(peer.peer_stat.read_keys as f64);
             }
-
-            self.store_stat.region_bytes_written.observe(peer.peer_stat.written_bytes as f64);
-            self.store_stat.region_keys_written.observe(peer.peer_stat.written_keys as f64);
             peer.peer_stat.written_bytes = 0;
             peer.peer_stat.written_keys = 0;
+            peer.peer_stat.read_bytes = 0;
+            peer.peer_stat.read_keys = 0;
         }
         self.store_stat.region_bytes_written.flush();
         self.store_stat.region_keys_written.flush();
+        self.store_stat.region_bytes_read.flush();
+        self.store_stat.region_keys_read.flush();
 
         self.register_report_region_flow_tick(event_loop);
     }
@@ -1487,7 +1670,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             if peer.delete_keys_hint < self.cfg.region_compact_delete_keys_count {
                 continue;
             }
-            for &cf in &[CF_DEFAULT, CF_WRITE] {
+            for &cf in LARGE_CFS {
                 let task = CompactTask {
                     cf_name: String::from(cf),
                     start_key: Some(keys::enc_start_key(peer.region())),
@@ -2037,6 +2220,16 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
             Msg::ComputeHashResult { region_id, index, hash } => {
                 self.on_hash_computed(region_id, index, hash);
             }
+            Msg::GetLeaderRegions { callback } => {
+                let regions = self.region_peers
+                    .values()
+                    .filter(|p| p.is_leader())
+                    .map(|p| (p.region().clone(), p.peer.clone()))
+                    .collect();
+                callback.call_box((regions,));
+            }
+            Msg::ReadFlow { read_flow } => self.on_read_flow(read_flow),
+            Msg::RaftLogPersisted { readies } => self.on_raft_log_persisted(readies),
         }
     }
 
@@ -2053,6 +2246,7 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
             Tick::CompactLockCf => self.on_compact_lock_cf(event_loop),
             Tick::ConsistencyCheck => self.on_consistency_check_tick(event_loop),
             Tick::ReportRegionFlow => self.on_report_region_flow(event_loop),
+            Tick::SafeTs => self.on_safe_ts_tick(event_loop),
         }
         slow_log!(t, "{} handle timeout {:?}", self.tag, timeout);
     }
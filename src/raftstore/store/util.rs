@@ -14,7 +14,7 @@
 use std::option::Option;
 
 use kvproto::metapb;
-use kvproto::eraftpb::{self, ConfChangeType, MessageType};
+use kvproto::eraftpb::{self, ConfChangeType, ConfState, MessageType};
 use kvproto::raft_serverpb::RaftMessage;
 use raftstore::{Result, Error};
 
@@ -75,11 +75,17 @@ pub fn is_first_vote_msg(msg: &RaftMessage) -> bool {
 
 const STR_CONF_CHANGE_ADD_NODE: &'static str = "AddNode";
 const STR_CONF_CHANGE_REMOVE_NODE: &'static str = "RemoveNode";
+const STR_CONF_CHANGE_ADD_LEARNER_NODE: &'static str = "AddLearnerNode";
+const STR_CONF_CHANGE_BEGIN_MEMBERSHIP_CHANGE: &'static str = "BeginMembershipChange";
+const STR_CONF_CHANGE_FINALIZE_MEMBERSHIP_CHANGE: &'static str = "FinalizeMembershipChange";
 
 pub fn conf_change_type_str(conf_type: &eraftpb::ConfChangeType) -> &'static str {
     match *conf_type {
         ConfChangeType::AddNode => STR_CONF_CHANGE_ADD_NODE,
         ConfChangeType::RemoveNode => STR_CONF_CHANGE_REMOVE_NODE,
+        ConfChangeType::AddLearnerNode => STR_CONF_CHANGE_ADD_LEARNER_NODE,
+        ConfChangeType::BeginMembershipChange => STR_CONF_CHANGE_BEGIN_MEMBERSHIP_CHANGE,
+        ConfChangeType::FinalizeMembershipChange => STR_CONF_CHANGE_FINALIZE_MEMBERSHIP_CHANGE,
     }
 }
 
@@ -89,11 +95,46 @@ pub fn is_epoch_stale(epoch: &metapb::RegionEpoch, check_epoch: &metapb::RegionE
     epoch.get_conf_ver() < check_epoch.get_conf_ver()
 }
 
+/// Builds the raft `ConfState` of the peers, learners are marked in the peer meta.
+pub fn conf_state_from_peers(peers: &[metapb::Peer]) -> ConfState {
+    let mut conf_state = ConfState::new();
+    for p in peers {
+        if p.get_is_learner() {
+            conf_state.mut_learners().push(p.get_id());
+        } else {
+            conf_state.mut_nodes().push(p.get_id());
+        }
+    }
+    conf_state
+}
+
+/// Builds the current raft `ConfState` of the region.
+///
+/// In a membership change, the region peers are the next configuration and `prev_peers`
+/// keeps the current one until the change is finalized.
+pub fn conf_state_from_region(region: &metapb::Region) -> ConfState {
+    if region.get_prev_peers().is_empty() {
+        conf_state_from_peers(region.get_peers())
+    } else {
+        conf_state_from_peers(region.get_prev_peers())
+    }
+}
+
+/// Builds the raft `ConfState` being switched to if the region is in a membership change.
+pub fn next_conf_state_from_region(region: &metapb::Region) -> Option<ConfState> {
+    if region.get_prev_peers().is_empty() {
+        None
+    } else {
+        Some(conf_state_from_peers(region.get_peers()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use kvproto::metapb;
     use kvproto::raft_serverpb::RaftMessage;
     use kvproto::eraftpb::{Message, ConfChangeType, MessageType};
+    use protobuf::RepeatedField;
 
     use super::*;
     use raftstore::store::peer_storage;
@@ -136,6 +177,33 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_conf_state_from_region() {
+        let mut region = metapb::Region::new();
+        region.mut_peers().push(new_peer(1, 1));
+        let mut learner = new_peer(2, 2);
+        learner.set_is_learner(true);
+        region.mut_peers().push(learner);
+        region.mut_peers().push(new_peer(3, 3));
+
+        let cs = conf_state_from_region(&region);
+        assert_eq!(cs.get_nodes(), &[1, 3]);
+        assert_eq!(cs.get_learners(), &[2]);
+        assert!(next_conf_state_from_region(&region).is_none());
+
+        // Replace peer 3 with peer 4 in a membership change.
+        let prev_peers = region.get_peers().to_vec();
+        region.mut_peers().pop();
+        region.mut_peers().push(new_peer(4, 4));
+        region.set_prev_peers(RepeatedField::from_vec(prev_peers));
+        let cs = conf_state_from_region(&region);
+        assert_eq!(cs.get_nodes(), &[1, 3]);
+        assert_eq!(cs.get_learners(), &[2]);
+        let cs = next_conf_state_from_region(&region).unwrap();
+        assert_eq!(cs.get_nodes(), &[1, 4]);
+        assert_eq!(cs.get_learners(), &[2]);
+    }
+
     #[test]
     fn test_first_vote_msg() {
         let tbl = vec![(MessageType::MsgRequestVote, peer_storage::RAFT_INIT_LOG_TERM + 1, true),
@@ -159,6 +227,12 @@ mod tests {
                    STR_CONF_CHANGE_ADD_NODE);
         assert_eq!(conf_change_type_str(&ConfChangeType::RemoveNode),
                    STR_CONF_CHANGE_REMOVE_NODE);
+        assert_eq!(conf_change_type_str(&ConfChangeType::AddLearnerNode),
+                   STR_CONF_CHANGE_ADD_LEARNER_NODE);
+        assert_eq!(conf_change_type_str(&ConfChangeType::BeginMembershipChange),
+                   STR_CONF_CHANGE_BEGIN_MEMBERSHIP_CHANGE);
+        assert_eq!(conf_change_type_str(&ConfChangeType::FinalizeMembershipChange),
+                   STR_CONF_CHANGE_FINALIZE_MEMBERSHIP_CHANGE);
     }
 
     #[test]
@@ -18,6 +18,7 @@ use std::fmt::{self, Debug, Display, Formatter};
 use std::collections::VecDeque;
 
 use rocksdb::{DB, WriteBatch, Writable};
+use rocksdb::rocksdb_options::WriteOptions;
 use protobuf::RepeatedField;
 
 use kvproto::metapb::{Peer as PeerMeta, Region};
@@ -25,16 +26,19 @@ use kvproto::eraftpb::{Entry, EntryType, ConfChange, ConfChangeType};
 use kvproto::raft_serverpb::{RaftApplyState, RaftTruncatedState, PeerState};
 use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse, ChangePeerRequest, CmdType,
                           AdminCmdType, Request, Response, AdminRequest, AdminResponse};
+use kvproto::import_sstpb::SSTMeta;
 
 use util::worker::Runnable;
 use util::{SlowTimer, rocksdb, escape};
-use util::collections::{HashMap, HashMapEntry as MapEntry};
-use storage::{CF_LOCK, CF_RAFT};
+use util::collections::{HashMap, HashSet, HashMapEntry as MapEntry};
+use storage::{CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAFT};
+use import::SSTImporter;
 use raftstore::{Result, Error};
 use raftstore::coprocessor::CoprocessorHost;
 use raftstore::store::{Store, cmd_resp, keys, util};
+use raftstore::store::store::delete_file_in_range;
 use raftstore::store::msg::Callback;
-use raftstore::store::engine::{Snapshot, Peekable, Mutable};
+use raftstore::store::engine::{Snapshot, Peekable, Mutable, delete_all_in_range};
 use raftstore::store::peer_storage::{self, write_initial_state, write_peer_state, compact_raft_log};
 use raftstore::store::peer::{parse_data_at, check_epoch, Peer};
 use raftstore::store::metrics::*;
@@ -146,6 +150,8 @@ struct ApplyContext<'a> {
     pub cbs: Vec<(Callback, RaftCmdResponse)>,
     pub wb_last_bytes: u64,
     pub wb_last_keys: u64,
+    // The SST files of the commands applied in `wb`, they are deleted after `wb` is written.
+    pub ssts: Vec<SSTMeta>,
 }
 
 impl<'a> ApplyContext<'a> {
@@ -156,6 +162,24 @@ impl<'a> ApplyContext<'a> {
             cbs: vec![],
             wb_last_bytes: 0,
             wb_last_keys: 0,
+            ssts: vec![],
+        }
+    }
+
+    /// Returns the options to write `wb`. The write is synced if there are SST files to
+    /// delete after it, so the commands ingesting them are never applied again.
+    pub fn write_opts(&self) -> WriteOptions {
+        let mut write_opts = WriteOptions::new();
+        write_opts.set_sync(!self.ssts.is_empty());
+        write_opts
+    }
+
+    /// Deletes the SST files after `wb` is written.
+    pub fn delete_ssts(&mut self, importer: &SSTImporter) {
+        for sst in self.ssts.drain(..) {
+            if let Err(e) = importer.delete(&sst) {
+                warn!("failed to delete sst {:?}: {:?}", sst, e);
+            }
         }
     }
 
@@ -226,6 +250,15 @@ fn should_flush_to_engine(cmd: &RaftCmdRequest, wb_keys: usize) -> bool {
         return true;
     }
 
+    // Delete range and ingest sst work on the engine directly, so the pending writes must be
+    // flushed first, otherwise they may be applied after the range is deleted or overwrite
+    // the ingested values.
+    if cmd.get_requests().iter().any(|r| {
+        r.get_cmd_type() == CmdType::DeleteRange || r.get_cmd_type() == CmdType::IngestSST
+    }) {
+        return true;
+    }
+
     // When write batch contains more than `recommended` keys, flush the batch to engine.
     if wb_keys >= WRITE_BATCH_MAX_KEYS {
         return true;
@@ -241,6 +274,7 @@ pub struct ApplyDelegate {
     // peer_tag, "[region region_id] peer_id"
     tag: String,
     engine: Arc<DB>,
+    importer: Arc<SSTImporter>,
     region: Region,
     // if we remove ourself in ChangePeer remove, we should set this flag, then
     // any following committed logs in same Ready should be applied failed.
@@ -261,16 +295,20 @@ impl ApplyDelegate {
         self.id
     }
 
-    fn from_peer(peer: &Peer) -> ApplyDelegate {
+    fn from_peer(peer: &Peer, importer: Arc<SSTImporter>) -> ApplyDelegate {
         let reg = Registration::new(peer);
-        ApplyDelegate::from_registration(peer.engine(), reg)
+        ApplyDelegate::from_registration(peer.engine(), importer, reg)
     }
 
-    fn from_registration(db: Arc<DB>, reg: Registration) -> ApplyDelegate {
+    fn from_registration(db: Arc<DB>,
+                         importer: Arc<SSTImporter>,
+                         reg: Registration)
+                         -> ApplyDelegate {
         ApplyDelegate {
             id: reg.id,
             tag: format!("[region {}] {}", reg.region.get_id(), reg.id),
             engine: db,
+            importer: importer,
             region: reg.region,
             pending_remove: false,
             apply_state: reg.apply_state,
@@ -369,11 +407,13 @@ impl ApplyDelegate {
                 self.update_metrics(apply_ctx);
 
                 // flush to engine
+                let write_opts = apply_ctx.write_opts();
                 self.engine
-                    .write(apply_ctx.wb.take().unwrap())
+                    .write_opt(apply_ctx.wb.take().unwrap(), &write_opts)
                     .unwrap_or_else(|e| {
                         panic!("{} failed to write to engine, error: {:?}", self.tag, e)
                     });
+                apply_ctx.delete_ssts(&self.importer);
 
                 // call callback
                 for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -407,6 +447,10 @@ impl ApplyDelegate {
         let index = entry.get_index();
         let term = entry.get_term();
         let conf_change: ConfChange = parse_data_at(entry.get_data(), index, &self.tag);
+        if conf_change.get_change_type() == ConfChangeType::FinalizeMembershipChange {
+            // It's proposed by raft itself without a command.
+            return Some(self.exec_finalize_membership_change(apply_ctx, index, term, conf_change));
+        }
         let cmd = parse_data_at(conf_change.get_context(), index, &self.tag);
         Some(self.process_raft_cmd(apply_ctx, index, term, cmd)
             .map_or_else(|| {
@@ -427,6 +471,38 @@ impl ApplyDelegate {
             }))
     }
 
+    // The region peers have been changed to the next configuration when the membership
+    // change begins, the previous configuration is dropped here and a removed peer needs
+    // to be destroyed.
+    fn exec_finalize_membership_change(&mut self,
+                                       apply_ctx: &mut ApplyContext,
+                                       index: u64,
+                                       term: u64,
+                                       conf_change: ConfChange)
+                                       -> ExecResult {
+        info!("{} finalize membership change of region {:?} at {}",
+              self.tag,
+              self.region,
+              index);
+        self.apply_state.set_applied_index(index);
+        self.applied_index_term = term;
+        self.region.clear_prev_peers();
+        let state = if self.region.get_peers().iter().all(|p| p.get_id() != self.id) {
+            self.pending_remove = true;
+            PeerState::Tombstone
+        } else {
+            PeerState::Normal
+        };
+        if let Err(e) = write_peer_state(apply_ctx.wb_mut(), &self.region, state) {
+            panic!("{} failed to update region state: {:?}", self.tag, e);
+        }
+        ExecResult::ChangePeer(ChangePeer {
+            conf_change: conf_change,
+            peer: Default::default(),
+            region: self.region.clone(),
+        })
+    }
+
     fn find_cb(&mut self, index: u64, term: u64, cmd: &RaftCmdRequest) -> Option<Callback> {
         if get_change_peer_cmd(cmd).is_some() {
             if let Some(mut cmd) = self.pending_cmds.take_conf_change() {
@@ -463,6 +539,16 @@ impl ApplyDelegate {
         let cmd_cb = self.find_cb(index, term, &cmd);
         apply_ctx.host.pre_apply(&self.region, &mut cmd);
         let (mut resp, exec_result) = self.apply_raft_cmd(apply_ctx.wb_mut(), index, term, &cmd);
+        if !resp.get_header().has_error() {
+            apply_ctx.host.post_apply(&self.region, index, &cmd);
+        }
+        // The files are useless whether they are ingested or not, but they are kept until the
+        // apply state is persisted, in case the command is applied again after a restart.
+        for req in cmd.get_requests() {
+            if req.get_cmd_type() == CmdType::IngestSST {
+                apply_ctx.ssts.push(req.get_ingest_sst().get_sst().clone());
+            }
+        }
 
         debug!("{} applied command at log index {}", self.tag, index);
 
@@ -471,7 +557,6 @@ impl ApplyDelegate {
             Some(cb) => cb,
         };
 
-        // TODO: Involve post apply hook.
         // TODO: if we have exec_result, maybe we should return this callback too. Outer
         // store will call it after handing exec result.
         cmd_resp::bind_term(&mut resp, self.term);
@@ -653,6 +738,13 @@ impl ApplyDelegate {
               util::conf_change_type_str(&change_type),
               region.get_region_epoch());
 
+        if !region.get_prev_peers().is_empty() {
+            error!("{} can't change peers of region {:?} in a membership change",
+                   self.tag,
+                   self.region);
+            return Err(box_err!("region {:?} is in a membership change", self.region));
+        }
+
         // TODO: we should need more check, like peer validation, duplicated id, etc.
         let exists = util::find_peer(&region, store_id).is_some();
         let conf_ver = region.get_region_epoch().get_conf_ver() + 1;
@@ -663,19 +755,29 @@ impl ApplyDelegate {
             ConfChangeType::AddNode => {
                 PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&["add_peer", "all"]).inc();
 
-                if exists {
-                    error!("{} can't add duplicated peer {:?} to region {:?}",
-                           self.tag,
-                           peer,
-                           self.region);
-                    return Err(box_err!("can't add duplicated peer {:?} to region {:?}",
-                                        peer,
-                                        self.region));
-                }
+                // Adding an existing learner as a node promotes it to a voter.
+                let pos = region.get_peers()
+                    .iter()
+                    .position(|p| p.get_id() == peer.get_id() && p.get_is_learner());
+                if let Some(pos) = pos {
+                    region.mut_peers()[pos].set_is_learner(false);
+                } else {
+                    if exists {
+                        error!("{} can't add duplicated peer {:?} to region {:?}",
+                               self.tag,
+                               peer,
+                               self.region);
+                        return Err(box_err!("can't add duplicated peer {:?} to region {:?}",
+                                            peer,
+                                            self.region));
+                    }
 
-                // TODO: Do we allow adding peer in same node?
+                    // TODO: Do we allow adding peer in same node?
 
-                region.mut_peers().push(peer.clone());
+                    let mut peer = peer.clone();
+                    peer.set_is_learner(false);
+                    region.mut_peers().push(peer);
+                }
 
                 PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&["add_peer", "success"]).inc();
 
@@ -712,6 +814,73 @@ impl ApplyDelegate {
                       peer.get_id(),
                       self.region);
             }
+            ConfChangeType::BeginMembershipChange => {
+                PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&["membership_change", "all"]).inc();
+
+                // The region peers switch to the next configuration at once, the current
+                // one is kept in `prev_peers` so that the joint consensus can be rebuilt
+                // after restart, until the change is finalized.
+                let peers = request.get_peers();
+                let mut store_ids = HashSet::default();
+                for p in peers {
+                    let valid = store_ids.insert(p.get_store_id()) &&
+                                util::find_peer(&self.region, p.get_store_id())
+                        .map_or(true, |old| old.get_id() == p.get_id());
+                    if !valid {
+                        error!("{} invalid peer {:?} in membership change of region {:?}",
+                               self.tag,
+                               p,
+                               self.region);
+                        return Err(box_err!("invalid peer {:?} in membership change of region \
+                                             {:?}",
+                                            p,
+                                            self.region));
+                    }
+                }
+                if peers.is_empty() {
+                    return Err(box_err!("can't change region {:?} to no peers", self.region));
+                }
+
+                region.set_prev_peers(RepeatedField::from_slice(self.region.get_peers()));
+                region.set_peers(RepeatedField::from_slice(peers));
+
+                PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&["membership_change", "success"])
+                    .inc();
+
+                info!("{} begin membership change from {:?} to {:?}",
+                      self.tag,
+                      self.region.get_peers(),
+                      peers);
+            }
+            ConfChangeType::FinalizeMembershipChange => {
+                return Err(box_err!("finalizing membership change is only proposed by raft"));
+            }
+            ConfChangeType::AddLearnerNode => {
+                PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&["add_learner", "all"]).inc();
+
+                if exists {
+                    error!("{} can't add duplicated learner {:?} to region {:?}",
+                           self.tag,
+                           peer,
+                           self.region);
+                    return Err(box_err!("can't add duplicated learner {:?} to region {:?}",
+                                        peer,
+                                        self.region));
+                }
+
+                // The learner is marked in the region meta, so it stays a learner after
+                // restart.
+                let mut learner = peer.clone();
+                learner.set_is_learner(true);
+                region.mut_peers().push(learner);
+
+                PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&["add_learner", "success"]).inc();
+
+                info!("{} add learner {:?} to region {:?}",
+                      self.tag,
+                      peer,
+                      self.region);
+            }
         }
 
         let state = if self.pending_remove {
@@ -754,6 +923,10 @@ impl ApplyDelegate {
 
         try!(util::check_key_in_region(split_key, &region));
 
+        if !region.get_prev_peers().is_empty() {
+            return Err(box_err!("can't split region {:?} in a membership change", region));
+        }
+
         info!("{} split at key: {}, region: {:?}",
               self.tag,
               escape(split_key),
@@ -870,6 +1043,13 @@ impl ApplyDelegate {
 
     fn exec_write_cmd(&mut self, ctx: &ExecContext) -> Result<RaftCmdResponse> {
         let requests = ctx.req.get_requests();
+        // Checks all the files before ingesting any of them, so a command ingesting many files
+        // is never applied partially.
+        for req in requests {
+            if req.get_cmd_type() == CmdType::IngestSST {
+                try!(self.check_sst(req.get_ingest_sst().get_sst()));
+            }
+        }
         let mut responses = Vec::with_capacity(requests.len());
 
         for req in requests {
@@ -877,6 +1057,8 @@ impl ApplyDelegate {
             let mut resp = try!(match cmd_type {
                 CmdType::Put => self.handle_put(ctx, req),
                 CmdType::Delete => self.handle_delete(ctx, req),
+                CmdType::DeleteRange => self.handle_delete_range(req),
+                CmdType::IngestSST => self.handle_ingest_sst(req),
                 // Readonly commands are handled in raftstore directly.
                 // Don't panic here in case there are old entries need to be applied.
                 // It's also safe to skip them here, because a restart must have happened,
@@ -970,6 +1152,92 @@ impl ApplyDelegate {
 
         Ok(resp)
     }
+
+    // The range is deleted from the engine directly instead of the write batch, so it is not
+    // atomic with the other requests in the same command. Only use it for the ranges that will
+    // never be accessed again, e.g. dropped tables and indexes.
+    fn handle_delete_range(&mut self, req: &Request) -> Result<Response> {
+        let (start_key, end_key) = (req.get_delete_range().get_start_key(),
+                                    req.get_delete_range().get_end_key());
+        if !end_key.is_empty() && start_key >= end_key {
+            return Err(box_err!("invalid delete range command, start_key: {}, end_key: {}",
+                                escape(start_key),
+                                escape(end_key)));
+        }
+        try!(check_data_key(start_key, &self.region));
+
+        let region_end_key = keys::enc_end_key(&self.region);
+        let (start_key, origin_end_key) = (keys::data_key(start_key), end_key);
+        let end_key = keys::data_end_key(origin_end_key);
+        if end_key > region_end_key {
+            return Err(Error::KeyNotInRegion(origin_end_key.to_vec(), self.region.clone()));
+        }
+        if !keys::is_data_range(&start_key, &end_key) {
+            return Err(box_err!("delete range [{}, {}) covers non-data keys",
+                                escape(&start_key),
+                                escape(&end_key)));
+        }
+
+        // Drop the whole sst files first to reduce the keys need to be deleted one by one.
+        delete_file_in_range(&self.engine, &start_key, &end_key)
+            .and_then(|_| delete_all_in_range(&self.engine, &start_key, &end_key))
+            .unwrap_or_else(|e| {
+                panic!("{} failed to delete range [{}, {}): {:?}",
+                       self.tag,
+                       escape(&start_key),
+                       escape(&end_key),
+                       e)
+            });
+
+        Ok(Response::new())
+    }
+
+    // Like delete range, the file is ingested into the engine directly, so it is not atomic
+    // with the other requests in the same command. The file is sent to the store before the
+    // command is proposed, only its metadata is in the log. It has been checked by
+    // `check_sst`, so a failure here is a disk failure, which panics like delete range.
+    //
+    // Ingesting a file again after a restart is fine, as the commands after it are applied
+    // again too.
+    fn handle_ingest_sst(&mut self, req: &Request) -> Result<Response> {
+        let sst = req.get_ingest_sst().get_sst();
+        self.importer.ingest(sst, &self.engine).unwrap_or_else(|e| {
+            panic!("{} failed to ingest sst {:?}: {:?}", self.tag, sst, e)
+        });
+        self.metrics.size_diff_hint += sst.get_length() as i64;
+
+        Ok(Response::new())
+    }
+
+    fn check_sst(&self, sst: &SSTMeta) -> Result<()> {
+        // The peers of the region may have changed since the file was sent, and the new ones
+        // don't have the file.
+        if sst.get_region_id() != self.region.get_id() ||
+           sst.get_region_epoch() != self.region.get_region_epoch() {
+            return Err(Error::StaleEpoch(format!("sst {:?} is sent to a stale region {:?}",
+                                                 sst,
+                                                 self.region),
+                                         vec![self.region.clone()]));
+        }
+        let (start_key, end_key) = (sst.get_range().get_start(), sst.get_range().get_end());
+        if start_key > end_key {
+            return Err(box_err!("invalid ingest range [{}, {}]",
+                                escape(start_key),
+                                escape(end_key)));
+        }
+        // Both keys of the range are in the file, so they must be in the region.
+        try!(check_data_key(start_key, &self.region));
+        try!(check_data_key(end_key, &self.region));
+        let cf = sst.get_cf_name();
+        if cf != CF_DEFAULT && cf != CF_WRITE {
+            return Err(box_err!("can't ingest sst into cf {}", cf));
+        }
+
+        if let Err(e) = self.importer.verify(sst) {
+            return Err(box_err!("failed to verify sst {:?}: {:?}", sst, e));
+        }
+        Ok(())
+    }
 }
 
 pub fn get_change_peer_cmd(msg: &RaftCmdRequest) -> Option<&ChangePeerRequest> {
@@ -1198,6 +1466,7 @@ pub enum TaskRes {
 pub struct Runner {
     db: Arc<DB>,
     host: Arc<CoprocessorHost>,
+    importer: Arc<SSTImporter>,
     delegates: HashMap<u64, ApplyDelegate>,
     notifier: Sender<TaskRes>,
 }
@@ -1206,11 +1475,12 @@ impl Runner {
     pub fn new<T, C>(store: &Store<T, C>, notifier: Sender<TaskRes>) -> Runner {
         let mut delegates = HashMap::with_capacity(store.get_peers().len());
         for (&region_id, p) in store.get_peers() {
-            delegates.insert(region_id, ApplyDelegate::from_peer(p));
+            delegates.insert(region_id, ApplyDelegate::from_peer(p, store.importer()));
         }
         Runner {
             db: store.engine(),
             host: store.coprocessor_host.clone(),
+            importer: store.importer(),
             delegates: delegates,
             notifier: notifier,
         }
@@ -1256,9 +1526,11 @@ impl Runner {
         }
 
         // Write to engine
+        let write_opts = apply_ctx.write_opts();
         self.db
-            .write(apply_ctx.wb.take().unwrap())
+            .write_opt(apply_ctx.wb.take().unwrap(), &write_opts)
             .unwrap_or_else(|e| panic!("failed to write to engine, error: {:?}", e));
+        apply_ctx.delete_ssts(&self.importer);
 
         // Call callbacks
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -1304,7 +1576,8 @@ impl Runner {
         let peer_id = s.id;
         let region_id = s.region.get_id();
         let term = s.term;
-        let delegate = ApplyDelegate::from_registration(self.db.clone(), s);
+        let delegate =
+            ApplyDelegate::from_registration(self.db.clone(), self.importer.clone(), s);
         info!("{} register to apply delegates at term {}",
               delegate.tag,
               delegate.term);
@@ -1360,6 +1633,7 @@ mod tests {
     use super::*;
     use storage::{CF_WRITE, ALL_CFS};
     use util::collections::HashMap;
+    use import::build_sst;
 
     pub fn create_tmp_engine(path: &str) -> (TempDir, Arc<DB>) {
         let path = TempDir::new(path).unwrap();
@@ -1367,10 +1641,15 @@ mod tests {
         (path, db)
     }
 
-    fn new_runner(db: Arc<DB>, host: Arc<CoprocessorHost>, tx: Sender<TaskRes>) -> Runner {
+    fn new_runner(db: Arc<DB>,
+                  host: Arc<CoprocessorHost>,
+                  importer: Arc<SSTImporter>,
+                  tx: Sender<TaskRes>)
+                  -> Runner {
         Runner {
             db: db,
             host: host,
+            importer: importer,
             delegates: HashMap::new(),
             notifier: tx,
         }
@@ -1392,6 +1671,22 @@ mod tests {
         let wb = WriteBatch::new();
         assert_eq!(should_flush_to_engine(&req, wb.count()), true);
 
+        // DeleteRange command
+        let mut req = RaftCmdRequest::new();
+        let mut delete_range = Request::new();
+        delete_range.set_cmd_type(CmdType::DeleteRange);
+        req.mut_requests().push(delete_range);
+        let wb = WriteBatch::new();
+        assert_eq!(should_flush_to_engine(&req, wb.count()), true);
+
+        // IngestSST command
+        let mut req = RaftCmdRequest::new();
+        let mut ingest_sst = Request::new();
+        ingest_sst.set_cmd_type(CmdType::IngestSST);
+        req.mut_requests().push(ingest_sst);
+        let wb = WriteBatch::new();
+        assert_eq!(should_flush_to_engine(&req, wb.count()), true);
+
         // Write batch keys reach WRITE_BATCH_MAX_KEYS
         let req = RaftCmdRequest::new();
         let wb = WriteBatch::new();
@@ -1415,8 +1710,10 @@ mod tests {
     fn test_basic_flow() {
         let (tx, rx) = mpsc::channel();
         let (_tmp, db) = create_tmp_engine("apply-basic");
+        let import_dir = TempDir::new("apply-basic-import").unwrap();
+        let importer = Arc::new(SSTImporter::new(import_dir.path()).unwrap());
         let host = Arc::new(CoprocessorHost::new());
-        let mut runner = new_runner(db.clone(), host, tx);
+        let mut runner = new_runner(db.clone(), host, importer, tx);
 
         let mut reg = Registration::default();
         reg.id = 1;
@@ -1600,6 +1897,14 @@ mod tests {
             self
         }
 
+        fn ingest_sst(mut self, meta: SSTMeta) -> EntryBuilder {
+            let mut cmd = Request::new();
+            cmd.set_cmd_type(CmdType::IngestSST);
+            cmd.mut_ingest_sst().set_sst(meta);
+            self.req.mut_requests().push(cmd);
+            self
+        }
+
         fn build(mut self) -> Entry {
             self.entry.set_data(self.req.write_to_bytes().unwrap());
             self.entry
@@ -1609,10 +1914,12 @@ mod tests {
     #[test]
     fn test_handle_raft_committed_entries() {
         let (_path, db) = create_tmp_engine("test-delegate");
+        let import_dir = TempDir::new("test-delegate-import").unwrap();
+        let importer = Arc::new(SSTImporter::new(import_dir.path()).unwrap());
         let mut reg = Registration::default();
         reg.region.set_end_key(b"k5".to_vec());
         reg.region.mut_region_epoch().set_version(3);
-        let mut delegate = ApplyDelegate::from_registration(db.clone(), reg);
+        let mut delegate = ApplyDelegate::from_registration(db.clone(), importer, reg);
         let (tx, rx) = mpsc::channel();
 
         let put_entry = EntryBuilder::new(1, 1)
@@ -1756,4 +2063,119 @@ mod tests {
         assert_eq!(delegate.apply_state.get_applied_index(),
                    WRITE_BATCH_MAX_KEYS as u64 + 6);
     }
+
+    #[test]
+    fn test_ingest_sst() {
+        let (_path, db) = create_tmp_engine("test-ingest-sst");
+        let import_dir = TempDir::new("test-ingest-sst-import").unwrap();
+        let importer = Arc::new(SSTImporter::new(import_dir.path()).unwrap());
+        let mut reg = Registration::default();
+        reg.region.set_end_key(b"k5".to_vec());
+        reg.region.mut_region_epoch().set_version(3);
+        reg.region.mut_region_epoch().set_conf_ver(1);
+        let mut delegate = ApplyDelegate::from_registration(db.clone(), importer.clone(), reg);
+        let (tx, rx) = mpsc::channel();
+        let host = CoprocessorHost::new();
+
+        let sst = build_sst(vec![(keys::data_key(b"k1"), b"v1".to_vec())]).unwrap();
+        let mut meta = SSTMeta::new();
+        meta.set_uuid(vec![1; 16]);
+        meta.set_region_epoch(delegate.region.get_region_epoch().clone());
+        meta.set_cf_name(CF_DEFAULT.to_owned());
+        meta.set_crc32(sst.crc32);
+        meta.set_length(sst.data.len() as u64);
+        meta.mut_range().set_start(b"k1".to_vec());
+        meta.mut_range().set_end(b"k1".to_vec());
+        importer.save(&meta, &sst.data).unwrap();
+
+        // The pending put must be written before the file is ingested, so it doesn't
+        // overwrite the ingested value.
+        let put_entry = EntryBuilder::new(1, 1).put(b"k1", b"v0").epoch(1, 3).build();
+        let ingest_entry = EntryBuilder::new(2, 1)
+            .ingest_sst(meta.clone())
+            .epoch(1, 3)
+            .capture_resp(&mut delegate, tx.clone())
+            .build();
+        let mut apply_ctx = ApplyContext::new(&host);
+        delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry, ingest_entry]);
+        // The file is kept until the apply state is written.
+        assert!(importer.exists(&meta));
+        db.write(apply_ctx.wb.take().unwrap()).unwrap();
+        apply_ctx.delete_ssts(&importer);
+        for (cb, resp) in apply_ctx.cbs.drain(..) {
+            cb(resp);
+        }
+        let resp = rx.try_recv().unwrap();
+        assert!(!resp.get_header().has_error(), "{:?}", resp);
+        assert_eq!(db.get(&keys::data_key(b"k1")).unwrap().unwrap(), b"v1");
+        assert!(!importer.exists(&meta));
+
+        // A missing file is rejected instead of panicking.
+        let ingest_entry = EntryBuilder::new(3, 1)
+            .ingest_sst(meta.clone())
+            .epoch(1, 3)
+            .capture_resp(&mut delegate, tx.clone())
+            .build();
+        let mut apply_ctx = ApplyContext::new(&host);
+        delegate.handle_raft_committed_entries(&mut apply_ctx, vec![ingest_entry]);
+        db.write(apply_ctx.wb.take().unwrap()).unwrap();
+        apply_ctx.delete_ssts(&importer);
+        for (cb, resp) in apply_ctx.cbs.drain(..) {
+            cb(resp);
+        }
+        let resp = rx.try_recv().unwrap();
+        assert!(resp.get_header().has_error());
+        assert_eq!(delegate.apply_state.get_applied_index(), 3);
+
+        // None of the files is ingested if any of them is invalid.
+        let sst2 = build_sst(vec![(keys::data_key(b"k2"), b"v2".to_vec())]).unwrap();
+        let mut meta2 = meta.clone();
+        meta2.set_uuid(vec![2; 16]);
+        meta2.set_crc32(sst2.crc32);
+        meta2.set_length(sst2.data.len() as u64);
+        meta2.mut_range().set_start(b"k2".to_vec());
+        meta2.mut_range().set_end(b"k2".to_vec());
+        importer.save(&meta2, &sst2.data).unwrap();
+        let mut meta3 = meta2.clone();
+        meta3.set_uuid(vec![3; 16]);
+        meta3.set_cf_name(CF_LOCK.to_owned());
+        importer.save(&meta3, &sst2.data).unwrap();
+        let ingest_entry = EntryBuilder::new(4, 1)
+            .ingest_sst(meta2.clone())
+            .ingest_sst(meta3.clone())
+            .epoch(1, 3)
+            .capture_resp(&mut delegate, tx.clone())
+            .build();
+        let mut apply_ctx = ApplyContext::new(&host);
+        delegate.handle_raft_committed_entries(&mut apply_ctx, vec![ingest_entry]);
+        db.write(apply_ctx.wb.take().unwrap()).unwrap();
+        apply_ctx.delete_ssts(&importer);
+        for (cb, resp) in apply_ctx.cbs.drain(..) {
+            cb(resp);
+        }
+        let resp = rx.try_recv().unwrap();
+        assert!(resp.get_header().has_error());
+        assert!(db.get(&keys::data_key(b"k2")).unwrap().is_none());
+        assert!(!importer.exists(&meta2));
+        assert!(!importer.exists(&meta3));
+
+        // A file sent before the peers changed is rejected and deleted.
+        meta.mut_region_epoch().set_conf_ver(0);
+        importer.save(&meta, &sst.data).unwrap();
+        let ingest_entry = EntryBuilder::new(5, 1)
+            .ingest_sst(meta.clone())
+            .epoch(1, 3)
+            .capture_resp(&mut delegate, tx.clone())
+            .build();
+        let mut apply_ctx = ApplyContext::new(&host);
+        delegate.handle_raft_committed_entries(&mut apply_ctx, vec![ingest_entry]);
+        db.write(apply_ctx.wb.take().unwrap()).unwrap();
+        apply_ctx.delete_ssts(&importer);
+        for (cb, resp) in apply_ctx.cbs.drain(..) {
+            cb(resp);
+        }
+        let resp = rx.try_recv().unwrap();
+        assert!(resp.get_header().get_error().has_stale_epoch());
+        assert!(!importer.exists(&meta));
+    }
 }
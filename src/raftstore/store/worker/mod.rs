@@ -49,6 +49,7 @@ mod region;
 mod split_check;
 mod compact;
 mod raftlog_gc;
+mod raftlog_write;
 mod pd;
 mod metrics;
 mod consistency_check;
@@ -58,6 +59,8 @@ pub use self::region::{Task as RegionTask, Runner as RegionRunner};
 pub use self::split_check::{Task as SplitCheckTask, Runner as SplitCheckRunner};
 pub use self::compact::{Task as CompactTask, Runner as CompactRunner};
 pub use self::raftlog_gc::{Task as RaftlogGcTask, Runner as RaftlogGcRunner};
+pub use self::raftlog_write::{Task as RaftlogWriteTask, Runner as RaftlogWriteRunner,
+                              PersistedReady};
 pub use self::pd::{Task as PdTask, Runner as PdRunner};
 pub use self::consistency_check::{Task as ConsistencyCheckTask, Runner as ConsistencyCheckRunner};
 pub use self::apply::{Task as ApplyTask, Runner as ApplyRunner, TaskRes as ApplyTaskRes, ApplyRes,
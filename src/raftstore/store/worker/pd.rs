@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::fmt::{self, Formatter, Display};
 
 use futures::Future;
@@ -29,6 +30,7 @@ use util::transport::SendCh;
 use pd::{PdClient, RegionStat};
 use raftstore::store::Msg;
 use raftstore::store::util::is_epoch_stale;
+use storage::MaxReadTs;
 
 use super::metrics::*;
 
@@ -48,6 +50,8 @@ pub enum Task {
         pending_peers: Vec<metapb::Peer>,
         written_bytes: u64,
         written_keys: u64,
+        read_bytes: u64,
+        read_keys: u64,
     },
     StoreHeartbeat { stats: pdpb::StoreStats },
     ReportSplit {
@@ -58,6 +62,13 @@ pub enum Task {
         region: metapb::Region,
         peer: metapb::Peer,
     },
+    // Gets a timestamp from PD and stores it to `tso` if it's larger.
+    UpdateTso { tso: Arc<AtomicUsize> },
+    InitMaxReadTs {
+        region_id: u64,
+        token: u64,
+        max_read_ts: MaxReadTs,
+    },
 }
 
 impl Display for Task {
@@ -82,6 +93,10 @@ impl Display for Task {
             Task::ValidatePeer { ref region, ref peer } => {
                 write!(f, "validate peer {:?} with region {:?}", peer, region)
             }
+            Task::UpdateTso { .. } => write!(f, "update tso"),
+            Task::InitMaxReadTs { region_id, .. } => {
+                write!(f, "init max read ts of region {}", region_id)
+            }
         }
     }
 }
@@ -165,6 +180,55 @@ impl<T: PdClient> Runner<T> {
         handle.spawn(f);
     }
 
+    fn handle_update_tso(&self, handle: &Handle, tso: Arc<AtomicUsize>) {
+        PD_REQ_COUNTER_VEC.with_label_values(&["get tso", "all"]).inc();
+
+        let f = self.pd_client
+            .get_tso()
+            .then(move |resp| {
+                match resp {
+                    Ok(ts) => {
+                        PD_REQ_COUNTER_VEC.with_label_values(&["get tso", "success"]).inc();
+                        // The futures run on one thread, so there is no concurrent update.
+                        if ts as usize > tso.load(Ordering::SeqCst) {
+                            tso.store(ts as usize, Ordering::SeqCst);
+                        }
+                    }
+                    Err(e) => {
+                        debug!("failed to get tso {:?}", e);
+                    }
+                }
+                Ok(())
+            });
+        handle.spawn(f);
+    }
+
+    fn handle_init_max_read_ts(&self,
+                               handle: &Handle,
+                               region_id: u64,
+                               token: u64,
+                               max_read_ts: MaxReadTs) {
+        PD_REQ_COUNTER_VEC.with_label_values(&["get tso", "all"]).inc();
+
+        let f = self.pd_client
+            .get_tso()
+            .then(move |resp| {
+                match resp {
+                    Ok(ts) => {
+                        PD_REQ_COUNTER_VEC.with_label_values(&["get tso", "success"]).inc();
+                        // Any read served by the old leader is before the ts.
+                        max_read_ts.init(region_id, token, ts);
+                    }
+                    Err(e) => {
+                        // Retried on the next safe ts tick.
+                        debug!("failed to get tso for region {}: {:?}", region_id, e);
+                    }
+                }
+                Ok(())
+            });
+        handle.spawn(f);
+    }
+
     fn handle_report_split(&self, handle: &Handle, left: metapb::Region, right: metapb::Region) {
         PD_REQ_COUNTER_VEC.with_label_values(&["report split", "all"]).inc();
 
@@ -303,18 +367,26 @@ impl<T: PdClient> Runnable<Task> for Runner<T> {
                               down_peers,
                               pending_peers,
                               written_bytes,
-                              written_keys } => {
+                              written_keys,
+                              read_bytes,
+                              read_keys } => {
                 self.handle_heartbeat(handle,
                                       region,
                                       peer,
                                       RegionStat::new(down_peers,
                                                       pending_peers,
                                                       written_bytes,
-                                                      written_keys))
+                                                      written_keys,
+                                                      read_bytes,
+                                                      read_keys))
             }
             Task::StoreHeartbeat { stats } => self.handle_store_heartbeat(handle, stats),
             Task::ReportSplit { left, right } => self.handle_report_split(handle, left, right),
             Task::ValidatePeer { region, peer } => self.handle_validate_peer(handle, region, peer),
+            Task::UpdateTso { tso } => self.handle_update_tso(handle, tso),
+            Task::InitMaxReadTs { region_id, token, max_read_ts } => {
+                self.handle_init_max_read_ts(handle, region_id, token, max_read_ts)
+            }
         };
     }
 }
@@ -0,0 +1,99 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::fmt::{self, Formatter, Display};
+
+use rocksdb::{DB, WriteBatch};
+use rocksdb::rocksdb_options::WriteOptions;
+
+use raftstore::store::Msg;
+use util::worker::Runnable;
+use util::transport::SendCh;
+
+/// A raft `Ready` whose entries, hard state and snapshot are in the write batch.
+#[derive(Debug, Clone, Copy)]
+pub struct PersistedReady {
+    pub region_id: u64,
+    pub peer_id: u64,
+    pub number: u64,
+}
+
+pub enum Task {
+    /// Writes the raft log and states of the readies, the raftstore is notified after the
+    /// batch is persisted.
+    Write {
+        wb: WriteBatch,
+        sync: bool,
+        readies: Vec<PersistedReady>,
+    },
+    /// Notifies the sender after all the writes scheduled before are persisted.
+    Flush { cb: Sender<()> },
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Task::Write { ref wb, ref readies, .. } => {
+                write!(f,
+                       "Write Raft Log Task [count: {}, readies: {}]",
+                       wb.count(),
+                       readies.len())
+            }
+            Task::Flush { .. } => write!(f, "Flush Raft Log Task"),
+        }
+    }
+}
+
+pub struct Runner {
+    engine: Arc<DB>,
+    ch: SendCh<Msg>,
+}
+
+impl Runner {
+    pub fn new(engine: Arc<DB>, ch: SendCh<Msg>) -> Runner {
+        Runner {
+            engine: engine,
+            ch: ch,
+        }
+    }
+
+    fn write(&mut self, wb: WriteBatch, sync: bool, readies: Vec<PersistedReady>) {
+        if !wb.is_empty() {
+            let mut write_opts = WriteOptions::new();
+            write_opts.set_sync(sync);
+            self.engine.write_opt(wb, &write_opts).unwrap_or_else(|e| {
+                panic!("failed to save raft log: {:?}", e);
+            });
+        }
+        if readies.is_empty() {
+            return;
+        }
+        if let Err(e) = self.ch.send(Msg::RaftLogPersisted { readies: readies }) {
+            error!("failed to notify persisted raft log: {:?}", e);
+        }
+    }
+}
+
+impl Runnable<Task> for Runner {
+    fn run(&mut self, task: Task) {
+        match task {
+            Task::Write { wb, sync, readies } => self.write(wb, sync, readies),
+            Task::Flush { cb } => {
+                // The receiver may have been dropped when the raftstore stops.
+                let _ = cb.send(());
+            }
+        }
+    }
+}
@@ -16,25 +16,30 @@ use std::fmt::Debug;
 use std::io::Write;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::u64;
 use mio::Token;
-use grpc::{RpcContext, UnarySink, ClientStreamingSink, RequestStream, RpcStatus, RpcStatusCode};
-use futures::{future, Future, Stream};
-use futures::sync::oneshot;
+use grpc::{RpcContext, UnarySink, ClientStreamingSink, ServerStreamingSink, RequestStream,
+           RpcStatus, RpcStatusCode, WriteFlags, Error as GrpcError};
+use futures::{future, Future, Sink, Stream};
+use futures::sync::{mpsc, oneshot};
 use protobuf::RepeatedField;
 use kvproto::tikvpb_grpc;
 use kvproto::raft_serverpb::*;
 use kvproto::kvrpcpb::*;
 use kvproto::coprocessor::*;
+use kvproto::cdcpb::{ChangeDataRequest, ChangeDataEvent};
 use kvproto::errorpb::{Error as RegionError, ServerIsBusy};
 
 use util::worker::Scheduler;
+use util::collections::HashMap;
 use util::buf::PipeBuffer;
-use storage::{self, Storage, Key, Options, Mutation};
+use storage::{self, Storage, Key, Options, Mutation, TxnStatus};
 use storage::txn::Error as TxnError;
-use storage::mvcc::Error as MvccError;
+use storage::mvcc::{Error as MvccError, LockType, WriteType};
 use storage::engine::Error as EngineError;
 use super::transport::RaftStoreRouter;
 use coprocessor::{RequestTask, EndPointTask};
+use cdc::{self, Task as CdcTask};
 use super::snap::Task as SnapTask;
 use super::metrics::*;
 use super::Error;
@@ -51,6 +56,8 @@ pub struct Service<T: RaftStoreRouter + 'static> {
     ch: T,
     // For handling snapshot.
     snap_scheduler: Scheduler<SnapTask>,
+    // For handling change data subscriptions.
+    cdc_scheduler: Scheduler<CdcTask>,
     token: Arc<AtomicUsize>, // TODO: remove it.
 }
 
@@ -58,13 +65,15 @@ impl<T: RaftStoreRouter + 'static> Service<T> {
     pub fn new(storage: Storage,
                end_point_scheduler: Scheduler<EndPointTask>,
                ch: T,
-               snap_scheduler: Scheduler<SnapTask>)
+               snap_scheduler: Scheduler<SnapTask>,
+               cdc_scheduler: Scheduler<CdcTask>)
                -> Service<T> {
         Service {
             storage: storage,
             end_point_scheduler: end_point_scheduler,
             ch: ch,
             snap_scheduler: snap_scheduler,
+            cdc_scheduler: cdc_scheduler,
             token: Arc::new(AtomicUsize::new(1)),
         }
     }
@@ -134,12 +143,20 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let mut options = Options::default();
         options.key_only = req.get_key_only();
 
+        let end_key = if req.get_end_key().is_empty() {
+            None
+        } else {
+            Some(Key::from_raw(req.get_end_key()))
+        };
+
         let (cb, future) = make_callback();
         let res = storage.async_scan(req.take_context(),
                                      Key::from_raw(req.get_start_key()),
+                                     end_key,
                                      req.get_limit() as usize,
                                      req.get_version(),
                                      options,
+                                     req.get_reverse(),
                                      cb);
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
@@ -173,19 +190,29 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let label = "kv_prewrite";
         let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
 
-        let mutations = req.take_mutations()
-            .into_iter()
-            .map(|mut x| {
-                match x.get_op() {
-                    Op::Put => Mutation::Put((Key::from_raw(x.get_key()), x.take_value())),
-                    Op::Del => Mutation::Delete(Key::from_raw(x.get_key())),
-                    Op::Lock => Mutation::Lock(Key::from_raw(x.get_key())),
+        let mut mutations = Vec::with_capacity(req.get_mutations().len());
+        for mut x in req.take_mutations().into_iter() {
+            let key = Key::from_raw(x.get_key());
+            let m = match x.get_op() {
+                Op::Put => Mutation::Put((key, x.take_value())),
+                Op::Del => Mutation::Delete(key),
+                Op::Lock => Mutation::Lock(key),
+                Op::Insert => Mutation::Insert((key, x.take_value())),
+                Op::CheckNotExists => Mutation::CheckNotExists(key),
+                op => {
+                    let e = box_err!("unexpected op {:?} in prewrite mutations", op);
+                    self.send_fail_status(ctx, sink, e, RpcStatusCode::InvalidArgument);
+                    return;
                 }
-            })
-            .collect();
+            };
+            mutations.push(m);
+        }
         let mut options = Options::default();
         options.lock_ttl = req.get_lock_ttl();
         options.skip_constraint_check = req.get_skip_constraint_check();
+        options.for_update_ts = req.get_for_update_ts();
+        options.is_pessimistic_lock = req.take_is_pessimistic_lock();
+        options.one_pc_commit_ts = req.get_one_pc_commit_ts();
 
         let (cb, future) = make_callback();
         let res = self.storage.async_prewrite(req.take_context(),
@@ -219,6 +246,50 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
+    fn kv_pessimistic_lock(&self,
+                           ctx: RpcContext,
+                           mut req: PessimisticLockRequest,
+                           sink: UnarySink<PessimisticLockResponse>) {
+        let label = "kv_pessimistic_lock";
+        let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
+
+        let keys = req.get_mutations().iter().map(|x| Key::from_raw(x.get_key())).collect();
+        let mut options = Options::default();
+        options.lock_ttl = req.get_lock_ttl();
+        options.for_update_ts = req.get_for_update_ts();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_acquire_pessimistic_lock(req.take_context(),
+                                                              keys,
+                                                              req.take_primary_lock(),
+                                                              req.get_start_version(),
+                                                              options,
+                                                              cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future.map_err(Error::from)
+            .map(|v| {
+                let mut resp = PessimisticLockResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    resp.set_errors(RepeatedField::from_vec(extract_key_errors(v)));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
     fn kv_commit(&self, ctx: RpcContext, mut req: CommitRequest, sink: UnarySink<CommitResponse>) {
         let label = "kv_commit";
         let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
@@ -256,8 +327,51 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn kv_import(&self, _: RpcContext, _: ImportRequest, _: UnarySink<ImportResponse>) {
-        unimplemented!();
+    fn kv_import(&self, ctx: RpcContext, mut req: ImportRequest, sink: UnarySink<ImportResponse>) {
+        let label = "kv_import";
+        let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
+
+        let mut mutations = Vec::with_capacity(req.get_mutations().len());
+        for mut x in req.take_mutations().into_iter() {
+            let key = Key::from_raw(x.get_key());
+            let m = match x.get_op() {
+                Op::Put => Mutation::Put((key, x.take_value())),
+                Op::Del => Mutation::Delete(key),
+                op => {
+                    let e = box_err!("unexpected op {:?} in import mutations", op);
+                    self.send_fail_status(ctx, sink, e, RpcStatusCode::InvalidArgument);
+                    return;
+                }
+            };
+            mutations.push(m);
+        }
+
+        let (cb, future) = make_callback();
+        let res = self.storage
+            .async_import(req.take_context(), mutations, req.get_commit_version(), cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future.map_err(Error::from)
+            .map(|v| {
+                let mut resp = ImportResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else if let Err(e) = v {
+                    resp.set_error(format!("{}", e));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
     }
 
     fn kv_cleanup(&self,
@@ -301,6 +415,94 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
+    fn kv_txn_heart_beat(&self,
+                         ctx: RpcContext,
+                         mut req: TxnHeartBeatRequest,
+                         sink: UnarySink<TxnHeartBeatResponse>) {
+        let label = "kv_txn_heart_beat";
+        let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_txn_heart_beat(req.take_context(),
+                                                    Key::from_raw(req.get_primary_lock()),
+                                                    req.get_start_version(),
+                                                    req.get_advise_lock_ttl(),
+                                                    cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future.map_err(Error::from)
+            .map(|v| {
+                let mut resp = TxnHeartBeatResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    match v {
+                        Ok(TxnStatus::Locked { ttl }) => resp.set_lock_ttl(ttl),
+                        Ok(_) => unreachable!(),
+                        Err(e) => resp.set_error(extract_key_error(&e)),
+                    }
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_check_txn_status(&self,
+                           ctx: RpcContext,
+                           mut req: CheckTxnStatusRequest,
+                           sink: UnarySink<CheckTxnStatusResponse>) {
+        let label = "kv_check_txn_status";
+        let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_check_txn_status(req.take_context(),
+                                                      Key::from_raw(req.get_primary_key()),
+                                                      req.get_lock_ts(),
+                                                      req.get_current_ts(),
+                                                      cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future.map_err(Error::from)
+            .map(|v| {
+                let mut resp = CheckTxnStatusResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    match v {
+                        Ok(TxnStatus::Locked { ttl }) => resp.set_lock_ttl(ttl),
+                        Ok(TxnStatus::Committed { commit_ts }) => {
+                            resp.set_commit_version(commit_ts)
+                        }
+                        // Both `lock_ttl` and `commit_version` are 0 if rolled back.
+                        Ok(TxnStatus::RolledBack) => {}
+                        Err(e) => resp.set_error(extract_key_error(&e)),
+                    }
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
     fn kv_batch_get(&self,
                     ctx: RpcContext,
                     mut req: BatchGetRequest,
@@ -381,8 +583,24 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let label = "kv_scan_lock";
         let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
 
+        let start_key = if req.get_start_key().is_empty() {
+            None
+        } else {
+            Some(Key::from_raw(req.get_start_key()))
+        };
+        let end_key = if req.get_end_key().is_empty() {
+            None
+        } else {
+            Some(Key::from_raw(req.get_end_key()))
+        };
+
         let (cb, future) = make_callback();
-        let res = self.storage.async_scan_lock(req.take_context(), req.get_max_version(), cb);
+        let res = self.storage.async_scan_lock(req.take_context(),
+                                               req.get_max_version(),
+                                               start_key,
+                                               end_key,
+                                               req.get_limit() as usize,
+                                               cb);
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -418,14 +636,17 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let label = "kv_resolve_lock";
         let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
 
-        let commit_ts = match req.get_commit_version() {
-            0 => None,
-            x => Some(x),
-        };
+        let mut txn_status = HashMap::default();
+        if req.get_txn_infos().is_empty() {
+            txn_status.insert(req.get_start_version(), req.get_commit_version());
+        } else {
+            for info in req.get_txn_infos() {
+                txn_status.insert(info.get_txn(), info.get_status());
+            }
+        }
 
         let (cb, future) = make_callback();
-        let res = self.storage
-            .async_resolve_lock(req.take_context(), req.get_start_version(), commit_ts, cb);
+        let res = self.storage.async_resolve_lock(req.take_context(), txn_status, cb);
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -482,6 +703,149 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
+    fn mvcc_get_by_key(&self,
+                       ctx: RpcContext,
+                       mut req: MvccGetByKeyRequest,
+                       sink: UnarySink<MvccGetByKeyResponse>) {
+        let label = "mvcc_get_by_key";
+        let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_mvcc_by_key(req.take_context(),
+                                                 Key::from_raw(req.get_key()),
+                                                 max_ts(req.get_version()),
+                                                 cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future.map_err(Error::from)
+            .map(|v| {
+                let mut resp = MvccGetByKeyResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    match v {
+                        Ok(mvcc) => resp.set_info(extract_mvcc_info(mvcc)),
+                        Err(e) => resp.set_error(format!("{}", e)),
+                    }
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn mvcc_scan(&self,
+                 ctx: RpcContext,
+                 mut req: MvccScanRequest,
+                 sink: UnarySink<MvccScanResponse>) {
+        let label = "mvcc_scan";
+        let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
+
+        let end_key = if req.get_end_key().is_empty() {
+            None
+        } else {
+            Some(Key::from_raw(req.get_end_key()))
+        };
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_mvcc_scan(req.take_context(),
+                                               Key::from_raw(req.get_start_key()),
+                                               end_key,
+                                               req.get_limit() as usize,
+                                               max_ts(req.get_version()),
+                                               cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future.map_err(Error::from)
+            .map(|v| {
+                let mut resp = MvccScanResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    match v {
+                        Ok(pairs) => {
+                            let infos = pairs.into_iter()
+                                .map(|(key, mvcc)| {
+                                    let mut key_info = MvccKeyInfo::new();
+                                    key_info.set_key(key);
+                                    key_info.set_info(extract_mvcc_info(mvcc));
+                                    key_info
+                                })
+                                .collect();
+                            resp.set_infos(RepeatedField::from_vec(infos));
+                        }
+                        Err(e) => resp.set_error(format!("{}", e)),
+                    }
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn unsafe_destroy_range(&self,
+                            ctx: RpcContext,
+                            mut req: UnsafeDestroyRangeRequest,
+                            sink: UnarySink<UnsafeDestroyRangeResponse>) {
+        let label = "unsafe_destroy_range";
+        let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
+
+        let (cb, future) = make_callback();
+        // An empty end key is kept empty so that storage rejects the unbounded range.
+        let end_key = if req.get_end_key().is_empty() {
+            vec![]
+        } else {
+            Key::from_raw(req.get_end_key()).encoded().to_owned()
+        };
+        let res = self.storage.async_unsafe_destroy_range(req.take_context(),
+                                                          Key::from_raw(req.get_start_key())
+                                                              .encoded()
+                                                              .to_owned(),
+                                                          end_key,
+                                                          cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future.map_err(Error::from)
+            .map(|v| {
+                let mut resp = UnsafeDestroyRangeResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else if let Err(e) = v {
+                    resp.set_error(format!("{}", e));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
     fn raw_get(&self, ctx: RpcContext, mut req: RawGetRequest, sink: UnarySink<RawGetResponse>) {
         let label = "raw_get";
         let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
@@ -522,8 +886,11 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
 
         let (cb, future) = make_callback();
-        let res = self.storage
-            .async_raw_put(req.take_context(), req.take_key(), req.take_value(), cb);
+        let res = self.storage.async_raw_put(req.take_context(),
+                                             req.take_key(),
+                                             req.take_value(),
+                                             req.get_ttl(),
+                                             cb);
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -583,6 +950,248 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
+    fn raw_scan(&self, ctx: RpcContext, mut req: RawScanRequest, sink: UnarySink<RawScanResponse>) {
+        let label = "raw_scan";
+        let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_raw_scan(req.take_context(),
+                                              req.take_start_key(),
+                                              req.get_limit() as usize,
+                                              req.get_key_only(),
+                                              req.get_reverse(),
+                                              cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future.map_err(Error::from)
+            .map(|v| {
+                let mut resp = RawScanResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    resp.set_kvs(RepeatedField::from_vec(extract_kv_pairs(v)));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn raw_batch_get(&self,
+                     ctx: RpcContext,
+                     mut req: RawBatchGetRequest,
+                     sink: UnarySink<RawBatchGetResponse>) {
+        let label = "raw_batch_get";
+        let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
+
+        let keys = req.take_keys().into_vec();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_raw_batch_get(req.take_context(), keys, cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future.map_err(Error::from)
+            .map(|v| {
+                let mut resp = RawBatchGetResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    resp.set_pairs(RepeatedField::from_vec(extract_kv_pairs(v)));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn raw_batch_put(&self,
+                     ctx: RpcContext,
+                     mut req: RawBatchPutRequest,
+                     sink: UnarySink<RawBatchPutResponse>) {
+        let label = "raw_batch_put";
+        let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
+
+        let pairs = req.take_pairs()
+            .into_iter()
+            .map(|mut x| (x.take_key(), x.take_value()))
+            .collect();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_raw_batch_put(req.take_context(), pairs, req.get_ttl(), cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future.map_err(Error::from)
+            .map(|v| {
+                let mut resp = RawBatchPutResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else if let Err(e) = v {
+                    resp.set_error(format!("{}", e));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn raw_batch_delete(&self,
+                        ctx: RpcContext,
+                        mut req: RawBatchDeleteRequest,
+                        sink: UnarySink<RawBatchDeleteResponse>) {
+        let label = "raw_batch_delete";
+        let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
+
+        let keys = req.take_keys().into_vec();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_raw_batch_delete(req.take_context(), keys, cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future.map_err(Error::from)
+            .map(|v| {
+                let mut resp = RawBatchDeleteResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else if let Err(e) = v {
+                    resp.set_error(format!("{}", e));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn raw_delete_range(&self,
+                        ctx: RpcContext,
+                        mut req: RawDeleteRangeRequest,
+                        sink: UnarySink<RawDeleteRangeResponse>) {
+        let label = "raw_delete_range";
+        let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_raw_delete_range(req.take_context(),
+                                                      req.take_start_key(),
+                                                      req.take_end_key(),
+                                                      cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future.map_err(Error::from)
+            .map(|v| {
+                let mut resp = RawDeleteRangeResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else if let Err(e) = v {
+                    resp.set_error(format!("{}", e));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn raw_compare_and_swap(&self,
+                            ctx: RpcContext,
+                            mut req: RawCASRequest,
+                            sink: UnarySink<RawCASResponse>) {
+        let label = "raw_compare_and_swap";
+        let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
+
+        let previous_value = if req.get_previous_not_exist() {
+            None
+        } else {
+            Some(req.take_previous_value())
+        };
+        let value = if req.get_delete() {
+            None
+        } else {
+            Some(req.take_value())
+        };
+        let (cb, future) = make_callback();
+        let res = self.storage.async_raw_compare_and_swap(req.take_context(),
+                                                          req.take_key(),
+                                                          previous_value,
+                                                          value,
+                                                          req.get_ttl(),
+                                                          cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future.map_err(Error::from)
+            .map(|v| {
+                let mut resp = RawCASResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    match v {
+                        Ok((previous_value, succeed)) => {
+                            resp.set_succeed(succeed);
+                            match previous_value {
+                                Some(v) => resp.set_previous_value(v),
+                                None => resp.set_previous_not_exist(true),
+                            }
+                        }
+                        Err(e) => resp.set_error(format!("{}", e)),
+                    }
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
     fn coprocessor(&self, ctx: RpcContext, req: Request, sink: UnarySink<Response>) {
         let label = "coprocessor";
         let timer = GRPC_MSG_HISTOGRAM_VEC.with_label_values(&[label]).start_timer();
@@ -661,6 +1270,36 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
             .and_then(|_| sink.success(Done::new()).map_err(Error::from))
             .then(|_| future::ok::<_, ()>(())));
     }
+
+    fn event_feed(&self,
+                  ctx: RpcContext,
+                  mut req: ChangeDataRequest,
+                  sink: ServerStreamingSink<ChangeDataEvent>) {
+        let label = "event_feed";
+        let (tx, rx) = mpsc::unbounded();
+        let task = CdcTask::Register {
+            ctx: req.take_context(),
+            checkpoint_ts: req.get_checkpoint_ts(),
+            sink: tx,
+        };
+        if let Err(e) = self.cdc_scheduler.schedule(task) {
+            let status = RpcStatus::new(RpcStatusCode::ResourceExhausted,
+                                        Some(format!("{}", Error::from(e))));
+            ctx.spawn(sink.fail(status).map_err(|_| ()));
+            return;
+        }
+
+        // The stream ends when the subscription is closed by the cdc worker, and the
+        // subscription is dropped by the worker once the client goes away.
+        let events = rx.map(|e| (cdc::encode_event(e), WriteFlags::default()))
+            .map_err(|_| GrpcError::RemoteStopped);
+        ctx.spawn(sink.send_all(events)
+            .map(|_| ())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            }));
+    }
 }
 
 fn extract_region_error<T>(res: &storage::Result<T>) -> Option<RegionError> {
@@ -704,8 +1343,21 @@ fn extract_key_error(err: &storage::Error) -> KeyError {
             lock_info.set_lock_ttl(ttl);
             key_error.set_locked(lock_info);
         }
+        storage::Error::Txn(TxnError::Mvcc(MvccError::AlreadyExist { ref key })) => {
+            let mut already_exist = AlreadyExist::new();
+            already_exist.set_key(key.to_owned());
+            key_error.set_already_exist(already_exist);
+        }
+        storage::Error::Txn(TxnError::Deadlock { lock_ts, ref lock_key, .. }) => {
+            warn!("txn deadlocks: {}", err);
+            let mut deadlock = Deadlock::new();
+            deadlock.set_lock_ts(lock_ts);
+            deadlock.set_lock_key(lock_key.to_owned());
+            key_error.set_deadlock(deadlock);
+        }
         storage::Error::Txn(TxnError::Mvcc(MvccError::WriteConflict)) |
-        storage::Error::Txn(TxnError::Mvcc(MvccError::TxnLockNotFound)) => {
+        storage::Error::Txn(TxnError::Mvcc(MvccError::TxnLockNotFound)) |
+        storage::Error::Txn(TxnError::CommitTsExpired { .. }) => {
             debug!("txn conflicts: {}", err);
             key_error.set_retryable(format!("{:?}", err));
         }
@@ -757,3 +1409,59 @@ fn extract_key_errors(res: storage::Result<Vec<storage::Result<()>>>) -> Vec<Key
         Err(e) => vec![extract_key_error(&e)],
     }
 }
+
+// Version 0 means to inspect all the versions.
+fn max_ts(version: u64) -> u64 {
+    if version == 0 {
+        u64::MAX
+    } else {
+        version
+    }
+}
+
+fn extract_mvcc_info(mvcc: storage::MvccInfo) -> MvccInfo {
+    let mut mvcc_info = MvccInfo::new();
+    if let Some(lock) = mvcc.lock {
+        let mut lock_info = MvccLock::new();
+        let op = match lock.lock_type {
+            LockType::Put => Op::Put,
+            LockType::Delete => Op::Del,
+            LockType::Lock => Op::Lock,
+            LockType::Pessimistic => Op::PessimisticLock,
+        };
+        lock_info.set_field_type(op);
+        lock_info.set_start_ts(lock.ts);
+        lock_info.set_primary(lock.primary);
+        lock_info.set_short_value(lock.short_value.unwrap_or_default());
+        mvcc_info.set_lock(lock_info);
+    }
+    let writes = mvcc.writes
+        .into_iter()
+        .map(|(commit_ts, write)| {
+            let mut write_info = MvccWrite::new();
+            let op = match write.write_type {
+                WriteType::Put => Op::Put,
+                WriteType::Delete => Op::Del,
+                WriteType::Lock => Op::Lock,
+                WriteType::Rollback => Op::Rollback,
+            };
+            write_info.set_field_type(op);
+            write_info.set_start_ts(write.start_ts);
+            write_info.set_commit_ts(commit_ts);
+            write_info.set_short_value(write.short_value.unwrap_or_default());
+            write_info
+        })
+        .collect();
+    mvcc_info.set_writes(RepeatedField::from_vec(writes));
+    let values = mvcc.values
+        .into_iter()
+        .map(|(start_ts, value)| {
+            let mut value_info = MvccValue::new();
+            value_info.set_start_ts(start_ts);
+            value_info.set_value(value);
+            value_info
+        })
+        .collect();
+    mvcc_info.set_values(RepeatedField::from_vec(values));
+    mvcc_info
+}
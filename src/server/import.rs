@@ -0,0 +1,125 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use futures::{future, stream, Future, Stream};
+use grpc::{ChannelBuilder, ClientStreamingSink, Environment, RequestStream, RpcContext,
+           RpcStatus, RpcStatusCode, UnarySink, WriteFlags, Error as GrpcError};
+use kvproto::import_sstpb::{SSTMeta, UploadRequest, UploadResponse, IngestRequest,
+                            IngestResponse};
+use kvproto::import_sstpb_grpc::{ImportSst, ImportSstClient};
+
+use import::{self, SSTImporter, SSTSender};
+use pd::PdClient;
+use super::Error;
+
+const UPLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Saves the SST files uploaded by `ImportClient` of the other stores.
+#[derive(Clone)]
+pub struct ImportSSTService {
+    importer: Arc<SSTImporter>,
+}
+
+impl ImportSSTService {
+    pub fn new(importer: Arc<SSTImporter>) -> ImportSSTService {
+        ImportSSTService { importer: importer }
+    }
+}
+
+impl ImportSst for ImportSSTService {
+    fn upload(&self,
+              ctx: RpcContext,
+              stream: RequestStream<UploadRequest>,
+              sink: ClientStreamingSink<UploadResponse>) {
+        let importer = self.importer.clone();
+        ctx.spawn(stream.map_err(Error::from)
+            .fold((None, vec![]), |(meta, mut data), mut chunk| {
+                let res: Result<_, Error> = if chunk.has_meta() {
+                    match meta {
+                        None => Ok((Some(chunk.take_meta()), data)),
+                        Some(_) => Err(box_err!("duplicated sst meta")),
+                    }
+                } else {
+                    data.extend_from_slice(chunk.get_data());
+                    Ok((meta, data))
+                };
+                future::result(res)
+            })
+            .and_then(move |(meta, data): (Option<SSTMeta>, Vec<u8>)| {
+                match meta {
+                    Some(meta) => importer.save(&meta, &data).map_err(|e| box_err!(e)),
+                    None => Err(box_err!("missing sst meta")),
+                }
+            })
+            .then(|res| match res {
+                Ok(()) => sink.success(UploadResponse::new()),
+                Err(e) => {
+                    error!("receive sst err: {:?}", e);
+                    let status = RpcStatus::new(RpcStatusCode::InvalidArgument,
+                                                Some(format!("{}", e)));
+                    sink.fail(status)
+                }
+            })
+            .map_err(|e| error!("reply upload err: {:?}", e)));
+    }
+
+    fn ingest(&self, ctx: RpcContext, _: IngestRequest, sink: UnarySink<IngestResponse>) {
+        // The uploaded files are ingested by `kv_import`, which proposes them to the region.
+        let status = RpcStatus::new(RpcStatusCode::Unimplemented, None);
+        ctx.spawn(sink.fail(status).map_err(|_| ()));
+    }
+}
+
+/// Uploads SST files to the `ImportSSTService` of the other stores.
+pub struct ImportClient<C: PdClient> {
+    env: Arc<Environment>,
+    pd_client: Arc<C>,
+}
+
+impl<C: PdClient> ImportClient<C> {
+    pub fn new(env: Arc<Environment>, pd_client: Arc<C>) -> ImportClient<C> {
+        ImportClient {
+            env: env,
+            pd_client: pd_client,
+        }
+    }
+}
+
+impl<C: PdClient> SSTSender for ImportClient<C> {
+    fn send(&self, store_id: u64, meta: &SSTMeta, data: &[u8]) -> import::Result<()> {
+        let store = box_try!(self.pd_client.get_store(store_id));
+
+        let mut chunks: Vec<Result<_, GrpcError>> =
+            Vec::with_capacity(data.len() / UPLOAD_CHUNK_SIZE + 2);
+        let mut chunk = UploadRequest::new();
+        chunk.set_meta(meta.clone());
+        chunks.push(Ok((chunk, WriteFlags::default())));
+        for buf in data.chunks(UPLOAD_CHUNK_SIZE) {
+            let mut chunk = UploadRequest::new();
+            chunk.set_data(buf.to_vec());
+            chunks.push(Ok((chunk, WriteFlags::default())));
+        }
+
+        let channel = ChannelBuilder::new(self.env.clone()).connect(store.get_address());
+        let client = ImportSstClient::new(channel);
+        let (sink, receiver) = client.upload();
+        stream::iter(chunks.into_iter())
+            .forward(sink)
+            .and_then(|_| receiver)
+            .wait()
+            .map(|_| ())
+            .map_err(|e| box_err!("failed to upload sst to store {}: {:?}", store_id, e))
+    }
+}
@@ -24,6 +24,7 @@ pub mod transport;
 pub mod node;
 pub mod resolve;
 pub mod snap;
+pub mod import;
 
 pub use self::config::{Config, DEFAULT_LISTENING_ADDR, DEFAULT_CLUSTER_ID};
 pub use self::errors::{Result, Error};
@@ -32,5 +33,6 @@ pub use self::transport::{ServerTransport, ServerRaftStoreRouter};
 pub use self::node::{Node, create_raft_storage};
 pub use self::resolve::{StoreAddrResolver, PdStoreAddrResolver};
 pub use self::raft_client::RaftClient;
+pub use self::import::{ImportSSTService, ImportClient};
 
 pub type OnResponse = Box<FnBox(Response) + Send>;
@@ -25,20 +25,27 @@ use kvproto::raft_serverpb::StoreIdent;
 use kvproto::metapb;
 use protobuf::RepeatedField;
 use util::transport::SendCh;
+use raftstore::coprocessor::{CoprocessorHost, RegionObserver};
 use raftstore::store::{self, Msg, SnapshotStatusMsg, StoreChannel, Store, Config as StoreConfig,
-                       keys, Peekable, Transport, SnapManager};
+                       keys, Iterable, Peekable, Transport, SnapManager};
 use super::Result;
 use super::config::Config;
-use storage::{Storage, RaftKv};
+use storage::{Storage, RaftKv, MaxReadTs, CF_DEFAULT};
+use import::{SSTImporter, SSTSender};
 use super::transport::RaftStoreRouter;
 
 const MAX_CHECK_CLUSTER_BOOTSTRAPPED_RETRY_COUNT: u64 = 60;
 const CHECK_CLUSTER_BOOTSTRAPPED_RETRY_SECONDS: u64 = 3;
 
-pub fn create_raft_storage<S>(router: S, db: Arc<DB>, cfg: &Config) -> Result<Storage>
+pub fn create_raft_storage<S>(router: S,
+                              db: Arc<DB>,
+                              importer: Arc<SSTImporter>,
+                              sst_sender: Arc<SSTSender>,
+                              cfg: &Config)
+                              -> Result<Storage>
     where S: RaftStoreRouter + 'static
 {
-    let engine = box RaftKv::new(db, router);
+    let engine = box RaftKv::new(db, router, importer, sst_sender);
     let store = try!(Storage::from_engine(engine, &cfg.storage));
     Ok(store)
 }
@@ -65,8 +72,11 @@ pub struct Node<C: PdClient + 'static> {
     cluster_id: u64,
     store: metapb::Store,
     store_cfg: StoreConfig,
+    enable_ttl: bool,
     store_handle: Option<thread::JoinHandle<()>>,
     ch: SendCh<Msg>,
+    // Moved to the store when it's started.
+    coprocessor_host: Option<CoprocessorHost>,
 
     pd_client: Arc<C>,
 }
@@ -102,9 +112,20 @@ impl<C> Node<C>
             cluster_id: cfg.cluster_id,
             store: store,
             store_cfg: cfg.raft_store.clone(),
+            enable_ttl: cfg.storage.enable_ttl,
             store_handle: None,
             pd_client: pd_client,
             ch: ch,
+            coprocessor_host: Some(CoprocessorHost::new()),
+        }
+    }
+
+    /// Registers an observer to the coprocessor host of the store, must be called before the
+    /// node is started.
+    pub fn register_observer(&mut self, priority: u32, ro: Box<RegionObserver + Send + Sync>) {
+        match self.coprocessor_host {
+            Some(ref mut host) => host.registry.register_observer(priority, ro),
+            None => panic!("register observer after store {} is started", self.store.get_id()),
         }
     }
 
@@ -113,7 +134,9 @@ impl<C> Node<C>
                     engine: Arc<DB>,
                     trans: T,
                     snap_mgr: SnapManager,
-                    snap_status_receiver: Receiver<SnapshotStatusMsg>)
+                    snap_status_receiver: Receiver<SnapshotStatusMsg>,
+                    importer: Arc<SSTImporter>,
+                    max_read_ts: MaxReadTs)
                     -> Result<()>
         where T: Transport + 'static
     {
@@ -131,6 +154,7 @@ impl<C> Node<C>
         }
 
         self.store.set_id(store_id);
+        try!(self.check_enable_ttl(&engine));
         try!(self.check_prepare_bootstrap_cluster(&engine));
         if !bootstrapped {
             // cluster is not bootstrapped, and we choose first store to bootstrap
@@ -147,7 +171,9 @@ impl<C> Node<C>
                               engine,
                               trans,
                               snap_mgr,
-                              snap_status_receiver));
+                              snap_status_receiver,
+                              importer,
+                              max_read_ts));
         Ok(())
     }
 
@@ -184,6 +210,43 @@ impl<C> Node<C>
         Ok(store_id)
     }
 
+    // Raw values are kept in CF_RAW_TTL with TTL enabled, or CF_DEFAULT without it, so the
+    // flag can't be changed once the store has data, or the written data would be hidden.
+    fn check_enable_ttl(&self, engine: &DB) -> Result<()> {
+        let key = keys::enable_ttl_key();
+        if let Some(v) = try!(engine.get_value(&key)) {
+            let enabled = !v.is_empty() && v[0] != 0;
+            if enabled != self.enable_ttl {
+                return Err(box_err!("storage.enable-ttl is {}, but the data of store {} is \
+                                     written with {}, it can't be changed",
+                                    self.enable_ttl,
+                                    self.store.get_id(),
+                                    enabled));
+            }
+            return Ok(());
+        }
+
+        // The store is new, or written before the flag is saved, without TTL.
+        if self.enable_ttl {
+            let mut empty = true;
+            try!(engine.scan_cf(CF_DEFAULT,
+                                keys::DATA_MIN_KEY,
+                                keys::DATA_MAX_KEY,
+                                false,
+                                &mut |_, _| {
+                                    empty = false;
+                                    Ok(false)
+                                }));
+            if !empty {
+                return Err(box_err!("store {} has data written without ttl, \
+                                     storage.enable-ttl can't be turned on",
+                                    self.store.get_id()));
+            }
+        }
+        box_try!(engine.put(&key, &[self.enable_ttl as u8]));
+        Ok(())
+    }
+
     fn alloc_id(&self) -> Result<u64> {
         let id = try!(self.pd_client.alloc_id());
         Ok(id)
@@ -272,13 +335,16 @@ impl<C> Node<C>
         Err(box_err!("check cluster bootstrapped failed"))
     }
 
+    #[allow(too_many_arguments)]
     fn start_store<T>(&mut self,
                       mut event_loop: EventLoop<Store<T, C>>,
                       store_id: u64,
                       db: Arc<DB>,
                       trans: T,
                       snap_mgr: SnapManager,
-                      snapshot_status_receiver: Receiver<SnapshotStatusMsg>)
+                      snapshot_status_receiver: Receiver<SnapshotStatusMsg>,
+                      importer: Arc<SSTImporter>,
+                      max_read_ts: MaxReadTs)
                       -> Result<()>
         where T: Transport + 'static
     {
@@ -292,6 +358,7 @@ impl<C> Node<C>
         let pd_client = self.pd_client.clone();
         let store = self.store.clone();
         let sender = event_loop.channel();
+        let coprocessor_host = self.coprocessor_host.take().unwrap_or_default();
 
         let (tx, rx) = mpsc::channel();
         let builder = thread::Builder::new().name(thd_name!(format!("raftstore-{}", store_id)));
@@ -300,7 +367,16 @@ impl<C> Node<C>
                 sender: sender,
                 snapshot_status_receiver: snapshot_status_receiver,
             };
-            let mut store = match Store::new(ch, store, cfg, db, trans, pd_client, snap_mgr) {
+            let mut store = match Store::new(ch,
+                                             store,
+                                             cfg,
+                                             db,
+                                             trans,
+                                             pd_client,
+                                             snap_mgr,
+                                             coprocessor_host,
+                                             importer,
+                                             max_read_ts) {
                 Err(e) => panic!("construct store {} err {:?}", store_id, e),
                 Ok(s) => s,
             };
@@ -12,28 +12,37 @@
 // limitations under the License.
 
 use std::sync::{Arc, RwLock};
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{self, Sender};
 use std::boxed::Box;
 use std::net::{SocketAddr, IpAddr};
 use std::str::FromStr;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 use grpc::{Server as GrpcServer, ServerBuilder, Environment, ChannelBuilder};
 use kvproto::tikvpb_grpc::*;
+use kvproto::import_sstpb_grpc::create_import_sst;
 use util::worker::Worker;
+use pd::PdClient;
 use storage::Storage;
+use import::SSTImporter;
 use raftstore::store::{SnapshotStatusMsg, SnapManager};
 
 use super::{Result, Config};
-use coprocessor::{EndPointHost, EndPointTask};
+use coprocessor::{EndPointHost, EndPointTask, READ_FLOW_REPORT_INTERVAL_MS};
+use cdc::{self, CdcObserver, ObservedRegions, Runner as CdcRunner, Task as CdcTask};
 use super::grpc_service::Service;
 use super::transport::{RaftStoreRouter, ServerTransport};
 use super::resolve::StoreAddrResolver;
 use super::snap::{Task as SnapTask, Runner as SnapHandler};
 use super::raft_client::RaftClient;
+use super::import::ImportSSTService;
 
 const DEFAULT_COPROCESSOR_BATCH: usize = 50;
 const MAX_GRPC_RECV_MSG_LEN: usize = 10 * 1024 * 1024;
 const MAX_GRPC_SEND_MSG_LEN: usize = 128 * 1024 * 1024;
+// How often the resolved ts of the regions subscribed by cdc is advanced.
+const CDC_MIN_TS_INTERVAL_SECS: u64 = 1;
 
 pub struct Server<T: RaftStoreRouter + 'static, S: StoreAddrResolver + 'static> {
     env: Arc<Environment>,
@@ -50,6 +59,10 @@ pub struct Server<T: RaftStoreRouter + 'static, S: StoreAddrResolver + 'static>
     // For sending/receiving snapshots.
     snap_mgr: SnapManager,
     snap_worker: Worker<SnapTask>,
+    // For streaming the changes of regions.
+    cdc_worker: Worker<CdcTask>,
+    cdc_observed: ObservedRegions,
+    cdc_ticker: Option<(JoinHandle<()>, Sender<()>)>,
 }
 
 impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
@@ -58,17 +71,20 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
                raft_router: T,
                snapshot_status_sender: Sender<SnapshotStatusMsg>,
                resolver: S,
-               snap_mgr: SnapManager)
+               snap_mgr: SnapManager,
+               importer: Arc<SSTImporter>)
                -> Result<Server<T, S>> {
         let env = Arc::new(Environment::new(cfg.grpc_concurrency));
         let raft_client = Arc::new(RwLock::new(RaftClient::new(env.clone(), cfg.clone())));
         let end_point_worker = Worker::new("end-point-worker");
         let snap_worker = Worker::new("snap-handler");
+        let cdc_worker = Worker::new("cdc-worker");
 
         let h = Service::new(storage.clone(),
                              end_point_worker.scheduler(),
                              raft_router.clone(),
-                             snap_worker.scheduler());
+                             snap_worker.scheduler(),
+                             cdc_worker.scheduler());
         let addr = try!(SocketAddr::from_str(&cfg.addr));
         let ip = format!("{}", addr.ip());
         let channel_args = ChannelBuilder::new(env.clone())
@@ -79,6 +95,7 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
             .build_args();
         let grpc_server = try!(ServerBuilder::new(env.clone())
             .register_service(create_tikv(h))
+            .register_service(create_import_sst(ImportSSTService::new(importer)))
             .bind(ip, addr.port())
             .channel_args(channel_args)
             .build());
@@ -104,6 +121,9 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
             end_point_worker: end_point_worker,
             snap_mgr: snap_mgr,
             snap_worker: snap_worker,
+            cdc_worker: cdc_worker,
+            cdc_observed: ObservedRegions::default(),
+            cdc_ticker: None,
         };
 
         Ok(svr)
@@ -113,17 +133,41 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
         self.trans.clone()
     }
 
-    pub fn start(&mut self, cfg: &Config) -> Result<()> {
+    /// Returns the observer that feeds the applied commands to the cdc worker, it should be
+    /// registered to the node before the node is started.
+    pub fn cdc_observer(&self) -> CdcObserver {
+        CdcObserver::new(self.cdc_worker.scheduler(), self.cdc_observed.clone())
+    }
+
+    pub fn start<C: PdClient + 'static>(&mut self, cfg: &Config, pd_client: Arc<C>) -> Result<()> {
         let end_point = EndPointHost::new(self.storage.get_engine(),
+                                          self.storage.get_max_read_ts(),
                                           self.end_point_worker.scheduler(),
                                           cfg.end_point_concurrency,
                                           cfg.end_point_txn_concurrency_on_busy,
                                           cfg.end_point_small_txn_tasks_limit);
-        box_try!(self.end_point_worker.start_batch(end_point, DEFAULT_COPROCESSOR_BATCH));
+        let tick = Duration::from_millis(READ_FLOW_REPORT_INTERVAL_MS);
+        box_try!(self.end_point_worker
+            .start_batch_with_tick(end_point, DEFAULT_COPROCESSOR_BATCH, Some(tick)));
         let snap_runner = SnapHandler::new(self.env.clone(),
                                            self.snap_mgr.clone(),
                                            self.raft_router.clone());
         box_try!(self.snap_worker.start(snap_runner));
+        let cdc_runner = CdcRunner::new(self.storage.get_engine(),
+                                        pd_client.clone(),
+                                        self.cdc_observed.clone());
+        box_try!(self.cdc_worker.start(cdc_runner));
+        let (tx, rx) = mpsc::channel();
+        let scheduler = self.cdc_worker.scheduler();
+        let h = try!(thread::Builder::new()
+            .name(thd_name!("cdc-min-ts"))
+            .spawn(move || {
+                cdc::run_min_ts_ticker(pd_client,
+                                       scheduler,
+                                       Duration::from_secs(CDC_MIN_TS_INTERVAL_SECS),
+                                       rx)
+            }));
+        self.cdc_ticker = Some((h, tx));
         self.grpc_server.start();
         info!("TiKV is ready to serve");
         Ok(())
@@ -132,6 +176,13 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
     pub fn stop(&mut self) -> Result<()> {
         self.end_point_worker.stop();
         self.snap_worker.stop();
+        if let Some((h, tx)) = self.cdc_ticker.take() {
+            let _ = tx.send(());
+            if h.join().is_err() {
+                error!("failed to join cdc min ts ticker");
+            }
+        }
+        self.cdc_worker.stop();
         if let Err(e) = self.storage.stop() {
             error!("failed to stop store: {:?}", e);
         }
@@ -155,11 +206,17 @@ mod tests {
     use std::net::SocketAddr;
     use std::sync::atomic::{AtomicUsize, Ordering};
 
+    use futures::Future;
+    use futures::future::ok;
+    use kvproto::{metapb, pdpb};
+
     use super::*;
     use super::super::{Result, Config};
+    use pd::{PdFuture, RegionStat, Result as PdResult};
     use super::super::transport::RaftStoreRouter;
     use super::super::resolve::{StoreAddrResolver, Callback as ResolveCallback};
     use storage::Storage;
+    use tempdir::TempDir;
     use kvproto::raft_serverpb::RaftMessage;
     use raftstore::Result as RaftStoreResult;
     use raftstore::store::Msg as StoreMsg;
@@ -209,6 +266,65 @@ mod tests {
         }
     }
 
+    struct MockPdClient;
+
+    impl PdClient for MockPdClient {
+        fn get_cluster_id(&self) -> PdResult<u64> {
+            unimplemented!();
+        }
+        fn bootstrap_cluster(&self, _: metapb::Store, _: metapb::Region) -> PdResult<()> {
+            unimplemented!();
+        }
+        fn is_cluster_bootstrapped(&self) -> PdResult<bool> {
+            unimplemented!();
+        }
+        fn alloc_id(&self) -> PdResult<u64> {
+            unimplemented!();
+        }
+        fn put_store(&self, _: metapb::Store) -> PdResult<()> {
+            unimplemented!();
+        }
+        fn get_store(&self, _: u64) -> PdResult<metapb::Store> {
+            unimplemented!();
+        }
+        fn get_cluster_config(&self) -> PdResult<metapb::Cluster> {
+            unimplemented!();
+        }
+        fn get_region(&self, _: &[u8]) -> PdResult<metapb::Region> {
+            unimplemented!();
+        }
+        fn get_region_by_id(&self, _: u64) -> PdFuture<Option<metapb::Region>> {
+            unimplemented!();
+        }
+        fn region_heartbeat(&self,
+                            _: metapb::Region,
+                            _: metapb::Peer,
+                            _: RegionStat)
+                            -> PdFuture<()> {
+            unimplemented!();
+        }
+        fn handle_region_heartbeat_response<F>(&self, _: u64, _: F) -> PdFuture<()>
+            where F: Fn(pdpb::RegionHeartbeatResponse) + Send + 'static
+        {
+            unimplemented!()
+        }
+        fn ask_split(&self, _: metapb::Region) -> PdFuture<pdpb::AskSplitResponse> {
+            unimplemented!();
+        }
+        fn store_heartbeat(&self, _: pdpb::StoreStats) -> PdFuture<()> {
+            unimplemented!();
+        }
+        fn report_split(&self, _: metapb::Region, _: metapb::Region) -> PdFuture<()> {
+            unimplemented!();
+        }
+        fn get_gc_safe_point(&self) -> PdFuture<u64> {
+            unimplemented!();
+        }
+        fn get_tso(&self) -> PdFuture<u64> {
+            ok(1).boxed()
+        }
+    }
+
     #[test]
     fn test_peer_resolve() {
         let mut cfg = Config::new();
@@ -222,6 +338,7 @@ mod tests {
         let report_unreachable_count = router.report_unreachable_count.clone();
         let (snapshot_status_sender, _) = mpsc::channel();
 
+        let import_dir = TempDir::new("test-peer-resolve").unwrap();
         let addr = Arc::new(Mutex::new(None));
         let mut server =
             Server::new(&cfg,
@@ -229,11 +346,12 @@ mod tests {
                         router,
                         snapshot_status_sender,
                         MockResolver { addr: addr.clone() },
-                        SnapManager::new("", None, cfg.raft_store.use_sst_file_snapshot))
+                        SnapManager::new("", None, cfg.raft_store.use_sst_file_snapshot),
+                        Arc::new(SSTImporter::new(import_dir.path()).unwrap()))
                 .unwrap();
         *addr.lock().unwrap() = Some(server.listening_addr());
 
-        server.start(&cfg).unwrap();
+        server.start(&cfg, Arc::new(MockPdClient)).unwrap();
 
         let mut trans = server.transport();
         for i in 0..10 {
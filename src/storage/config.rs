@@ -11,12 +11,27 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::time::Duration;
+
+use raftstore::store::EngineStallSignals;
+use super::Result;
+
 const DEFAULT_STORE_PATH: &'static str = "";
 const DEFAULT_SCHED_CAPACITY: usize = 10240;
 const DEFAULT_SCHED_MSG_PER_TICK: usize = 1024;
 const DEFAULT_SCHED_CONCURRENCY: usize = 102400;
 const DEFAULT_SCHED_WORKER_POOL_SIZE: usize = 4;
 const DEFAULT_SCHED_TOO_BUSY_THRESHOLD: usize = 1000;
+// Keep the limits below RocksDB's own slowdown and stop triggers, so that low priority writes are
+// throttled before RocksDB stalls all writes.
+const DEFAULT_SCHED_PENDING_COMPACTION_BYTES_SOFT_LIMIT: u64 = 32 * 1024 * 1024 * 1024;
+const DEFAULT_SCHED_PENDING_COMPACTION_BYTES_HARD_LIMIT: u64 = 128 * 1024 * 1024 * 1024;
+const DEFAULT_SCHED_L0_FILES_SOFT_LIMIT: u64 = 16;
+const DEFAULT_SCHED_L0_FILES_HARD_LIMIT: u64 = 28;
+const DEFAULT_SCHED_IMMUTABLE_MEM_TABLES_SOFT_LIMIT: u64 = 3;
+const DEFAULT_SCHED_IMMUTABLE_MEM_TABLES_HARD_LIMIT: u64 = 4;
+const DEFAULT_WAIT_FOR_LOCK_TIMEOUT_MS: u64 = 3000;
+const DEFAULT_GC_CHECK_INTERVAL_SECS: u64 = 10;
 
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -26,6 +41,19 @@ pub struct Config {
     pub sched_concurrency: usize,
     pub sched_worker_pool_size: usize,
     pub sched_too_busy_threshold: usize,
+    pub sched_pending_compaction_bytes_soft_limit: u64,
+    pub sched_pending_compaction_bytes_hard_limit: u64,
+    pub sched_l0_files_soft_limit: u64,
+    pub sched_l0_files_hard_limit: u64,
+    pub sched_immutable_mem_tables_soft_limit: u64,
+    pub sched_immutable_mem_tables_hard_limit: u64,
+    // Whether raw values carry an expiry time. It can't be changed once data is written.
+    pub enable_ttl: bool,
+    // How long a write blocked by a lock waits for the lock to be released, 0 disables waiting.
+    pub wait_for_lock_timeout: Duration,
+    // How often the GC safe point in PD is checked to collect garbage of the leader regions,
+    // 0 disables it.
+    pub gc_check_interval: Duration,
 }
 
 impl Default for Config {
@@ -37,6 +65,17 @@ impl Default for Config {
             sched_concurrency: DEFAULT_SCHED_CONCURRENCY,
             sched_worker_pool_size: DEFAULT_SCHED_WORKER_POOL_SIZE,
             sched_too_busy_threshold: DEFAULT_SCHED_TOO_BUSY_THRESHOLD,
+            sched_pending_compaction_bytes_soft_limit:
+                DEFAULT_SCHED_PENDING_COMPACTION_BYTES_SOFT_LIMIT,
+            sched_pending_compaction_bytes_hard_limit:
+                DEFAULT_SCHED_PENDING_COMPACTION_BYTES_HARD_LIMIT,
+            sched_l0_files_soft_limit: DEFAULT_SCHED_L0_FILES_SOFT_LIMIT,
+            sched_l0_files_hard_limit: DEFAULT_SCHED_L0_FILES_HARD_LIMIT,
+            sched_immutable_mem_tables_soft_limit: DEFAULT_SCHED_IMMUTABLE_MEM_TABLES_SOFT_LIMIT,
+            sched_immutable_mem_tables_hard_limit: DEFAULT_SCHED_IMMUTABLE_MEM_TABLES_HARD_LIMIT,
+            enable_ttl: false,
+            wait_for_lock_timeout: Duration::from_millis(DEFAULT_WAIT_FOR_LOCK_TIMEOUT_MS),
+            gc_check_interval: Duration::from_secs(DEFAULT_GC_CHECK_INTERVAL_SECS),
         }
     }
 }
@@ -45,4 +84,66 @@ impl Config {
     pub fn new() -> Config {
         Config::default()
     }
+
+    pub fn validate(&self) -> Result<()> {
+        let limits = [("pending-compaction-bytes",
+                       self.sched_pending_compaction_bytes_soft_limit,
+                       self.sched_pending_compaction_bytes_hard_limit),
+                      ("l0-files", self.sched_l0_files_soft_limit, self.sched_l0_files_hard_limit),
+                      ("immutable-mem-tables",
+                       self.sched_immutable_mem_tables_soft_limit,
+                       self.sched_immutable_mem_tables_hard_limit)];
+        for &(name, soft, hard) in &limits {
+            // A limit of 0 disables the signal.
+            if soft > 0 && hard > 0 && soft > hard {
+                return Err(box_err!("storage.scheduler-{}-soft-limit {} must <= hard limit {}",
+                                    name,
+                                    soft,
+                                    hard));
+            }
+        }
+        Ok(())
+    }
+
+    /// Write stall signals above which low priority writes are rejected.
+    pub fn stall_soft_limits(&self) -> EngineStallSignals {
+        EngineStallSignals {
+            pending_compaction_bytes: self.sched_pending_compaction_bytes_soft_limit,
+            l0_files: self.sched_l0_files_soft_limit,
+            immutable_mem_tables: self.sched_immutable_mem_tables_soft_limit,
+        }
+    }
+
+    /// Write stall signals above which all writes except high priority ones are rejected.
+    pub fn stall_hard_limits(&self) -> EngineStallSignals {
+        EngineStallSignals {
+            pending_compaction_bytes: self.sched_pending_compaction_bytes_hard_limit,
+            l0_files: self.sched_l0_files_hard_limit,
+            immutable_mem_tables: self.sched_immutable_mem_tables_hard_limit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_stall_limits() {
+        let mut cfg = Config::new();
+        assert!(cfg.validate().is_ok());
+
+        cfg.sched_l0_files_soft_limit = cfg.sched_l0_files_hard_limit + 1;
+        assert!(cfg.validate().is_err());
+        // A disabled hard limit doesn't bound the soft limit.
+        cfg.sched_l0_files_hard_limit = 0;
+        assert!(cfg.validate().is_ok());
+        cfg.sched_l0_files_soft_limit = 0;
+        cfg.sched_l0_files_hard_limit = DEFAULT_SCHED_L0_FILES_HARD_LIMIT;
+        assert!(cfg.validate().is_ok());
+
+        cfg.sched_pending_compaction_bytes_soft_limit =
+            cfg.sched_pending_compaction_bytes_hard_limit + 1;
+        assert!(cfg.validate().is_err());
+    }
 }
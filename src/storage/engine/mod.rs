@@ -21,12 +21,14 @@ use self::rocksdb::EngineRocksdb;
 use storage::{Key, Value, CfName, CF_DEFAULT};
 use kvproto::kvrpcpb::Context;
 use kvproto::errorpb::Error as ErrorHeader;
+use util::collections::HashMap;
 
 mod rocksdb;
 pub mod raftkv;
 mod metrics;
 use self::metrics::*;
 use super::super::raftstore::store::engine::IterOption;
+use super::super::raftstore::store::EngineStallSignals;
 
 // only used for rocksdb without persistent.
 pub const TEMP_DIR: &'static str = "";
@@ -50,12 +52,26 @@ impl CbContext {
 pub enum Modify {
     Delete(CfName, Key),
     Put(CfName, Key, Value),
+    // Destroys all the keys in `[start_key, end_key)` of every column family.
+    DeleteRange(Key, Key),
+    // Ingests the sorted pairs into the column family as an SST file, bypassing the memtable.
+    Ingest(CfName, Vec<(Key, Value)>),
 }
 
 pub trait Engine: Send + Debug {
     fn async_write(&self, ctx: &Context, batch: Vec<Modify>, callback: Callback<()>) -> Result<()>;
     fn async_snapshot(&self, ctx: &Context, callback: Callback<Box<Snapshot>>) -> Result<()>;
 
+    /// Takes a snapshot for reading at `read_ts`. If `ctx` allows replica read, a follower
+    /// can serve it once all the changes committed before `read_ts` have been applied.
+    fn async_snapshot_at(&self,
+                         ctx: &Context,
+                         _read_ts: u64,
+                         callback: Callback<Box<Snapshot>>)
+                         -> Result<()> {
+        self.async_snapshot(ctx, callback)
+    }
+
     fn write(&self, ctx: &Context, batch: Vec<Modify>) -> Result<()> {
         let timeout = Duration::from_secs(DEFAULT_TIMEOUT_SECS);
         match wait_op!(|cb| self.async_write(ctx, batch, cb).unwrap(), timeout) {
@@ -88,6 +104,16 @@ pub trait Engine: Send + Debug {
         self.write(ctx, vec![Modify::Delete(cf, key)])
     }
 
+    /// Returns the write stall signals of the underlying RocksDB, or `None` if the engine
+    /// doesn't expose them.
+    fn stall_signals(&self) -> Option<EngineStallSignals> {
+        None
+    }
+
+    /// Reports the flow read from each region since the last report. It's dropped if the
+    /// engine doesn't keep statistics of regions.
+    fn report_read_flow(&self, _read_flow: HashMap<u64, FlowStatistics>) {}
+
     /// Create a share Engine pointer.
     fn clone(&self) -> Box<Engine + 'static>;
 }
@@ -104,6 +130,11 @@ pub trait Snapshot: Send {
                    mode: ScanMode)
                    -> Result<Cursor<'a>>;
     fn clone(&self) -> Box<Snapshot>;
+
+    /// Checks whether the key belongs to the data of the snapshot.
+    fn validate_key(&self, _: &Key) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub trait Iterator {
@@ -143,6 +174,28 @@ pub enum ScanMode {
     Mixed,
 }
 
+/// FlowStatistics collects the keys and bytes read for users, which are reported to PD to
+/// find the regions hot in reading.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct FlowStatistics {
+    pub read_keys: usize,
+    pub read_bytes: usize,
+}
+
+impl FlowStatistics {
+    #[inline]
+    pub fn add_read(&mut self, key: &[u8], value: &[u8]) {
+        self.read_keys += 1;
+        self.read_bytes += key.len() + value.len();
+    }
+
+    #[inline]
+    pub fn add(&mut self, other: &FlowStatistics) {
+        self.read_keys += other.read_keys;
+        self.read_bytes += other.read_bytes;
+    }
+}
+
 /// Statistics collects the ops taken when fetching data.
 #[derive(Default)]
 pub struct Statistics {
@@ -154,6 +207,8 @@ pub struct Statistics {
     pub prev: usize,
     pub seek: usize,
     pub seek_for_prev: usize,
+    // The keys and values returned to user, increased by the caller too.
+    pub flow_stats: FlowStatistics,
 }
 
 impl Statistics {
@@ -459,6 +514,7 @@ mod tests {
         test_near_seek(e.as_ref());
         test_cf(e.as_ref());
         test_empty_write(e.as_ref());
+        test_delete_range(e.as_ref());
     }
 
     #[test]
@@ -766,4 +822,18 @@ mod tests {
     fn test_empty_write(engine: &Engine) {
         engine.write(&Context::new(), vec![]).unwrap();
     }
+
+    fn test_delete_range(engine: &Engine) {
+        must_put(engine, b"r1", b"v1");
+        must_put_cf(engine, "cf", b"r2", b"v2");
+        must_put(engine, b"r3", b"v3");
+        engine.write(&Context::new(),
+                   vec![Modify::Put(CF_DEFAULT, make_key(b"r0"), b"v0".to_vec()),
+                        Modify::DeleteRange(make_key(b"r1"), make_key(b"r3"))])
+            .unwrap();
+        assert_has(engine, b"r0", b"v0");
+        assert_none(engine, b"r1");
+        assert_none_cf(engine, "cf", b"r2");
+        assert_has(engine, b"r3", b"v3");
+    }
 }
@@ -16,26 +16,36 @@ use server::transport::RaftStoreRouter;
 use raftstore::errors::Error as RaftServerError;
 use raftstore::coprocessor::{RegionSnapshot, RegionIterator};
 use raftstore::store::engine::Peekable;
+use raftstore::store::{keys, util};
+use import::{self, SSTImporter, SSTSender};
 use storage;
 use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse, RaftRequestHeader, Request, Response,
-                          CmdType, DeleteRequest, PutRequest};
+                          CmdType, DeleteRequest, PutRequest, DeleteRangeRequest,
+                          IngestSSTRequest};
+use kvproto::import_sstpb::SSTMeta;
 use kvproto::errorpb;
 use kvproto::kvrpcpb::Context;
+use kvproto::metapb::Region;
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
 use std::fmt::{self, Formatter, Debug};
 use std::io::Error as IoError;
 use std::time::Duration;
 use std::result;
 use rocksdb::DB;
 use protobuf::RepeatedField;
+use rand::{self, Rng};
+use threadpool::ThreadPool;
 
 use storage::engine;
-use super::{CbContext, Engine, Modify, Cursor, Snapshot, ScanMode, Callback,
+use super::{CbContext, Engine, Modify, Cursor, Snapshot, ScanMode, Callback, FlowStatistics,
             Iterator as EngineIterator};
 use storage::{Key, Value, CfName, CF_DEFAULT};
 use super::metrics::*;
 use raftstore::store::engine::IterOption;
+use raftstore::store::{EngineStallSignals, get_engine_stall_signals, Msg as StoreMsg};
+use util::collections::HashMap;
 
 quick_error! {
     #[derive(Debug)]
@@ -93,6 +103,9 @@ fn get_tag_from_engine_error(e: &engine::Error) -> &'static str {
 
 pub type Result<T> = result::Result<T, Error>;
 
+const IMPORT_POOL_SIZE: usize = 2;
+const SST_UUID_LEN: usize = 16;
+
 impl From<Error> for engine::Error {
     fn from(e: Error) -> engine::Error {
         match e {
@@ -114,6 +127,10 @@ impl From<RaftServerError> for engine::Error {
 pub struct RaftKv<S: RaftStoreRouter + 'static> {
     db: Arc<DB>,
     router: S,
+    // For sending the SST files of ingest modifies to the replicas.
+    importer: Arc<SSTImporter>,
+    sst_sender: Arc<SSTSender>,
+    import_pool: Arc<Mutex<ThreadPool>>,
 }
 
 enum CmdRes {
@@ -147,10 +164,18 @@ fn on_result(mut resp: RaftCmdResponse,
 
 impl<S: RaftStoreRouter> RaftKv<S> {
     /// Create a RaftKv using specified configuration.
-    pub fn new(db: Arc<DB>, router: S) -> RaftKv<S> {
+    pub fn new(db: Arc<DB>,
+               router: S,
+               importer: Arc<SSTImporter>,
+               sst_sender: Arc<SSTSender>)
+               -> RaftKv<S> {
+        let pool = ThreadPool::new_with_name(thd_name!("raftkv-import"), IMPORT_POOL_SIZE);
         RaftKv {
             db: db,
             router: router,
+            importer: importer,
+            sst_sender: sst_sender,
+            import_pool: Arc::new(Mutex::new(pool)),
         }
     }
 
@@ -173,34 +198,89 @@ impl<S: RaftStoreRouter> RaftKv<S> {
         if ctx.get_term() != 0 {
             header.set_term(ctx.get_term());
         }
+        header.set_replica_read(ctx.get_replica_read());
         header
     }
 
     fn exec_requests(&self, ctx: &Context, reqs: Vec<Request>, cb: Callback<CmdRes>) -> Result<()> {
-        let header = self.new_request_header(ctx);
+        self.exec_requests_at(ctx, 0, reqs, cb)
+    }
+
+    fn exec_requests_at(&self,
+                        ctx: &Context,
+                        read_ts: u64,
+                        reqs: Vec<Request>,
+                        cb: Callback<CmdRes>)
+                        -> Result<()> {
+        let mut header = self.new_request_header(ctx);
+        header.set_read_ts(read_ts);
         let mut cmd = RaftCmdRequest::new();
         cmd.set_header(header);
         cmd.set_requests(RepeatedField::from_vec(reqs));
         self.call_command(cmd, cb)
     }
-}
 
-fn invalid_resp_type(exp: CmdType, act: CmdType) -> Error {
-    Error::InvalidResponse(format!("cmd type not match, want {:?}, got {:?}!", exp, act))
-}
+    fn get_region(&self, ctx: &Context) -> engine::Result<Region> {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Snap);
+        let (tx, rx) = mpsc::channel();
+        try!(self.exec_requests(ctx, vec![req], box move |(_, res)| tx.send(res).unwrap()));
+        match box_try!(rx.recv()) {
+            Ok(CmdRes::Snap(s)) => Ok(s.get_region().clone()),
+            Ok(CmdRes::Resp(r)) => {
+                Err(invalid_resp_type(CmdType::Snap, r[0].get_cmd_type()).into())
+            }
+            Err(e) => Err(e),
+        }
+    }
 
-impl<S: RaftStoreRouter> Debug for RaftKv<S> {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "RaftKv")
+    /// Builds the SST file of the ingest modify and sends it to all the stores of `region`.
+    fn send_sst(&self,
+                ctx: &Context,
+                region: &Region,
+                cf: CfName,
+                kvs: Vec<(Key, Value)>)
+                -> engine::Result<IngestSSTRequest> {
+        let (start, end) = match (kvs.first(), kvs.last()) {
+            (Some(first), Some(last)) => {
+                (first.0.encoded().to_owned(), last.0.encoded().to_owned())
+            }
+            _ => return Err(box_err!("no key to ingest")),
+        };
+        let kvs = kvs.into_iter().map(|(k, v)| (keys::data_key(k.encoded()), v));
+        let sst = box_try!(import::build_sst(kvs));
+
+        let mut meta = SSTMeta::new();
+        meta.set_uuid(rand::thread_rng().gen_iter().take(SST_UUID_LEN).collect());
+        meta.set_region_id(region.get_id());
+        meta.set_region_epoch(region.get_region_epoch().clone());
+        meta.set_cf_name(cf.to_owned());
+        meta.set_crc32(sst.crc32);
+        meta.set_length(sst.data.len() as u64);
+        meta.mut_range().set_start(start);
+        meta.mut_range().set_end(end);
+        for peer in region.get_peers() {
+            let store_id = peer.get_store_id();
+            if store_id == ctx.get_peer().get_store_id() {
+                box_try!(self.importer.save(&meta, &sst.data));
+            } else {
+                box_try!(self.sst_sender.send(store_id, &meta, &sst.data));
+            }
+        }
+
+        let mut ingest = IngestSSTRequest::new();
+        ingest.set_sst(meta);
+        Ok(ingest)
     }
-}
 
-impl<S: RaftStoreRouter> Engine for RaftKv<S> {
-    fn async_write(&self,
-                   ctx: &Context,
-                   mut modifies: Vec<Modify>,
-                   cb: Callback<()>)
-                   -> engine::Result<()> {
+    /// Converts `modifies` to raft requests. `region` is only needed by ingest modifies, whose
+    /// SST files are sent to the stores of the region, only the metadata of the files is put
+    /// in the requests.
+    fn gen_requests(&self,
+                    ctx: &Context,
+                    mut modifies: Vec<Modify>,
+                    region: Option<&Region>)
+                    -> engine::Result<Vec<Request>> {
         let mut reqs = Vec::with_capacity(modifies.len());
         while !modifies.is_empty() {
             let m = modifies.pop().unwrap();
@@ -225,10 +305,32 @@ impl<S: RaftStoreRouter> Engine for RaftKv<S> {
                     req.set_cmd_type(CmdType::Put);
                     req.set_put(put);
                 }
+                Modify::DeleteRange(start_key, end_key) => {
+                    let mut delete_range = DeleteRangeRequest::new();
+                    delete_range.set_start_key(start_key.encoded().to_owned());
+                    delete_range.set_end_key(end_key.encoded().to_owned());
+                    req.set_cmd_type(CmdType::DeleteRange);
+                    req.set_delete_range(delete_range);
+                }
+                Modify::Ingest(cf, kvs) => {
+                    let region = match region {
+                        Some(region) => region,
+                        None => return Err(box_err!("no region to ingest sst into")),
+                    };
+                    req.set_cmd_type(CmdType::IngestSST);
+                    req.set_ingest_sst(try!(self.send_sst(ctx, region, cf, kvs)));
+                }
             }
             reqs.push(req);
         }
+        Ok(reqs)
+    }
 
+    fn write_requests(&self,
+                      ctx: &Context,
+                      reqs: Vec<Request>,
+                      cb: Callback<()>)
+                      -> engine::Result<()> {
         ASYNC_REQUESTS_COUNTER_VEC.with_label_values(&["write", "all"]).inc();
         let req_timer = ASYNC_REQUESTS_DURATIONS_VEC.with_label_values(&["write"]).start_timer();
 
@@ -259,16 +361,77 @@ impl<S: RaftStoreRouter> Engine for RaftKv<S> {
             })
     }
 
+    /// Sending the SST files may take a while, so the write is done in the import pool, which
+    /// waits until it's applied.
+    fn async_ingest(&self,
+                    ctx: &Context,
+                    modifies: Vec<Modify>,
+                    cb: Callback<()>)
+                    -> engine::Result<()> {
+        let kv: RaftKv<S> = Clone::clone(self);
+        let ctx = ctx.clone();
+        self.import_pool.lock().unwrap().execute(move || {
+            let res = kv.get_region(&ctx)
+                .and_then(|region| kv.gen_requests(&ctx, modifies, Some(&region)))
+                .and_then(|reqs| {
+                    let (tx, rx) = mpsc::channel();
+                    try!(kv.write_requests(&ctx, reqs, box move |res| tx.send(res).unwrap()));
+                    rx.recv().map_err(|e| box_err!(e))
+                });
+            match res {
+                Ok((cb_ctx, res)) => cb((cb_ctx, res)),
+                Err(e) => cb((CbContext::new(), Err(e))),
+            }
+        });
+        Ok(())
+    }
+}
+
+fn invalid_resp_type(exp: CmdType, act: CmdType) -> Error {
+    Error::InvalidResponse(format!("cmd type not match, want {:?}, got {:?}!", exp, act))
+}
+
+impl<S: RaftStoreRouter> Debug for RaftKv<S> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "RaftKv")
+    }
+}
+
+impl<S: RaftStoreRouter> Engine for RaftKv<S> {
+    fn async_write(&self,
+                   ctx: &Context,
+                   modifies: Vec<Modify>,
+                   cb: Callback<()>)
+                   -> engine::Result<()> {
+        if modifies.iter().any(|m| match *m {
+            Modify::Ingest(..) => true,
+            _ => false,
+        }) {
+            return self.async_ingest(ctx, modifies, cb);
+        }
+        let reqs = try!(self.gen_requests(ctx, modifies, None));
+        self.write_requests(ctx, reqs, cb)
+    }
+
     fn async_snapshot(&self, ctx: &Context, cb: Callback<Box<Snapshot>>) -> engine::Result<()> {
+        self.async_snapshot_at(ctx, 0, cb)
+    }
+
+    fn async_snapshot_at(&self,
+                         ctx: &Context,
+                         read_ts: u64,
+                         cb: Callback<Box<Snapshot>>)
+                         -> engine::Result<()> {
         let mut req = Request::new();
         req.set_cmd_type(CmdType::Snap);
 
         ASYNC_REQUESTS_COUNTER_VEC.with_label_values(&["snapshot", "all"]).inc();
         let req_timer = ASYNC_REQUESTS_DURATIONS_VEC.with_label_values(&["snapshot"]).start_timer();
 
-        self.exec_requests(ctx,
-                           vec![req],
-                           box move |(cb_ctx, res)| {
+        self.exec_requests_at(ctx,
+                              read_ts,
+                              vec![req],
+                              box move |(cb_ctx, res)| {
                 match res {
                     Ok(CmdRes::Resp(r)) => {
                         cb((cb_ctx,
@@ -294,8 +457,24 @@ impl<S: RaftStoreRouter> Engine for RaftKv<S> {
             })
     }
 
+    fn stall_signals(&self) -> Option<EngineStallSignals> {
+        Some(get_engine_stall_signals(&self.db))
+    }
+
+    fn report_read_flow(&self, read_flow: HashMap<u64, FlowStatistics>) {
+        if let Err(e) = self.router.try_send(StoreMsg::ReadFlow { read_flow: read_flow }) {
+            debug!("failed to report read flow: {:?}", e);
+        }
+    }
+
     fn clone(&self) -> Box<Engine> {
-        box RaftKv::new(self.db.clone(), self.router.clone())
+        box RaftKv {
+            db: self.db.clone(),
+            router: self.router.clone(),
+            importer: self.importer.clone(),
+            sst_sender: self.sst_sender.clone(),
+            import_pool: self.import_pool.clone(),
+        }
     }
 }
 
@@ -327,6 +506,11 @@ impl Snapshot for RegionSnapshot {
     fn clone(&self) -> Box<Snapshot> {
         Box::new(RegionSnapshot::clone(self))
     }
+
+    fn validate_key(&self, key: &Key) -> engine::Result<()> {
+        try!(util::check_key_in_region(key.encoded(), self.get_region()));
+        Ok(())
+    }
 }
 
 impl<'a> EngineIterator for RegionIterator<'a> {
@@ -12,11 +12,15 @@
 // limitations under the License.
 
 use std::fmt::{self, Formatter, Debug, Display};
+use std::mem;
 use std::sync::{Arc, Mutex};
 use rocksdb::{DB, Writable, SeekKey, WriteBatch, DBIterator};
 use kvproto::kvrpcpb::Context;
 use storage::{Key, Value, CfName, CF_DEFAULT};
-use raftstore::store::engine::{SyncSnapshot as RocksSnapshot, Peekable, Iterable, IterOption};
+use raftstore::store::engine::{SyncSnapshot as RocksSnapshot, Peekable, Iterable, IterOption,
+                               delete_all_in_range};
+use raftstore::store::{EngineStallSignals, get_engine_stall_signals};
+use import;
 use util::escape;
 use util::rocksdb;
 use util::worker::{Runnable, Worker, Scheduler};
@@ -68,6 +72,7 @@ impl Drop for EngineRocksdbCore {
 pub struct EngineRocksdb {
     core: Arc<Mutex<EngineRocksdbCore>>,
     sched: Scheduler<Task>,
+    db: Arc<DB>,
 }
 
 impl EngineRocksdb {
@@ -81,10 +86,11 @@ impl EngineRocksdb {
             _ => (path.to_owned(), None),
         };
         let mut worker = Worker::new("engine-rocksdb");
-        let db = try!(rocksdb::new_engine(&path, cfs));
-        box_try!(worker.start(Runner(Arc::new(db))));
+        let db = Arc::new(try!(rocksdb::new_engine(&path, cfs)));
+        box_try!(worker.start(Runner(db.clone())));
         Ok(EngineRocksdb {
             sched: worker.scheduler(),
+            db: db,
             core: Arc::new(Mutex::new(EngineRocksdbCore {
                 temp_dir: temp_dir,
                 worker: worker,
@@ -102,7 +108,7 @@ impl Debug for EngineRocksdb {
 }
 
 fn write_modifies(db: &DB, modifies: Vec<Modify>) -> Result<()> {
-    let wb = WriteBatch::new();
+    let mut wb = WriteBatch::new();
     for rev in modifies {
         let res = match rev {
             Modify::Delete(cf, k) => {
@@ -125,6 +131,26 @@ fn write_modifies(db: &DB, modifies: Vec<Modify>) -> Result<()> {
                     wb.put_cf(handle, k.encoded(), &v)
                 }
             }
+            Modify::DeleteRange(start_key, end_key) => {
+                trace!("EngineRocksdb: delete_range [{}, {})", start_key, end_key);
+                // The range is deleted from db directly, so flush the previous modifies first.
+                let prev = mem::replace(&mut wb, WriteBatch::new());
+                db.write(prev)
+                    .and_then(|_| {
+                        delete_all_in_range(db, start_key.encoded(), end_key.encoded())
+                            .map_err(|e| format!("{:?}", e))
+                    })
+            }
+            Modify::Ingest(cf, kvs) => {
+                trace!("EngineRocksdb: ingest {} keys to {}", kvs.len(), cf);
+                let prev = mem::replace(&mut wb, WriteBatch::new());
+                db.write(prev).and_then(|_| {
+                    let kvs = kvs.into_iter().map(|(k, v)| (k.into_encoded(), v));
+                    import::build_sst(kvs)
+                        .and_then(|sst| import::ingest_sst(db, cf, &sst.data, sst.crc32))
+                        .map_err(|e| format!("{:?}", e))
+                })
+            }
         };
         if let Err(msg) = res {
             return Err(Error::RocksDb(msg));
@@ -147,10 +173,15 @@ impl Engine for EngineRocksdb {
         Ok(())
     }
 
+    fn stall_signals(&self) -> Option<EngineStallSignals> {
+        Some(get_engine_stall_signals(&self.db))
+    }
+
     fn clone(&self) -> Box<Engine> {
         box EngineRocksdb {
             core: self.core.clone(),
             sched: self.sched.clone(),
+            db: self.db.clone(),
         }
     }
 }
@@ -0,0 +1,560 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Display, Formatter};
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use futures::Future;
+use kvproto::kvrpcpb::{Context, CommandPri};
+use kvproto::metapb::{Peer, Region};
+
+use pd::PdClient;
+use raftstore::store::Msg as StoreMsg;
+use server::transport::RaftStoreRouter;
+use util::transport::SyncSendCh;
+use util::worker::{Worker, Scheduler, Runnable};
+use super::engine::{self, Engine, ScanMode, Snapshot, Statistics};
+use super::mvcc::MvccReader;
+use super::metrics::*;
+use super::txn::Msg;
+use super::{Callback, Command, Error, Key, Result, StorageCb};
+
+// TODO: make it configurable.
+pub const GC_BATCH_SIZE: usize = 512;
+
+// Only one GC task from the clients is allowed at the same time, others are rejected as too
+// busy.
+const GC_MAX_PENDING_TASKS: usize = 1;
+
+// The tasks of the gc manager are limited separately, so that they don't reject the clients'.
+const GC_MAX_AUTO_PENDING_TASKS: usize = 1;
+
+// How long the runner waits before retrying a batch rejected by the busy scheduler.
+const GC_BACKOFF_MS: u64 = 1000;
+
+// How many times a batch is retried before the task fails as too busy.
+const GC_MAX_BACKOFF_TIMES: usize = 10;
+
+// How long the runner sleeps before checking whether it is resumed.
+const GC_PAUSE_CHECK_INTERVAL_MS: u64 = 100;
+
+// How long the runner waits for a snapshot or a batch to be written.
+const GC_WAIT_TIMEOUT_SECS: u64 = 60;
+
+/// Collects garbage of all keys in the region of `ctx`, keeping the latest version of each key
+/// that is visible at `safe_point`.
+pub struct GcTask {
+    pub ctx: Context,
+    pub safe_point: u64,
+    pub callback: Callback<()>,
+}
+
+impl Display for GcTask {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f,
+               "gc region {} @ {}",
+               self.ctx.get_region_id(),
+               self.safe_point)
+    }
+}
+
+struct GcRunner {
+    engine: Box<Engine>,
+    sched_ch: SyncSendCh<Msg>,
+    paused: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl GcRunner {
+    fn check_stopped(&self) -> Result<()> {
+        if self.stopped.load(Ordering::SeqCst) {
+            return Err(box_err!("gc worker is stopped"));
+        }
+        Ok(())
+    }
+
+    fn wait_if_paused(&self) {
+        while self.paused.load(Ordering::SeqCst) && !self.stopped.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(GC_PAUSE_CHECK_INTERVAL_MS));
+        }
+    }
+
+    fn snapshot(&self, ctx: &Context) -> Result<Box<Snapshot>> {
+        let (tx, rx) = mpsc::channel();
+        try!(self.engine.async_snapshot(ctx,
+                                        box move |(_, res)| {
+                                            let _ = tx.send(res);
+                                        }));
+        let timeout = Duration::from_secs(GC_WAIT_TIMEOUT_SECS);
+        match rx.recv_timeout(timeout) {
+            Ok(res) => res.map_err(Error::from),
+            Err(_) => Err(Error::from(engine::Error::Timeout(timeout))),
+        }
+    }
+
+    /// Collects garbage of `keys` with a command of the scheduler, so that it holds the
+    /// latches of the keys like other writes. The command is retried later if the scheduler
+    /// is too busy, at most `GC_MAX_BACKOFF_TIMES` times.
+    fn gc_keys(&self, ctx: &Context, safe_point: u64, keys: Vec<Key>) -> Result<()> {
+        let mut ctx = ctx.clone();
+        ctx.set_priority(CommandPri::Low);
+        let mut backoff_times = 0;
+        loop {
+            let (tx, rx) = mpsc::channel();
+            let cmd = Command::Gc {
+                ctx: ctx.clone(),
+                safe_point: safe_point,
+                keys: keys.clone(),
+            };
+            let cb = StorageCb::Boolean(box move |res| {
+                let _ = tx.send(res);
+            });
+            box_try!(self.sched_ch.try_send(Msg::RawCmd { cmd: cmd, cb: cb }));
+            let timeout = Duration::from_secs(GC_WAIT_TIMEOUT_SECS);
+            match rx.recv_timeout(timeout) {
+                Ok(Err(Error::SchedTooBusy)) => {
+                    backoff_times += 1;
+                    if backoff_times > GC_MAX_BACKOFF_TIMES {
+                        return Err(Error::SchedTooBusy);
+                    }
+                    try!(self.check_stopped());
+                    GC_BACKOFF_COUNTER.inc();
+                    thread::sleep(Duration::from_millis(GC_BACKOFF_MS));
+                }
+                Ok(res) => return res,
+                Err(_) => return Err(Error::from(engine::Error::Timeout(timeout))),
+            }
+        }
+    }
+
+    /// Scans a batch of keys from `start` and collects their garbage. Returns the key to
+    /// continue with, or `None` if the region is done.
+    fn gc_batch(&self, ctx: &Context, safe_point: u64, start: Option<Key>) -> Result<Option<Key>> {
+        let snapshot = try!(self.snapshot(ctx));
+        let mut statistics = Statistics::default();
+        let (keys, next) = {
+            let mut reader = MvccReader::new(snapshot.as_ref(),
+                                             &mut statistics,
+                                             Some(ScanMode::Forward),
+                                             false,
+                                             None,
+                                             ctx.get_isolation_level());
+            let is_range_start = start.is_none();
+            let (keys, next) = try!(reader.scan_keys(start, GC_BATCH_SIZE).map_err(txn_err));
+            if keys.is_empty() && is_range_start {
+                KV_COMMAND_GC_EMPTY_RANGE_COUNTER.inc();
+            }
+            (keys, next)
+        };
+        GC_KEYS_COUNTER_VEC.with_label_values(&["scanned"]).inc_by(keys.len() as f64).unwrap();
+        if !keys.is_empty() {
+            try!(self.gc_keys(ctx, safe_point, keys));
+        }
+        Ok(next)
+    }
+
+    fn gc(&self, ctx: &Context, safe_point: u64) -> Result<()> {
+        let mut next = None;
+        loop {
+            self.wait_if_paused();
+            try!(self.check_stopped());
+            next = try!(self.gc_batch(ctx, safe_point, next));
+            if next.is_none() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn txn_err(e: ::storage::mvcc::Error) -> Error {
+    Error::from(::storage::txn::Error::from(e))
+}
+
+impl Runnable<GcTask> for GcRunner {
+    fn run(&mut self, task: GcTask) {
+        let timer = GC_TASK_HISTOGRAM.start_timer();
+        let GcTask { ctx, safe_point, callback } = task;
+        let res = self.gc(&ctx, safe_point);
+        if let Err(ref e) = res {
+            GC_TASK_FAIL_COUNTER.inc();
+            warn!("failed to gc region {} @ {}: {:?}",
+                  ctx.get_region_id(),
+                  safe_point,
+                  e);
+        }
+        timer.observe_duration();
+        callback(res);
+    }
+}
+
+/// Polls the GC safe point from PD, and collects garbage of the regions led by the local store
+/// whenever it advances.
+struct GcManager<C, R> {
+    pd_client: Arc<C>,
+    router: R,
+    gc_worker: GcWorker,
+    // The safe point that all the leader regions have been collected to.
+    safe_point: u64,
+}
+
+impl<C: PdClient, R: RaftStoreRouter> GcManager<C, R> {
+    fn get_leader_regions(&self) -> Result<Vec<(Region, Peer)>> {
+        let (tx, rx) = mpsc::channel();
+        box_try!(self.router.send(StoreMsg::GetLeaderRegions {
+            callback: box move |regions: Vec<(Region, Peer)>| {
+                let _ = tx.send(regions);
+            },
+        }));
+        Ok(box_try!(rx.recv_timeout(Duration::from_secs(GC_WAIT_TIMEOUT_SECS))))
+    }
+
+    fn gc_region(&self, region: Region, peer: Peer, safe_point: u64) -> Result<()> {
+        let mut ctx = Context::new();
+        ctx.set_region_id(region.get_id());
+        ctx.set_region_epoch(region.get_region_epoch().clone());
+        ctx.set_peer(peer);
+        let (tx, rx) = mpsc::channel();
+        try!(self.gc_worker.async_auto_gc(ctx,
+                                          safe_point,
+                                          box move |res| {
+                                              let _ = tx.send(res);
+                                          }));
+        box_try!(rx.recv())
+    }
+
+    /// Collects garbage of the leader regions if the safe point advances. Returns false if it's
+    /// stopped.
+    fn gc_round(&mut self, stop: &mpsc::Receiver<()>) -> Result<bool> {
+        let safe_point = box_try!(self.pd_client.get_gc_safe_point().wait());
+        if safe_point <= self.safe_point {
+            return Ok(true);
+        }
+        let regions = try!(self.get_leader_regions());
+        info!("start to gc {} regions @ {}", regions.len(), safe_point);
+        let mut failed = 0;
+        for (region, peer) in regions {
+            if stop.try_recv() != Err(mpsc::TryRecvError::Empty) {
+                return Ok(false);
+            }
+            let region_id = region.get_id();
+            if let Err(e) = self.gc_region(region, peer, safe_point) {
+                warn!("[region {}] failed to gc @ {}: {:?}", region_id, safe_point, e);
+                failed += 1;
+            }
+        }
+        // Tries again in the next round if any region fails.
+        if failed == 0 {
+            info!("finish gc @ {}", safe_point);
+            self.safe_point = safe_point;
+            GC_SAFE_POINT_GAUGE.set(safe_point as f64);
+        }
+        Ok(true)
+    }
+
+    fn run(&mut self, interval: Duration, stop: mpsc::Receiver<()>) {
+        loop {
+            match stop.recv_timeout(interval) {
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+            match self.gc_round(&stop) {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(e) => warn!("failed to gc leader regions: {:?}", e),
+            }
+        }
+    }
+}
+
+/// `GcWorker` collects garbage of regions in the background. Tasks run through the engine,
+/// so only the leader of a region can handle them, and the writes go through the scheduler.
+pub struct GcWorker {
+    engine: Box<Engine>,
+    sched_ch: SyncSendCh<Msg>,
+    worker: Arc<Mutex<Worker<GcTask>>>,
+    worker_scheduler: Scheduler<GcTask>,
+    // Number of tasks from the clients that are scheduled but not finished yet.
+    pending: Arc<AtomicUsize>,
+    // Number of tasks from the gc manager that are scheduled but not finished yet.
+    auto_pending: Arc<AtomicUsize>,
+    paused: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+    // The thread of the gc manager and the sender to stop it.
+    auto_gc: Arc<Mutex<Option<(JoinHandle<()>, mpsc::Sender<()>)>>>,
+}
+
+impl GcWorker {
+    pub fn new(engine: Box<Engine>, sched_ch: SyncSendCh<Msg>) -> GcWorker {
+        let worker = Worker::new("gc-worker");
+        let worker_scheduler = worker.scheduler();
+        GcWorker {
+            engine: engine,
+            sched_ch: sched_ch,
+            worker: Arc::new(Mutex::new(worker)),
+            worker_scheduler: worker_scheduler,
+            pending: Arc::new(AtomicUsize::new(0)),
+            auto_pending: Arc::new(AtomicUsize::new(0)),
+            paused: Arc::new(AtomicBool::new(false)),
+            stopped: Arc::new(AtomicBool::new(false)),
+            auto_gc: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        let runner = GcRunner {
+            engine: self.engine.clone(),
+            sched_ch: self.sched_ch.clone(),
+            paused: self.paused.clone(),
+            stopped: self.stopped.clone(),
+        };
+        box_try!(self.worker.lock().unwrap().start(runner));
+        Ok(())
+    }
+
+    /// Collects garbage of the regions led by the local store every `interval` if the GC safe
+    /// point in PD advances.
+    pub fn start_auto_gc<C, R>(&self,
+                               pd_client: Arc<C>,
+                               router: R,
+                               interval: Duration)
+                               -> Result<()>
+        where C: PdClient + 'static,
+              R: RaftStoreRouter + 'static
+    {
+        let mut auto_gc = self.auto_gc.lock().unwrap();
+        if auto_gc.is_some() {
+            return Err(box_err!("gc manager is already running"));
+        }
+        let mut manager = GcManager {
+            pd_client: pd_client,
+            router: router,
+            gc_worker: self.clone(),
+            safe_point: 0,
+        };
+        let (tx, rx) = mpsc::channel();
+        let h = try!(thread::Builder::new()
+            .name(thd_name!("gc-manager"))
+            .spawn(move || manager.run(interval, rx)));
+        *auto_gc = Some((h, tx));
+        Ok(())
+    }
+
+    pub fn stop(&mut self) -> Result<()> {
+        // Let the running task fail fast instead of blocking on pause or backoff.
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some((h, tx)) = self.auto_gc.lock().unwrap().take() {
+            let _ = tx.send(());
+            if let Err(e) = h.join() {
+                return Err(box_err!("failed to join gc manager, err:{:?}", e));
+            }
+        }
+        let h = self.worker.lock().unwrap().stop();
+        if let Some(h) = h {
+            if let Err(e) = h.join() {
+                return Err(box_err!("failed to join gc worker, err:{:?}", e));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn async_gc(&self, ctx: Context, safe_point: u64, callback: Callback<()>) -> Result<()> {
+        self.schedule(ctx,
+                      safe_point,
+                      &self.pending,
+                      GC_MAX_PENDING_TASKS,
+                      callback)
+    }
+
+    fn async_auto_gc(&self, ctx: Context, safe_point: u64, callback: Callback<()>) -> Result<()> {
+        self.schedule(ctx,
+                      safe_point,
+                      &self.auto_pending,
+                      GC_MAX_AUTO_PENDING_TASKS,
+                      callback)
+    }
+
+    fn schedule(&self,
+                ctx: Context,
+                safe_point: u64,
+                pending: &Arc<AtomicUsize>,
+                max_pending: usize,
+                callback: Callback<()>)
+                -> Result<()> {
+        if pending.fetch_add(1, Ordering::SeqCst) >= max_pending {
+            pending.fetch_sub(1, Ordering::SeqCst);
+            SCHED_TOO_BUSY_COUNTER_VEC.with_label_values(&["gc"]).inc();
+            callback(Err(Error::SchedTooBusy));
+            return Ok(());
+        }
+        let pending = pending.clone();
+        let task = GcTask {
+            ctx: ctx,
+            safe_point: safe_point,
+            callback: box move |res| {
+                pending.fetch_sub(1, Ordering::SeqCst);
+                callback(res);
+            },
+        };
+        if let Err(e) = self.worker_scheduler.schedule(task) {
+            pending.fetch_sub(1, Ordering::SeqCst);
+            return Err(box_err!("failed to schedule gc task: {:?}", e));
+        }
+        Ok(())
+    }
+
+    /// Pauses garbage collection after the current batch, e.g. when the store is under heavy
+    /// load. Scheduled tasks resume where they stopped once `resume` is called.
+    pub fn pause(&self) {
+        info!("gc worker paused");
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            info!("gc worker resumed");
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+}
+
+impl Clone for GcWorker {
+    fn clone(&self) -> GcWorker {
+        GcWorker {
+            engine: self.engine.clone(),
+            sched_ch: self.sched_ch.clone(),
+            worker: self.worker.clone(),
+            worker_scheduler: self.worker_scheduler.clone(),
+            pending: self.pending.clone(),
+            auto_pending: self.auto_pending.clone(),
+            paused: self.paused.clone(),
+            stopped: self.stopped.clone(),
+            auto_gc: self.auto_gc.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use kvproto::kvrpcpb::Context;
+
+    use storage::{self, make_key, Mutation, Options, Storage, ALL_CFS, TEMP_DIR, Statistics};
+    use storage::config::Config;
+    use storage::engine::{self, Engine};
+    use storage::mvcc::{MvccTxn, MvccReader};
+    use kvproto::kvrpcpb::IsolationLevel;
+    use super::*;
+
+    fn must_put(engine: &Engine, key: &[u8], value: &[u8], start_ts: u64, commit_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   start_ts,
+                                   None,
+                                   IsolationLevel::SI);
+        txn.prewrite(Mutation::Put((make_key(key), value.to_vec())),
+                      key,
+                      &Options::default())
+            .unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   start_ts,
+                                   None,
+                                   IsolationLevel::SI);
+        txn.commit(&make_key(key), commit_ts).unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_get(engine: &Engine, key: &[u8], ts: u64) -> Option<Vec<u8>> {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(snapshot.as_ref(),
+                                         &mut statistics,
+                                         None,
+                                         true,
+                                         None,
+                                         IsolationLevel::SI);
+        reader.get(&make_key(key), ts).unwrap()
+    }
+
+    fn must_gc(storage: &Storage, safe_point: u64) -> storage::Result<()> {
+        let (tx, rx) = channel();
+        storage.async_gc(Context::new(),
+                         safe_point,
+                         box move |res: storage::Result<()>| tx.send(res).unwrap())
+            .unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn test_gc_worker() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let keys: Vec<Vec<u8>> = (0..GC_BATCH_SIZE + 10)
+            .map(|i| format!("k{:04}", i).into_bytes())
+            .collect();
+        for k in &keys {
+            must_put(engine.as_ref(), k, b"v1", 5, 10);
+            must_put(engine.as_ref(), k, b"v2", 15, 20);
+        }
+
+        let config = Config::default();
+        let mut storage = Storage::from_engine(engine.clone(), &config).unwrap();
+        storage.start(&config).unwrap();
+        must_gc(&storage, 25).unwrap();
+        for k in &keys {
+            assert!(must_get(engine.as_ref(), k, 12).is_none());
+            assert_eq!(must_get(engine.as_ref(), k, 22).unwrap(), b"v2");
+        }
+
+        // A paused worker doesn't make progress until it is resumed.
+        storage.pause_gc();
+        assert!(storage.gc_worker.is_paused());
+        let (tx, rx) = channel();
+        let cb_tx = tx.clone();
+        storage.async_gc(Context::new(),
+                         25,
+                         box move |res: storage::Result<()>| cb_tx.send(res).unwrap())
+            .unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(300)).is_err());
+        // The clients' tasks are limited, but they don't reject the gc manager's.
+        match must_gc(&storage, 25) {
+            Err(storage::Error::SchedTooBusy) => {}
+            res => panic!("expect too busy, got {:?}", res),
+        }
+        storage.gc_worker
+            .async_auto_gc(Context::new(),
+                           25,
+                           box move |res: storage::Result<()>| tx.send(res).unwrap())
+            .unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(300)).is_err());
+        storage.resume_gc();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+
+        storage.stop().unwrap();
+    }
+}
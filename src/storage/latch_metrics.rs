@@ -0,0 +1,128 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+use kvproto::kvrpcpb::CommandPri;
+use prometheus::{self, Opts, Gauge, GaugeVec, Desc, proto, Collector};
+
+use util::duration_to_sec;
+use super::{LatchInfo, Storage, Result};
+
+const DUMP_LATCHES_TIMEOUT_SECS: u64 = 1;
+
+/// Reports the latches in use of the scheduler with the metrics, dumped from `storage` whenever
+/// the metrics are collected.
+pub fn monitor_latches(storage: Storage) -> Result<()> {
+    let lc = LatchesCollector::new(storage);
+    box_try!(prometheus::register(Box::new(lc)));
+    Ok(())
+}
+
+struct LatchesCollector {
+    storage: Mutex<Storage>,
+    descs: Vec<Desc>,
+}
+
+fn latches_gauge() -> Gauge {
+    Gauge::new("tikv_scheduler_latches_in_use",
+               "Number of latches owned by the commands.")
+        .unwrap()
+}
+
+fn latch_commands_gauge_vec() -> GaugeVec {
+    GaugeVec::new(Opts::new("tikv_scheduler_latch_commands",
+                            "Number of commands owning or waiting for the latches."),
+                  &["type", "state", "priority"])
+        .unwrap()
+}
+
+fn latch_wait_gauge_vec() -> GaugeVec {
+    GaugeVec::new(Opts::new("tikv_scheduler_latch_max_wait_duration_seconds",
+                            "Max time a command has waited for a latch."),
+                  &["priority"])
+        .unwrap()
+}
+
+fn priority_tag(priority: CommandPri) -> &'static str {
+    match priority {
+        CommandPri::Low => "low",
+        CommandPri::Normal => "normal",
+        CommandPri::High => "high",
+    }
+}
+
+impl LatchesCollector {
+    fn new(storage: Storage) -> LatchesCollector {
+        let mut descs: Vec<Desc> = latches_gauge().desc().into_iter().cloned().collect();
+        descs.extend(latch_commands_gauge_vec().desc().into_iter().cloned());
+        descs.extend(latch_wait_gauge_vec().desc().into_iter().cloned());
+        LatchesCollector {
+            storage: Mutex::new(storage),
+            descs: descs,
+        }
+    }
+
+    fn dump(&self) -> Option<Vec<LatchInfo>> {
+        let (tx, rx) = mpsc::channel();
+        let cb = box move |infos: Vec<LatchInfo>| {
+            let _ = tx.send(infos);
+        };
+        if let Err(e) = self.storage.lock().unwrap().async_dump_latches(cb) {
+            warn!("failed to dump latches: {:?}", e);
+            return None;
+        }
+        match rx.recv_timeout(Duration::from_secs(DUMP_LATCHES_TIMEOUT_SECS)) {
+            Ok(infos) => Some(infos),
+            Err(e) => {
+                warn!("failed to dump latches: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Collector for LatchesCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        // The gauges are built from scratch, so the latches released since the last collection
+        // are not reported.
+        let latches = latches_gauge();
+        let commands = latch_commands_gauge_vec();
+        let max_wait = latch_wait_gauge_vec();
+        let infos = self.dump().unwrap_or_else(Vec::new);
+        latches.set(infos.len() as f64);
+        for info in &infos {
+            let priority = priority_tag(info.owner.priority);
+            commands.with_label_values(&[info.owner.tag, "owner", priority]).inc();
+            for waiter in &info.waiters {
+                let priority = priority_tag(waiter.priority);
+                commands.with_label_values(&[waiter.tag, "waiter", priority]).inc();
+                let wait = duration_to_sec(waiter.duration);
+                let gauge = max_wait.with_label_values(&[priority]);
+                if wait > gauge.get() {
+                    gauge.set(wait);
+                }
+            }
+        }
+
+        let mut mfs = latches.collect();
+        mfs.extend(commands.collect());
+        mfs.extend(max_wait.collect());
+        mfs
+    }
+}
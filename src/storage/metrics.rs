@@ -28,6 +28,13 @@ lazy_static! {
             &["type", "stage"]
         ).unwrap();
 
+    pub static ref LOCK_WAIT_COUNTER_VEC: CounterVec =
+        register_counter_vec!(
+            "tikv_scheduler_lock_wait_total",
+            "Total number of lock waits of write commands.",
+            &["type"]
+        ).unwrap();
+
     pub static ref SCHED_CONTEX_GAUGE: Gauge =
         register_gauge!(
             "tikv_scheduler_contex_total",
@@ -57,6 +64,14 @@ lazy_static! {
             exponential_buckets(0.0005, 2.0, 20).unwrap()
         ).unwrap();
 
+    pub static ref SCHED_LATCH_SLOT_WAIT_HISTOGRAM_VEC: HistogramVec =
+        register_histogram_vec!(
+            "tikv_scheduler_latch_slot_wait_duration_seconds",
+            "Bucketed histogram of waiting for a single latch slot",
+            &["priority"],
+            exponential_buckets(0.0005, 2.0, 20).unwrap()
+        ).unwrap();
+
     pub static ref SCHED_TOO_BUSY_COUNTER_VEC: CounterVec =
         register_counter_vec!(
             "tikv_scheduler_too_busy_total",
@@ -64,6 +79,13 @@ lazy_static! {
             &["type"]
         ).unwrap();
 
+    pub static ref SCHED_WRITE_STALL_COUNTER_VEC: CounterVec =
+        register_counter_vec!(
+            "tikv_scheduler_write_stall_total",
+            "Total count of writes rejected by engine write stall signals",
+            &["type"]
+        ).unwrap();
+
     pub static ref SCHED_COMMANDS_PRI_COUNTER_VEC: CounterVec =
         register_counter_vec!(
             "tikv_scheduler_commands_pri_total",
@@ -98,4 +120,43 @@ lazy_static! {
             "tikv_storage_gc_empty_range_total",
             "Total number of empty range found by gc"
         ).unwrap();
+
+    pub static ref GC_KEYS_COUNTER_VEC: CounterVec =
+        register_counter_vec!(
+            "tikv_storage_gc_keys_total",
+            "Total number of keys scanned or skipped by gc worker",
+            &["type"]
+        ).unwrap();
+
+    pub static ref GC_WRITE_BATCH_SIZE_HISTOGRAM: Histogram =
+        register_histogram!(
+            "tikv_storage_gc_write_batch_size",
+            "Bucketed histogram of modifies written by gc worker in a batch",
+            exponential_buckets(1.0, 2.0, 16).unwrap()
+        ).unwrap();
+
+    pub static ref GC_TASK_HISTOGRAM: Histogram =
+        register_histogram!(
+            "tikv_storage_gc_task_duration_seconds",
+            "Bucketed histogram of gc task duration",
+            exponential_buckets(0.001, 2.0, 20).unwrap()
+        ).unwrap();
+
+    pub static ref GC_TASK_FAIL_COUNTER: Counter =
+        register_counter!(
+            "tikv_storage_gc_task_fail_total",
+            "Total number of failed gc tasks"
+        ).unwrap();
+
+    pub static ref GC_BACKOFF_COUNTER: Counter =
+        register_counter!(
+            "tikv_storage_gc_backoff_total",
+            "Total number of gc batches delayed because the scheduler is busy"
+        ).unwrap();
+
+    pub static ref GC_SAFE_POINT_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_storage_gc_safe_point",
+            "The safe point that the regions led by the store are collected to"
+        ).unwrap();
 }
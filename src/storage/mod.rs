@@ -18,23 +18,37 @@ use std::sync::mpsc::{self, Receiver};
 use std::error;
 use std::sync::{Arc, Mutex};
 use std::io::Error as IoError;
+use std::time::Duration;
 use kvproto::kvrpcpb::{LockInfo, CommandPri};
 use kvproto::errorpb;
 use self::metrics::*;
+use pd::PdClient;
+use server::transport::RaftStoreRouter;
+use util::escape;
+use util::collections::HashMap;
+use util::worker::FutureWorker;
 
 pub mod engine;
 pub mod mvcc;
 pub mod txn;
 pub mod config;
+pub mod gc_worker;
 pub mod types;
+pub mod ttl;
 mod metrics;
+mod latch_metrics;
 
 pub use self::config::Config;
 pub use self::engine::{Engine, Snapshot, TEMP_DIR, new_local_engine, Modify, Cursor,
-                       Error as EngineError, ScanMode, Statistics};
+                       Error as EngineError, ScanMode, Statistics, FlowStatistics};
 pub use self::engine::raftkv::RaftKv;
-pub use self::txn::{SnapshotStore, Scheduler, Msg};
+pub use self::txn::{SnapshotStore, Scheduler, Msg, LatchesCallback, LatchInfo, LatchWaiter,
+                    WaiterManager, WaiterTask, MaxReadTs};
 pub use self::types::{Key, Value, KvPair, make_key};
+pub use self::mvcc::{TxnStatus, MvccInfo};
+use self::mvcc::Lock;
+pub use self::gc_worker::GcWorker;
+pub use self::latch_metrics::monitor_latches;
 pub type Callback<T> = Box<FnBox(Result<T>) + Send>;
 
 pub type CfName = &'static str;
@@ -42,9 +56,11 @@ pub const CF_DEFAULT: CfName = "default";
 pub const CF_LOCK: CfName = "lock";
 pub const CF_WRITE: CfName = "write";
 pub const CF_RAFT: CfName = "raft";
+// Raw key-value pairs with TTL, see `ttl`.
+pub const CF_RAW_TTL: CfName = "raw_ttl";
 // Cfs that should be very large generally.
-pub const LARGE_CFS: &'static [CfName] = &[CF_DEFAULT, CF_WRITE];
-pub const ALL_CFS: &'static [CfName] = &[CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAFT];
+pub const LARGE_CFS: &'static [CfName] = &[CF_DEFAULT, CF_WRITE, CF_RAW_TTL];
+pub const ALL_CFS: &'static [CfName] = &[CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAFT, CF_RAW_TTL];
 
 // Short value max len must <= 255.
 pub const SHORT_VALUE_MAX_LEN: usize = 64;
@@ -59,6 +75,10 @@ pub enum Mutation {
     Put((Key, Value)),
     Delete(Key),
     Lock(Key),
+    /// Puts the value only if the key doesn't exist.
+    Insert((Key, Value)),
+    /// Checks that the key doesn't exist, without writing anything.
+    CheckNotExists(Key),
 }
 
 #[allow(match_same_arms)]
@@ -68,6 +88,17 @@ impl Mutation {
             Mutation::Put((ref key, _)) => key,
             Mutation::Delete(ref key) => key,
             Mutation::Lock(ref key) => key,
+            Mutation::Insert((ref key, _)) => key,
+            Mutation::CheckNotExists(ref key) => key,
+        }
+    }
+
+    /// Returns true if the key must not have a committed value when the mutation is prewritten.
+    pub fn should_not_exist(&self) -> bool {
+        match *self {
+            Mutation::Insert(_) |
+            Mutation::CheckNotExists(_) => true,
+            _ => false,
         }
     }
 }
@@ -80,6 +111,10 @@ pub enum StorageCb {
     SingleValue(Callback<Option<Value>>),
     KvPairs(Callback<Vec<Result<KvPair>>>),
     Locks(Callback<Vec<LockInfo>>),
+    TxnStatus(Callback<TxnStatus>),
+    MvccInfoByKey(Callback<MvccInfo>),
+    MvccInfos(Callback<Vec<(Vec<u8>, MvccInfo)>>),
+    RawCompareAndSwap(Callback<(Option<Value>, bool)>),
 }
 
 pub enum Command {
@@ -96,9 +131,12 @@ pub enum Command {
     Scan {
         ctx: Context,
         start_key: Key,
+        // Scans up to the end of the region if it is `None`.
+        end_key: Option<Key>,
         limit: usize,
         start_ts: u64,
         options: Options,
+        reverse: bool,
     },
     Prewrite {
         ctx: Context,
@@ -107,6 +145,13 @@ pub enum Command {
         start_ts: u64,
         options: Options,
     },
+    AcquirePessimisticLock {
+        ctx: Context,
+        keys: Vec<Key>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        options: Options,
+    },
     Commit {
         ctx: Context,
         keys: Vec<Key>,
@@ -123,21 +168,84 @@ pub enum Command {
         keys: Vec<Key>,
         start_ts: u64,
     },
-    ScanLock { ctx: Context, max_ts: u64 },
-    ResolveLock {
+    ScanLock {
         ctx: Context,
+        max_ts: u64,
+        start_key: Option<Key>,
+        end_key: Option<Key>,
+        limit: usize,
+    },
+    TxnHeartBeat {
+        ctx: Context,
+        primary_key: Key,
         start_ts: u64,
-        commit_ts: Option<u64>,
+        advise_ttl: u64,
+    },
+    CheckTxnStatus {
+        ctx: Context,
+        primary_key: Key,
+        lock_ts: u64,
+        current_ts: u64,
+    },
+    ResolveLock {
+        ctx: Context,
+        // start_ts -> commit_ts, the locks of a transaction are rolled back if its commit_ts
+        // is 0
+        txn_status: HashMap<u64, u64>,
         scan_key: Option<Key>,
-        keys: Vec<Key>,
+        key_locks: Vec<(Key, Lock)>,
+    },
+    MvccByKey { ctx: Context, key: Key, max_ts: u64 },
+    MvccScan {
+        ctx: Context,
+        start_key: Key,
+        end_key: Option<Key>,
+        limit: usize,
+        max_ts: u64,
     },
+    Import {
+        ctx: Context,
+        mutations: Vec<Mutation>,
+        commit_ts: u64,
+    },
+    // Collects garbage of `keys`, it's sent by the gc worker.
     Gc {
         ctx: Context,
         safe_point: u64,
-        scan_key: Option<Key>,
         keys: Vec<Key>,
     },
     RawGet { ctx: Context, key: Key },
+    RawBatchGet { ctx: Context, keys: Vec<Key> },
+    RawScan {
+        ctx: Context,
+        start_key: Key,
+        limit: usize,
+        key_only: bool,
+        reverse: bool,
+    },
+    RawDeleteRange {
+        ctx: Context,
+        start_key: Key,
+        end_key: Key,
+        // The keys to delete in this batch, they are scanned first if it's empty.
+        keys: Vec<Key>,
+    },
+    // Puts or deletes raw keys, it holds their latches so it's serialized with
+    // `RawCompareAndSwap`.
+    RawWrite {
+        ctx: Context,
+        // `None` means deleting the key.
+        pairs: Vec<(Key, Option<Value>)>,
+    },
+    RawCompareAndSwap {
+        ctx: Context,
+        key: Key,
+        // `None` means the key must not exist.
+        previous_value: Option<Value>,
+        // `None` means deleting the key.
+        value: Option<Value>,
+        ttl: u64,
+    },
     Pause { ctx: Context, duration: u64 },
 }
 
@@ -154,11 +262,13 @@ impl Display for Command {
                        start_ts,
                        ctx)
             }
-            Command::Scan { ref ctx, ref start_key, limit, start_ts, .. } => {
+            Command::Scan { ref ctx, ref start_key, ref end_key, limit, start_ts, reverse, .. } => {
                 write!(f,
-                       "kv::command::scan {}({}) @ {} | {:?}",
+                       "kv::command::scan [{}, {:?})({}) reverse {} @ {} | {:?}",
                        start_key,
+                       end_key,
                        limit,
+                       reverse,
                        start_ts,
                        ctx)
             }
@@ -169,6 +279,14 @@ impl Display for Command {
                        start_ts,
                        ctx)
             }
+            Command::AcquirePessimisticLock { ref ctx, ref keys, start_ts, ref options, .. } => {
+                write!(f,
+                       "kv::command::acquirepessimisticlock keys({}) @ {} {} | {:?}",
+                       keys.len(),
+                       start_ts,
+                       options.for_update_ts,
+                       ctx)
+            }
             Command::Commit { ref ctx, ref keys, lock_ts, commit_ts, .. } => {
                 write!(f,
                        "kv::command::commit {} {} -> {} | {:?}",
@@ -187,26 +305,91 @@ impl Display for Command {
                        start_ts,
                        ctx)
             }
-            Command::ScanLock { ref ctx, max_ts, .. } => {
-                write!(f, "kv::scan_lock {} | {:?}", max_ts, ctx)
+            Command::ScanLock { ref ctx, max_ts, ref start_key, ref end_key, limit } => {
+                write!(f,
+                       "kv::scan_lock {:?} {:?} {} @ {} | {:?}",
+                       start_key,
+                       end_key,
+                       limit,
+                       max_ts,
+                       ctx)
             }
-            Command::ResolveLock { ref ctx, start_ts, commit_ts, .. } => {
+            Command::TxnHeartBeat { ref ctx, ref primary_key, start_ts, advise_ttl } => {
                 write!(f,
-                       "kv::resolve_txn {} -> {:?} | {:?}",
+                       "kv::command::txn_heart_beat {} @ {} ttl {} | {:?}",
+                       primary_key,
                        start_ts,
+                       advise_ttl,
+                       ctx)
+            }
+            Command::CheckTxnStatus { ref ctx, ref primary_key, lock_ts, current_ts } => {
+                write!(f,
+                       "kv::command::check_txn_status {} @ {} curr {} | {:?}",
+                       primary_key,
+                       lock_ts,
+                       current_ts,
+                       ctx)
+            }
+            Command::ResolveLock { ref ctx, ref txn_status, .. } => {
+                write!(f, "kv::resolve_txn {:?} | {:?}", txn_status, ctx)
+            }
+            Command::MvccByKey { ref ctx, ref key, max_ts } => {
+                write!(f, "kv::command::mvcc_by_key {} @ {} | {:?}", key, max_ts, ctx)
+            }
+            Command::MvccScan { ref ctx, ref start_key, ref end_key, limit, max_ts } => {
+                write!(f,
+                       "kv::command::mvcc_scan [{}, {:?})({}) @ {} | {:?}",
+                       start_key,
+                       end_key,
+                       limit,
+                       max_ts,
+                       ctx)
+            }
+            Command::Import { ref ctx, ref mutations, commit_ts } => {
+                write!(f,
+                       "kv::command::import mutations({}) @ {} | {:?}",
+                       mutations.len(),
                        commit_ts,
                        ctx)
             }
-            Command::Gc { ref ctx, safe_point, ref scan_key, .. } => {
+            Command::Gc { ref ctx, safe_point, ref keys } => {
                 write!(f,
-                       "kv::command::gc scan {:?} @ {} | {:?}",
-                       scan_key,
+                       "kv::command::gc keys({}) @ {} | {:?}",
+                       keys.len(),
                        safe_point,
                        ctx)
             }
             Command::RawGet { ref ctx, ref key } => {
                 write!(f, "kv::command::rawget {:?} | {:?}", key, ctx)
             }
+            Command::RawBatchGet { ref ctx, ref keys } => {
+                write!(f, "kv::command::raw_batch_get {} | {:?}", keys.len(), ctx)
+            }
+            Command::RawScan { ref ctx, ref start_key, limit, reverse, .. } => {
+                write!(f,
+                       "kv::command::rawscan {:?}({}) reverse {} | {:?}",
+                       start_key,
+                       limit,
+                       reverse,
+                       ctx)
+            }
+            Command::RawDeleteRange { ref ctx, ref start_key, ref end_key, .. } => {
+                write!(f,
+                       "kv::command::raw_delete_range [{:?}, {:?}) | {:?}",
+                       start_key,
+                       end_key,
+                       ctx)
+            }
+            Command::RawWrite { ref ctx, ref pairs } => {
+                write!(f, "kv::command::raw_write {} | {:?}", pairs.len(), ctx)
+            }
+            Command::RawCompareAndSwap { ref ctx, ref key, ref value, .. } => {
+                write!(f,
+                       "kv::command::raw_compare_and_swap {:?} delete {} | {:?}",
+                       key,
+                       value.is_none(),
+                       ctx)
+            }
             Command::Pause { ref ctx, duration } => {
                 write!(f, "kv::command::pause {} ms | {:?}", duration, ctx)
             }
@@ -220,8 +403,6 @@ impl Debug for Command {
     }
 }
 
-pub const CMD_TAG_GC: &'static str = "gc";
-
 impl Command {
     pub fn readonly(&self) -> bool {
         match *self {
@@ -229,10 +410,14 @@ impl Command {
             Command::BatchGet { .. } |
             Command::Scan { .. } |
             Command::ScanLock { .. } |
+            Command::MvccByKey { .. } |
+            Command::MvccScan { .. } |
             Command::RawGet { .. } |
+            Command::RawBatchGet { .. } |
+            Command::RawScan { .. } |
             Command::Pause { .. } => true,
-            Command::ResolveLock { ref keys, .. } |
-            Command::Gc { ref keys, .. } => keys.is_empty(),
+            Command::ResolveLock { ref key_locks, .. } => key_locks.is_empty(),
+            Command::RawDeleteRange { ref keys, .. } => keys.is_empty(),
             _ => false,
         }
     }
@@ -259,13 +444,24 @@ impl Command {
             Command::BatchGet { .. } => "batch_get",
             Command::Scan { .. } => "scan",
             Command::Prewrite { .. } => "prewrite",
+            Command::AcquirePessimisticLock { .. } => "acquire_pessimistic_lock",
             Command::Commit { .. } => "commit",
             Command::Cleanup { .. } => "cleanup",
             Command::Rollback { .. } => "rollback",
             Command::ScanLock { .. } => "scan_lock",
+            Command::TxnHeartBeat { .. } => "txn_heart_beat",
+            Command::CheckTxnStatus { .. } => "check_txn_status",
             Command::ResolveLock { .. } => "resolve_lock",
-            Command::Gc { .. } => CMD_TAG_GC,
+            Command::MvccByKey { .. } => "mvcc_by_key",
+            Command::MvccScan { .. } => "mvcc_scan",
+            Command::Import { .. } => "import",
+            Command::Gc { .. } => "gc",
             Command::RawGet { .. } => "raw_get",
+            Command::RawBatchGet { .. } => "raw_batch_get",
+            Command::RawScan { .. } => "raw_scan",
+            Command::RawDeleteRange { .. } => "raw_delete_range",
+            Command::RawWrite { .. } => "raw_write",
+            Command::RawCompareAndSwap { .. } => "raw_compare_and_swap",
             Command::Pause { .. } => "pause",
         }
     }
@@ -276,13 +472,24 @@ impl Command {
             Command::BatchGet { start_ts, .. } |
             Command::Scan { start_ts, .. } |
             Command::Prewrite { start_ts, .. } |
+            Command::AcquirePessimisticLock { start_ts, .. } |
             Command::Cleanup { start_ts, .. } |
             Command::Rollback { start_ts, .. } |
-            Command::ResolveLock { start_ts, .. } => start_ts,
-            Command::Commit { lock_ts, .. } => lock_ts,
-            Command::ScanLock { max_ts, .. } => max_ts,
+            Command::TxnHeartBeat { start_ts, .. } => start_ts,
+            Command::Commit { lock_ts, .. } |
+            Command::CheckTxnStatus { lock_ts, .. } => lock_ts,
+            Command::Import { commit_ts, .. } => commit_ts,
             Command::Gc { safe_point, .. } => safe_point,
+            Command::ScanLock { max_ts, .. } |
+            Command::MvccByKey { max_ts, .. } |
+            Command::MvccScan { max_ts, .. } => max_ts,
             Command::RawGet { .. } |
+            Command::RawBatchGet { .. } |
+            Command::RawScan { .. } |
+            Command::RawDeleteRange { .. } |
+            Command::RawWrite { .. } |
+            Command::RawCompareAndSwap { .. } |
+            Command::ResolveLock { .. } |
             Command::Pause { .. } => 0,
         }
     }
@@ -293,13 +500,24 @@ impl Command {
             Command::BatchGet { ref ctx, .. } |
             Command::Scan { ref ctx, .. } |
             Command::Prewrite { ref ctx, .. } |
+            Command::AcquirePessimisticLock { ref ctx, .. } |
             Command::Commit { ref ctx, .. } |
             Command::Cleanup { ref ctx, .. } |
             Command::Rollback { ref ctx, .. } |
             Command::ScanLock { ref ctx, .. } |
+            Command::TxnHeartBeat { ref ctx, .. } |
+            Command::CheckTxnStatus { ref ctx, .. } |
             Command::ResolveLock { ref ctx, .. } |
+            Command::MvccByKey { ref ctx, .. } |
+            Command::MvccScan { ref ctx, .. } |
+            Command::Import { ref ctx, .. } |
             Command::Gc { ref ctx, .. } |
             Command::RawGet { ref ctx, .. } |
+            Command::RawBatchGet { ref ctx, .. } |
+            Command::RawScan { ref ctx, .. } |
+            Command::RawDeleteRange { ref ctx, .. } |
+            Command::RawWrite { ref ctx, .. } |
+            Command::RawCompareAndSwap { ref ctx, .. } |
             Command::Pause { ref ctx, .. } => ctx,
         }
     }
@@ -310,13 +528,24 @@ impl Command {
             Command::BatchGet { ref mut ctx, .. } |
             Command::Scan { ref mut ctx, .. } |
             Command::Prewrite { ref mut ctx, .. } |
+            Command::AcquirePessimisticLock { ref mut ctx, .. } |
             Command::Commit { ref mut ctx, .. } |
             Command::Cleanup { ref mut ctx, .. } |
             Command::Rollback { ref mut ctx, .. } |
             Command::ScanLock { ref mut ctx, .. } |
+            Command::TxnHeartBeat { ref mut ctx, .. } |
+            Command::CheckTxnStatus { ref mut ctx, .. } |
             Command::ResolveLock { ref mut ctx, .. } |
+            Command::MvccByKey { ref mut ctx, .. } |
+            Command::MvccScan { ref mut ctx, .. } |
+            Command::Import { ref mut ctx, .. } |
             Command::Gc { ref mut ctx, .. } |
             Command::RawGet { ref mut ctx, .. } |
+            Command::RawBatchGet { ref mut ctx, .. } |
+            Command::RawScan { ref mut ctx, .. } |
+            Command::RawDeleteRange { ref mut ctx, .. } |
+            Command::RawWrite { ref mut ctx, .. } |
+            Command::RawCompareAndSwap { ref mut ctx, .. } |
             Command::Pause { ref mut ctx, .. } => ctx,
         }
     }
@@ -329,6 +558,12 @@ pub struct Options {
     pub lock_ttl: u64,
     pub skip_constraint_check: bool,
     pub key_only: bool,
+    // Non-zero for pessimistic transactions.
+    pub for_update_ts: u64,
+    // Whether each mutation of a pessimistic prewrite is locked pessimistically.
+    pub is_pessimistic_lock: Vec<bool>,
+    // Non-zero to commit the prewrite in one phase at this timestamp, without any lock.
+    pub one_pc_commit_ts: u64,
 }
 
 impl Options {
@@ -337,6 +572,9 @@ impl Options {
             lock_ttl: lock_ttl,
             skip_constraint_check: skip_constraint_check,
             key_only: key_only,
+            for_update_ts: 0,
+            is_pessimistic_lock: vec![],
+            one_pc_commit_ts: 0,
         }
     }
 }
@@ -344,12 +582,16 @@ impl Options {
 struct StorageHandle {
     handle: Option<thread::JoinHandle<()>>,
     receiver: Option<Receiver<Msg>>,
+    waiter_mgr_worker: FutureWorker<WaiterTask>,
 }
 
 pub struct Storage {
     engine: Box<Engine>,
     sendch: SyncSendCh<Msg>,
     handle: Arc<Mutex<StorageHandle>>,
+    gc_worker: GcWorker,
+    enable_ttl: bool,
+    max_read_ts: MaxReadTs,
 }
 
 impl Storage {
@@ -359,12 +601,16 @@ impl Storage {
 
         info!("storage {:?} started.", engine);
         Ok(Storage {
+            gc_worker: GcWorker::new(engine.clone(), sendch.clone()),
             engine: engine,
             sendch: sendch,
             handle: Arc::new(Mutex::new(StorageHandle {
                 handle: None,
                 receiver: Some(rx),
+                waiter_mgr_worker: FutureWorker::new("waiter-manager"),
             })),
+            enable_ttl: config.enable_ttl,
+            max_read_ts: MaxReadTs::new(),
         })
     }
 
@@ -374,6 +620,7 @@ impl Storage {
     }
 
     pub fn start(&mut self, config: &Config) -> Result<()> {
+        try!(config.validate());
         let mut handle = self.handle.lock().unwrap();
         if handle.handle.is_some() {
             return Err(box_err!("scheduler is already running"));
@@ -385,13 +632,31 @@ impl Storage {
         let sched_concurrency = config.sched_concurrency;
         let sched_worker_pool_size = config.sched_worker_pool_size;
         let sched_too_busy_threshold = config.sched_too_busy_threshold;
+        let stall_soft_limits = config.stall_soft_limits();
+        let stall_hard_limits = config.stall_hard_limits();
+        let enable_ttl = self.enable_ttl;
+        let waiter_mgr = if config.wait_for_lock_timeout > Duration::from_secs(0) {
+            let waiter_mgr = WaiterManager::new(self.sendch.clone(),
+                                                handle.waiter_mgr_worker.scheduler(),
+                                                config.wait_for_lock_timeout);
+            try!(handle.waiter_mgr_worker.start(waiter_mgr));
+            Some(handle.waiter_mgr_worker.scheduler())
+        } else {
+            None
+        };
         let ch = self.sendch.clone();
+        let max_read_ts = self.max_read_ts.clone();
         let h = try!(builder.spawn(move || {
             let mut sched = Scheduler::new(engine,
                                            ch,
                                            sched_concurrency,
                                            sched_worker_pool_size,
-                                           sched_too_busy_threshold);
+                                           sched_too_busy_threshold,
+                                           stall_soft_limits,
+                                           stall_hard_limits,
+                                           enable_ttl,
+                                           waiter_mgr,
+                                           max_read_ts);
             if let Err(e) = sched.run(rx) {
                 panic!("scheduler run err:{:?}", e);
             }
@@ -399,6 +664,7 @@ impl Storage {
         }));
         handle.handle = Some(h);
 
+        try!(self.gc_worker.start());
         Ok(())
     }
 
@@ -408,6 +674,9 @@ impl Storage {
             return Ok(());
         }
 
+        // The gc worker writes through the scheduler, so stop it first.
+        try!(self.gc_worker.stop());
+
         if let Err(e) = self.sendch.send(Msg::Quit) {
             error!("send quit cmd to scheduler failed, error:{:?}", e);
             return Err(box_err!("failed to ask sched to quit: {:?}", e));
@@ -417,6 +686,13 @@ impl Storage {
         if let Err(e) = h.join() {
             return Err(box_err!("failed to join sched_handle, err:{:?}", e));
         }
+        // Stop the waiter manager after the scheduler, the waiting commands get their results
+        // without waiting.
+        if let Some(h) = handle.waiter_mgr_worker.stop() {
+            if let Err(e) = h.join() {
+                return Err(box_err!("failed to join waiter manager, err:{:?}", e));
+            }
+        }
 
         info!("storage {:?} closed.", self.engine);
         Ok(())
@@ -426,6 +702,11 @@ impl Storage {
         self.engine.clone()
     }
 
+    /// Gets the max read ts of the regions, the readers out of storage must update it too.
+    pub fn get_max_read_ts(&self) -> MaxReadTs {
+        self.max_read_ts.clone()
+    }
+
     fn send(&self, cmd: Command, cb: StorageCb) -> Result<()> {
         box_try!(self.sendch.try_send(Msg::RawCmd { cmd: cmd, cb: cb }));
         Ok(())
@@ -465,20 +746,29 @@ impl Storage {
         Ok(())
     }
 
+    /// Scans at most `limit` keys visible at `start_ts` in range [`start_key`, `end_key`).
+    ///
+    /// A reverse scan returns keys in descending order within [`end_key`, `start_key`), so
+    /// `start_key` is the exclusive upper bound and `end_key` the inclusive lower bound.
+    #[allow(too_many_arguments)]
     pub fn async_scan(&self,
                       ctx: Context,
                       start_key: Key,
+                      end_key: Option<Key>,
                       limit: usize,
                       start_ts: u64,
                       options: Options,
+                      reverse: bool,
                       callback: Callback<Vec<Result<KvPair>>>)
                       -> Result<()> {
         let cmd = Command::Scan {
             ctx: ctx,
             start_key: start_key,
+            end_key: end_key,
             limit: limit,
             start_ts: start_ts,
             options: options,
+            reverse: reverse,
         };
         let tag = cmd.tag();
         try!(self.send(cmd, StorageCb::KvPairs(callback)));
@@ -516,6 +806,27 @@ impl Storage {
         Ok(())
     }
 
+    pub fn async_acquire_pessimistic_lock(&self,
+                                          ctx: Context,
+                                          keys: Vec<Key>,
+                                          primary: Vec<u8>,
+                                          start_ts: u64,
+                                          options: Options,
+                                          callback: Callback<Vec<Result<()>>>)
+                                          -> Result<()> {
+        let cmd = Command::AcquirePessimisticLock {
+            ctx: ctx,
+            keys: keys,
+            primary: primary,
+            start_ts: start_ts,
+            options: options,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::Booleans(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
     pub fn async_commit(&self,
                         ctx: Context,
                         keys: Vec<Key>,
@@ -552,6 +863,26 @@ impl Storage {
         Ok(())
     }
 
+    /// Imports sorted `mutations` committed at `commit_ts` by ingesting SST files, without going
+    /// through prewrite and commit. Only puts and deletes are supported, and there must be no
+    /// locks or running writes in the range of the keys.
+    pub fn async_import(&self,
+                        ctx: Context,
+                        mutations: Vec<Mutation>,
+                        commit_ts: u64,
+                        callback: Callback<()>)
+                        -> Result<()> {
+        let cmd = Command::Import {
+            ctx: ctx,
+            mutations: mutations,
+            commit_ts: commit_ts,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::Boolean(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
     pub fn async_rollback(&self,
                           ctx: Context,
                           keys: Vec<Key>,
@@ -569,14 +900,23 @@ impl Storage {
         Ok(())
     }
 
+    /// Scans at most `limit` locks in `[start_key, end_key)` whose ts is not greater than
+    /// `max_ts`, 0 means no limit. Fetch the next page from the key after the last returned one.
+    #[allow(too_many_arguments)]
     pub fn async_scan_lock(&self,
                            ctx: Context,
                            max_ts: u64,
+                           start_key: Option<Key>,
+                           end_key: Option<Key>,
+                           limit: usize,
                            callback: Callback<Vec<LockInfo>>)
                            -> Result<()> {
         let cmd = Command::ScanLock {
             ctx: ctx,
             max_ts: max_ts,
+            start_key: start_key,
+            end_key: end_key,
+            limit: limit,
         };
         let tag = cmd.tag();
         try!(self.send(cmd, StorageCb::Locks(callback)));
@@ -584,18 +924,56 @@ impl Storage {
         Ok(())
     }
 
+    pub fn async_txn_heart_beat(&self,
+                                ctx: Context,
+                                primary_key: Key,
+                                start_ts: u64,
+                                advise_ttl: u64,
+                                callback: Callback<TxnStatus>)
+                                -> Result<()> {
+        let cmd = Command::TxnHeartBeat {
+            ctx: ctx,
+            primary_key: primary_key,
+            start_ts: start_ts,
+            advise_ttl: advise_ttl,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::TxnStatus(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    pub fn async_check_txn_status(&self,
+                                  ctx: Context,
+                                  primary_key: Key,
+                                  lock_ts: u64,
+                                  current_ts: u64,
+                                  callback: Callback<TxnStatus>)
+                                  -> Result<()> {
+        let cmd = Command::CheckTxnStatus {
+            ctx: ctx,
+            primary_key: primary_key,
+            lock_ts: lock_ts,
+            current_ts: current_ts,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::TxnStatus(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Resolves the locks of the transactions in `txn_status` in one pass, it maps the start_ts
+    /// of a transaction to its commit_ts, or to 0 if the transaction should be rolled back.
     pub fn async_resolve_lock(&self,
                               ctx: Context,
-                              start_ts: u64,
-                              commit_ts: Option<u64>,
+                              txn_status: HashMap<u64, u64>,
                               callback: Callback<()>)
                               -> Result<()> {
         let cmd = Command::ResolveLock {
             ctx: ctx,
-            start_ts: start_ts,
-            commit_ts: commit_ts,
+            txn_status: txn_status,
             scan_key: None,
-            keys: vec![],
+            key_locks: vec![],
         };
         let tag = cmd.tag();
         try!(self.send(cmd, StorageCb::Boolean(callback)));
@@ -603,19 +981,83 @@ impl Storage {
         Ok(())
     }
 
-    pub fn async_gc(&self, ctx: Context, safe_point: u64, callback: Callback<()>) -> Result<()> {
-        let cmd = Command::Gc {
+    /// Gets the lock, write records and values of `key` that are not newer than `max_ts`,
+    /// mainly for debugging.
+    pub fn async_mvcc_by_key(&self,
+                             ctx: Context,
+                             key: Key,
+                             max_ts: u64,
+                             callback: Callback<MvccInfo>)
+                             -> Result<()> {
+        let cmd = Command::MvccByKey {
             ctx: ctx,
-            safe_point: safe_point,
-            scan_key: None,
-            keys: vec![],
+            key: key,
+            max_ts: max_ts,
         };
         let tag = cmd.tag();
-        try!(self.send(cmd, StorageCb::Boolean(callback)));
+        try!(self.send(cmd, StorageCb::MvccInfoByKey(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Like `async_mvcc_by_key`, but for at most `limit` keys in range [`start_key`, `end_key`).
+    pub fn async_mvcc_scan(&self,
+                           ctx: Context,
+                           start_key: Key,
+                           end_key: Option<Key>,
+                           limit: usize,
+                           max_ts: u64,
+                           callback: Callback<Vec<(Vec<u8>, MvccInfo)>>)
+                           -> Result<()> {
+        let cmd = Command::MvccScan {
+            ctx: ctx,
+            start_key: start_key,
+            end_key: end_key,
+            limit: limit,
+            max_ts: max_ts,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::MvccInfos(callback)));
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
     }
 
+    /// Collects garbage of the region in `ctx` with the background gc worker.
+    pub fn async_gc(&self, ctx: Context, safe_point: u64, callback: Callback<()>) -> Result<()> {
+        try!(self.gc_worker.async_gc(ctx, safe_point, callback));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&["gc"]).inc();
+        Ok(())
+    }
+
+    /// Pauses the gc worker, e.g. when the store is under heavy load.
+    pub fn pause_gc(&self) {
+        self.gc_worker.pause();
+    }
+
+    pub fn resume_gc(&self) {
+        self.gc_worker.resume();
+    }
+
+    /// Collects garbage of the regions led by the local store in the background whenever the
+    /// GC safe point in PD advances. It's checked every `interval`.
+    pub fn start_auto_gc<C, R>(&self,
+                               pd_client: Arc<C>,
+                               router: R,
+                               interval: Duration)
+                               -> Result<()>
+        where C: PdClient + 'static,
+              R: RaftStoreRouter + 'static
+    {
+        self.gc_worker.start_auto_gc(pd_client, router, interval)
+    }
+
+    /// Reports the owner and waiters of every latch in use, for diagnosing hot keys. tikv-server
+    /// logs them on SIGUSR1, and reports them with the metrics, see `monitor_latches`.
+    pub fn async_dump_latches(&self, callback: LatchesCallback) -> Result<()> {
+        box_try!(self.sendch.try_send(Msg::DumpLatches { cb: callback }));
+        Ok(())
+    }
+
     pub fn async_raw_get(&self,
                          ctx: Context,
                          key: Vec<u8>,
@@ -630,18 +1072,86 @@ impl Storage {
         Ok(())
     }
 
+    pub fn async_raw_batch_get(&self,
+                               ctx: Context,
+                               keys: Vec<Vec<u8>>,
+                               callback: Callback<Vec<Result<KvPair>>>)
+                               -> Result<()> {
+        let cmd = Command::RawBatchGet {
+            ctx: ctx,
+            keys: keys.into_iter().map(Key::from_encoded).collect(),
+        };
+        try!(self.send(cmd, StorageCb::KvPairs(callback)));
+        RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["batch_get"]).inc();
+        Ok(())
+    }
+
+    /// Scans at most `limit` raw key-value pairs starting from `start_key`.
+    ///
+    /// When `reverse` is true, keys are returned in descending order and `start_key`
+    /// is treated as an exclusive upper bound.
+    pub fn async_raw_scan(&self,
+                          ctx: Context,
+                          start_key: Vec<u8>,
+                          limit: usize,
+                          key_only: bool,
+                          reverse: bool,
+                          callback: Callback<Vec<Result<KvPair>>>)
+                          -> Result<()> {
+        let cmd = Command::RawScan {
+            ctx: ctx,
+            start_key: Key::from_encoded(start_key),
+            limit: limit,
+            key_only: key_only,
+            reverse: reverse,
+        };
+        try!(self.send(cmd, StorageCb::KvPairs(callback)));
+        let tag = if reverse { "reverse_scan" } else { "scan" };
+        RAWKV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Appends the expiry time to a raw value if TTL is enabled.
+    fn encode_raw_value(&self, mut value: Vec<u8>, ttl: u64) -> Result<Vec<u8>> {
+        if !self.enable_ttl {
+            if ttl != 0 {
+                return Err(Error::TTLNotEnabled);
+            }
+            return Ok(value);
+        }
+        ttl::append_expire_ts(&mut value, ttl::ttl_to_expire_ts(ttl));
+        Ok(value)
+    }
+
+    /// Puts or deletes raw keys while holding their latches.
+    fn raw_write(&self,
+                 ctx: Context,
+                 pairs: Vec<(Key, Option<Value>)>,
+                 callback: Callback<()>)
+                 -> Result<()> {
+        let cmd = Command::RawWrite {
+            ctx: ctx,
+            pairs: pairs,
+        };
+        self.send(cmd, StorageCb::Boolean(callback))
+    }
+
+    /// Puts a raw key-value pair that expires after `ttl` seconds. A `ttl` of 0 never expires.
     pub fn async_raw_put(&self,
                          ctx: Context,
                          key: Vec<u8>,
                          value: Vec<u8>,
+                         ttl: u64,
                          callback: Callback<()>)
                          -> Result<()> {
-        try!(self.engine
-            .async_write(&ctx,
-                         vec![Modify::Put(CF_DEFAULT, Key::from_encoded(key), value)],
-                         box |(_, res): (_, engine::Result<_>)| {
-                             callback(res.map_err(Error::from))
-                         }));
+        let value = match self.encode_raw_value(value, ttl) {
+            Ok(value) => value,
+            Err(e) => {
+                callback(Err(e));
+                return Ok(());
+            }
+        };
+        try!(self.raw_write(ctx, vec![(Key::from_encoded(key), Some(value))], callback));
         RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["put"]).inc();
         Ok(())
     }
@@ -651,13 +1161,123 @@ impl Storage {
                             key: Vec<u8>,
                             callback: Callback<()>)
                             -> Result<()> {
+        try!(self.raw_write(ctx, vec![(Key::from_encoded(key), None)], callback));
+        RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["delete"]).inc();
+        Ok(())
+    }
+
+    /// Puts raw key-value pairs that expire after `ttl` seconds. A `ttl` of 0 never expires.
+    pub fn async_raw_batch_put(&self,
+                               ctx: Context,
+                               pairs: Vec<KvPair>,
+                               ttl: u64,
+                               callback: Callback<()>)
+                               -> Result<()> {
+        let mut encoded = Vec::with_capacity(pairs.len());
+        for (k, v) in pairs {
+            match self.encode_raw_value(v, ttl) {
+                Ok(v) => encoded.push((Key::from_encoded(k), Some(v))),
+                Err(e) => {
+                    callback(Err(e));
+                    return Ok(());
+                }
+            }
+        }
+        try!(self.raw_write(ctx, encoded, callback));
+        RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["batch_put"]).inc();
+        Ok(())
+    }
+
+    pub fn async_raw_batch_delete(&self,
+                                  ctx: Context,
+                                  keys: Vec<Vec<u8>>,
+                                  callback: Callback<()>)
+                                  -> Result<()> {
+        let pairs = keys.into_iter().map(|k| (Key::from_encoded(k), None)).collect();
+        try!(self.raw_write(ctx, pairs, callback));
+        RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["batch_delete"]).inc();
+        Ok(())
+    }
+
+    /// Deletes all raw keys in range [`start_key`, `end_key`).
+    ///
+    /// An empty `end_key` means the range is unbounded. The range is deleted in batches, so it
+    /// is not atomic, but every batch holds the latches of the keys it deletes.
+    pub fn async_raw_delete_range(&self,
+                                  ctx: Context,
+                                  start_key: Vec<u8>,
+                                  end_key: Vec<u8>,
+                                  callback: Callback<()>)
+                                  -> Result<()> {
+        let cmd = Command::RawDeleteRange {
+            ctx: ctx,
+            start_key: Key::from_encoded(start_key),
+            end_key: Key::from_encoded(end_key),
+            keys: vec![],
+        };
+        try!(self.send(cmd, StorageCb::Boolean(callback)));
+        RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["delete_range"]).inc();
+        Ok(())
+    }
+
+    /// Atomically sets `key` to `value`, or deletes it if `value` is `None`, if its current value
+    /// is `previous_value`. `None` as `previous_value` means the key must not exist.
+    ///
+    /// The callback gets the current value before the command and whether it succeeded. The
+    /// command holds the latch of `key`, so it is serialized with other compare-and-swaps and
+    /// with raw puts, deletes and range deletes of `key`.
+    pub fn async_raw_compare_and_swap(&self,
+                                      ctx: Context,
+                                      key: Vec<u8>,
+                                      previous_value: Option<Vec<u8>>,
+                                      value: Option<Vec<u8>>,
+                                      ttl: u64,
+                                      callback: Callback<(Option<Value>, bool)>)
+                                      -> Result<()> {
+        if ttl != 0 && !self.enable_ttl {
+            callback(Err(Error::TTLNotEnabled));
+            return Ok(());
+        }
+        let cmd = Command::RawCompareAndSwap {
+            ctx: ctx,
+            key: Key::from_encoded(key),
+            previous_value: previous_value,
+            value: value,
+            ttl: ttl,
+        };
+        try!(self.send(cmd, StorageCb::RawCompareAndSwap(callback)));
+        RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["compare_and_swap"]).inc();
+        Ok(())
+    }
+
+    /// Destroys all the keys in range [`start_key`, `end_key`) on every replica of the region,
+    /// without leaving any MVCC record.
+    ///
+    /// It is unsafe because the range is deleted from the engine directly: running
+    /// transactions and snapshots may see the keys disappear. Only use it for the data that
+    /// will never be accessed again, e.g. dropped tables and indexes.
+    pub fn async_unsafe_destroy_range(&self,
+                                      ctx: Context,
+                                      start_key: Vec<u8>,
+                                      end_key: Vec<u8>,
+                                      callback: Callback<()>)
+                                      -> Result<()> {
+        // The range must be bounded, an empty end key would destroy the rest of the region.
+        if end_key.is_empty() || start_key >= end_key {
+            callback(Err(box_err!("invalid range to destroy: [{}, {})",
+                                  escape(&start_key),
+                                  escape(&end_key))));
+            return Ok(());
+        }
+        let modifies = vec![Modify::DeleteRange(Key::from_encoded(start_key),
+                                                Key::from_encoded(end_key))];
         try!(self.engine
             .async_write(&ctx,
-                         vec![Modify::Delete(CF_DEFAULT, Key::from_encoded(key))],
+                         modifies,
                          box |(_, res): (_, engine::Result<_>)| {
                              callback(res.map_err(Error::from))
                          }));
-        RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["delete"]).inc();
+        KV_COMMAND_COUNTER_VEC.with_label_values(&["unsafe_destroy_range"]).inc();
         Ok(())
     }
 }
@@ -668,6 +1288,8 @@ impl Clone for Storage {
             engine: self.engine.clone(),
             sendch: self.sendch.clone(),
             handle: self.handle.clone(),
+            gc_worker: self.gc_worker.clone(),
+            enable_ttl: self.enable_ttl,
         }
     }
 }
@@ -701,6 +1323,9 @@ quick_error! {
         SchedTooBusy {
             description("scheduler is too busy")
         }
+        TTLNotEnabled {
+            description("ttl is not enabled")
+        }
     }
 }
 
@@ -726,7 +1351,11 @@ pub fn get_tag_from_header(header: &errorpb::Error) -> &'static str {
 mod tests {
     use super::*;
     use std::sync::mpsc::{channel, Sender};
+    use std::thread;
+    use std::time::Duration;
     use kvproto::kvrpcpb::Context;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use raftstore::store::EngineStallSignals;
 
     fn expect_get_none(done: Sender<i32>, id: i32) -> Callback<Option<Value>> {
         Box::new(move |x: Result<Option<Value>>| {
@@ -887,9 +1516,11 @@ mod tests {
         rx.recv().unwrap();
         storage.async_scan(Context::new(),
                         make_key(b"\x00"),
+                        None,
                         1000,
                         5,
                         Options::default(),
+                        false,
                         expect_scan(tx.clone(),
                                     vec![
             Some((b"a".to_vec(), b"aa".to_vec())),
@@ -1001,6 +1632,97 @@ mod tests {
         storage.stop().unwrap();
     }
 
+    #[test]
+    fn test_wait_for_lock() {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        for &(k, ts) in &[(b"x", 10), (b"y", 20)] {
+            storage.async_prewrite(Context::new(),
+                                vec![Mutation::Put((make_key(k), k.to_vec()))],
+                                k.to_vec(),
+                                ts,
+                                Options::default(),
+                                expect_ok(tx.clone(), 0))
+                .unwrap();
+            rx.recv().unwrap();
+        }
+
+        // txn 20 waits for the lock of txn 10 on "x".
+        let done = tx.clone();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"x".to_vec()))],
+                            b"y".to_vec(),
+                            20,
+                            Options::default(),
+                            box move |res: Result<Vec<Result<()>>>| {
+                                assert!(res.unwrap().is_empty());
+                                done.send(1).unwrap();
+                            })
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+        // txn 10 waiting for the lock of txn 20 on "y" is a deadlock.
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"y"), b"y".to_vec()))],
+                            b"x".to_vec(),
+                            10,
+                            Options::default(),
+                            expect_fail(tx.clone(), 2))
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), 2);
+
+        // txn 20 goes on after txn 10 commits.
+        storage.async_commit(Context::new(),
+                          vec![make_key(b"x")],
+                          10,
+                          15,
+                          expect_ok(tx.clone(), 3))
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), 3);
+        assert_eq!(rx.recv().unwrap(), 1);
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_wait_for_lock_resolved() {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"x".to_vec()))],
+                            b"x".to_vec(),
+                            10,
+                            Options::default(),
+                            expect_ok(tx.clone(), 0))
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), 0);
+
+        // txn 20 waits for the lock of txn 10 on "x".
+        let done = tx.clone();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"x".to_vec()))],
+                            b"x".to_vec(),
+                            20,
+                            Options::default(),
+                            box move |res: Result<Vec<Result<()>>>| {
+                                assert!(res.unwrap().is_empty());
+                                done.send(1).unwrap();
+                            })
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        // txn 20 goes on after the lock of txn 10 is rolled back by resolve lock.
+        let mut txn_status = HashMap::default();
+        txn_status.insert(10, 0);
+        storage.async_resolve_lock(Context::new(), txn_status, expect_ok(tx.clone(), 2))
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), 2);
+        assert_eq!(rx.recv().unwrap(), 1);
+        storage.stop().unwrap();
+    }
+
     #[test]
     fn test_sched_too_busy() {
         let mut config = Config::new();
@@ -1041,6 +1763,91 @@ mod tests {
         storage.stop().unwrap();
     }
 
+    #[derive(Debug)]
+    struct StallEngine {
+        engine: Box<Engine>,
+        signals: Arc<Mutex<EngineStallSignals>>,
+    }
+
+    impl Engine for StallEngine {
+        fn async_write(&self,
+                       ctx: &Context,
+                       batch: Vec<Modify>,
+                       callback: engine::Callback<()>)
+                       -> engine::Result<()> {
+            self.engine.async_write(ctx, batch, callback)
+        }
+
+        fn async_snapshot(&self,
+                          ctx: &Context,
+                          callback: engine::Callback<Box<Snapshot>>)
+                          -> engine::Result<()> {
+            self.engine.async_snapshot(ctx, callback)
+        }
+
+        fn stall_signals(&self) -> Option<EngineStallSignals> {
+            Some(*self.signals.lock().unwrap())
+        }
+
+        fn clone(&self) -> Box<Engine + 'static> {
+            box StallEngine {
+                engine: self.engine.clone(),
+                signals: self.signals.clone(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_sched_write_stall() {
+        let mut config = Config::new();
+        config.sched_l0_files_soft_limit = 10;
+        config.sched_l0_files_hard_limit = 20;
+        let signals = Arc::new(Mutex::new(EngineStallSignals::default()));
+        let engine = box StallEngine {
+            engine: engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap(),
+            signals: signals.clone(),
+        };
+        let mut storage = Storage::from_engine(engine, &config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        fn prewrite(storage: &Storage, pri: CommandPri, key: &[u8], cb: Callback<Vec<Result<()>>>) {
+            let mut ctx = Context::new();
+            ctx.set_priority(pri);
+            storage.async_prewrite(ctx,
+                                vec![Mutation::Put((make_key(key), b"v".to_vec()))],
+                                key.to_vec(),
+                                100,
+                                Options::default(),
+                                cb)
+                .unwrap();
+        }
+        // The signals are refreshed once a second.
+        let refresh = || thread::sleep(Duration::from_millis(1100));
+
+        // Above the soft limit, only low priority writes are rejected.
+        signals.lock().unwrap().l0_files = 10;
+        refresh();
+        prewrite(&storage, CommandPri::Low, b"a", expect_too_busy(tx.clone(), 0));
+        assert_eq!(rx.recv().unwrap(), 0);
+        prewrite(&storage, CommandPri::Normal, b"b", expect_ok(tx.clone(), 1));
+        assert_eq!(rx.recv().unwrap(), 1);
+
+        // Above the hard limit, only high priority writes are accepted.
+        signals.lock().unwrap().l0_files = 20;
+        refresh();
+        prewrite(&storage, CommandPri::Normal, b"c", expect_too_busy(tx.clone(), 2));
+        assert_eq!(rx.recv().unwrap(), 2);
+        prewrite(&storage, CommandPri::High, b"d", expect_ok(tx.clone(), 3));
+        assert_eq!(rx.recv().unwrap(), 3);
+
+        // All writes are accepted once the engine catches up.
+        signals.lock().unwrap().l0_files = 0;
+        refresh();
+        prewrite(&storage, CommandPri::Low, b"e", expect_ok(tx.clone(), 4));
+        assert_eq!(rx.recv().unwrap(), 4);
+        storage.stop().unwrap();
+    }
+
     #[test]
     fn test_cleanup() {
         let config = Config::new();
@@ -1070,6 +1877,174 @@ mod tests {
         storage.stop().unwrap();
     }
 
+    #[test]
+    fn test_one_pc_max_read_ts() {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage.async_get(Context::new(), make_key(b"x"), 30, expect_get_none(tx.clone(), 0))
+            .unwrap();
+        rx.recv().unwrap();
+
+        let mut options = Options::default();
+        // The value read at 30 can't be changed by a commit before it.
+        options.one_pc_commit_ts = 25;
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                            b"x".to_vec(),
+                            20,
+                            options,
+                            box move |res: Result<_>| {
+                                match res {
+                                    Err(Error::Txn(txn::Error::CommitTsExpired {
+                                        max_read_ts, ..
+                                    })) => assert_eq!(max_read_ts, 30),
+                                    res => panic!("expect commit ts expired, got {:?}", res),
+                                }
+                                tx.send(1).unwrap();
+                            })
+            .unwrap();
+        rx.recv().unwrap();
+        let (tx, rx) = channel();
+        storage.async_get(Context::new(), make_key(b"x"), 30, expect_get_none(tx.clone(), 2))
+            .unwrap();
+        rx.recv().unwrap();
+
+        let mut options = Options::default();
+        options.one_pc_commit_ts = 35;
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                            b"x".to_vec(),
+                            20,
+                            options,
+                            expect_ok(tx.clone(), 3))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_get(Context::new(),
+                       make_key(b"x"),
+                       40,
+                       expect_get_val(tx.clone(), b"100".to_vec(), 4))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_unsafe_destroy_range() {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        for (i, k) in [b"a", b"b", b"c"].iter().enumerate() {
+            storage.async_prewrite(Context::new(),
+                                vec![Mutation::Put((make_key(*k), b"v".to_vec()))],
+                                k.to_vec(),
+                                100,
+                                Options::default(),
+                                expect_ok(tx.clone(), i as i32))
+                .unwrap();
+            rx.recv().unwrap();
+        }
+        let (start_key, end_key) = (make_key(b"a").encoded().clone(),
+                                    make_key(b"c").encoded().clone());
+        // Unbounded and empty ranges are rejected.
+        storage.async_unsafe_destroy_range(Context::new(),
+                                        start_key.clone(),
+                                        vec![],
+                                        expect_fail(tx.clone(), 3))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_unsafe_destroy_range(Context::new(),
+                                        end_key.clone(),
+                                        start_key.clone(),
+                                        expect_fail(tx.clone(), 4))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_unsafe_destroy_range(Context::new(),
+                                        start_key.clone(),
+                                        start_key.clone(),
+                                        expect_fail(tx.clone(), 5))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_unsafe_destroy_range(Context::new(),
+                                        start_key.clone(),
+                                        end_key.clone(),
+                                        expect_ok(tx.clone(), 6))
+            .unwrap();
+        rx.recv().unwrap();
+        // Only the lock of "c" is left.
+        storage.async_scan_lock(Context::new(),
+                             101,
+                             None,
+                             None,
+                             0,
+                             box move |res: Result<Vec<LockInfo>>| {
+                                 let locks = res.unwrap();
+                                 assert_eq!(locks.len(), 1);
+                                 assert_eq!(locks[0].get_key(), b"c");
+                                 tx.send(7).unwrap();
+                             })
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), 7);
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_import() {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let long_value = vec![b'v'; SHORT_VALUE_MAX_LEN + 1];
+        storage.async_import(Context::new(),
+                          vec![Mutation::Put((make_key(b"a"), b"1".to_vec())),
+                               Mutation::Put((make_key(b"b"), long_value.clone()))],
+                          10,
+                          expect_ok(tx.clone(), 0))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_get(Context::new(),
+                       make_key(b"a"),
+                       10,
+                       expect_get_val(tx.clone(), b"1".to_vec(), 1))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_get(Context::new(),
+                       make_key(b"b"),
+                       10,
+                       expect_get_val(tx.clone(), long_value, 2))
+            .unwrap();
+        rx.recv().unwrap();
+
+        // Unsorted keys are rejected.
+        storage.async_import(Context::new(),
+                          vec![Mutation::Put((make_key(b"d"), b"1".to_vec())),
+                               Mutation::Put((make_key(b"c"), b"1".to_vec()))],
+                          20,
+                          expect_fail(tx.clone(), 3))
+            .unwrap();
+        rx.recv().unwrap();
+
+        // Locked keys in the range are rejected.
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"y"), b"100".to_vec()))],
+                            b"y".to_vec(),
+                            30,
+                            Options::default(),
+                            expect_ok(tx.clone(), 4))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_import(Context::new(),
+                          vec![Mutation::Put((make_key(b"x"), b"1".to_vec())),
+                               Mutation::Delete(make_key(b"z"))],
+                          40,
+                          expect_fail(tx.clone(), 5))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
     #[test]
     fn test_high_priority_get_put() {
         let config = Config::new();
@@ -1159,4 +2134,108 @@ mod tests {
 
         storage.stop().unwrap();
     }
+
+    #[derive(Debug)]
+    struct BlockWriteEngine {
+        engine: Box<Engine>,
+        block: Arc<AtomicBool>,
+    }
+
+    impl Engine for BlockWriteEngine {
+        fn async_write(&self,
+                       ctx: &Context,
+                       batch: Vec<Modify>,
+                       callback: engine::Callback<()>)
+                       -> engine::Result<()> {
+            let block = self.block.clone();
+            self.engine.async_write(ctx,
+                                    batch,
+                                    box move |res| {
+                thread::spawn(move || {
+                    while block.load(Ordering::SeqCst) {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    callback(res);
+                });
+            })
+        }
+
+        fn async_snapshot(&self,
+                          ctx: &Context,
+                          callback: engine::Callback<Box<Snapshot>>)
+                          -> engine::Result<()> {
+            self.engine.async_snapshot(ctx, callback)
+        }
+
+        fn clone(&self) -> Box<Engine + 'static> {
+            box BlockWriteEngine {
+                engine: self.engine.clone(),
+                block: self.block.clone(),
+            }
+        }
+    }
+
+    fn must_dump_latches(storage: &Storage) -> Vec<LatchInfo> {
+        let (tx, rx) = channel();
+        storage.async_dump_latches(box move |infos: Vec<LatchInfo>| {
+                tx.send(infos).unwrap();
+            })
+            .unwrap();
+        rx.recv().unwrap()
+    }
+
+    #[test]
+    fn test_dump_latches() {
+        let mut config = Config::new();
+        config.wait_for_lock_timeout = Duration::from_secs(0);
+        let block = Arc::new(AtomicBool::new(true));
+        let engine = box BlockWriteEngine {
+            engine: engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap(),
+            block: block.clone(),
+        };
+        let mut storage = Storage::from_engine(engine, &config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        // The first prewrite owns the latches of x and y until its write finishes.
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"100".to_vec())),
+                                 Mutation::Put((make_key(b"y"), b"100".to_vec()))],
+                            b"x".to_vec(),
+                            100,
+                            Options::default(),
+                            expect_ok(tx.clone(), 0))
+            .unwrap();
+        let mut ctx = Context::new();
+        ctx.set_priority(CommandPri::High);
+        storage.async_prewrite(ctx,
+                            vec![Mutation::Put((make_key(b"x"), b"101".to_vec()))],
+                            b"x".to_vec(),
+                            101,
+                            Options::default(),
+                            expect_ok(tx.clone(), 1))
+            .unwrap();
+
+        // x and y may share a latch, so check the owner of every latch in use.
+        let infos = must_dump_latches(&storage);
+        assert!(!infos.is_empty());
+        let owner = infos[0].owner.cid;
+        for info in &infos {
+            assert_eq!(info.owner.cid, owner);
+            assert_eq!(info.owner.tag, "prewrite");
+            assert_eq!(info.owner.priority, CommandPri::Normal);
+        }
+        let waiters: Vec<_> = infos.iter().flat_map(|info| info.waiters.iter()).collect();
+        assert_eq!(waiters.len(), 1);
+        let waiter = waiters[0];
+        assert_ne!(waiter.cid, owner);
+        assert_eq!(waiter.tag, "prewrite");
+        assert_eq!(waiter.priority, CommandPri::High);
+
+        // All latches are released once the commands finish.
+        block.store(false, Ordering::SeqCst);
+        assert_eq!(rx.recv().unwrap(), 0);
+        assert_eq!(rx.recv().unwrap(), 1);
+        assert!(must_dump_latches(&storage).is_empty());
+        storage.stop().unwrap();
+    }
 }
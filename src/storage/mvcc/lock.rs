@@ -23,18 +23,24 @@ pub enum LockType {
     Put,
     Delete,
     Lock,
+    Pessimistic,
 }
 
 const FLAG_PUT: u8 = b'P';
 const FLAG_DELETE: u8 = b'D';
 const FLAG_LOCK: u8 = b'L';
+const FLAG_PESSIMISTIC: u8 = b'S';
+
+const FOR_UPDATE_TS_PREFIX: u8 = b'f';
 
 impl LockType {
     pub fn from_mutation(mutation: &Mutation) -> LockType {
         match *mutation {
-            Mutation::Put(_) => LockType::Put,
+            Mutation::Put(_) |
+            Mutation::Insert(_) => LockType::Put,
             Mutation::Delete(_) => LockType::Delete,
-            Mutation::Lock(_) => LockType::Lock,
+            Mutation::Lock(_) |
+            Mutation::CheckNotExists(_) => LockType::Lock,
         }
     }
 
@@ -43,6 +49,7 @@ impl LockType {
             FLAG_PUT => Some(LockType::Put),
             FLAG_DELETE => Some(LockType::Delete),
             FLAG_LOCK => Some(LockType::Lock),
+            FLAG_PESSIMISTIC => Some(LockType::Pessimistic),
             _ => None,
         }
     }
@@ -52,6 +59,7 @@ impl LockType {
             LockType::Put => FLAG_PUT,
             LockType::Delete => FLAG_DELETE,
             LockType::Lock => FLAG_LOCK,
+            LockType::Pessimistic => FLAG_PESSIMISTIC,
         }
     }
 }
@@ -63,6 +71,8 @@ pub struct Lock {
     pub ts: u64,
     pub ttl: u64,
     pub short_value: Option<Value>,
+    // Only set for locks of pessimistic transactions.
+    pub for_update_ts: u64,
 }
 
 impl Lock {
@@ -78,13 +88,21 @@ impl Lock {
             ts: ts,
             ttl: ttl,
             short_value: short_value,
+            for_update_ts: 0,
         }
     }
 
+    pub fn with_for_update_ts(mut self, for_update_ts: u64) -> Lock {
+        self.for_update_ts = for_update_ts;
+        self
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut b = Vec::with_capacity(1 + MAX_VAR_U64_LEN + self.primary.len() + MAX_VAR_U64_LEN +
                                        SHORT_VALUE_MAX_LEN +
-                                       2);
+                                       2 +
+                                       1 +
+                                       8);
         b.push(self.lock_type.to_u8());
         b.encode_compact_bytes(&self.primary).unwrap();
         b.encode_var_u64(self.ts).unwrap();
@@ -94,6 +112,10 @@ impl Lock {
             b.push(v.len() as u8);
             b.extend_from_slice(v);
         }
+        if self.for_update_ts > 0 {
+            b.push(FOR_UPDATE_TS_PREFIX);
+            b.encode_u64(self.for_update_ts).unwrap();
+        }
         b
     }
 
@@ -110,24 +132,26 @@ impl Lock {
             try!(b.decode_var_u64())
         };
 
-        if b.is_empty() {
-            return Ok(Lock::new(lock_type, primary, ts, ttl, None));
-        }
-
-        let flag = try!(b.read_u8());
-        assert_eq!(flag,
-                   SHORT_VALUE_PREFIX,
-                   "invalid flag [{:?}] in write",
-                   flag);
-
-        let len = try!(b.read_u8());
-        if len as usize != b.len() {
-            panic!("short value len [{}] not equal to content len [{}]",
-                   len,
-                   b.len());
+        let mut short_value = None;
+        let mut for_update_ts = 0;
+        while !b.is_empty() {
+            match try!(b.read_u8()) {
+                SHORT_VALUE_PREFIX => {
+                    let len = try!(b.read_u8()) as usize;
+                    if b.len() < len {
+                        panic!("short value len [{}] exceeds content len [{}]",
+                               len,
+                               b.len());
+                    }
+                    short_value = Some(b[..len].to_vec());
+                    b = &b[len..];
+                }
+                FOR_UPDATE_TS_PREFIX => for_update_ts = try!(b.decode_u64()),
+                flag => panic!("invalid flag [{:?}] in lock", flag),
+            }
         }
 
-        Ok(Lock::new(lock_type, primary, ts, ttl, Some(b.to_vec())))
+        Ok(Lock::new(lock_type, primary, ts, ttl, short_value).with_for_update_ts(for_update_ts))
     }
 }
 
@@ -179,7 +203,15 @@ mod tests {
                                        b"pk".to_vec(),
                                        1,
                                        10,
-                                       Some(b"short_value".to_vec()))];
+                                       Some(b"short_value".to_vec())),
+                             Lock::new(LockType::Pessimistic, b"pk".to_vec(), 1, 10, None)
+                                 .with_for_update_ts(5),
+                             Lock::new(LockType::Put,
+                                       b"pk".to_vec(),
+                                       1,
+                                       10,
+                                       Some(b"short_value".to_vec()))
+                                 .with_for_update_ts(5)];
         for (i, lock) in locks.drain(..).enumerate() {
             let v = lock.to_bytes();
             let l = Lock::parse(&v[..]).unwrap_or_else(|e| panic!("#{} parse() err: {:?}", i, e));
@@ -18,8 +18,9 @@ mod write;
 mod metrics;
 
 use std::io;
-pub use self::txn::{MvccTxn, MAX_TXN_WRITE_SIZE};
-pub use self::reader::MvccReader;
+pub use self::txn::{MvccTxn, TxnStatus, MAX_TXN_WRITE_SIZE, extract_physical,
+                    compose_ts};
+pub use self::reader::{MvccReader, MvccInfo};
 pub use self::lock::{Lock, LockType};
 pub use self::write::{Write, WriteType};
 use util::escape;
@@ -59,6 +60,27 @@ quick_error! {
         TxnLockNotFound {description("txn lock not found")}
         WriteConflict {description("write conflict")}
         KeyVersion {description("bad format key(version)")}
+        PessimisticLockNotFound {start_ts: u64, key: Vec<u8>} {
+            description("pessimistic lock not found")
+            display("pessimistic lock not found, start_ts:{}, key:{}", start_ts, escape(key))
+        }
+        PessimisticLockRolledBack {start_ts: u64, key: Vec<u8>} {
+            description("pessimistic lock already rollbacked")
+            display("pessimistic lock already rollbacked, start_ts:{}, key:{}",
+                    start_ts,
+                    escape(key))
+        }
+        AlreadyExist {key: Vec<u8>} {
+            description("key already exists")
+            display("key {} already exists", escape(key))
+        }
+        LockTypeNotMatch {start_ts: u64, key: Vec<u8>, pessimistic: bool} {
+            description("lock type not match")
+            display("lock type not match, start_ts:{}, key:{}, pessimistic:{}",
+                    start_ts,
+                    escape(key),
+                    pessimistic)
+        }
     }
 }
 
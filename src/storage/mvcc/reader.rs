@@ -14,12 +14,22 @@
 use storage::engine::{Snapshot, Cursor, ScanMode, Statistics};
 use storage::{Key, Value, CF_LOCK, CF_WRITE};
 use super::{Error, Result};
-use super::lock::Lock;
+use super::lock::{Lock, LockType};
 use super::write::{Write, WriteType};
 use raftstore::store::engine::IterOption;
 use std::u64;
 use kvproto::kvrpcpb::IsolationLevel;
 
+/// All the MVCC records of a key, newest first.
+#[derive(Debug, Default)]
+pub struct MvccInfo {
+    pub lock: Option<Lock>,
+    // `(commit_ts, write)` pairs, including rollbacks.
+    pub writes: Vec<(u64, Write)>,
+    // `(start_ts, value)` pairs of the long values in CF_DEFAULT.
+    pub values: Vec<(u64, Value)>,
+}
+
 pub struct MvccReader<'a> {
     snapshot: &'a Snapshot,
     statistics: &'a mut Statistics,
@@ -32,6 +42,8 @@ pub struct MvccReader<'a> {
     key_only: bool,
 
     fill_cache: bool,
+    // Reverse seeks stop at `lower_bound`, forward seeks are bounded by `upper_bound`.
+    lower_bound: Option<Vec<u8>>,
     upper_bound: Option<Vec<u8>>,
     isolation_level: IsolationLevel,
 }
@@ -54,6 +66,7 @@ impl<'a> MvccReader<'a> {
             isolation_level: isolation_level,
             key_only: false,
             fill_cache: fill_cache,
+            lower_bound: None,
             upper_bound: upper_bound,
         }
     }
@@ -69,6 +82,11 @@ impl<'a> MvccReader<'a> {
         self.key_only = key_only;
     }
 
+    /// Sets the encoded key that `reverse_seek` never goes below.
+    pub fn set_lower_bound(&mut self, lower_bound: Option<Vec<u8>>) {
+        self.lower_bound = lower_bound;
+    }
+
     pub fn load_data(&mut self, key: &Key, ts: u64) -> Result<Value> {
         if self.key_only {
             return Ok(vec![]);
@@ -180,7 +198,8 @@ impl<'a> MvccReader<'a> {
 
     fn check_lock(&mut self, key: &Key, mut ts: u64) -> Result<Option<u64>> {
         if let Some(lock) = try!(self.load_lock(key)) {
-            if lock.ts <= ts {
+            // Pessimistic locks carry no data, so they never block readers.
+            if lock.ts <= ts && lock.lock_type != LockType::Pessimistic {
                 if ts == u64::MAX && try!(key.raw()) == lock.primary {
                     // when ts==u64::MAX(which means to get latest committed version for
                     // primary key),and current key is the primary key, returns the latest
@@ -200,7 +219,15 @@ impl<'a> MvccReader<'a> {
         Ok(Some(ts))
     }
 
-    pub fn get(&mut self, key: &Key, mut ts: u64) -> Result<Option<Value>> {
+    pub fn get(&mut self, key: &Key, ts: u64) -> Result<Option<Value>> {
+        let value = try!(self.get_version(key, ts));
+        if let Some(ref v) = value {
+            self.statistics.flow_stats.add_read(key.encoded(), v);
+        }
+        Ok(value)
+    }
+
+    fn get_version(&mut self, key: &Key, mut ts: u64) -> Result<Option<Value>> {
         // Check for locks that signal concurrent writes.
         match self.isolation_level {
             IsolationLevel::SI => {
@@ -353,6 +380,11 @@ impl<'a> MvccReader<'a> {
                     }
                 }
             };
+            if let Some(ref lower_bound) = self.lower_bound {
+                if key.encoded() < lower_bound {
+                    return Ok(None);
+                }
+            }
             if let Some(v) = try!(self.get(&key, ts)) {
                 return Ok(Some((key, v)));
             }
@@ -393,6 +425,76 @@ impl<'a> MvccReader<'a> {
         Ok((locks, None))
     }
 
+    /// Scans at most `limit` keys from `start` that have a lock or any write record.
+    pub fn scan_mvcc_keys(&mut self, mut start: Key, limit: usize) -> Result<Vec<Key>> {
+        assert!(self.scan_mode.is_some());
+        try!(self.create_write_cursor());
+        try!(self.create_lock_cursor());
+
+        let mut keys = vec![];
+        while keys.len() < limit {
+            let key = {
+                let mut w_cur = self.write_cursor.as_mut().unwrap();
+                let mut l_cur = self.lock_cursor.as_mut().unwrap();
+                let w_key = if try!(w_cur.near_seek(&start, self.statistics)) {
+                    Some(try!(Key::from_encoded(w_cur.key().to_vec()).truncate_ts()))
+                } else {
+                    None
+                };
+                let l_key = if try!(l_cur.near_seek(&start, self.statistics)) {
+                    Some(Key::from_encoded(l_cur.key().to_vec()))
+                } else {
+                    None
+                };
+                match (w_key, l_key) {
+                    (None, None) => break,
+                    (Some(k), None) | (None, Some(k)) => k,
+                    (Some(wk), Some(lk)) => if wk.encoded() < lk.encoded() { wk } else { lk },
+                }
+            };
+            start = key.append_ts(0);
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    /// Scans all the values of `key` in CF_DEFAULT whose start_ts is not larger than `max_ts`.
+    pub fn scan_values_in_default(&mut self, key: &Key, max_ts: u64) -> Result<Vec<(u64, Value)>> {
+        let iter_opt = IterOption::new(None, self.fill_cache);
+        let mut cursor = try!(self.snapshot.iter(iter_opt, self.get_scan_mode(false)));
+        let mut ok = try!(cursor.seek(&key.append_ts(max_ts), self.statistics));
+        let mut values = vec![];
+        while ok {
+            let k = Key::from_encoded(cursor.key().to_vec());
+            if &try!(k.truncate_ts()) != key {
+                break;
+            }
+            values.push((try!(k.decode_ts()), cursor.value().to_vec()));
+            ok = cursor.next(self.statistics);
+        }
+        Ok(values)
+    }
+
+    /// Collects the lock, write records and values of `key` that are not newer than `max_ts`.
+    pub fn get_mvcc_info(&mut self, key: &Key, max_ts: u64) -> Result<MvccInfo> {
+        let mut info = MvccInfo::default();
+        if let Some(lock) = try!(self.load_lock(key)) {
+            if lock.ts <= max_ts {
+                info.lock = Some(lock);
+            }
+        }
+        let mut ts = max_ts;
+        while let Some((commit_ts, write)) = try!(self.seek_write(key, ts)) {
+            info.writes.push((commit_ts, write));
+            if commit_ts == 0 {
+                break;
+            }
+            ts = commit_ts - 1;
+        }
+        info.values = try!(self.scan_values_in_default(key, max_ts));
+        Ok(info)
+    }
+
     pub fn scan_keys(&mut self,
                      mut start: Option<Key>,
                      limit: usize)
@@ -24,6 +24,28 @@ use kvproto::kvrpcpb::IsolationLevel;
 
 pub const MAX_TXN_WRITE_SIZE: usize = 32 * 1024;
 
+// The lower bits of a timestamp from PD are the logical part.
+const TSO_PHYSICAL_SHIFT_BITS: u64 = 18;
+
+/// Extracts the physical part (in milliseconds) of a timestamp.
+pub fn extract_physical(ts: u64) -> u64 {
+    ts >> TSO_PHYSICAL_SHIFT_BITS
+}
+
+/// Composes a timestamp from its physical part (in milliseconds) and its logical part.
+pub fn compose_ts(physical: u64, logical: u64) -> u64 {
+    (physical << TSO_PHYSICAL_SHIFT_BITS) + logical
+}
+
+/// The status of a transaction, checked on its primary key.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TxnStatus {
+    /// The primary lock is still alive.
+    Locked { ttl: u64 },
+    Committed { commit_ts: u64 },
+    RolledBack,
+}
+
 pub struct MvccTxn<'a> {
     reader: MvccReader<'a>,
     start_ts: u64,
@@ -71,8 +93,11 @@ impl<'a> MvccTxn<'a> {
                 lock_type: LockType,
                 primary: Vec<u8>,
                 ttl: u64,
-                short_value: Option<Value>) {
-        let lock = Lock::new(lock_type, primary, self.start_ts, ttl, short_value).to_bytes();
+                short_value: Option<Value>,
+                for_update_ts: u64) {
+        let lock = Lock::new(lock_type, primary, self.start_ts, ttl, short_value)
+            .with_for_update_ts(for_update_ts)
+            .to_bytes();
         self.write_size += CF_LOCK.len() + key.encoded().len() + lock.len();
         self.writes.push(Modify::Put(CF_LOCK, key, lock));
     }
@@ -115,18 +140,105 @@ impl<'a> MvccTxn<'a> {
                     primary: &[u8],
                     options: &Options)
                     -> Result<()> {
-        let key = mutation.key();
-        if !options.skip_constraint_check {
-            if let Some((commit, _)) = try!(self.reader.seek_write(key, u64::max_value())) {
+        {
+            let key = mutation.key();
+            if !options.skip_constraint_check {
+                if let Some((commit, _)) = try!(self.reader.seek_write(key, u64::max_value())) {
+                    // Abort on writes after our start timestamp ...
+                    if commit >= self.start_ts {
+                        return Err(Error::WriteConflict);
+                    }
+                }
+            }
+            // ... or locks at any timestamp.
+            if let Some(lock) = try!(self.reader.load_lock(key)) {
+                if lock.ts != self.start_ts {
+                    return Err(Error::KeyIsLocked {
+                        key: try!(key.raw()),
+                        primary: lock.primary,
+                        ts: lock.ts,
+                        ttl: lock.ttl,
+                    });
+                }
+                // No need to overwrite the lock and data.
+                // If we use single delete, we can't put a key multiple times.
+                info!("duplicated prewrite with start_ts {}, ignore it.",
+                      self.start_ts);
+                return Ok(());
+            }
+            if mutation.should_not_exist() {
+                try!(self.check_not_exists(key));
+            }
+        }
+        if let Mutation::CheckNotExists(_) = mutation {
+            return Ok(());
+        }
+
+        self.prewrite_key_value(mutation, primary, options.lock_ttl, 0);
+        Ok(())
+    }
+
+    /// Returns `AlreadyExist` if `key` has a committed value at the latest version.
+    fn check_not_exists(&mut self, key: &Key) -> Result<()> {
+        if try!(self.reader.get(key, u64::max_value())).is_some() {
+            return Err(Error::AlreadyExist { key: try!(key.raw()) });
+        }
+        Ok(())
+    }
+
+    fn prewrite_key_value(&mut self,
+                          mutation: Mutation,
+                          primary: &[u8],
+                          lock_ttl: u64,
+                          for_update_ts: u64) {
+        let key = mutation.key().clone();
+        let lock_type = LockType::from_mutation(&mutation);
+        let (short_value, long_value) = match mutation {
+            Mutation::Put((_, value)) |
+            Mutation::Insert((_, value)) => {
+                if is_short_value(&value) {
+                    (Some(value), None)
+                } else {
+                    (None, Some(value))
+                }
+            }
+            _ => (None, None),
+        };
+
+        if let Some(value) = long_value {
+            let ts = self.start_ts;
+            self.put_value(&key, ts, value);
+        }
+        self.lock_key(key,
+                      lock_type,
+                      primary.to_vec(),
+                      lock_ttl,
+                      short_value,
+                      for_update_ts);
+    }
+
+    /// Commits `mutation` at `commit_ts` in one phase (1PC): the write record is produced
+    /// directly, without locking the key first. It conflicts with other transactions the same
+    /// way as `prewrite`.
+    pub fn one_pc_commit(&mut self,
+                         mutation: Mutation,
+                         commit_ts: u64,
+                         options: &Options)
+                         -> Result<()> {
+        {
+            let key = mutation.key();
+            if let Some((commit, write)) = try!(self.reader.seek_write(key, u64::max_value())) {
+                if write.start_ts == self.start_ts && write.write_type != WriteType::Rollback {
+                    info!("duplicated 1pc with start_ts {}, ignore it.", self.start_ts);
+                    return Ok(());
+                }
                 // Abort on writes after our start timestamp ...
-                if commit >= self.start_ts {
+                if !options.skip_constraint_check && commit >= self.start_ts {
                     return Err(Error::WriteConflict);
                 }
             }
-        }
-        // ... or locks at any timestamp.
-        if let Some(lock) = try!(self.reader.load_lock(key)) {
-            if lock.ts != self.start_ts {
+            // ... or locks at any timestamp.
+            if let Some(lock) = try!(self.reader.load_lock(key)) {
                 return Err(Error::KeyIsLocked {
                     key: try!(key.raw()),
                     primary: lock.primary,
@@ -134,42 +246,142 @@ impl<'a> MvccTxn<'a> {
                     ttl: lock.ttl,
                 });
             }
-            // No need to overwrite the lock and data.
-            // If we use single delete, we can't put a key multiple times.
-            info!("duplicated prewrite with start_ts {}, ignore it.",
-                  self.start_ts);
+            if mutation.should_not_exist() {
+                try!(self.check_not_exists(key));
+            }
+        }
+        if let Mutation::CheckNotExists(_) = mutation {
             return Ok(());
         }
 
-        let short_value = if let Mutation::Put((_, ref value)) = mutation {
-            if is_short_value(value) {
-                Some(value.clone())
-            } else {
-                None
+        let key = mutation.key().clone();
+        // `LockType::Pessimistic` never comes from a mutation.
+        let write_type = WriteType::from_lock_type(LockType::from_mutation(&mutation)).unwrap();
+        let short_value = match mutation {
+            Mutation::Put((_, value)) |
+            Mutation::Insert((_, value)) => {
+                if is_short_value(&value) {
+                    Some(value)
+                } else {
+                    let ts = self.start_ts;
+                    self.put_value(&key, ts, value);
+                    None
+                }
             }
-        } else {
-            None
+            _ => None,
         };
+        let write = Write::new(write_type, self.start_ts, short_value);
+        self.put_write(&key, commit_ts, write.to_bytes());
+        Ok(())
+    }
+
+    /// Acquires a pessimistic lock on `key` before the transaction prewrites it. The lock
+    /// doesn't carry any value and is ignored by readers.
+    pub fn acquire_pessimistic_lock(&mut self,
+                                    key: Key,
+                                    primary: &[u8],
+                                    for_update_ts: u64,
+                                    options: &Options)
+                                    -> Result<()> {
+        if let Some(lock) = try!(self.reader.load_lock(&key)) {
+            if lock.ts != self.start_ts {
+                return Err(Error::KeyIsLocked {
+                    key: try!(key.raw()),
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                });
+            }
+            if lock.lock_type != LockType::Pessimistic {
+                return Err(Error::LockTypeNotMatch {
+                    start_ts: self.start_ts,
+                    key: try!(key.raw()),
+                    pessimistic: false,
+                });
+            }
+            // Overwrite the lock only if a newer `for_update_ts` is given.
+            if for_update_ts <= lock.for_update_ts {
+                info!("duplicated pessimistic lock with start_ts {}, ignore it.",
+                      self.start_ts);
+                return Ok(());
+            }
+        } else {
+            // Abort on writes after our `for_update_ts` ...
+            if let Some((commit_ts, _)) = try!(self.reader.seek_write(&key, u64::max_value())) {
+                if commit_ts > for_update_ts {
+                    return Err(Error::WriteConflict);
+                }
+            }
+            // ... or if the transaction has been rolled back already.
+            if let Some((_, WriteType::Rollback)) =
+                try!(self.reader.get_txn_commit_info(&key, self.start_ts)) {
+                return Err(Error::PessimisticLockRolledBack {
+                    start_ts: self.start_ts,
+                    key: try!(key.raw()),
+                });
+            }
+        }
 
-        self.lock_key(key.clone(),
-                      LockType::from_mutation(&mutation),
+        self.lock_key(key,
+                      LockType::Pessimistic,
                       primary.to_vec(),
                       options.lock_ttl,
-                      short_value);
+                      None,
+                      for_update_ts);
+        Ok(())
+    }
 
-        if let Mutation::Put((_, ref value)) = mutation {
-            if !is_short_value(value) {
-                let ts = self.start_ts;
-                self.put_value(key, ts, value.clone());
+    /// Prewrites a key of a pessimistic transaction. Keys which are locked pessimistically
+    /// are converted to normal locks, others go through the optimistic checks.
+    pub fn pessimistic_prewrite(&mut self,
+                                mutation: Mutation,
+                                primary: &[u8],
+                                is_pessimistic_lock: bool,
+                                options: &Options)
+                                -> Result<()> {
+        if !is_pessimistic_lock {
+            return self.prewrite(mutation, primary, options);
+        }
+
+        let (lock_type, for_update_ts) = match try!(self.reader.load_lock(mutation.key())) {
+            Some(ref lock) if lock.ts == self.start_ts => (lock.lock_type, lock.for_update_ts),
+            _ => {
+                // The pessimistic lock may have been cleaned up by another transaction.
+                return Err(Error::PessimisticLockNotFound {
+                    start_ts: self.start_ts,
+                    key: try!(mutation.key().raw()),
+                });
             }
+        };
+        if lock_type != LockType::Pessimistic {
+            // Already converted by a previous prewrite.
+            info!("duplicated prewrite with start_ts {}, ignore it.",
+                  self.start_ts);
+            return Ok(());
         }
+        if mutation.should_not_exist() {
+            // The pessimistic lock is ours, so readers don't stop at it.
+            try!(self.check_not_exists(mutation.key()));
+        }
+
+        self.prewrite_key_value(mutation, primary, options.lock_ttl, for_update_ts);
         Ok(())
     }
 
     pub fn commit(&mut self, key: &Key, commit_ts: u64) -> Result<()> {
-        let (lock_type, short_value) = match try!(self.reader.load_lock(key)) {
+        let (write_type, short_value) = match try!(self.reader.load_lock(key)) {
             Some(ref mut lock) if lock.ts == self.start_ts => {
-                (lock.lock_type, lock.short_value.take())
+                match WriteType::from_lock_type(lock.lock_type) {
+                    Some(write_type) => (write_type, lock.short_value.take()),
+                    None => {
+                        // A pessimistic lock must be prewritten before committing.
+                        return Err(Error::LockTypeNotMatch {
+                            start_ts: self.start_ts,
+                            key: try!(key.raw()),
+                            pessimistic: true,
+                        });
+                    }
+                }
             }
             _ => {
                 return match try!(self.reader.get_txn_commit_info(key, self.start_ts)) {
@@ -190,14 +402,24 @@ impl<'a> MvccTxn<'a> {
                 };
             }
         };
-        let write = Write::new(WriteType::from_lock_type(lock_type),
-                               self.start_ts,
-                               short_value);
+        let write = Write::new(write_type, self.start_ts, short_value);
         self.put_write(key, commit_ts, write.to_bytes());
         self.unlock_key(key.clone());
         Ok(())
     }
 
+    /// Commits `key` when resolving locks of a committed transaction. Pessimistic locks are
+    /// never committed, they are just removed.
+    pub fn resolve_commit(&mut self, key: &Key, commit_ts: u64) -> Result<()> {
+        if let Some(lock) = try!(self.reader.load_lock(key)) {
+            if lock.ts == self.start_ts && lock.lock_type == LockType::Pessimistic {
+                self.unlock_key(key.clone());
+                return Ok(());
+            }
+        }
+        self.commit(key, commit_ts)
+    }
+
     pub fn rollback(&mut self, key: &Key) -> Result<()> {
         match try!(self.reader.load_lock(key)) {
             Some(ref lock) if lock.ts == self.start_ts => {
@@ -237,6 +459,58 @@ impl<'a> MvccTxn<'a> {
         Ok(())
     }
 
+    /// Extends the TTL of the primary lock to `advise_ttl` and returns the TTL of the lock
+    /// afterwards. The TTL is never decreased.
+    pub fn txn_heart_beat(&mut self, primary_key: Key, advise_ttl: u64) -> Result<u64> {
+        let lock = match try!(self.reader.load_lock(&primary_key)) {
+            Some(lock) => lock,
+            None => return Err(Error::TxnLockNotFound),
+        };
+        if lock.ts != self.start_ts {
+            info!("txn heart beat lock not found, key:{}, start_ts:{}",
+                  primary_key,
+                  self.start_ts);
+            return Err(Error::TxnLockNotFound);
+        }
+        if lock.ttl >= advise_ttl {
+            return Ok(lock.ttl);
+        }
+        self.lock_key(primary_key,
+                      lock.lock_type,
+                      lock.primary,
+                      advise_ttl,
+                      lock.short_value,
+                      lock.for_update_ts);
+        Ok(advise_ttl)
+    }
+
+    /// Checks the status of the transaction by its primary key. A primary lock whose TTL has
+    /// expired at `current_ts` is rolled back. If neither the lock nor a commit record is
+    /// found, a rollback record is written so a late prewrite can't succeed.
+    pub fn check_txn_status(&mut self, primary_key: &Key, current_ts: u64) -> Result<TxnStatus> {
+        if let Some(lock) = try!(self.reader.load_lock(primary_key)) {
+            if lock.ts == self.start_ts {
+                if extract_physical(lock.ts) + lock.ttl >= extract_physical(current_ts) {
+                    return Ok(TxnStatus::Locked { ttl: lock.ttl });
+                }
+                info!("txn lock expired, key:{}, start_ts:{}, current_ts:{}",
+                      primary_key,
+                      self.start_ts,
+                      current_ts);
+                try!(self.rollback(primary_key));
+                return Ok(TxnStatus::RolledBack);
+            }
+        }
+        match try!(self.reader.get_txn_commit_info(primary_key, self.start_ts)) {
+            Some((_, WriteType::Rollback)) => Ok(TxnStatus::RolledBack),
+            Some((commit_ts, _)) => Ok(TxnStatus::Committed { commit_ts: commit_ts }),
+            None => {
+                try!(self.rollback(primary_key));
+                Ok(TxnStatus::RolledBack)
+            }
+        }
+    }
+
     pub fn gc(&mut self, key: &Key, safe_point: u64) -> Result<()> {
         let mut remove_older = false;
         let mut ts: u64 = u64::max_value();
@@ -302,9 +576,10 @@ impl<'a> MvccTxn<'a> {
 #[cfg(test)]
 mod tests {
     use kvproto::kvrpcpb::{Context, IsolationLevel};
-    use super::MvccTxn;
-    use super::super::MvccReader;
+    use super::{MvccTxn, TxnStatus};
+    use super::super::{MvccReader, Result, Error};
     use super::super::write::{Write, WriteType};
+    use super::super::lock::LockType;
     use storage::{make_key, Mutation, ALL_CFS, CF_WRITE, ScanMode, Options, SHORT_VALUE_MAX_LEN,
                   Statistics};
     use storage::engine::{self, Engine, TEMP_DIR};
@@ -785,6 +1060,233 @@ mod tests {
         must_get_rc(engine.as_ref(), key, 20, v1);
     }
 
+    #[test]
+    fn test_pessimistic_lock() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k, v) = (b"k1", b"v1");
+
+        // Normal.
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 1, 1);
+        must_pessimistic_locked(engine.as_ref(), k, 1, 1);
+        // Reads are not blocked by pessimistic locks.
+        must_get_none(engine.as_ref(), k, 2);
+        // Pessimistic locks can't be committed.
+        must_commit_err(engine.as_ref(), k, 1, 2);
+        must_pessimistic_prewrite_put(engine.as_ref(), k, v, k, 1, 1, true);
+        must_locked(engine.as_ref(), k, 1);
+        // Duplicated prewrite is ignored.
+        must_pessimistic_prewrite_put(engine.as_ref(), k, v, k, 1, 1, true);
+        must_commit(engine.as_ref(), k, 1, 2);
+        must_unlocked(engine.as_ref(), k);
+        must_get(engine.as_ref(), k, 3, v);
+
+        // Lock conflict.
+        must_prewrite_lock(engine.as_ref(), k, k, 3);
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 4, 4);
+        must_rollback(engine.as_ref(), k, 3);
+        must_unlocked(engine.as_ref(), k);
+
+        // Write conflict.
+        must_prewrite_put(engine.as_ref(), k, v, k, 5);
+        must_commit(engine.as_ref(), k, 5, 6);
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 4, 4);
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 4, 7);
+        must_pessimistic_locked(engine.as_ref(), k, 4, 7);
+        // Relocking with a larger `for_update_ts` updates the lock.
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 4, 8);
+        must_pessimistic_locked(engine.as_ref(), k, 4, 8);
+        must_rollback(engine.as_ref(), k, 4);
+        must_unlocked(engine.as_ref(), k);
+
+        // Can't lock after rollback.
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 4, 8);
+        must_unlocked(engine.as_ref(), k);
+
+        // Prewrite fails if the pessimistic lock is gone.
+        must_pessimistic_prewrite_put_err(engine.as_ref(), k, v, k, 9, 9, true);
+        // Keys without pessimistic locks are prewritten optimistically.
+        must_pessimistic_prewrite_put(engine.as_ref(), k, v, k, 9, 9, false);
+        must_locked(engine.as_ref(), k, 9);
+        must_rollback(engine.as_ref(), k, 9);
+
+        // Resolving a committed transaction removes its remaining pessimistic locks.
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 10, 10);
+        must_resolve_commit(engine.as_ref(), k, 10, 11);
+        must_unlocked(engine.as_ref(), k);
+        must_get_commit_ts_none(engine.as_ref(), k, 10);
+        must_get(engine.as_ref(), k, 12, v);
+    }
+
+    #[test]
+    fn test_txn_heart_beat() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k, v) = (b"k1", b"v1");
+
+        must_txn_heart_beat_err(engine.as_ref(), k, 5, 100);
+        must_prewrite_put(engine.as_ref(), k, v, k, 5);
+        must_txn_heart_beat(engine.as_ref(), k, 5, 100, 100);
+        // The TTL can't be decreased.
+        must_txn_heart_beat(engine.as_ref(), k, 5, 50, 100);
+        must_txn_heart_beat(engine.as_ref(), k, 5, 150, 150);
+        // The value is kept.
+        must_commit(engine.as_ref(), k, 5, 10);
+        must_get(engine.as_ref(), k, 15, v);
+        must_txn_heart_beat_err(engine.as_ref(), k, 5, 200);
+    }
+
+    #[test]
+    fn test_check_txn_status() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k, v) = (b"k1", b"v1");
+        let ts = |physical: u64| physical << 18;
+
+        // The lock is alive until its TTL expires.
+        must_prewrite_put(engine.as_ref(), k, v, k, ts(5));
+        must_txn_heart_beat(engine.as_ref(), k, ts(5), 100, 100);
+        must_check_txn_status(engine.as_ref(),
+                              k,
+                              ts(5),
+                              ts(50),
+                              TxnStatus::Locked { ttl: 100 });
+        must_locked(engine.as_ref(), k, ts(5));
+        must_check_txn_status(engine.as_ref(), k, ts(5), ts(106), TxnStatus::RolledBack);
+        must_unlocked(engine.as_ref(), k);
+        must_written(engine.as_ref(), k, ts(5), ts(5), WriteType::Rollback);
+        must_check_txn_status(engine.as_ref(), k, ts(5), ts(106), TxnStatus::RolledBack);
+
+        // Committed.
+        must_prewrite_put(engine.as_ref(), k, v, k, ts(110));
+        must_commit(engine.as_ref(), k, ts(110), ts(120));
+        must_check_txn_status(engine.as_ref(),
+                              k,
+                              ts(110),
+                              ts(130),
+                              TxnStatus::Committed { commit_ts: ts(120) });
+
+        // Neither lock nor commit record is found.
+        must_check_txn_status(engine.as_ref(), k, ts(140), ts(150), TxnStatus::RolledBack);
+        must_prewrite_lock_err(engine.as_ref(), k, k, ts(140));
+    }
+
+    #[test]
+    fn test_one_pc_commit() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k, v) = (b"k1", b"v1");
+        let long_value = gen_value(b'v', SHORT_VALUE_MAX_LEN + 1);
+
+        must_one_pc_commit_put(engine.as_ref(), k, v, 5, 10);
+        must_unlocked(engine.as_ref(), k);
+        must_written(engine.as_ref(), k, 5, 10, WriteType::Put);
+        must_get_none(engine.as_ref(), k, 8);
+        must_get(engine.as_ref(), k, 12, v);
+        // Duplicated 1pc is ignored.
+        must_one_pc_commit_put(engine.as_ref(), k, v, 5, 10);
+
+        // Long values are written to CF_DEFAULT.
+        must_one_pc_commit_put(engine.as_ref(), k, &long_value, 15, 20);
+        must_get(engine.as_ref(), k, 22, &long_value);
+
+        // Write conflict.
+        must_one_pc_commit_put_err(engine.as_ref(), k, v, 18, 25);
+        must_get(engine.as_ref(), k, 30, &long_value);
+
+        // Locked by a 2pc transaction.
+        must_prewrite_put(engine.as_ref(), k, v, k, 30);
+        must_one_pc_commit_put_err(engine.as_ref(), k, v, 35, 40);
+        must_commit(engine.as_ref(), k, 30, 40);
+        must_one_pc_commit_put(engine.as_ref(), k, b"v3", 45, 50);
+        must_get(engine.as_ref(), k, 55, b"v3");
+    }
+
+    #[test]
+    fn test_insert_and_check_not_exists() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k, v) = (b"k1", b"v1");
+        let insert = || Mutation::Insert((make_key(k), v.to_vec()));
+        let check_not_exists = || Mutation::CheckNotExists(make_key(k));
+
+        // Nothing is written for CheckNotExists.
+        prewrite_mutation(engine.as_ref(), check_not_exists(), k, 5).unwrap();
+        must_unlocked(engine.as_ref(), k);
+
+        prewrite_mutation(engine.as_ref(), insert(), k, 5).unwrap();
+        must_locked(engine.as_ref(), k, 5);
+        // Duplicated prewrite is ignored.
+        prewrite_mutation(engine.as_ref(), insert(), k, 5).unwrap();
+        must_commit(engine.as_ref(), k, 5, 10);
+        must_get(engine.as_ref(), k, 12, v);
+
+        match prewrite_mutation(engine.as_ref(), insert(), k, 15) {
+            Err(Error::AlreadyExist { key }) => assert_eq!(key, k.to_vec()),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match prewrite_mutation(engine.as_ref(), check_not_exists(), k, 15) {
+            Err(Error::AlreadyExist { .. }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // The key can be inserted again once it is deleted.
+        must_prewrite_delete(engine.as_ref(), k, k, 20);
+        must_commit(engine.as_ref(), k, 20, 25);
+        prewrite_mutation(engine.as_ref(), check_not_exists(), k, 30).unwrap();
+        prewrite_mutation(engine.as_ref(), insert(), k, 30).unwrap();
+        must_commit(engine.as_ref(), k, 30, 35);
+        must_get(engine.as_ref(), k, 40, v);
+    }
+
+    #[test]
+    fn test_mvcc_info() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k1, k2, k3) = (b"k1", b"k2", b"k3");
+        let long_value = gen_value(b'v', SHORT_VALUE_MAX_LEN + 1);
+
+        must_prewrite_put(engine.as_ref(), k1, b"v1", k1, 5);
+        must_commit(engine.as_ref(), k1, 5, 10);
+        must_prewrite_put(engine.as_ref(), k1, &long_value, k1, 15);
+        must_commit(engine.as_ref(), k1, 15, 20);
+        must_prewrite_delete(engine.as_ref(), k1, k1, 25);
+        must_rollback(engine.as_ref(), k1, 25);
+        must_prewrite_put(engine.as_ref(), k1, &long_value, k1, 30);
+        // k2 is only locked and k3 has nothing left.
+        must_prewrite_lock(engine.as_ref(), k2, k2, 35);
+
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(snapshot.as_ref(),
+                                         &mut statistics,
+                                         None,
+                                         true,
+                                         None,
+                                         IsolationLevel::SI);
+        let info = reader.get_mvcc_info(&make_key(k1), u64::max_value()).unwrap();
+        assert_eq!(info.lock.unwrap().ts, 30);
+        let writes: Vec<_> = info.writes.iter().map(|&(ts, ref w)| (ts, w.write_type)).collect();
+        assert_eq!(writes,
+                   vec![(25, WriteType::Rollback), (20, WriteType::Put), (10, WriteType::Put)]);
+        assert_eq!(info.writes[2].1.short_value, Some(b"v1".to_vec()));
+        assert_eq!(info.values,
+                   vec![(30, long_value.clone()), (15, long_value.clone())]);
+
+        // Records newer than `max_ts` are skipped.
+        let info = reader.get_mvcc_info(&make_key(k1), 18).unwrap();
+        assert!(info.lock.is_none());
+        assert_eq!(info.writes.len(), 1);
+        assert_eq!(info.values, vec![(15, long_value.clone())]);
+
+        let info = reader.get_mvcc_info(&make_key(k3), u64::max_value()).unwrap();
+        assert!(info.lock.is_none() && info.writes.is_empty() && info.values.is_empty());
+
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(snapshot.as_ref(),
+                                         &mut statistics,
+                                         Some(ScanMode::Forward),
+                                         true,
+                                         None,
+                                         IsolationLevel::SI);
+        let keys = reader.scan_mvcc_keys(make_key(b""), 10).unwrap();
+        assert_eq!(keys, vec![make_key(k1), make_key(k2)]);
+    }
+
     fn must_get(engine: &Engine, key: &[u8], ts: u64, expect: &[u8]) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
@@ -875,6 +1377,57 @@ mod tests {
         engine.write(&ctx, txn.modifies()).unwrap();
     }
 
+    fn one_pc_commit_put(engine: &Engine,
+                         key: &[u8],
+                         value: &[u8],
+                         start_ts: u64,
+                         commit_ts: u64)
+                         -> Result<()> {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   start_ts,
+                                   None,
+                                   IsolationLevel::SI);
+        try!(txn.one_pc_commit(Mutation::Put((make_key(key), value.to_vec())),
+                               commit_ts,
+                               &Options::default()));
+        engine.write(&ctx, txn.modifies()).unwrap();
+        Ok(())
+    }
+
+    fn must_one_pc_commit_put(engine: &Engine,
+                              key: &[u8],
+                              value: &[u8],
+                              start_ts: u64,
+                              commit_ts: u64) {
+        one_pc_commit_put(engine, key, value, start_ts, commit_ts).unwrap();
+    }
+
+    fn must_one_pc_commit_put_err(engine: &Engine,
+                                  key: &[u8],
+                                  value: &[u8],
+                                  start_ts: u64,
+                                  commit_ts: u64) {
+        assert!(one_pc_commit_put(engine, key, value, start_ts, commit_ts).is_err());
+    }
+
+    fn prewrite_mutation(engine: &Engine, mutation: Mutation, pk: &[u8], ts: u64) -> Result<()> {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   ts,
+                                   None,
+                                   IsolationLevel::SI);
+        try!(txn.prewrite(mutation, pk, &Options::default()));
+        engine.write(&ctx, txn.modifies()).unwrap();
+        Ok(())
+    }
+
     fn must_prewrite_lock_err(engine: &Engine, key: &[u8], pk: &[u8], ts: u64) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
@@ -887,6 +1440,90 @@ mod tests {
         assert!(txn.prewrite(Mutation::Lock(make_key(key)), pk, &Options::default()).is_err());
     }
 
+    fn must_acquire_pessimistic_lock(engine: &Engine,
+                                     key: &[u8],
+                                     pk: &[u8],
+                                     start_ts: u64,
+                                     for_update_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   start_ts,
+                                   None,
+                                   IsolationLevel::SI);
+        txn.acquire_pessimistic_lock(make_key(key), pk, for_update_ts, &Options::default())
+            .unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_acquire_pessimistic_lock_err(engine: &Engine,
+                                         key: &[u8],
+                                         pk: &[u8],
+                                         start_ts: u64,
+                                         for_update_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   start_ts,
+                                   None,
+                                   IsolationLevel::SI);
+        assert!(txn.acquire_pessimistic_lock(make_key(key), pk, for_update_ts, &Options::default())
+            .is_err());
+    }
+
+    fn must_pessimistic_prewrite_put(engine: &Engine,
+                                     key: &[u8],
+                                     value: &[u8],
+                                     pk: &[u8],
+                                     start_ts: u64,
+                                     for_update_ts: u64,
+                                     is_pessimistic_lock: bool) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   start_ts,
+                                   None,
+                                   IsolationLevel::SI);
+        let mut options = Options::default();
+        options.for_update_ts = for_update_ts;
+        txn.pessimistic_prewrite(Mutation::Put((make_key(key), value.to_vec())),
+                                  pk,
+                                  is_pessimistic_lock,
+                                  &options)
+            .unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_pessimistic_prewrite_put_err(engine: &Engine,
+                                         key: &[u8],
+                                         value: &[u8],
+                                         pk: &[u8],
+                                         start_ts: u64,
+                                         for_update_ts: u64,
+                                         is_pessimistic_lock: bool) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   start_ts,
+                                   None,
+                                   IsolationLevel::SI);
+        let mut options = Options::default();
+        options.for_update_ts = for_update_ts;
+        assert!(txn.pessimistic_prewrite(Mutation::Put((make_key(key), value.to_vec())),
+                                  pk,
+                                  is_pessimistic_lock,
+                                  &options)
+            .is_err());
+    }
+
     fn must_commit(engine: &Engine, key: &[u8], start_ts: u64, commit_ts: u64) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
@@ -900,6 +1537,19 @@ mod tests {
         engine.write(&ctx, txn.modifies()).unwrap();
     }
 
+    fn must_resolve_commit(engine: &Engine, key: &[u8], start_ts: u64, commit_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   start_ts,
+                                   None,
+                                   IsolationLevel::SI);
+        txn.resolve_commit(&make_key(key), commit_ts).unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
     fn must_commit_err(engine: &Engine, key: &[u8], start_ts: u64, commit_ts: u64) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
@@ -937,6 +1587,57 @@ mod tests {
         assert!(txn.rollback(&make_key(key)).is_err());
     }
 
+    fn must_txn_heart_beat(engine: &Engine,
+                           primary_key: &[u8],
+                           start_ts: u64,
+                           advise_ttl: u64,
+                           expect_ttl: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   start_ts,
+                                   None,
+                                   IsolationLevel::SI);
+        let ttl = txn.txn_heart_beat(make_key(primary_key), advise_ttl).unwrap();
+        assert_eq!(ttl, expect_ttl);
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_txn_heart_beat_err(engine: &Engine,
+                               primary_key: &[u8],
+                               start_ts: u64,
+                               advise_ttl: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   start_ts,
+                                   None,
+                                   IsolationLevel::SI);
+        assert!(txn.txn_heart_beat(make_key(primary_key), advise_ttl).is_err());
+    }
+
+    fn must_check_txn_status(engine: &Engine,
+                             primary_key: &[u8],
+                             lock_ts: u64,
+                             current_ts: u64,
+                             expect: TxnStatus) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(snapshot.as_ref(),
+                                   &mut statistics,
+                                   lock_ts,
+                                   None,
+                                   IsolationLevel::SI);
+        let status = txn.check_txn_status(&make_key(primary_key), current_ts).unwrap();
+        assert_eq!(status, expect);
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
     fn must_gc(engine: &Engine, key: &[u8], safe_point: u64) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
@@ -963,6 +1664,21 @@ mod tests {
         assert_eq!(lock.ts, start_ts);
     }
 
+    fn must_pessimistic_locked(engine: &Engine, key: &[u8], start_ts: u64, for_update_ts: u64) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(snapshot.as_ref(),
+                                         &mut statistics,
+                                         None,
+                                         true,
+                                         None,
+                                         IsolationLevel::SI);
+        let lock = reader.load_lock(&make_key(key)).unwrap().unwrap();
+        assert_eq!(lock.ts, start_ts);
+        assert_eq!(lock.lock_type, LockType::Pessimistic);
+        assert_eq!(lock.for_update_ts, for_update_ts);
+    }
+
     fn must_unlocked(engine: &Engine, key: &[u8]) {
         let snapshot = engine.snapshot(&Context::new()).unwrap();
         let mut statistics = Statistics::default();
@@ -32,11 +32,14 @@ const FLAG_LOCK: u8 = b'L';
 const FLAG_ROLLBACK: u8 = b'R';
 
 impl WriteType {
-    pub fn from_lock_type(tp: LockType) -> WriteType {
+    /// Returns `None` for pessimistic locks, which must be converted by prewrite before they
+    /// can be committed.
+    pub fn from_lock_type(tp: LockType) -> Option<WriteType> {
         match tp {
-            LockType::Put => WriteType::Put,
-            LockType::Delete => WriteType::Delete,
-            LockType::Lock => WriteType::Lock,
+            LockType::Put => Some(WriteType::Put),
+            LockType::Delete => Some(WriteType::Delete),
+            LockType::Lock => Some(WriteType::Lock),
+            LockType::Pessimistic => None,
         }
     }
 
@@ -127,7 +130,7 @@ mod tests {
                              (None, WriteType::Rollback, FLAG_ROLLBACK)];
         for (i, (lock_type, write_type, flag)) in tests.drain(..).enumerate() {
             if lock_type.is_some() {
-                let wt = WriteType::from_lock_type(lock_type.unwrap());
+                let wt = WriteType::from_lock_type(lock_type.unwrap()).unwrap();
                 assert_eq!(wt,
                            write_type,
                            "#{}, expect from_lock_type({:?}) returns {:?}, but got {:?}",
@@ -155,6 +158,11 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_write_type_from_pessimistic_lock() {
+        assert_eq!(WriteType::from_lock_type(LockType::Pessimistic), None);
+    }
+
     #[test]
     fn test_write() {
         // Test `Write::to_bytes()` and `Write::parse()` works as a pair.
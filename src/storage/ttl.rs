@@ -0,0 +1,103 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Time-to-live of raw values.
+//!
+//! When TTL is enabled, raw key-value pairs are kept in CF_RAW_TTL instead of CF_DEFAULT, and
+//! every raw value is followed by its expiry time, encoded as a big-endian u64 of seconds since
+//! the Unix epoch. An expiry time of 0 means the value never expires. Expired values are hidden
+//! from raw reads and dropped by `TTLCompactionFilter` in `util::rocksdb` during compaction,
+//! which is only installed on CF_RAW_TTL so the transactional data and the local metadata in
+//! CF_DEFAULT are never touched. The flag is saved in the store at the first start, and the
+//! store refuses to start if it's changed, as the raw data written before would be hidden.
+
+use time;
+
+use util::codec::number::{NumberDecoder, NumberEncoder};
+use util::codec::{Error, Result};
+use super::{CfName, CF_DEFAULT, CF_RAW_TTL};
+
+pub const TTL_SUFFIX_LEN: usize = 8;
+
+/// Returns the cf that keeps the raw key-value pairs.
+pub fn raw_cf(enable_ttl: bool) -> CfName {
+    if enable_ttl { CF_RAW_TTL } else { CF_DEFAULT }
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+pub fn current_ts() -> u64 {
+    time::get_time().sec as u64
+}
+
+/// Converts a TTL in seconds to an expiry time. A TTL of 0 never expires.
+pub fn ttl_to_expire_ts(ttl: u64) -> u64 {
+    if ttl == 0 {
+        return 0;
+    }
+    current_ts().saturating_add(ttl)
+}
+
+pub fn append_expire_ts(value: &mut Vec<u8>, expire_ts: u64) {
+    value.encode_u64(expire_ts).unwrap();
+}
+
+/// Splits a value written with TTL enabled into the user value and the expiry time.
+pub fn split_expire_ts(value: &[u8]) -> Result<(&[u8], u64)> {
+    if value.len() < TTL_SUFFIX_LEN {
+        return Err(Error::InvalidDataType(format!("value with ttl is too short: {}",
+                                                  value.len())));
+    }
+    let (user_value, mut suffix) = value.split_at(value.len() - TTL_SUFFIX_LEN);
+    let expire_ts = try!(suffix.decode_u64());
+    Ok((user_value, expire_ts))
+}
+
+pub fn is_expired(expire_ts: u64, now: u64) -> bool {
+    expire_ts != 0 && expire_ts <= now
+}
+
+/// Returns the user value of `value` if it has not expired at `now`.
+pub fn strip_expired(mut value: Vec<u8>, now: u64) -> Result<Option<Vec<u8>>> {
+    let (len, expire_ts) = {
+        let (user_value, expire_ts) = try!(split_expire_ts(&value));
+        (user_value.len(), expire_ts)
+    };
+    if is_expired(expire_ts, now) {
+        return Ok(None);
+    }
+    value.truncate(len);
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expire_ts() {
+        let mut value = b"v".to_vec();
+        append_expire_ts(&mut value, 10);
+        assert_eq!(value.len(), 1 + TTL_SUFFIX_LEN);
+        assert_eq!(split_expire_ts(&value).unwrap(), (&b"v"[..], 10));
+        assert_eq!(strip_expired(value.clone(), 9).unwrap(), Some(b"v".to_vec()));
+        assert_eq!(strip_expired(value, 10).unwrap(), None);
+
+        let mut value = vec![];
+        append_expire_ts(&mut value, 0);
+        assert_eq!(strip_expired(value, u64::max_value()).unwrap(), Some(vec![]));
+
+        assert!(split_expire_ts(b"short").is_err());
+        assert_eq!(ttl_to_expire_ts(0), 0);
+        assert!(ttl_to_expire_ts(10) >= current_ts() + 10);
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use util::collections::{HashMap, HashSet};
+
+/// The wait-for graph of the transactions waiting for locks, transactions are identified by
+/// their start ts.
+///
+/// Only the waits on the local store are known, so a deadlock across stores is broken by
+/// the wait timeout instead.
+#[derive(Default)]
+pub struct DetectTable {
+    // waiter ts -> lock ts -> number of the waits
+    wait_for_map: HashMap<u64, HashMap<u64, usize>>,
+}
+
+impl DetectTable {
+    /// Adds a wait of `txn_ts` for the lock of `lock_ts`. Returns true and doesn't add it if
+    /// the wait closes a cycle, in which case `txn_ts` should be aborted.
+    pub fn detect(&mut self, txn_ts: u64, lock_ts: u64) -> bool {
+        if self.reachable(lock_ts, txn_ts) {
+            return true;
+        }
+        let locks = self.wait_for_map.entry(txn_ts).or_insert_with(HashMap::default);
+        *locks.entry(lock_ts).or_insert(0) += 1;
+        false
+    }
+
+    /// Removes a wait of `txn_ts` for the lock of `lock_ts`.
+    pub fn clean_up_wait_for(&mut self, txn_ts: u64, lock_ts: u64) {
+        let no_wait = match self.wait_for_map.get_mut(&txn_ts) {
+            Some(locks) => {
+                let released = match locks.get_mut(&lock_ts) {
+                    Some(cnt) => {
+                        *cnt -= 1;
+                        *cnt == 0
+                    }
+                    None => false,
+                };
+                if released {
+                    locks.remove(&lock_ts);
+                }
+                locks.is_empty()
+            }
+            None => false,
+        };
+        if no_wait {
+            self.wait_for_map.remove(&txn_ts);
+        }
+    }
+
+    fn reachable(&self, from: u64, to: u64) -> bool {
+        let mut visited = HashSet::default();
+        let mut stack = vec![from];
+        while let Some(ts) = stack.pop() {
+            if ts == to {
+                return true;
+            }
+            if !visited.insert(ts) {
+                continue;
+            }
+            if let Some(locks) = self.wait_for_map.get(&ts) {
+                stack.extend(locks.keys());
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_table() {
+        let mut table = DetectTable::default();
+        assert!(!table.detect(1, 2));
+        assert!(!table.detect(2, 3));
+        assert!(!table.detect(1, 3));
+        // 3 -> 1 -> 2 -> 3
+        assert!(table.detect(3, 1));
+        assert!(!table.detect(4, 1));
+
+        // The wait of 1 for 2 is counted twice.
+        assert!(!table.detect(1, 2));
+        table.clean_up_wait_for(1, 2);
+        assert!(table.detect(3, 1));
+        table.clean_up_wait_for(1, 2);
+        table.clean_up_wait_for(1, 3);
+        assert!(!table.detect(3, 1));
+        assert!(table.detect(1, 4));
+        // Cleaning up an unknown wait is harmless.
+        table.clean_up_wait_for(5, 6);
+    }
+}
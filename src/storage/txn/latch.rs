@@ -14,21 +14,53 @@
 
 #![allow(deprecated)]
 
+use std::cmp;
 use std::collections::VecDeque;
 use std::hash::{Hash, SipHasher as DefaultHasher, Hasher};
+use std::time::{Duration, Instant};
 use std::usize;
 
+use kvproto::kvrpcpb::CommandPri;
+
+use util::{duration_to_ms, duration_to_sec};
+use super::super::metrics::*;
+
+// A waiter is promoted by one priority for every interval it has waited, so that the lower
+// priority commands are not starved by the higher priority ones.
+const LATCH_AGING_INTERVAL_MS: u64 = 500;
+
+/// A command in the waiting queue of a latch.
+#[derive(Clone)]
+struct Waiter {
+    cid: u64,
+    priority: CommandPri,
+    // When the command entered the queue, or when it got the latch if it is at the front.
+    since: Instant,
+}
+
+impl Waiter {
+    /// Returns the rank of the priority, raised by the time the command has waited.
+    fn aged_rank(&self, now: Instant) -> u8 {
+        let aged = duration_to_ms(now.duration_since(self.since)) / LATCH_AGING_INTERVAL_MS;
+        let rank = priority_rank(self.priority) as u64 + aged;
+        cmp::min(rank, priority_rank(CommandPri::High) as u64) as u8
+    }
+}
+
 /// Latch which is used to serialize accesses to resources hashed to the same slot.
 ///
 /// Latches are indexed by slot IDs. The keys of a command are hashed to slot IDs, then the command
 /// is added to the waiting queues of the latches.
 ///
-/// If command A is ahead of command B in one latch, it must be ahead of command B in all the
-/// overlapping latches. This is an invariant ensured by the `gen_lock`, `acquire` and `release`.
+/// The command at the front of the queue owns the latch. A command acquires its latches in the
+/// ascending order of slot IDs and waits on at most one latch at a time, so the owners can never
+/// wait for each other in a cycle. Waiters are ordered by priority, and by arrival within the same
+/// priority; the owner is never preempted. The priority of a waiter rises as it waits, so a
+/// command waits a bounded time before no later one can jump ahead of it.
 #[derive(Clone)]
 struct Latch {
     // store waiting commands
-    pub waiting: VecDeque<u64>,
+    waiting: VecDeque<Waiter>,
 }
 
 impl Latch {
@@ -36,6 +68,43 @@ impl Latch {
     pub fn new() -> Latch {
         Latch { waiting: VecDeque::new() }
     }
+
+    /// Enqueues a command behind the owner and all waiters with the same or a higher aged
+    /// priority.
+    fn push(&mut self, cid: u64, priority: CommandPri) {
+        let now = Instant::now();
+        let waiter = Waiter {
+            cid: cid,
+            priority: priority,
+            since: now,
+        };
+        let rank = priority_rank(priority);
+        let pos = self.waiting
+            .iter()
+            .skip(1)
+            .position(|w| w.aged_rank(now) < rank)
+            .map(|pos| pos + 1);
+        match pos {
+            Some(pos) => self.waiting.insert(pos, waiter),
+            None => self.waiting.push_back(waiter),
+        }
+    }
+}
+
+fn priority_rank(priority: CommandPri) -> u8 {
+    match priority {
+        CommandPri::Low => 0,
+        CommandPri::Normal => 1,
+        CommandPri::High => 2,
+    }
+}
+
+fn priority_tag(priority: CommandPri) -> &'static str {
+    match priority {
+        CommandPri::Low => "low",
+        CommandPri::Normal => "normal",
+        CommandPri::High => "high",
+    }
 }
 
 /// Lock required for a command.
@@ -46,6 +115,9 @@ pub struct Lock {
 
     /// The number of latches that the command has acquired.
     pub owned_count: usize,
+
+    /// The priority of the command, higher priority commands are queued ahead of lower ones.
+    pub priority: CommandPri,
 }
 
 impl Lock {
@@ -54,6 +126,7 @@ impl Lock {
         Lock {
             required_slots: required_slots,
             owned_count: 0,
+            priority: CommandPri::Normal,
         }
     }
 
@@ -67,6 +140,24 @@ impl Lock {
     }
 }
 
+/// A command in a latch, used for diagnostics.
+#[derive(Debug, Clone)]
+pub struct LatchWaiter {
+    pub cid: u64,
+    pub tag: &'static str,
+    pub priority: CommandPri,
+    /// How long the command has owned the latch, or has waited for it.
+    pub duration: Duration,
+}
+
+/// The owner and waiters of a latch, used for diagnostics.
+#[derive(Debug, Clone)]
+pub struct LatchInfo {
+    pub slot: usize,
+    pub owner: LatchWaiter,
+    pub waiters: Vec<LatchWaiter>,
+}
+
 /// Latches which are used for concurrency control in the scheduler.
 ///
 /// Each latch is indexed by a slot ID, hence the term latch and slot are used interchangably, but
@@ -109,18 +200,18 @@ impl Latches {
         for i in &lock.required_slots[lock.owned_count..] {
             let latch = &mut self.slots[*i];
 
-            let front = latch.waiting.front().cloned();
+            let front = latch.waiting.front().map(|w| w.cid);
             match front {
                 Some(cid) => {
                     if cid == who {
                         acquired_count += 1;
                     } else {
-                        latch.waiting.push_back(who);
+                        latch.push(who, lock.priority);
                         break;
                     }
                 }
                 None => {
-                    latch.waiting.push_back(who);
+                    latch.push(who, lock.priority);
                     acquired_count += 1;
                 }
             }
@@ -138,15 +229,45 @@ impl Latches {
         for i in &lock.required_slots[..lock.owned_count] {
             let latch = &mut self.slots[*i];
             let front = latch.waiting.pop_front().unwrap();
-            assert_eq!(front, who);
-
-            if let Some(wakeup) = latch.waiting.front() {
-                wakeup_list.push(*wakeup);
+            assert_eq!(front.cid, who);
+
+            if let Some(wakeup) = latch.waiting.front_mut() {
+                let tag = priority_tag(wakeup.priority);
+                SCHED_LATCH_SLOT_WAIT_HISTOGRAM_VEC.with_label_values(&[tag])
+                    .observe(duration_to_sec(wakeup.since.elapsed()));
+                wakeup.since = Instant::now();
+                wakeup_list.push(wakeup.cid);
             }
         }
         wakeup_list
     }
 
+    /// Returns the owner and waiters of all latches in use. Tags are left empty, the caller
+    /// knows the commands behind the IDs.
+    pub fn dump(&self) -> Vec<LatchInfo> {
+        let to_waiter = |w: &Waiter| {
+            LatchWaiter {
+                cid: w.cid,
+                tag: "",
+                priority: w.priority,
+                duration: w.since.elapsed(),
+            }
+        };
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, latch)| {
+                latch.waiting.front().map(|owner| {
+                    LatchInfo {
+                        slot: slot,
+                        owner: to_waiter(owner),
+                        waiters: latch.waiting.iter().skip(1).map(&to_waiter).collect(),
+                    }
+                })
+            })
+            .collect()
+    }
+
     /// Calculates the slot ID by hashing the `key`.
     fn calc_slot<H>(&self, key: &H) -> usize
         where H: Hash
@@ -159,7 +280,11 @@ impl Latches {
 
 #[cfg(test)]
 mod tests {
-    use super::{Latches, Lock};
+    use std::time::{Duration, Instant};
+
+    use kvproto::kvrpcpb::CommandPri;
+
+    use super::{Latches, Lock, LATCH_AGING_INTERVAL_MS};
 
     #[test]
     fn test_wakeup() {
@@ -232,4 +357,63 @@ mod tests {
         assert_eq!(acquired_c, true);
 
     }
+
+    #[test]
+    fn test_wakeup_by_priority() {
+        let mut latches = Latches::new(256);
+
+        let mut lock_a = Lock::new(vec![1]);
+        let mut lock_b = Lock::new(vec![1]);
+        let mut lock_c = Lock::new(vec![1]);
+        lock_c.priority = CommandPri::High;
+        let mut lock_d = Lock::new(vec![1]);
+        lock_d.priority = CommandPri::Low;
+        let (cid_a, cid_b, cid_c, cid_d) = (1, 2, 3, 4);
+
+        assert!(latches.acquire(&mut lock_a, cid_a));
+        assert!(!latches.acquire(&mut lock_d, cid_d));
+        assert!(!latches.acquire(&mut lock_b, cid_b));
+        assert!(!latches.acquire(&mut lock_c, cid_c));
+
+        // c jumps ahead of the normal and low priority waiters, but not the owner.
+        let infos = latches.dump();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].slot, 1);
+        assert_eq!(infos[0].owner.cid, cid_a);
+        let waiters: Vec<u64> = infos[0].waiters.iter().map(|w| w.cid).collect();
+        assert_eq!(waiters, vec![cid_c, cid_b, cid_d]);
+
+        assert_eq!(latches.release(&lock_a, cid_a), vec![cid_c]);
+        assert!(latches.acquire(&mut lock_c, cid_c));
+        assert_eq!(latches.release(&lock_c, cid_c), vec![cid_b]);
+        assert!(latches.acquire(&mut lock_b, cid_b));
+        assert_eq!(latches.release(&lock_b, cid_b), vec![cid_d]);
+        assert!(latches.acquire(&mut lock_d, cid_d));
+        latches.release(&lock_d, cid_d);
+        assert!(latches.dump().is_empty());
+    }
+
+    #[test]
+    fn test_wakeup_by_aged_priority() {
+        let mut latches = Latches::new(256);
+
+        let mut lock_a = Lock::new(vec![1]);
+        let mut lock_b = Lock::new(vec![1]);
+        lock_b.priority = CommandPri::Low;
+        let mut lock_c = Lock::new(vec![1]);
+        let mut lock_d = Lock::new(vec![1]);
+        lock_d.priority = CommandPri::High;
+        let (cid_a, cid_b, cid_c, cid_d) = (1, 2, 3, 4);
+
+        assert!(latches.acquire(&mut lock_a, cid_a));
+        assert!(!latches.acquire(&mut lock_b, cid_b));
+        // b has waited long enough to be promoted to high priority.
+        let since = Instant::now() - Duration::from_millis(LATCH_AGING_INTERVAL_MS * 2);
+        latches.slots[1].waiting[1].since = since;
+        assert!(!latches.acquire(&mut lock_c, cid_c));
+        assert!(!latches.acquire(&mut lock_d, cid_d));
+
+        let waiters: Vec<u64> = latches.dump()[0].waiters.iter().map(|w| w.cid).collect();
+        assert_eq!(waiters, vec![cid_b, cid_d, cid_c]);
+    }
 }
@@ -0,0 +1,143 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracks the max ts the regions are read at.
+//!
+//! A one-phase commit writes the committed record directly without leaving a lock, so a
+//! reader that has read the keys at a ts greater than or equal to the commit ts would not
+//! see the transaction, while a later reader at the same ts would. Readers record their ts
+//! here before taking the snapshot, and a one-phase commit at a ts not greater than the max
+//! read ts of its region is rejected.
+//!
+//! The reads served by the other stores are not recorded here. A follower sends the ts of its
+//! replica reads to the leader along with the read index request, and a new leader doesn't
+//! know the reads served by the old one, so the max read ts of its region is unknown until
+//! it gets a timestamp from PD, see `reset` and `init`.
+
+use std::{cmp, u64};
+use std::sync::{Arc, Mutex};
+
+use util::collections::HashMap;
+
+#[derive(Default)]
+struct Inner {
+    // region id -> max read ts
+    regions: HashMap<u64, u64>,
+    // region id -> token, the regions whose max read ts is unknown.
+    unknown: HashMap<u64, u64>,
+    next_token: u64,
+    // max read ts of all the regions
+    max_ts: u64,
+}
+
+/// The max read ts of the regions in a store, it's shared by all the readers.
+#[derive(Clone, Default)]
+pub struct MaxReadTs {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl MaxReadTs {
+    pub fn new() -> MaxReadTs {
+        MaxReadTs::default()
+    }
+
+    /// Records that the region is read at `ts`.
+    pub fn update(&self, region_id: u64, ts: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.max_ts = cmp::max(inner.max_ts, ts);
+        // A region seen for the first time may be split from a region that has been read,
+        // so it starts from the max read ts of the store.
+        let max_ts = inner.max_ts;
+        let region_ts = inner.regions.entry(region_id).or_insert(max_ts);
+        *region_ts = cmp::max(*region_ts, ts);
+    }
+
+    /// Gets the max read ts of the region, it's the max read ts of the store if the region
+    /// hasn't been read, or `u64::MAX` if it's unknown.
+    pub fn get(&self, region_id: u64) -> u64 {
+        let inner = self.inner.lock().unwrap();
+        if inner.unknown.contains_key(&region_id) {
+            return u64::MAX;
+        }
+        inner.regions.get(&region_id).cloned().unwrap_or(inner.max_ts)
+    }
+
+    /// Marks the max read ts of the region unknown, it's called when a peer of this store
+    /// becomes the leader of the region. Returns the token to `init` it.
+    pub fn reset(&self, region_id: u64) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_token += 1;
+        let token = inner.next_token;
+        inner.unknown.insert(region_id, token);
+        token
+    }
+
+    /// Returns the token of the region if its max read ts is unknown.
+    pub fn unknown_token(&self, region_id: u64) -> Option<u64> {
+        self.inner.lock().unwrap().unknown.get(&region_id).cloned()
+    }
+
+    /// Initializes the max read ts of the region with `ts` got from PD after `reset`, all the
+    /// reads served before by the other stores are below it. It's ignored if the region has
+    /// been reset again since the `token` was returned.
+    pub fn init(&self, region_id: u64, token: u64, ts: u64) {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.unknown.get(&region_id) != Some(&token) {
+                return;
+            }
+            inner.unknown.remove(&region_id);
+        }
+        self.update(region_id, ts);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::u64;
+
+    use super::*;
+
+    #[test]
+    fn test_max_read_ts() {
+        let max_read_ts = MaxReadTs::new();
+        assert_eq!(max_read_ts.get(1), 0);
+
+        max_read_ts.update(1, 10);
+        max_read_ts.update(1, 5);
+        assert_eq!(max_read_ts.get(1), 10);
+        // The regions that haven't been read use the max read ts of the store.
+        assert_eq!(max_read_ts.get(2), 10);
+
+        max_read_ts.update(2, 3);
+        assert_eq!(max_read_ts.get(2), 10);
+        max_read_ts.update(2, 20);
+        assert_eq!(max_read_ts.get(2), 20);
+        assert_eq!(max_read_ts.get(1), 10);
+        assert_eq!(max_read_ts.get(3), 20);
+
+        // The max read ts is unknown until it's initialized with the latest token.
+        let token = max_read_ts.reset(1);
+        assert_eq!(max_read_ts.unknown_token(1), Some(token));
+        assert_eq!(max_read_ts.get(1), u64::MAX);
+        max_read_ts.update(1, 15);
+        assert_eq!(max_read_ts.get(1), u64::MAX);
+        let new_token = max_read_ts.reset(1);
+        max_read_ts.init(1, token, 30);
+        assert_eq!(max_read_ts.get(1), u64::MAX);
+        max_read_ts.init(1, new_token, 25);
+        assert_eq!(max_read_ts.unknown_token(1), None);
+        assert_eq!(max_read_ts.get(1), 25);
+        assert_eq!(max_read_ts.get(2), 20);
+    }
+}
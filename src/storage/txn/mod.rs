@@ -14,12 +14,19 @@
 mod store;
 mod scheduler;
 mod latch;
+mod deadlock;
+mod waiter_manager;
+mod max_read_ts;
 
 use std::error;
 use std::io::Error as IoError;
 
-pub use self::scheduler::{Scheduler, Msg, GC_BATCH_SIZE, RESOLVE_LOCK_BATCH_SIZE};
+pub use self::scheduler::{Scheduler, Msg, LatchesCallback, RESOLVE_LOCK_BATCH_SIZE,
+                          RAW_DELETE_RANGE_BATCH_SIZE};
 pub use self::store::SnapshotStore;
+pub use self::latch::{LatchInfo, LatchWaiter};
+pub use self::waiter_manager::{WaiterManager, Task as WaiterTask};
+pub use self::max_read_ts::MaxReadTs;
 
 quick_error! {
     #[derive(Debug)]
@@ -61,6 +68,20 @@ quick_error! {
                         start_ts,
                         commit_ts)
         }
+        CommitTsExpired {start_ts: u64, commit_ts: u64, max_read_ts: u64} {
+            description("commit ts is expired")
+            display("commit ts {} of transaction {} is not greater than max read ts {}",
+                        commit_ts,
+                        start_ts,
+                        max_read_ts)
+        }
+        Deadlock {start_ts: u64, lock_ts: u64, lock_key: Vec<u8>} {
+            description("deadlock")
+            display("transaction {} deadlocks waiting for lock {}@{}",
+                        start_ts,
+                        ::util::escape(lock_key),
+                        lock_ts)
+        }
     }
 }
 
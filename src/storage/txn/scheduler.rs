@@ -31,36 +31,50 @@
 //! is ensured by the transaction protocol implemented in the client library, which is transparent
 //! to the scheduler.
 
-use std::boxed::Box;
+use std::boxed::{Box, FnBox};
 use std::fmt::{self, Formatter, Debug};
-use std::sync::mpsc::Receiver;
-use std::time::Duration;
+use std::mem;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
 use std::thread;
+use std::u64;
 
 use threadpool::ThreadPool;
 use prometheus::HistogramTimer;
 use kvproto::kvrpcpb::{Context, LockInfo, CommandPri};
 
 use storage::{Engine, Command, Snapshot, StorageCb, Result as StorageResult,
-              Error as StorageError, ScanMode, Statistics};
-use storage::mvcc::{MvccTxn, MvccReader, Error as MvccError, MAX_TXN_WRITE_SIZE};
-use storage::{Key, Value, KvPair, CMD_TAG_GC};
+              Error as StorageError, ScanMode, Statistics, FlowStatistics};
+use storage::mvcc::{MvccTxn, MvccReader, MvccInfo, TxnStatus, Error as MvccError, Write,
+                    WriteType, MAX_TXN_WRITE_SIZE};
+use storage::{Key, Value, KvPair, Mutation, CfName, CF_DEFAULT, CF_WRITE, is_short_value};
+use storage::ttl;
 use storage::engine::{CbContext, Result as EngineResult, Callback as EngineCallback, Modify};
+use raftstore::store::engine::IterOption;
+use raftstore::store::EngineStallSignals;
 use util::transport::{SyncSendCh, Error as TransportError};
 use util::SlowTimer;
 use util::collections::HashMap;
+use util::worker::{FutureScheduler, Stopped};
 
 use super::Result;
 use super::Error;
 use super::store::SnapshotStore;
-use super::latch::{Latches, Lock};
+use super::latch::{Latches, Lock, LatchInfo};
+use super::waiter_manager::Task as WaiterTask;
+use super::max_read_ts::MaxReadTs;
 use super::super::metrics::*;
 
-// TODO: make it configurable.
-pub const GC_BATCH_SIZE: usize = 512;
-
 pub const RESOLVE_LOCK_BATCH_SIZE: usize = 512;
 
+pub const RAW_DELETE_RANGE_BATCH_SIZE: usize = 512;
+
+// Reading properties from RocksDB is not free, so don't do it for every write.
+const STALL_SIGNALS_REFRESH_INTERVAL_MS: u64 = 1000;
+
+// Sending the read flow to the engine for every read is too expensive, so aggregate it.
+const READ_FLOW_REPORT_INTERVAL_MS: u64 = 1000;
+
 /// Process result of a command.
 pub enum ProcessResult {
     Res,
@@ -68,7 +82,14 @@ pub enum ProcessResult {
     MultiKvpairs { pairs: Vec<StorageResult<KvPair>> },
     Value { value: Option<Value> },
     Locks { locks: Vec<LockInfo> },
+    TxnStatus { txn_status: TxnStatus },
+    MvccKey { mvcc: MvccInfo },
+    MvccKvs { pairs: Vec<(Vec<u8>, MvccInfo)> },
     NextCommand { cmd: Command },
+    RawCompareAndSwap {
+        previous_value: Option<Value>,
+        succeed: bool,
+    },
     Failed { err: StorageError },
 }
 
@@ -81,7 +102,11 @@ pub enum Msg {
         cb_ctx: CbContext,
         snapshot: EngineResult<Box<Snapshot>>,
     },
-    ReadFinished { cid: u64, pr: ProcessResult },
+    ReadFinished {
+        cid: u64,
+        pr: ProcessResult,
+        read_flow: FlowStatistics,
+    },
     WritePrepareFinished {
         cid: u64,
         cmd: Command,
@@ -95,8 +120,11 @@ pub enum Msg {
         cb_ctx: CbContext,
         result: EngineResult<()>,
     },
+    DumpLatches { cb: LatchesCallback },
 }
 
+pub type LatchesCallback = Box<FnBox(Vec<LatchInfo>) + Send>;
+
 /// Debug for messages.
 impl Debug for Msg {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
@@ -112,12 +140,13 @@ impl Debug for Msg {
                 write!(f, "WritePrepareFailed [cid={}, err={:?}]", cid, err)
             }
             Msg::WriteFinished { cid, .. } => write!(f, "WriteFinished [cid={}]", cid),
+            Msg::DumpLatches { .. } => write!(f, "DumpLatches"),
         }
     }
 }
 
 /// Delivers the process result of a command to the storage callback.
-fn execute_callback(callback: StorageCb, pr: ProcessResult) {
+pub fn execute_callback(callback: StorageCb, pr: ProcessResult) {
     match callback {
         StorageCb::Boolean(cb) => {
             match pr {
@@ -154,6 +183,36 @@ fn execute_callback(callback: StorageCb, pr: ProcessResult) {
                 _ => panic!("process result mismatch"),
             }
         }
+        StorageCb::TxnStatus(cb) => {
+            match pr {
+                ProcessResult::TxnStatus { txn_status } => cb(Ok(txn_status)),
+                ProcessResult::Failed { err } => cb(Err(err)),
+                _ => panic!("process result mismatch"),
+            }
+        }
+        StorageCb::RawCompareAndSwap(cb) => {
+            match pr {
+                ProcessResult::RawCompareAndSwap { previous_value, succeed } => {
+                    cb(Ok((previous_value, succeed)))
+                }
+                ProcessResult::Failed { err } => cb(Err(err)),
+                _ => panic!("process result mismatch"),
+            }
+        }
+        StorageCb::MvccInfoByKey(cb) => {
+            match pr {
+                ProcessResult::MvccKey { mvcc } => cb(Ok(mvcc)),
+                ProcessResult::Failed { err } => cb(Err(err)),
+                _ => panic!("process result mismatch"),
+            }
+        }
+        StorageCb::MvccInfos(cb) => {
+            match pr {
+                ProcessResult::MvccKvs { pairs } => cb(Ok(pairs)),
+                ProcessResult::Failed { err } => cb(Err(err)),
+                _ => panic!("process result mismatch"),
+            }
+        }
     }
 }
 
@@ -169,6 +228,10 @@ pub struct RunningCtx {
     latch_timer: Option<HistogramTimer>,
     _timer: HistogramTimer,
     slow_timer: SlowTimer,
+    // the smallest and the largest key written by the command, used to keep imports away
+    write_range: Option<(Key, Key)>,
+    // the locks released by the command, whose waiters are woken up after the write
+    released_locks: Vec<(u64, Vec<Key>)>,
 }
 
 impl RunningCtx {
@@ -177,6 +240,7 @@ impl RunningCtx {
         let tag = cmd.tag();
         let ts = cmd.ts();
         let region_id = cmd.get_context().get_region_id();
+        let write_range = key_range(&command_keys(&cmd)).map(|(s, e)| (s.clone(), e.clone()));
         RunningCtx {
             cid: cid,
             cmd: Some(cmd),
@@ -188,6 +252,8 @@ impl RunningCtx {
             latch_timer: Some(SCHED_LATCH_HISTOGRAM_VEC.with_label_values(&[tag]).start_timer()),
             _timer: SCHED_HISTOGRAM_VEC.with_label_values(&[tag]).start_timer(),
             slow_timer: SlowTimer::new(),
+            write_range: write_range,
+            released_locks: vec![],
         }
     }
 }
@@ -245,19 +311,46 @@ pub struct Scheduler {
     // high priority commands will be delivered to this pool
     high_priority_pool: ThreadPool,
 
-    has_gc_command: bool,
 
     // used to control write flow
     running_write_count: usize,
+
+    // write stall signals of the engine, refreshed every `STALL_SIGNALS_REFRESH_INTERVAL_MS`
+    stall_signals: EngineStallSignals,
+    stall_signals_refresh_time: Instant,
+    stall_soft_limits: EngineStallSignals,
+    stall_hard_limits: EngineStallSignals,
+
+    // whether raw values carry an expiry time
+    enable_ttl: bool,
+
+    // cid -> key range of the running imports
+    importing_ranges: HashMap<u64, (Key, Key)>,
+
+    // commands blocked by locks wait in the waiter manager if it's set
+    waiter_mgr: Option<FutureScheduler<WaiterTask>>,
+
+    // region id -> flow read since `read_flow_report_time`
+    read_flow: HashMap<u64, FlowStatistics>,
+    read_flow_report_time: Instant,
+
+    // max ts the regions are read at, 1pc transactions must commit after it
+    max_read_ts: MaxReadTs,
 }
 
 impl Scheduler {
     /// Creates a scheduler.
+    #[allow(too_many_arguments)]
     pub fn new(engine: Box<Engine>,
                schedch: SyncSendCh<Msg>,
                concurrency: usize,
                worker_pool_size: usize,
-               sched_too_busy_threshold: usize)
+               sched_too_busy_threshold: usize,
+               stall_soft_limits: EngineStallSignals,
+               stall_hard_limits: EngineStallSignals,
+               enable_ttl: bool,
+               waiter_mgr: Option<FutureScheduler<WaiterTask>>,
+               max_read_ts: MaxReadTs)
                -> Scheduler {
         Scheduler {
             engine: engine,
@@ -269,15 +362,28 @@ impl Scheduler {
             worker_pool: ThreadPool::new_with_name(thd_name!("sched-worker-pool"),
                                                    worker_pool_size),
             high_priority_pool: ThreadPool::new_with_name(thd_name!("sched-high-pri-pool"), 1),
-            has_gc_command: false,
             running_write_count: 0,
+            stall_signals: EngineStallSignals::default(),
+            stall_signals_refresh_time: Instant::now(),
+            stall_soft_limits: stall_soft_limits,
+            stall_hard_limits: stall_hard_limits,
+            enable_ttl: enable_ttl,
+            importing_ranges: Default::default(),
+            waiter_mgr: waiter_mgr,
+            read_flow: HashMap::default(),
+            read_flow_report_time: Instant::now(),
+            max_read_ts: max_read_ts,
         }
     }
 }
 
 /// Processes a read command within a worker thread, then posts `ReadFinished` message back to the
 /// event loop.
-fn process_read(cid: u64, mut cmd: Command, ch: SyncSendCh<Msg>, snapshot: Box<Snapshot>) {
+fn process_read(cid: u64,
+                mut cmd: Command,
+                ch: SyncSendCh<Msg>,
+                snapshot: Box<Snapshot>,
+                enable_ttl: bool) {
     debug!("process read cmd(cid={}) in worker pool.", cid);
     SCHED_WORKER_COUNTER_VEC.with_label_values(&[cmd.tag(), "read"]).inc();
     let tag = cmd.tag();
@@ -318,11 +424,31 @@ fn process_read(cid: u64, mut cmd: Command, ch: SyncSendCh<Msg>, snapshot: Box<S
             }
         }
         // Scans a range starting with `start_key` up to `limit` rows from the snapshot.
-        Command::Scan { ref ctx, ref start_key, limit, start_ts, ref options, .. } => {
+        Command::Scan { ref ctx,
+                        ref start_key,
+                        ref end_key,
+                        limit,
+                        start_ts,
+                        ref options,
+                        reverse } => {
             let snap_store =
                 SnapshotStore::new(snapshot.as_ref(), start_ts, ctx.get_isolation_level());
-            let res = snap_store.scanner(ScanMode::Forward, options.key_only, None, &mut statistics)
-                .and_then(|mut scanner| scanner.scan(start_key.clone(), limit))
+            let bound = end_key.as_ref().map(|k| k.encoded().to_owned());
+            let (mode, lower_bound, upper_bound) = if reverse {
+                (ScanMode::Backward, bound, None)
+            } else {
+                (ScanMode::Forward, None, bound)
+            };
+            let res = snap_store.scanner(mode,
+                         options.key_only,
+                         lower_bound,
+                         upper_bound,
+                         &mut statistics)
+                .and_then(|mut scanner| if reverse {
+                    scanner.reverse_scan(start_key.clone(), limit)
+                } else {
+                    scanner.scan(start_key.clone(), limit)
+                })
                 .and_then(|mut results| {
                     KV_COMMAND_KEYREAD_HISTOGRAM_VEC.with_label_values(&[tag])
                         .observe(results.len() as f64);
@@ -334,15 +460,17 @@ fn process_read(cid: u64, mut cmd: Command, ch: SyncSendCh<Msg>, snapshot: Box<S
                 Err(e) => ProcessResult::Failed { err: e.into() },
             }
         }
-        // Scans locks with timestamp <= `max_ts`
-        Command::ScanLock { ref ctx, max_ts, .. } => {
+        // Scans locks with timestamp <= `max_ts` in the range
+        Command::ScanLock { ref ctx, max_ts, ref mut start_key, ref end_key, limit } => {
+            let upper_bound = end_key.as_ref().map(|k| k.encoded().to_owned());
             let mut reader = MvccReader::new(snapshot.as_ref(),
                                              &mut statistics,
                                              Some(ScanMode::Forward),
                                              true,
-                                             None,
+                                             upper_bound,
                                              ctx.get_isolation_level());
-            let res = reader.scan_lock(None, |lock| lock.ts <= max_ts, None)
+            let limit = if limit == 0 { None } else { Some(limit) };
+            let res = reader.scan_lock(start_key.take(), |lock| lock.ts <= max_ts, limit)
                 .map_err(Error::from)
                 .and_then(|(v, _)| {
                     let mut locks = vec![];
@@ -362,9 +490,9 @@ fn process_read(cid: u64, mut cmd: Command, ch: SyncSendCh<Msg>, snapshot: Box<S
                 Err(e) => ProcessResult::Failed { err: e.into() },
             }
         }
-        // Scan the locks with timestamp `start_ts`, then either commit them if the command has
-        // commit timestamp populated or rollback otherwise.
-        Command::ResolveLock { ref ctx, start_ts, commit_ts, ref mut scan_key, .. } => {
+        // Scans the locks of the transactions in `txn_status` in one pass, then either commits
+        // them if the transaction has a commit timestamp or rolls them back otherwise.
+        Command::ResolveLock { ref ctx, ref txn_status, ref mut scan_key, .. } => {
             let mut reader = MvccReader::new(snapshot.as_ref(),
                                              &mut statistics,
                                              Some(ScanMode::Forward),
@@ -372,22 +500,20 @@ fn process_read(cid: u64, mut cmd: Command, ch: SyncSendCh<Msg>, snapshot: Box<S
                                              None,
                                              ctx.get_isolation_level());
             let res = reader.scan_lock(scan_key.take(),
-                           |lock| lock.ts == start_ts,
+                           |lock| txn_status.contains_key(&lock.ts),
                            Some(RESOLVE_LOCK_BATCH_SIZE))
                 .map_err(Error::from)
-                .and_then(|(v, next_scan_key)| {
-                    let keys: Vec<Key> = v.into_iter().map(|x| x.0).collect();
+                .and_then(|(key_locks, next_scan_key)| {
                     KV_COMMAND_KEYREAD_HISTOGRAM_VEC.with_label_values(&[tag])
-                        .observe(keys.len() as f64);
-                    if keys.is_empty() {
+                        .observe(key_locks.len() as f64);
+                    if key_locks.is_empty() {
                         Ok(None)
                     } else {
                         Ok(Some(Command::ResolveLock {
                             ctx: ctx.clone(),
-                            start_ts: start_ts,
-                            commit_ts: commit_ts,
+                            txn_status: txn_status.clone(),
                             scan_key: next_scan_key,
-                            keys: keys,
+                            key_locks: key_locks,
                         }))
                     }
                 });
@@ -397,49 +523,124 @@ fn process_read(cid: u64, mut cmd: Command, ch: SyncSendCh<Msg>, snapshot: Box<S
                 Err(e) => ProcessResult::Failed { err: e.into() },
             }
         }
-        // Collects garbage.
-        Command::Gc { ref ctx, safe_point, ref mut scan_key, .. } => {
+        Command::MvccByKey { ref ctx, ref key, max_ts } => {
             let mut reader = MvccReader::new(snapshot.as_ref(),
                                              &mut statistics,
-                                             Some(ScanMode::Forward),
-                                             true,
+                                             None,
+                                             false,
                                              None,
                                              ctx.get_isolation_level());
-            // scan_key is used as start_key here,and Range start gc with scan_key=none.
-            let is_range_start_key = scan_key.is_none();
-            let res = reader.scan_keys(scan_key.take(), GC_BATCH_SIZE)
+            match reader.get_mvcc_info(key, max_ts) {
+                Ok(mvcc) => {
+                    KV_COMMAND_KEYREAD_HISTOGRAM_VEC.with_label_values(&[tag]).observe(1f64);
+                    ProcessResult::MvccKey { mvcc: mvcc }
+                }
+                Err(e) => ProcessResult::Failed { err: StorageError::from(Error::from(e)) },
+            }
+        }
+        Command::MvccScan { ref ctx, ref start_key, ref end_key, limit, max_ts } => {
+            let res = mvcc_scan(snapshot.as_ref(),
+                                ctx,
+                                start_key,
+                                end_key,
+                                limit,
+                                max_ts,
+                                &mut statistics);
+            match res {
+                Ok(pairs) => {
+                    KV_COMMAND_KEYREAD_HISTOGRAM_VEC.with_label_values(&[tag])
+                        .observe(pairs.len() as f64);
+                    ProcessResult::MvccKvs { pairs: pairs }
+                }
+                Err(e) => ProcessResult::Failed { err: e.into() },
+            }
+        }
+        Command::RawGet { ref key, .. } => {
+            KV_COMMAND_KEYREAD_HISTOGRAM_VEC.with_label_values(&[tag]).observe(1f64);
+            let res = snapshot.get_cf(ttl::raw_cf(enable_ttl), key)
                 .map_err(Error::from)
-                .and_then(|(keys, next_start)| {
+                .and_then(|val| match val {
+                    Some(v) => decode_raw_value(v, enable_ttl),
+                    None => Ok(None),
+                });
+            if let Ok(Some(ref v)) = res {
+                statistics.flow_stats.add_read(key.encoded(), v);
+            }
+            match res {
+                Ok(val) => ProcessResult::Value { value: val },
+                Err(e) => ProcessResult::Failed { err: StorageError::from(e) },
+            }
+        }
+        Command::RawBatchGet { ref keys, .. } => {
+            KV_COMMAND_KEYREAD_HISTOGRAM_VEC.with_label_values(&[tag])
+                .observe(keys.len() as f64);
+            let cf = ttl::raw_cf(enable_ttl);
+            let mut pairs = vec![];
+            for k in keys {
+                match snapshot.get_cf(cf, k).map_err(Error::from) {
+                    Ok(Some(v)) => {
+                        match decode_raw_value(v, enable_ttl) {
+                            Ok(Some(v)) => {
+                                statistics.flow_stats.add_read(k.encoded(), &v);
+                                pairs.push(Ok((k.encoded().to_owned(), v)))
+                            }
+                            Ok(None) => {}
+                            Err(e) => pairs.push(Err(StorageError::from(e))),
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => pairs.push(Err(StorageError::from(e))),
+                }
+            }
+            ProcessResult::MultiKvpairs { pairs: pairs }
+        }
+        Command::RawScan { ref start_key, limit, key_only, reverse, .. } => {
+            let res = raw_scan(snapshot.as_ref(),
+                               start_key,
+                               limit,
+                               key_only,
+                               reverse,
+                               enable_ttl,
+                               &mut statistics);
+            KV_COMMAND_SCAN_INEFFICIENCY.observe(statistics.inefficiency());
+            match res {
+                Ok(pairs) => {
+                    KV_COMMAND_KEYREAD_HISTOGRAM_VEC.with_label_values(&[tag])
+                        .observe(pairs.len() as f64);
+                    ProcessResult::MultiKvpairs { pairs: pairs }
+                }
+                Err(e) => ProcessResult::Failed { err: e.into() },
+            }
+        }
+        // Scans a batch of keys to delete, they are deleted by the next command which holds
+        // their latches.
+        Command::RawDeleteRange { ref ctx, ref start_key, ref end_key, .. } => {
+            match raw_scan_keys(snapshot.as_ref(),
+                                start_key,
+                                end_key,
+                                ttl::raw_cf(enable_ttl),
+                                &mut statistics) {
+                Ok(keys) => {
                     KV_COMMAND_KEYREAD_HISTOGRAM_VEC.with_label_values(&[tag])
                         .observe(keys.len() as f64);
                     if keys.is_empty() {
-                        // empty range
-                        if is_range_start_key {
-                            KV_COMMAND_GC_EMPTY_RANGE_COUNTER.inc();
-                        }
-                        Ok(None)
+                        ProcessResult::Res
                     } else {
-                        Ok(Some(Command::Gc {
+                        // Continues from the key right after the last one of the batch.
+                        let mut next_start = keys[keys.len() - 1].encoded().to_owned();
+                        next_start.push(0);
+                        let cmd = Command::RawDeleteRange {
                             ctx: ctx.clone(),
-                            safe_point: safe_point,
-                            scan_key: next_start,
+                            start_key: Key::from_encoded(next_start),
+                            end_key: end_key.clone(),
                             keys: keys,
-                        }))
+                        };
+                        ProcessResult::NextCommand { cmd: cmd }
                     }
-                });
-            match res {
-                Ok(Some(cmd)) => ProcessResult::NextCommand { cmd: cmd },
-                Ok(None) => ProcessResult::Res,
+                }
                 Err(e) => ProcessResult::Failed { err: e.into() },
             }
         }
-        Command::RawGet { ref key, .. } => {
-            KV_COMMAND_KEYREAD_HISTOGRAM_VEC.with_label_values(&[tag]).observe(1f64);
-            match snapshot.get(key) {
-                Ok(val) => ProcessResult::Value { value: val },
-                Err(e) => ProcessResult::Failed { err: StorageError::from(e) },
-            }
-        }
         Command::Pause { duration, .. } => {
             thread::sleep(Duration::from_millis(duration));
             ProcessResult::Res
@@ -447,17 +648,137 @@ fn process_read(cid: u64, mut cmd: Command, ch: SyncSendCh<Msg>, snapshot: Box<S
         _ => panic!("unsupported read command"),
     };
 
-    if let Err(e) = ch.send(Msg::ReadFinished { cid: cid, pr: pr }) {
+    let msg = Msg::ReadFinished {
+        cid: cid,
+        pr: pr,
+        read_flow: statistics.flow_stats,
+    };
+    if let Err(e) = ch.send(msg) {
         // Todo: if this happens we need to clean up command's context
         panic!("send read finished failed, cid={}, err={:?}", cid, e);
     }
 }
 
+/// Collects the MVCC records of at most `limit` keys in range [`start_key`, `end_key`).
+fn mvcc_scan(snapshot: &Snapshot,
+             ctx: &Context,
+             start_key: &Key,
+             end_key: &Option<Key>,
+             limit: usize,
+             max_ts: u64,
+             statistics: &mut Statistics)
+             -> Result<Vec<(Vec<u8>, MvccInfo)>> {
+    let keys = {
+        let upper_bound = end_key.as_ref().map(|k| k.encoded().to_owned());
+        let mut reader = MvccReader::new(snapshot,
+                                         statistics,
+                                         Some(ScanMode::Forward),
+                                         false,
+                                         upper_bound,
+                                         ctx.get_isolation_level());
+        try!(reader.scan_mvcc_keys(start_key.clone(), limit))
+    };
+    // Versions of a key are visited in the reverse order of the keys, so use another reader.
+    let mut reader = MvccReader::new(snapshot,
+                                     statistics,
+                                     None,
+                                     false,
+                                     None,
+                                     ctx.get_isolation_level());
+    let mut pairs = Vec::with_capacity(keys.len());
+    for key in keys {
+        let info = try!(reader.get_mvcc_info(&key, max_ts));
+        pairs.push((try!(key.raw()), info));
+    }
+    Ok(pairs)
+}
+
+/// Strips the expiry time from a raw value if TTL is enabled. Returns `None` if it has expired.
+fn decode_raw_value(value: Value, enable_ttl: bool) -> Result<Option<Value>> {
+    if !enable_ttl {
+        return Ok(Some(value));
+    }
+    ttl::strip_expired(value, ttl::current_ts()).map_err(Error::from)
+}
+
+/// Returns at most `RAW_DELETE_RANGE_BATCH_SIZE` raw keys in range [`start_key`, `end_key`).
+fn raw_scan_keys(snapshot: &Snapshot,
+                 start_key: &Key,
+                 end_key: &Key,
+                 cf: CfName,
+                 statistics: &mut Statistics)
+                 -> Result<Vec<Key>> {
+    let upper_bound = if end_key.encoded().is_empty() {
+        None
+    } else {
+        Some(end_key.encoded().to_owned())
+    };
+    let iter_opt = IterOption::new(upper_bound, false);
+    let mut cursor = try!(snapshot.iter_cf(cf, iter_opt, ScanMode::Forward));
+    let mut keys = vec![];
+    let mut valid = try!(cursor.seek(start_key, statistics));
+    while valid && keys.len() < RAW_DELETE_RANGE_BATCH_SIZE {
+        keys.push(Key::from_encoded(cursor.key().to_vec()));
+        valid = cursor.next(statistics);
+    }
+    Ok(keys)
+}
+
+/// Scans raw key-value pairs, skipping the expired ones.
+///
+/// A reverse scan returns keys in descending order, starting from the largest key that is
+/// smaller than `start_key`.
+fn raw_scan(snapshot: &Snapshot,
+            start_key: &Key,
+            limit: usize,
+            key_only: bool,
+            reverse: bool,
+            enable_ttl: bool,
+            statistics: &mut Statistics)
+            -> Result<Vec<StorageResult<KvPair>>> {
+    let mode = if reverse {
+        ScanMode::Backward
+    } else {
+        ScanMode::Forward
+    };
+    let cf = ttl::raw_cf(enable_ttl);
+    let mut cursor = try!(snapshot.iter_cf(cf, IterOption::default(), mode));
+    let mut valid = if reverse {
+        try!(cursor.reverse_seek(start_key, statistics))
+    } else {
+        try!(cursor.seek(start_key, statistics))
+    };
+    let mut pairs = vec![];
+    while valid && pairs.len() < limit {
+        let value = if key_only && !enable_ttl {
+            Some(vec![])
+        } else {
+            try!(decode_raw_value(cursor.value().to_vec(), enable_ttl))
+        };
+        if let Some(value) = value {
+            let value = if key_only { vec![] } else { value };
+            statistics.flow_stats.add_read(cursor.key(), &value);
+            pairs.push(Ok((cursor.key().to_vec(), value)));
+        }
+        valid = if reverse {
+            cursor.prev(statistics)
+        } else {
+            cursor.next(statistics)
+        };
+    }
+    statistics.processed += pairs.len();
+    Ok(pairs)
+}
+
 /// Processes a write command within a worker thread, then posts either a `WritePrepareFinished`
 /// message if successful or a `WritePrepareFailed` message back to the event loop.
-fn process_write(cid: u64, cmd: Command, ch: SyncSendCh<Msg>, snapshot: Box<Snapshot>) {
+fn process_write(cid: u64,
+                 cmd: Command,
+                 ch: SyncSendCh<Msg>,
+                 snapshot: Box<Snapshot>,
+                 enable_ttl: bool) {
     SCHED_WORKER_COUNTER_VEC.with_label_values(&[cmd.tag(), "write"]).inc();
-    if let Err(e) = process_write_impl(cid, cmd, ch.clone(), snapshot.as_ref()) {
+    if let Err(e) = process_write_impl(cid, cmd, ch.clone(), snapshot.as_ref(), enable_ttl) {
         if let Err(err) = ch.send(Msg::WritePrepareFailed { cid: cid, err: e }) {
             // Todo: if this happens, lock will hold for ever
             panic!("send WritePrepareFailed message to channel failed. cid={}, err={:?}",
@@ -470,19 +791,81 @@ fn process_write(cid: u64, cmd: Command, ch: SyncSendCh<Msg>, snapshot: Box<Snap
 fn process_write_impl(cid: u64,
                       mut cmd: Command,
                       ch: SyncSendCh<Msg>,
-                      snapshot: &Snapshot)
+                      snapshot: &Snapshot,
+                      enable_ttl: bool)
                       -> Result<()> {
     let mut statistics = Statistics::default();
     let (pr, modifies) = match cmd {
         Command::Prewrite { ref ctx, ref mutations, ref primary, start_ts, ref options, .. } => {
+            let one_pc = options.one_pc_commit_ts != 0;
+            if one_pc {
+                if options.one_pc_commit_ts <= start_ts {
+                    return Err(Error::InvalidTxnTso {
+                        start_ts: start_ts,
+                        commit_ts: options.one_pc_commit_ts,
+                    });
+                }
+                if options.for_update_ts != 0 {
+                    // Pessimistic locks must be removed by the commit phase.
+                    return Err(box_err!("1pc is not supported by pessimistic transactions"));
+                }
+                for m in mutations {
+                    try!(snapshot.validate_key(m.key()));
+                }
+            }
             let mut txn = MvccTxn::new(snapshot,
                                        &mut statistics,
                                        start_ts,
                                        None,
                                        ctx.get_isolation_level());
             let mut locks = vec![];
-            for m in mutations {
-                match txn.prewrite(m.clone(), primary, options) {
+            for (i, m) in mutations.iter().enumerate() {
+                // All the mutations are proposed together, so a 1pc transaction is committed
+                // atomically as they belong to the region of `ctx`.
+                let res = if one_pc {
+                    txn.one_pc_commit(m.clone(), options.one_pc_commit_ts, options)
+                } else if options.for_update_ts == 0 {
+                    txn.prewrite(m.clone(), primary, options)
+                } else {
+                    let is_pessimistic_lock = options.is_pessimistic_lock
+                        .get(i)
+                        .cloned()
+                        .unwrap_or(false);
+                    txn.pessimistic_prewrite(m.clone(), primary, is_pessimistic_lock, options)
+                };
+                match res {
+                    Ok(_) => {}
+                    e @ Err(MvccError::KeyIsLocked { .. }) => {
+                        locks.push(e.map_err(Error::from).map_err(StorageError::from));
+                    }
+                    Err(e) => return Err(Error::from(e)),
+                }
+            }
+            if locks.is_empty() {
+                let pr = ProcessResult::MultiRes { results: vec![] };
+                (pr, txn.modifies())
+            } else {
+                // Skip write stage if some keys are locked.
+                let pr = ProcessResult::MultiRes { results: locks };
+                (pr, vec![])
+            }
+        }
+        Command::AcquirePessimisticLock { ref ctx,
+                                          ref keys,
+                                          ref primary,
+                                          start_ts,
+                                          ref options } => {
+            let mut txn = MvccTxn::new(snapshot,
+                                       &mut statistics,
+                                       start_ts,
+                                       None,
+                                       ctx.get_isolation_level());
+            let mut locks = vec![];
+            for k in keys {
+                match txn.acquire_pessimistic_lock(k.clone(),
+                                                   primary,
+                                                   options.for_update_ts,
+                                                   options) {
                     Ok(_) => {}
                     e @ Err(MvccError::KeyIsLocked { .. }) => {
                         locks.push(e.map_err(Error::from).map_err(StorageError::from));
@@ -529,6 +912,28 @@ fn process_write_impl(cid: u64,
             let pr = ProcessResult::Res;
             (pr, txn.modifies())
         }
+        Command::TxnHeartBeat { ref ctx, ref primary_key, start_ts, advise_ttl } => {
+            let mut txn = MvccTxn::new(snapshot,
+                                       &mut statistics,
+                                       start_ts,
+                                       None,
+                                       ctx.get_isolation_level());
+            let ttl = try!(txn.txn_heart_beat(primary_key.clone(), advise_ttl));
+
+            let pr = ProcessResult::TxnStatus { txn_status: TxnStatus::Locked { ttl: ttl } };
+            (pr, txn.modifies())
+        }
+        Command::CheckTxnStatus { ref ctx, ref primary_key, lock_ts, current_ts } => {
+            let mut txn = MvccTxn::new(snapshot,
+                                       &mut statistics,
+                                       lock_ts,
+                                       None,
+                                       ctx.get_isolation_level());
+            let txn_status = try!(txn.check_txn_status(primary_key, current_ts));
+
+            let pr = ProcessResult::TxnStatus { txn_status: txn_status };
+            (pr, txn.modifies())
+        }
         Command::Rollback { ref ctx, ref keys, start_ts, .. } => {
             let mut txn = MvccTxn::new(snapshot,
                                        &mut statistics,
@@ -542,73 +947,142 @@ fn process_write_impl(cid: u64,
             let pr = ProcessResult::Res;
             (pr, txn.modifies())
         }
-        Command::ResolveLock { ref ctx, start_ts, commit_ts, ref mut scan_key, ref keys } => {
-            if let Some(cts) = commit_ts {
-                if cts <= start_ts {
+        Command::ResolveLock { ref ctx, ref txn_status, ref mut scan_key, ref key_locks } => {
+            for (&start_ts, &commit_ts) in txn_status.iter() {
+                if commit_ts > 0 && commit_ts <= start_ts {
                     return Err(Error::InvalidTxnTso {
                         start_ts: start_ts,
-                        commit_ts: cts,
+                        commit_ts: commit_ts,
                     });
                 }
             }
             let mut scan_key = scan_key.take();
-            let mut txn = MvccTxn::new(snapshot,
-                                       &mut statistics,
-                                       start_ts,
-                                       None,
-                                       ctx.get_isolation_level());
-            for k in keys {
-                match commit_ts {
-                    Some(ts) => try!(txn.commit(k, ts)),
-                    None => try!(txn.rollback(k)),
+            let mut modifies = vec![];
+            let mut write_size = 0;
+            for &(ref k, ref lock) in key_locks {
+                // The locks of different transactions are resolved by their own `MvccTxn`.
+                let mut txn = MvccTxn::new(snapshot,
+                                           &mut statistics,
+                                           lock.ts,
+                                           None,
+                                           ctx.get_isolation_level());
+                match txn_status[&lock.ts] {
+                    0 => try!(txn.rollback(k)),
+                    commit_ts => try!(txn.resolve_commit(k, commit_ts)),
                 }
-                if txn.write_size() >= MAX_TXN_WRITE_SIZE {
+                write_size += txn.write_size();
+                modifies.extend(txn.modifies());
+                if write_size >= MAX_TXN_WRITE_SIZE {
                     scan_key = Some(k.to_owned());
                     break;
                 }
             }
             if scan_key.is_none() {
-                (ProcessResult::Res, txn.modifies())
+                (ProcessResult::Res, modifies)
             } else {
                 let pr = ProcessResult::NextCommand {
                     cmd: Command::ResolveLock {
                         ctx: ctx.clone(),
-                        start_ts: start_ts,
-                        commit_ts: commit_ts,
+                        txn_status: txn_status.clone(),
                         scan_key: scan_key.take(),
-                        keys: vec![],
+                        key_locks: vec![],
                     },
                 };
-                (pr, txn.modifies())
+                (pr, modifies)
             }
         }
-        Command::Gc { ref ctx, safe_point, ref mut scan_key, ref keys } => {
-            let mut scan_key = scan_key.take();
+        Command::Import { ref ctx, ref mut mutations, commit_ts } => {
+            let modifies =
+                try!(gen_import_modifies(snapshot, &mut statistics, ctx, mutations, commit_ts));
+            (ProcessResult::Res, modifies)
+        }
+        Command::Gc { ref ctx, safe_point, ref mut keys } => {
             let mut txn = MvccTxn::new(snapshot,
                                        &mut statistics,
                                        0,
                                        Some(ScanMode::Forward),
                                        ctx.get_isolation_level());
-            for k in keys {
+            let mut rest = None;
+            for (i, k) in keys.iter().enumerate() {
+                let write_size = txn.write_size();
                 try!(txn.gc(k, safe_point));
+                if txn.write_size() == write_size {
+                    GC_KEYS_COUNTER_VEC.with_label_values(&["skipped"]).inc();
+                }
                 if txn.write_size() >= MAX_TXN_WRITE_SIZE {
-                    scan_key = Some(k.to_owned());
+                    // The key may still have versions left, so start over from it.
+                    rest = Some(i);
                     break;
                 }
             }
-            if scan_key.is_none() {
-                (ProcessResult::Res, txn.modifies())
+            let modifies = txn.modifies();
+            GC_WRITE_BATCH_SIZE_HISTOGRAM.observe(modifies.len() as f64);
+            match rest {
+                Some(i) => {
+                    let pr = ProcessResult::NextCommand {
+                        cmd: Command::Gc {
+                            ctx: ctx.clone(),
+                            safe_point: safe_point,
+                            keys: keys.split_off(i),
+                        },
+                    };
+                    (pr, modifies)
+                }
+                None => (ProcessResult::Res, modifies),
+            }
+        }
+        Command::RawDeleteRange { ref ctx, ref start_key, ref end_key, ref keys } => {
+            let cf = ttl::raw_cf(enable_ttl);
+            let modifies = keys.iter().map(|k| Modify::Delete(cf, k.clone())).collect();
+            if keys.len() < RAW_DELETE_RANGE_BATCH_SIZE {
+                (ProcessResult::Res, modifies)
             } else {
+                // There may be more keys after this batch, scans them again.
                 let pr = ProcessResult::NextCommand {
-                    cmd: Command::Gc {
+                    cmd: Command::RawDeleteRange {
                         ctx: ctx.clone(),
-                        safe_point: safe_point,
-                        scan_key: scan_key.take(),
+                        start_key: start_key.clone(),
+                        end_key: end_key.clone(),
                         keys: vec![],
                     },
                 };
-                (pr, txn.modifies())
+                (pr, modifies)
+            }
+        }
+        Command::RawWrite { ref mut pairs, .. } => {
+            let cf = ttl::raw_cf(enable_ttl);
+            let modifies = pairs.drain(..)
+                .map(|(k, v)| match v {
+                    Some(v) => Modify::Put(cf, k, v),
+                    None => Modify::Delete(cf, k),
+                })
+                .collect();
+            (ProcessResult::Res, modifies)
+        }
+        Command::RawCompareAndSwap { ref key, ref previous_value, ref mut value, ttl, .. } => {
+            let cf = ttl::raw_cf(enable_ttl);
+            let current = match try!(snapshot.get_cf(cf, key)) {
+                Some(v) => try!(decode_raw_value(v, enable_ttl)),
+                None => None,
+            };
+            let succeed = current == *previous_value;
+            let mut modifies = vec![];
+            if succeed {
+                match value.take() {
+                    Some(mut v) => {
+                        if enable_ttl {
+                            ttl::append_expire_ts(&mut v, ttl::ttl_to_expire_ts(ttl));
+                        }
+                        modifies.push(Modify::Put(cf, key.clone(), v));
+                    }
+                    None => modifies.push(Modify::Delete(cf, key.clone())),
+                }
             }
+            let pr = ProcessResult::RawCompareAndSwap {
+                previous_value: current,
+                succeed: succeed,
+            };
+            (pr, modifies)
         }
         _ => panic!("unsupported write command"),
     };
@@ -623,6 +1097,167 @@ fn process_write_impl(cid: u64,
     Ok(())
 }
 
+/// Generates the modifies that ingest `mutations` as committed at `commit_ts`. There must be no
+/// lock in the range of the keys, since nothing would resolve a lock behind the imported data.
+fn gen_import_modifies(snapshot: &Snapshot,
+                       statistics: &mut Statistics,
+                       ctx: &Context,
+                       mutations: &mut Vec<Mutation>,
+                       commit_ts: u64)
+                       -> Result<Vec<Modify>> {
+    {
+        let keys: Vec<&Key> = mutations.iter().map(|m| m.key()).collect();
+        if keys.windows(2).any(|w| w[0].encoded() >= w[1].encoded()) {
+            return Err(box_err!("keys to import must be sorted and unique"));
+        }
+        let (start, end) = match key_range(&keys) {
+            Some(range) => range,
+            None => return Ok(vec![]),
+        };
+        let mut reader = MvccReader::new(snapshot,
+                                         statistics,
+                                         Some(ScanMode::Forward),
+                                         true,
+                                         None,
+                                         ctx.get_isolation_level());
+        let (locks, _) = try!(reader.scan_lock(Some(start.clone()), |_| true, Some(1)));
+        if let Some((key, lock)) = locks.into_iter().next() {
+            if key.encoded() <= end.encoded() {
+                return Err(Error::from(MvccError::KeyIsLocked {
+                    key: try!(key.raw()),
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                }));
+            }
+        }
+    }
+
+    let mut writes = Vec::with_capacity(mutations.len());
+    let mut values = vec![];
+    for m in mutations.drain(..) {
+        let (key, write) = match m {
+            Mutation::Put((key, value)) => {
+                if is_short_value(&value) {
+                    (key, Write::new(WriteType::Put, commit_ts, Some(value)))
+                } else {
+                    values.push((key.append_ts(commit_ts), value));
+                    (key, Write::new(WriteType::Put, commit_ts, None))
+                }
+            }
+            Mutation::Delete(key) => (key, Write::new(WriteType::Delete, commit_ts, None)),
+            m => return Err(box_err!("mutation {:?} can't be imported", m)),
+        };
+        writes.push((key.append_ts(commit_ts), write.to_bytes()));
+    }
+    // The values are ingested first, so a write record is never seen without its value.
+    let mut modifies = vec![];
+    if !values.is_empty() {
+        modifies.push(Modify::Ingest(CF_DEFAULT, values));
+    }
+    modifies.push(Modify::Ingest(CF_WRITE, writes));
+    Ok(modifies)
+}
+
+/// Returns the keys that a write command writes, or nothing for a read-only command.
+fn command_keys(cmd: &Command) -> Vec<&Key> {
+    match *cmd {
+        Command::Prewrite { ref mutations, .. } |
+        Command::Import { ref mutations, .. } => mutations.iter().map(|x| x.key()).collect(),
+        Command::AcquirePessimisticLock { ref keys, .. } |
+        Command::Commit { ref keys, .. } |
+        Command::Rollback { ref keys, .. } => keys.iter().collect(),
+        Command::ResolveLock { ref key_locks, .. } => key_locks.iter().map(|x| &x.0).collect(),
+        Command::Gc { ref keys, .. } |
+        Command::RawDeleteRange { ref keys, .. } => keys.iter().collect(),
+        Command::RawWrite { ref pairs, .. } => pairs.iter().map(|x| &x.0).collect(),
+        Command::Cleanup { ref key, .. } |
+        Command::RawCompareAndSwap { ref key, .. } => vec![key],
+        Command::TxnHeartBeat { ref primary_key, .. } |
+        Command::CheckTxnStatus { ref primary_key, .. } => vec![primary_key],
+        _ => vec![],
+    }
+}
+
+/// Returns the ts and the key of the first lock that blocks a prewrite or a pessimistic lock
+/// command.
+fn first_key_locked(cmd: &Command, pr: &ProcessResult) -> Option<(u64, Key)> {
+    match *cmd {
+        Command::Prewrite { .. } |
+        Command::AcquirePessimisticLock { .. } => {}
+        _ => return None,
+    }
+    let results = match *pr {
+        ProcessResult::MultiRes { ref results } => results,
+        _ => return None,
+    };
+    results.iter()
+        .filter_map(|res| match *res {
+            Err(StorageError::Txn(Error::Mvcc(MvccError::KeyIsLocked { ref key, ts, .. }))) => {
+                Some((ts, Key::from_raw(key)))
+            }
+            _ => None,
+        })
+        .next()
+}
+
+/// Returns the ts and the keys of the locks released by a write command.
+fn released_locks(cmd: &Command) -> Vec<(u64, Vec<Key>)> {
+    match *cmd {
+        Command::Commit { ref keys, lock_ts, .. } => vec![(lock_ts, keys.clone())],
+        Command::Rollback { ref keys, start_ts, .. } => vec![(start_ts, keys.clone())],
+        Command::ResolveLock { ref key_locks, .. } => {
+            let mut locks: HashMap<u64, Vec<Key>> = HashMap::default();
+            for &(ref key, ref lock) in key_locks {
+                locks.entry(lock.ts).or_insert_with(Vec::new).push(key.clone());
+            }
+            locks.into_iter().collect()
+        }
+        Command::Cleanup { ref key, start_ts, .. } => vec![(start_ts, vec![key.clone()])],
+        Command::CheckTxnStatus { ref primary_key, lock_ts, .. } => {
+            vec![(lock_ts, vec![primary_key.clone()])]
+        }
+        _ => vec![],
+    }
+}
+
+/// Returns the ts a read command reads at.
+fn read_ts(cmd: &Command) -> Option<u64> {
+    match *cmd {
+        // Reading at the max ts only gets the latest committed values, it doesn't prevent
+        // anything from being committed.
+        Command::Get { start_ts, .. } |
+        Command::BatchGet { start_ts, .. } |
+        Command::Scan { start_ts, .. } if start_ts != u64::MAX => Some(start_ts),
+        _ => None,
+    }
+}
+
+/// Returns the start ts and the commit ts of a one-phase commit prewrite.
+fn one_pc_ts(cmd: &Command) -> Option<(u64, u64)> {
+    match *cmd {
+        Command::Prewrite { start_ts, ref options, .. } if options.one_pc_commit_ts != 0 => {
+            Some((start_ts, options.one_pc_commit_ts))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the smallest and the largest one of `keys`.
+fn key_range<'a>(keys: &[&'a Key]) -> Option<(&'a Key, &'a Key)> {
+    let start = keys.iter().min_by(|a, b| a.encoded().cmp(b.encoded()));
+    let end = keys.iter().max_by(|a, b| a.encoded().cmp(b.encoded()));
+    match (start, end) {
+        (Some(start), Some(end)) => Some((start, end)),
+        _ => None,
+    }
+}
+
+/// Returns true if the inclusive ranges `a` and `b` overlap.
+fn ranges_overlap(a: (&Key, &Key), b: (&Key, &Key)) -> bool {
+    a.0.encoded() <= b.1.encoded() && b.0.encoded() <= a.1.encoded()
+}
+
 impl Scheduler {
     /// Generates the next command ID.
     fn gen_id(&mut self) -> u64 {
@@ -634,9 +1269,6 @@ impl Scheduler {
         if ctx.lock.is_write_lock() {
             self.running_write_count += 1;
         }
-        if ctx.tag == CMD_TAG_GC {
-            self.has_gc_command = true;
-        }
         let cid = ctx.cid;
         if self.cmd_ctxs.insert(cid, ctx).is_some() {
             panic!("command cid={} shouldn't exist", cid);
@@ -650,9 +1282,7 @@ impl Scheduler {
         if ctx.lock.is_write_lock() {
             self.running_write_count -= 1;
         }
-        if ctx.tag == CMD_TAG_GC {
-            self.has_gc_command = false;
-        }
+        self.importing_ranges.remove(&cid);
         SCHED_CONTEX_GAUGE.set(self.cmd_ctxs.len() as f64);
         ctx
     }
@@ -683,11 +1313,12 @@ impl Scheduler {
         }
         let ch = self.schedch.clone();
         let readcmd = cmd.readonly();
+        let enable_ttl = self.enable_ttl;
         let worker_pool = self.fetch_worker_pool(cmd.priority());
         if readcmd {
-            worker_pool.execute(move || process_read(cid, cmd, ch, snapshot));
+            worker_pool.execute(move || process_read(cid, cmd, ch, snapshot, enable_ttl));
         } else {
-            worker_pool.execute(move || process_write(cid, cmd, ch, snapshot));
+            worker_pool.execute(move || process_write(cid, cmd, ch, snapshot, enable_ttl));
         }
     }
 
@@ -727,30 +1358,120 @@ impl Scheduler {
         let cid = self.gen_id();
         debug!("received new command, cid={}, cmd={}", cid, cmd);
         let lock = gen_command_lock(&self.latches, &cmd);
+        let is_import = match cmd {
+            Command::Import { .. } => true,
+            _ => false,
+        };
+        // The read ts must be recorded before the snapshot is taken, so that a 1pc transaction
+        // whose commit is checked after this never commits below it.
+        if let Some(ts) = read_ts(&cmd) {
+            self.max_read_ts.update(cmd.get_context().get_region_id(), ts);
+        }
         let ctx = RunningCtx::new(cid, cmd, lock, callback);
+        if is_import {
+            if let Some(ref range) = ctx.write_range {
+                self.importing_ranges.insert(cid, range.clone());
+            }
+        }
         self.insert_ctx(ctx);
         self.lock_and_get_snapshot(cid);
     }
 
-    fn too_busy(&self) -> bool {
-        self.running_write_count >= self.sched_too_busy_threshold
+    /// Aggregates the flow read from a region, and reports the flow of all the regions to the
+    /// engine every `READ_FLOW_REPORT_INTERVAL_MS`.
+    fn add_read_flow(&mut self, region_id: u64, read_flow: FlowStatistics) {
+        if read_flow.read_keys > 0 {
+            self.read_flow.entry(region_id).or_insert_with(FlowStatistics::default).add(&read_flow);
+        }
+        self.report_read_flow();
+    }
+
+    /// Reports the flow read from all the regions if `READ_FLOW_REPORT_INTERVAL_MS` has passed
+    /// since the last report. It's also called when no message arrives in the interval, so the
+    /// flow is reported even if no more reads come.
+    fn report_read_flow(&mut self) {
+        let report_interval = Duration::from_millis(READ_FLOW_REPORT_INTERVAL_MS);
+        if self.read_flow_report_time.elapsed() < report_interval {
+            return;
+        }
+        self.read_flow_report_time = Instant::now();
+        if !self.read_flow.is_empty() {
+            let read_flow = mem::replace(&mut self.read_flow, HashMap::default());
+            self.engine.report_read_flow(read_flow);
+        }
+    }
+
+    fn refresh_stall_signals(&mut self) {
+        let refresh_interval = Duration::from_millis(STALL_SIGNALS_REFRESH_INTERVAL_MS);
+        if self.stall_signals_refresh_time.elapsed() < refresh_interval {
+            return;
+        }
+        self.stall_signals_refresh_time = Instant::now();
+        if let Some(signals) = self.engine.stall_signals() {
+            if signals != self.stall_signals {
+                debug!("engine write stall signals changed to {:?}", signals);
+            }
+            self.stall_signals = signals;
+        }
+    }
+
+    /// Checks whether the engine is close to stalling writes. Low priority writes are rejected
+    /// once any signal reaches its soft limit, others once any signal reaches its hard limit.
+    fn engine_too_busy(&mut self, pri: CommandPri) -> bool {
+        self.refresh_stall_signals();
+        if self.stall_signals.exceeds(&self.stall_hard_limits) {
+            SCHED_WRITE_STALL_COUNTER_VEC.with_label_values(&["hard"]).inc();
+            return true;
+        }
+        if pri == CommandPri::Low && self.stall_signals.exceeds(&self.stall_soft_limits) {
+            SCHED_WRITE_STALL_COUNTER_VEC.with_label_values(&["soft"]).inc();
+            return true;
+        }
+        false
+    }
+
+    fn too_busy(&mut self, pri: CommandPri) -> bool {
+        self.running_write_count >= self.sched_too_busy_threshold || self.engine_too_busy(pri)
+    }
+
+    /// Imports bypass the transaction protocol, so an import never runs together with other
+    /// writes in its range. Returns true if `cmd` has to wait for the running commands.
+    fn conflicts_with_import(&self, cmd: &Command) -> bool {
+        let is_import = match *cmd {
+            Command::Import { .. } => true,
+            _ => false,
+        };
+        if !is_import && self.importing_ranges.is_empty() {
+            return false;
+        }
+        let keys = command_keys(cmd);
+        let range = match key_range(&keys) {
+            Some(range) => range,
+            None => return false,
+        };
+        if is_import {
+            self.cmd_ctxs
+                .values()
+                .filter_map(|ctx| ctx.write_range.as_ref())
+                .any(|r| ranges_overlap((&r.0, &r.1), range))
+        } else {
+            self.importing_ranges.values().any(|r| ranges_overlap((&r.0, &r.1), range))
+        }
     }
 
     fn on_receive_new_cmd(&mut self, cmd: Command, callback: StorageCb) {
         // write flow control
-        if cmd.need_flow_control() && self.too_busy() {
+        if cmd.need_flow_control() && self.too_busy(cmd.priority()) {
             SCHED_TOO_BUSY_COUNTER_VEC.with_label_values(&[cmd.tag()]).inc();
             execute_callback(callback,
                              ProcessResult::Failed { err: StorageError::SchedTooBusy });
             return;
         }
-        // Allow 1 GC command at the same time.
-        if cmd.tag() == CMD_TAG_GC && self.has_gc_command {
-            SCHED_TOO_BUSY_COUNTER_VEC.with_label_values(&[cmd.tag()]).inc();
+        if self.conflicts_with_import(&cmd) {
+            SCHED_TOO_BUSY_COUNTER_VEC.with_label_values(&["import_conflict"]).inc();
             execute_callback(callback,
                              ProcessResult::Failed { err: StorageError::SchedTooBusy });
             return;
-
         }
         self.schedule_command(cmd, callback);
     }
@@ -785,7 +1506,14 @@ impl Scheduler {
             }
         };
 
-        if let Err(e) = self.engine.async_snapshot(self.extract_context(cid), cb) {
+        // Only the reads at a snapshot ts can be served by followers with a safe ts.
+        let read_ts = match *self.cmd_ctxs[&cid].cmd.as_ref().unwrap() {
+            Command::Get { start_ts, .. } |
+            Command::BatchGet { start_ts, .. } |
+            Command::Scan { start_ts, .. } => start_ts,
+            _ => 0,
+        };
+        if let Err(e) = self.engine.async_snapshot_at(self.extract_context(cid), read_ts, cb) {
             SCHED_STAGE_COUNTER_VEC.with_label_values(&[self.get_ctx_tag(cid), "async_snap_err"])
                 .inc();
             self.finish_with_err(cid, Error::from(e));
@@ -818,10 +1546,11 @@ impl Scheduler {
     ///
     /// If a next command is present, continues to execute; otherwise, delivers the result to the
     /// callback.
-    fn on_read_finished(&mut self, cid: u64, pr: ProcessResult) {
+    fn on_read_finished(&mut self, cid: u64, pr: ProcessResult, read_flow: FlowStatistics) {
         debug!("read command(cid={}) finished", cid);
         let mut ctx = self.remove_ctx(cid);
         SCHED_STAGE_COUNTER_VEC.with_label_values(&[ctx.tag, "read_finish"]).inc();
+        self.add_read_flow(ctx.region_id, read_flow);
         let cb = ctx.callback.take().unwrap();
         if let ProcessResult::NextCommand { cmd } = pr {
             SCHED_STAGE_COUNTER_VEC.with_label_values(&[ctx.tag, "next_cmd"]).inc();
@@ -855,8 +1584,32 @@ impl Scheduler {
                                  to_be_write: Vec<Modify>) {
         SCHED_STAGE_COUNTER_VEC.with_label_values(&[self.get_ctx_tag(cid), "write"]).inc();
         if to_be_write.is_empty() {
+            if self.waiter_mgr.is_some() {
+                if let Some((lock_ts, lock_key)) = first_key_locked(&cmd, &pr) {
+                    return self.wait_for_lock(cid, cmd, pr, lock_ts, lock_key);
+                }
+            }
             return self.on_write_finished(cid, pr, Ok(()));
         }
+        if let Some((start_ts, commit_ts)) = one_pc_ts(&cmd) {
+            // Checked right before the write is proposed, the reads scheduled later take their
+            // snapshots after it.
+            let max_read_ts = self.max_read_ts.get(cmd.get_context().get_region_id());
+            if commit_ts <= max_read_ts {
+                SCHED_STAGE_COUNTER_VEC.with_label_values(&[self.get_ctx_tag(cid),
+                                                            "commit_ts_expired"])
+                    .inc();
+                return self.finish_with_err(cid,
+                                            Error::CommitTsExpired {
+                                                start_ts: start_ts,
+                                                commit_ts: commit_ts,
+                                                max_read_ts: max_read_ts,
+                                            });
+            }
+        }
+        if self.waiter_mgr.is_some() {
+            self.cmd_ctxs.get_mut(&cid).unwrap().released_locks = released_locks(&cmd);
+        }
         let engine_cb = make_engine_cb(cid, pr, self.schedch.clone());
         if let Err(e) = self.engine.async_write(cmd.get_context(), to_be_write, engine_cb) {
             SCHED_STAGE_COUNTER_VEC.with_label_values(&[self.get_ctx_tag(cid), "async_write_err"])
@@ -871,8 +1624,14 @@ impl Scheduler {
         debug!("write finished for command, cid={}", cid);
         let mut ctx = self.remove_ctx(cid);
         let cb = ctx.callback.take().unwrap();
+        let released_locks = mem::replace(&mut ctx.released_locks, vec![]);
         let pr = match result {
-            Ok(()) => pr,
+            Ok(()) => {
+                for (lock_ts, keys) in released_locks {
+                    self.wake_up_waiters(lock_ts, keys);
+                }
+                pr
+            }
             Err(e) => ProcessResult::Failed { err: ::storage::Error::from(e) },
         };
         if let ProcessResult::NextCommand { cmd } = pr {
@@ -885,6 +1644,41 @@ impl Scheduler {
         self.release_lock(&ctx.lock, cid);
     }
 
+    /// Hands a command blocked by the lock of `lock_ts` on `lock_key` over to the waiter
+    /// manager, and releases its latches so that the lock can be resolved.
+    fn wait_for_lock(&mut self,
+                     cid: u64,
+                     cmd: Command,
+                     pr: ProcessResult,
+                     lock_ts: u64,
+                     lock_key: Key) {
+        let mut ctx = self.remove_ctx(cid);
+        SCHED_STAGE_COUNTER_VEC.with_label_values(&[ctx.tag, "wait_for_lock"]).inc();
+        let task = WaiterTask::WaitFor {
+            cmd: cmd,
+            cb: ctx.callback.take().unwrap(),
+            pr: pr,
+            lock_ts: lock_ts,
+            lock_key: lock_key,
+        };
+        if let Err(Stopped(task)) = self.waiter_mgr.as_ref().unwrap().schedule(task) {
+            if let WaiterTask::WaitFor { cb, pr, .. } = task {
+                execute_callback(cb, pr);
+            }
+        }
+        self.release_lock(&ctx.lock, cid);
+    }
+
+    fn wake_up_waiters(&self, lock_ts: u64, keys: Vec<Key>) {
+        let task = WaiterTask::WakeUp {
+            lock_ts: lock_ts,
+            keys: keys,
+        };
+        if let Err(Stopped(task)) = self.waiter_mgr.as_ref().unwrap().schedule(task) {
+            warn!("failed to wake up waiters: {}", task);
+        }
+    }
+
     /// Releases all the latches held by a command.
     fn release_lock(&mut self, lock: &Lock, cid: u64) {
         let wakeup_list = self.latches.release(lock, cid);
@@ -901,16 +1695,36 @@ impl Scheduler {
         }
     }
 
+    /// Reports which commands own and wait for the latches in use.
+    fn on_dump_latches(&self, cb: LatchesCallback) {
+        let mut infos = self.latches.dump();
+        for info in &mut infos {
+            for w in Some(&mut info.owner).into_iter().chain(info.waiters.iter_mut()) {
+                w.tag = self.cmd_ctxs.get(&w.cid).map_or("unknown", |ctx| ctx.tag);
+            }
+        }
+        cb(infos);
+    }
+
     pub fn run(&mut self, receiver: Receiver<Msg>) -> Result<()> {
+        let report_interval = Duration::from_millis(READ_FLOW_REPORT_INTERVAL_MS);
         loop {
-            let msg = box_try!(receiver.recv());
+            let msg = match receiver.recv_timeout(report_interval) {
+                Err(RecvTimeoutError::Timeout) => {
+                    self.report_read_flow();
+                    continue;
+                }
+                res => box_try!(res),
+            };
             match msg {
                 Msg::Quit => return Ok(()),
                 Msg::RawCmd { cmd, cb } => self.on_receive_new_cmd(cmd, cb),
                 Msg::SnapshotFinished { cid, cb_ctx, snapshot } => {
                     self.on_snapshot_finished(cid, cb_ctx, snapshot)
                 }
-                Msg::ReadFinished { cid, pr } => self.on_read_finished(cid, pr),
+                Msg::ReadFinished { cid, pr, read_flow } => {
+                    self.on_read_finished(cid, pr, read_flow)
+                }
                 Msg::WritePrepareFinished { cid, cmd, pr, to_be_write } => {
                     self.on_write_prepare_finished(cid, cmd, pr, to_be_write)
                 }
@@ -918,6 +1732,7 @@ impl Scheduler {
                 Msg::WriteFinished { cid, pr, result, .. } => {
                     self.on_write_finished(cid, pr, result)
                 }
+                Msg::DumpLatches { cb } => self.on_dump_latches(cb),
             }
         }
     }
@@ -928,17 +1743,14 @@ impl Scheduler {
 /// Basically, read-only commands require no latches, write commands require latches hashed
 /// by the referenced keys.
 pub fn gen_command_lock(latches: &Latches, cmd: &Command) -> Lock {
-    match *cmd {
-        Command::Prewrite { ref mutations, .. } => {
-            let keys: Vec<&Key> = mutations.iter().map(|x| x.key()).collect();
-            latches.gen_lock(&keys)
-        }
-        Command::Commit { ref keys, .. } |
-        Command::Rollback { ref keys, .. } |
-        Command::ResolveLock { ref keys, .. } => latches.gen_lock(keys),
-        Command::Cleanup { ref key, .. } => latches.gen_lock(&[key]),
-        _ => Lock::new(vec![]),
-    }
+    let keys = command_keys(cmd);
+    let mut lock = if keys.is_empty() {
+        Lock::new(vec![])
+    } else {
+        latches.gen_lock(&keys)
+    };
+    lock.priority = cmd.priority();
+    lock
 }
 
 #[cfg(test)]
@@ -946,10 +1758,13 @@ mod tests {
     use super::*;
     use kvproto::kvrpcpb::Context;
     use storage::txn::latch::*;
+    use storage::mvcc::{Lock as MvccLock, LockType};
     use storage::{Command, make_key, Options, Mutation};
 
     #[test]
     fn test_command_latches() {
+        let mut txn_status = HashMap::default();
+        txn_status.insert(10, 20);
         let readonly_cmds = vec![Command::Get {
                                      ctx: Context::new(),
                                      key: make_key(b"k"),
@@ -963,26 +1778,28 @@ mod tests {
                                  Command::Scan {
                                      ctx: Context::new(),
                                      start_key: make_key(b"k"),
+                                     end_key: None,
                                      limit: 100,
                                      start_ts: 25,
                                      options: Options::default(),
+                                     reverse: false,
                                  },
                                  Command::ScanLock {
                                      ctx: Context::new(),
                                      max_ts: 5,
+                                     start_key: None,
+                                     end_key: None,
+                                     limit: 0,
                                  },
                                  Command::ResolveLock {
                                      ctx: Context::new(),
-                                     start_ts: 10,
-                                     commit_ts: Some(20),
+                                     txn_status: txn_status.clone(),
                                      scan_key: None,
-                                     keys: vec![],
+                                     key_locks: vec![],
                                  },
-                                 Command::Gc {
+                                 Command::Pause {
                                      ctx: Context::new(),
-                                     safe_point: 5,
-                                     scan_key: None,
-                                     keys: vec![make_key(b"k")],
+                                     duration: 5,
                                  }];
         let write_cmds = vec![Command::Prewrite {
                                   ctx: Context::new(),
@@ -991,6 +1808,13 @@ mod tests {
                                   start_ts: 10,
                                   options: Options::default(),
                               },
+                              Command::AcquirePessimisticLock {
+                                  ctx: Context::new(),
+                                  keys: vec![make_key(b"k")],
+                                  primary: b"k".to_vec(),
+                                  start_ts: 10,
+                                  options: Options::default(),
+                              },
                               Command::Commit {
                                   ctx: Context::new(),
                                   keys: vec![make_key(b"k")],
@@ -1007,12 +1831,39 @@ mod tests {
                                   keys: vec![make_key(b"k")],
                                   start_ts: 10,
                               },
-                              Command::ResolveLock {
+                              Command::TxnHeartBeat {
                                   ctx: Context::new(),
+                                  primary_key: make_key(b"k"),
                                   start_ts: 10,
-                                  commit_ts: Some(20),
+                                  advise_ttl: 100,
+                              },
+                              Command::CheckTxnStatus {
+                                  ctx: Context::new(),
+                                  primary_key: make_key(b"k"),
+                                  lock_ts: 10,
+                                  current_ts: 20,
+                              },
+                              Command::ResolveLock {
+                                  ctx: Context::new(),
+                                  txn_status: txn_status,
                                   scan_key: None,
-                                  keys: vec![make_key(b"k")],
+                                  key_locks: vec![(make_key(b"k"),
+                                                   MvccLock::new(LockType::Put,
+                                                                 b"k".to_vec(),
+                                                                 10,
+                                                                 0,
+                                                                 None))],
+                              },
+                              Command::RawWrite {
+                                  ctx: Context::new(),
+                                  pairs: vec![(make_key(b"k"), Some(b"v".to_vec()))],
+                              },
+                              Command::RawCompareAndSwap {
+                                  ctx: Context::new(),
+                                  key: make_key(b"k"),
+                                  previous_value: None,
+                                  value: Some(b"v".to_vec()),
+                                  ttl: 0,
                               }];
 
         let mut latches = Latches::new(1024);
@@ -65,9 +65,12 @@ impl<'a> SnapshotStore<'a> {
 
     /// Create a scanner.
     /// when key_only is true, all the returned value will be empty.
+    /// `lower_bound` and `upper_bound` are encoded keys that limit the range to scan, the
+    /// former is inclusive and the latter is exclusive.
     pub fn scanner(&self,
                    mode: ScanMode,
                    key_only: bool,
+                   lower_bound: Option<Vec<u8>>,
                    upper_bound: Option<Vec<u8>>,
                    statistics: &'a mut Statistics)
                    -> Result<StoreScanner> {
@@ -78,6 +81,7 @@ impl<'a> SnapshotStore<'a> {
                                          upper_bound,
                                          self.isolation_level);
         reader.set_key_only(key_only);
+        reader.set_lower_bound(lower_bound);
         Ok(StoreScanner {
             reader: reader,
             start_ts: self.start_ts,
@@ -249,6 +253,31 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_snapshot_store_read_flow() {
+        let key_num = 10;
+        let store = TestStore::new(key_num);
+        let snapshot_store = store.store();
+        let mut statistics = Statistics::default();
+        let key = make_key(store.keys[0].as_bytes());
+        snapshot_store.get(&key, &mut statistics).unwrap();
+        assert_eq!(statistics.flow_stats.read_keys, 1);
+        let read_bytes = key.encoded().len() + store.keys[0].len();
+        assert_eq!(statistics.flow_stats.read_bytes, read_bytes);
+
+        let mut statistics = Statistics::default();
+        {
+            let mut scanner = snapshot_store.scanner(ScanMode::Forward,
+                                                     false,
+                                                     None,
+                                                     None,
+                                                     &mut statistics)
+                .unwrap();
+            assert_eq!(scanner.scan(key, 5).unwrap().len(), 5);
+        }
+        assert_eq!(statistics.flow_stats.read_keys, 5);
+    }
+
     #[test]
     fn test_snapshot_store_batch_get() {
         let key_num = 100;
@@ -273,8 +302,8 @@ mod test {
         let store = TestStore::new(key_num);
         let snapshot_store = store.store();
         let mut statistics = Statistics::default();
-        let mut scanner = snapshot_store.scanner(ScanMode::Forward, false, None, &mut statistics)
-            .unwrap();
+        let mut scanner =
+            snapshot_store.scanner(ScanMode::Forward, false, None, None, &mut statistics).unwrap();
 
         let key = format!("{}{}", KEY_PREFIX, START_ID);
         let start_key = make_key(key.as_bytes());
@@ -297,8 +326,8 @@ mod test {
         let store = TestStore::new(key_num);
         let snapshot_store = store.store();
         let mut statistics = Statistics::default();
-        let mut scanner = snapshot_store.scanner(ScanMode::Backward, false, None, &mut statistics)
-            .unwrap();
+        let mut scanner =
+            snapshot_store.scanner(ScanMode::Backward, false, None, None, &mut statistics).unwrap();
 
         let half = (key_num / 2) as usize;
         let key = format!("{}{}", KEY_PREFIX, START_ID + (half as u64) - 1);
@@ -318,13 +347,55 @@ mod test {
     }
 
     #[test]
-    fn test_snapshot_store_seek() {
+    fn test_snapshot_store_bounded_scan() {
         let key_num = 100;
         let store = TestStore::new(key_num);
         let snapshot_store = store.store();
+        let lower_key = make_key(format!("{}{}", KEY_PREFIX, START_ID + 10).as_bytes());
+        let upper_key = make_key(format!("{}{}", KEY_PREFIX, START_ID + 20).as_bytes());
+        let expect: Vec<Option<KvPair>> = store.keys[10..20]
+            .iter()
+            .map(|k| Some((k.clone().into_bytes(), k.clone().into_bytes())))
+            .collect();
+
         let mut statistics = Statistics::default();
-        let mut scanner = snapshot_store.scanner(ScanMode::Forward, false, None, &mut statistics)
+        let mut scanner = snapshot_store.scanner(ScanMode::Forward,
+                     false,
+                     None,
+                     Some(upper_key.encoded().to_owned()),
+                     &mut statistics)
             .unwrap();
+        let result: Vec<Option<KvPair>> = scanner.scan(lower_key.clone(), key_num as usize)
+            .unwrap()
+            .into_iter()
+            .map(Result::ok)
+            .collect();
+        assert_eq!(result, expect);
+
+        let mut statistics = Statistics::default();
+        let mut scanner = snapshot_store.scanner(ScanMode::Backward,
+                     false,
+                     Some(lower_key.encoded().to_owned()),
+                     None,
+                     &mut statistics)
+            .unwrap();
+        let mut result: Vec<Option<KvPair>> = scanner.reverse_scan(upper_key, key_num as usize)
+            .unwrap()
+            .into_iter()
+            .map(Result::ok)
+            .collect();
+        result.reverse();
+        assert_eq!(result, expect);
+    }
+
+    #[test]
+    fn test_snapshot_store_seek() {
+        let key_num = 100;
+        let store = TestStore::new(key_num);
+        let snapshot_store = store.store();
+        let mut statistics = Statistics::default();
+        let mut scanner =
+            snapshot_store.scanner(ScanMode::Forward, false, None, None, &mut statistics).unwrap();
 
         let key = format!("{}{}aaa", KEY_PREFIX, START_ID);
         let start_key = make_key(key.as_bytes());
@@ -341,8 +412,8 @@ mod test {
         let store = TestStore::new(key_num);
         let snapshot_store = store.store();
         let mut statistics = Statistics::default();
-        let mut scanner = snapshot_store.scanner(ScanMode::Backward, false, None, &mut statistics)
-            .unwrap();
+        let mut scanner =
+            snapshot_store.scanner(ScanMode::Backward, false, None, None, &mut statistics).unwrap();
 
         let key = format!("{}{}aaa", KEY_PREFIX, START_ID);
         let start_key = make_key(key.as_bytes());
@@ -0,0 +1,216 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Waiter manager which parks the write commands blocked by locks.
+//!
+//! When a prewrite or a pessimistic lock command meets a lock of another transaction, the
+//! scheduler hands it over to the waiter manager instead of returning `KeyIsLocked` at once.
+//! The command is scheduled again when the lock is released by commit, rollback or resolve
+//! lock, and it gets the original `KeyIsLocked` result if the lock is still there after the
+//! wait timeout. A command whose wait would close a cycle in the wait-for graph is aborted
+//! with a `Deadlock` error.
+
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+use std::time::Duration;
+
+use futures::Future;
+use tokio_core::reactor::Handle;
+use tokio_timer::Timer;
+
+use storage::{Command, Key, StorageCb, Error as StorageError};
+use util::collections::HashMap;
+use util::transport::SyncSendCh;
+use util::worker::{FutureRunnable, FutureScheduler};
+use super::Error;
+use super::deadlock::DetectTable;
+use super::scheduler::{execute_callback, Msg, ProcessResult};
+use super::super::metrics::*;
+
+pub enum Task {
+    /// `cmd` waits for the lock of `lock_ts` on `lock_key`, `pr` is its result without waiting.
+    WaitFor {
+        cmd: Command,
+        cb: StorageCb,
+        pr: ProcessResult,
+        lock_ts: u64,
+        lock_key: Key,
+    },
+    /// The locks of `lock_ts` on `keys` are released.
+    WakeUp { lock_ts: u64, keys: Vec<Key> },
+    Timeout { lock_ts: u64, id: u64 },
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Task::WaitFor { ref cmd, lock_ts, ref lock_key, .. } => {
+                write!(f, "{} waits for lock {} at {}", cmd, lock_key, lock_ts)
+            }
+            Task::WakeUp { lock_ts, ref keys } => {
+                write!(f, "wake up waiters of {} keys at {}", keys.len(), lock_ts)
+            }
+            Task::Timeout { lock_ts, id } => write!(f, "waiter {} for {} timeout", id, lock_ts),
+        }
+    }
+}
+
+struct Waiter {
+    id: u64,
+    start_ts: u64,
+    lock_key: Key,
+    cmd: Command,
+    cb: StorageCb,
+    pr: ProcessResult,
+}
+
+pub struct WaiterManager {
+    sched_ch: SyncSendCh<Msg>,
+    scheduler: FutureScheduler<Task>,
+    timer: Timer,
+    wait_timeout: Duration,
+    // lock ts -> waiters
+    waiters: HashMap<u64, Vec<Waiter>>,
+    detect_table: DetectTable,
+    id_alloc: u64,
+}
+
+impl WaiterManager {
+    pub fn new(sched_ch: SyncSendCh<Msg>,
+               scheduler: FutureScheduler<Task>,
+               wait_timeout: Duration)
+               -> WaiterManager {
+        WaiterManager {
+            sched_ch: sched_ch,
+            scheduler: scheduler,
+            timer: Timer::default(),
+            wait_timeout: wait_timeout,
+            waiters: HashMap::default(),
+            detect_table: DetectTable::default(),
+            id_alloc: 0,
+        }
+    }
+
+    fn handle_wait_for(&mut self,
+                       handle: &Handle,
+                       cmd: Command,
+                       cb: StorageCb,
+                       pr: ProcessResult,
+                       lock_ts: u64,
+                       lock_key: Key) {
+        let start_ts = cmd.ts();
+        if self.detect_table.detect(start_ts, lock_ts) {
+            LOCK_WAIT_COUNTER_VEC.with_label_values(&["deadlock"]).inc();
+            info!("transaction {} is aborted for deadlock on lock {} at {}",
+                  start_ts,
+                  lock_key,
+                  lock_ts);
+            let err = Error::Deadlock {
+                start_ts: start_ts,
+                lock_ts: lock_ts,
+                lock_key: lock_key.raw().unwrap_or_else(|_| lock_key.encoded().to_owned()),
+            };
+            execute_callback(cb, ProcessResult::Failed { err: StorageError::from(err) });
+            return;
+        }
+        LOCK_WAIT_COUNTER_VEC.with_label_values(&["wait"]).inc();
+
+        self.id_alloc += 1;
+        let id = self.id_alloc;
+        self.waiters.entry(lock_ts).or_insert_with(Vec::new).push(Waiter {
+            id: id,
+            start_ts: start_ts,
+            lock_key: lock_key,
+            cmd: cmd,
+            cb: cb,
+            pr: pr,
+        });
+
+        let scheduler = self.scheduler.clone();
+        let f = self.timer.sleep(self.wait_timeout).then(move |_| {
+            if let Err(e) = scheduler.schedule(Task::Timeout {
+                lock_ts: lock_ts,
+                id: id,
+            }) {
+                error!("failed to schedule waiter timeout: {}", e);
+            }
+            Ok(())
+        });
+        handle.spawn(f);
+    }
+
+    fn handle_wake_up(&mut self, lock_ts: u64, keys: Vec<Key>) {
+        let waiters = match self.waiters.remove(&lock_ts) {
+            Some(waiters) => waiters,
+            None => return,
+        };
+        let (woken, waiting): (Vec<_>, Vec<_>) =
+            waiters.into_iter().partition(|w| keys.contains(&w.lock_key));
+        if !waiting.is_empty() {
+            self.waiters.insert(lock_ts, waiting);
+        }
+        for w in woken {
+            LOCK_WAIT_COUNTER_VEC.with_label_values(&["wake_up"]).inc();
+            self.detect_table.clean_up_wait_for(w.start_ts, lock_ts);
+            // Executes the command again, it may succeed or wait for another lock.
+            if let Err(e) = self.sched_ch.send(Msg::RawCmd {
+                cmd: w.cmd,
+                cb: w.cb,
+            }) {
+                error!("failed to reschedule command waiting for {}: {:?}", lock_ts, e);
+            }
+        }
+    }
+
+    fn handle_timeout(&mut self, lock_ts: u64, id: u64) {
+        let waiter = {
+            let waiters = match self.waiters.get_mut(&lock_ts) {
+                Some(waiters) => waiters,
+                // The waiter has been woken up.
+                None => return,
+            };
+            match waiters.iter().position(|w| w.id == id) {
+                Some(pos) => waiters.swap_remove(pos),
+                None => return,
+            }
+        };
+        if self.waiters[&lock_ts].is_empty() {
+            self.waiters.remove(&lock_ts);
+        }
+        LOCK_WAIT_COUNTER_VEC.with_label_values(&["timeout"]).inc();
+        self.detect_table.clean_up_wait_for(waiter.start_ts, lock_ts);
+        execute_callback(waiter.cb, waiter.pr);
+    }
+}
+
+impl FutureRunnable<Task> for WaiterManager {
+    fn run(&mut self, task: Task, handle: &Handle) {
+        match task {
+            Task::WaitFor { cmd, cb, pr, lock_ts, lock_key } => {
+                self.handle_wait_for(handle, cmd, cb, pr, lock_ts, lock_key)
+            }
+            Task::WakeUp { lock_ts, keys } => self.handle_wake_up(lock_ts, keys),
+            Task::Timeout { lock_ts, id } => self.handle_timeout(lock_ts, id),
+        }
+    }
+
+    fn shutdown(&mut self) {
+        // Returns the original results to the waiting commands.
+        let waiters = mem::replace(&mut self.waiters, HashMap::default());
+        for (_, waiters) in waiters {
+            for w in waiters {
+                execute_callback(w.cb, w.pr);
+            }
+        }
+    }
+}
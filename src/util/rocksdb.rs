@@ -14,8 +14,9 @@
 use std::fs;
 use std::path::Path;
 
-use storage::CF_DEFAULT;
-use rocksdb::{DB, Options, SliceTransform};
+use storage::{CF_DEFAULT, CF_RAW_TTL};
+use storage::ttl;
+use rocksdb::{DB, Options, SliceTransform, CompactionFilter};
 
 pub use rocksdb::CFHandle;
 
@@ -64,7 +65,11 @@ pub fn new_engine(path: &str, cfs: &[&str]) -> Result<DB, String> {
     db_opts.enable_statistics();
     let mut cfs_opts = Vec::with_capacity(cfs.len());
     for cf in cfs {
-        cfs_opts.push(CFOptions::new(*cf, Options::new()));
+        let mut opts = Options::new();
+        if *cf == CF_RAW_TTL {
+            try!(set_ttl_compaction_filter(&mut opts));
+        }
+        cfs_opts.push(CFOptions::new(*cf, opts));
     }
     new_engine_opt(path, db_opts, cfs_opts)
 }
@@ -226,12 +231,32 @@ impl SliceTransform for NoopSliceTransform {
     }
 }
 
+/// Drops expired raw values during compaction. It must only be installed on CF_RAW_TTL, where
+/// every value carries an expiry time, see `storage::ttl`.
+pub struct TTLCompactionFilter;
+
+impl CompactionFilter for TTLCompactionFilter {
+    fn filter(&mut self, _: usize, _: &[u8], value: &[u8]) -> bool {
+        match ttl::split_expire_ts(value) {
+            Ok((_, expire_ts)) => ttl::is_expired(expire_ts, ttl::current_ts()),
+            // Keep what we don't understand.
+            Err(_) => false,
+        }
+    }
+}
+
+pub fn set_ttl_compaction_filter(opts: &mut Options) -> Result<(), String> {
+    opts.set_compaction_filter("ttl_compaction_filter", false, box TTLCompactionFilter)
+}
+
 #[cfg(test)]
 mod tests {
-    use rocksdb::{DB, Options};
+    use kvproto::raft_serverpb::StoreIdent;
+    use rocksdb::{DB, Options, CompactOptions, Writable};
     use tempdir::TempDir;
-    use storage::CF_DEFAULT;
-    use super::{check_and_open, CFOptions};
+    use raftstore::store::{keys, Mutable, Peekable};
+    use storage::{ttl, make_key, ALL_CFS, CF_DEFAULT, CF_RAW_TTL};
+    use super::{check_and_open, get_cf_handle, new_engine, CFOptions};
 
     #[test]
     fn test_check_and_open() {
@@ -260,6 +285,45 @@ mod tests {
         column_families_must_eq(path_str, &[CF_DEFAULT]);
     }
 
+    #[test]
+    fn test_ttl_compaction_filter() {
+        let path = TempDir::new("_util_rocksdb_test_ttl_compaction_filter").expect("");
+        let db = new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap();
+
+        let cases = vec![(b"k1", 1, false), (b"k2", 0, true), (b"k3", u64::max_value(), true)];
+        let raw_handle = get_cf_handle(&db, CF_RAW_TTL).unwrap();
+        for &(k, expire_ts, _) in &cases {
+            let mut value = b"v".to_vec();
+            ttl::append_expire_ts(&mut value, expire_ts);
+            db.put_cf(raw_handle, &keys::data_key(k), &value).unwrap();
+        }
+        // Keys that are not written with ttl are kept.
+        db.put_cf(raw_handle, &keys::data_key(b"k4"), b"v").unwrap();
+
+        // Transactional values and local metadata in CF_DEFAULT are kept even if they look like
+        // expired raw values.
+        let mut txn_value = b"v".to_vec();
+        ttl::append_expire_ts(&mut txn_value, 1);
+        let txn_key = keys::data_key(make_key(b"k1").append_ts(10).encoded());
+        db.put(&txn_key, &txn_value).unwrap();
+        let mut ident = StoreIdent::new();
+        ident.set_cluster_id(1);
+        ident.set_store_id(1);
+        db.put_msg(keys::STORE_IDENT_KEY, &ident).unwrap();
+
+        for cf in ALL_CFS {
+            let handle = get_cf_handle(&db, cf).unwrap();
+            db.compact_range_cf_opt(handle, &CompactOptions::new(), None, None);
+        }
+        for &(k, _, exist) in &cases {
+            assert_eq!(db.get_cf(raw_handle, &keys::data_key(k)).unwrap().is_some(), exist);
+        }
+        assert!(db.get_cf(raw_handle, &keys::data_key(b"k4")).unwrap().is_some());
+        assert_eq!(&*db.get_value(&txn_key).unwrap().unwrap(), &txn_value[..]);
+        let ident2: StoreIdent = db.get_msg(keys::STORE_IDENT_KEY).unwrap().unwrap();
+        assert_eq!(ident2, ident);
+    }
+
     fn column_families_must_eq(path: &str, excepted: &[&str]) {
         let opts = Options::new();
         let cfs_list = DB::list_column_families(&opts, path).unwrap();
@@ -22,8 +22,9 @@ use std::thread::{self, JoinHandle, Builder};
 use std::io;
 use std::fmt::{self, Formatter, Display, Debug};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::mpsc::{self, Sender, Receiver, SendError};
+use std::sync::mpsc::{self, Sender, Receiver, SendError, RecvTimeoutError};
 use std::error::Error;
+use std::time::{Duration, Instant};
 
 use util::SlowTimer;
 use self::metrics::*;
@@ -62,6 +63,8 @@ pub trait BatchRunnable<T: Display> {
     ///
     /// Please note that ts will be clear after invoking this method.
     fn run_batch(&mut self, ts: &mut Vec<T>);
+    /// Called every tick interval if the worker is started with a tick.
+    fn on_tick(&mut self) {}
     fn shutdown(&mut self) {}
 }
 
@@ -144,15 +147,34 @@ pub struct Worker<T: Display> {
     handle: Option<JoinHandle<()>>,
 }
 
-fn poll<R, T>(mut runner: R, rx: Receiver<Option<T>>, counter: Arc<AtomicUsize>, batch_size: usize)
+fn poll<R, T>(mut runner: R,
+              rx: Receiver<Option<T>>,
+              counter: Arc<AtomicUsize>,
+              batch_size: usize,
+              tick: Option<Duration>)
     where R: BatchRunnable<T> + Send + 'static,
           T: Display + Send + 'static
 {
     let name = thread::current().name().unwrap().to_owned();
     let mut keep_going = true;
     let mut buffer = Vec::with_capacity(batch_size);
+    let mut last_tick = Instant::now();
     while keep_going {
-        let t = rx.recv();
+        if let Some(tick) = tick {
+            if last_tick.elapsed() >= tick {
+                runner.on_tick();
+                last_tick = Instant::now();
+            }
+        }
+        let t = match tick {
+            Some(tick) => {
+                match rx.recv_timeout(tick) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    t => t.map_err(|_| ()),
+                }
+            }
+            None => rx.recv().map_err(|_| ()),
+        };
         match t {
             Ok(Some(t)) => buffer.push(t),
             _ => break,
@@ -193,6 +215,18 @@ impl<T: Display + Send + 'static> Worker<T> {
 
     pub fn start_batch<R>(&mut self, runner: R, batch_size: usize) -> Result<(), io::Error>
         where R: BatchRunnable<T> + Send + 'static
+    {
+        self.start_batch_with_tick(runner, batch_size, None)
+    }
+
+    /// Start the worker, `BatchRunnable::on_tick` of the runner is called every `tick` if
+    /// it's specified.
+    pub fn start_batch_with_tick<R>(&mut self,
+                                    runner: R,
+                                    batch_size: usize,
+                                    tick: Option<Duration>)
+                                    -> Result<(), io::Error>
+        where R: BatchRunnable<T> + Send + 'static
     {
         let mut receiver = self.receiver.lock().unwrap();
         info!("starting working thread: {}", self.scheduler.name);
@@ -205,7 +239,7 @@ impl<T: Display + Send + 'static> Worker<T> {
         let counter = self.scheduler.counter.clone();
         let h = try!(Builder::new()
             .name(thd_name!(self.scheduler.name.as_ref()))
-            .spawn(move || poll(runner, rx, counter, batch_size)));
+            .spawn(move || poll(runner, rx, counter, batch_size, tick)));
         self.handle = Some(h);
         Ok(())
     }
@@ -278,6 +312,10 @@ mod test {
             self.ch.send(ms.to_vec()).unwrap();
         }
 
+        fn on_tick(&mut self) {
+            self.ch.send(vec![0]).unwrap();
+        }
+
         fn shutdown(&mut self) {
             self.ch.send(vec![]).unwrap();
         }
@@ -342,6 +380,24 @@ mod test {
         assert!(rx.recv().is_err());
     }
 
+    #[test]
+    fn test_batch_with_tick() {
+        let mut worker = Worker::new("test-worker-tick");
+        let (tx, rx) = mpsc::channel();
+        let tick = Duration::from_millis(50);
+        worker.start_batch_with_tick(BatchRunner { ch: tx }, 10, Some(tick)).unwrap();
+        // Ticks are called without any task.
+        assert_eq!(rx.recv_timeout(Duration::from_secs(3)).unwrap(), vec![0]);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(3)).unwrap(), vec![0]);
+        worker.stop().unwrap().join().unwrap();
+        loop {
+            // when runner is shutdown, it will send back an empty vector.
+            if rx.recv_timeout(Duration::from_secs(3)).unwrap().is_empty() {
+                break;
+            }
+        }
+    }
+
     #[test]
     fn test_autowired_batch() {
         let mut worker = Worker::new("test-worker-batch");
@@ -3,7 +3,7 @@ use tikv::coprocessor;
 use kvproto::kvrpcpb::Context;
 use tikv::util::codec::{table, Datum, datum};
 use tikv::util::codec::number::*;
-use tikv::storage::{Mutation, Key, ALL_CFS};
+use tikv::storage::{Mutation, Key, MaxReadTs, ALL_CFS};
 use tikv::storage::engine::{self, Engine, TEMP_DIR};
 use tikv::util::worker::Worker;
 use kvproto::coprocessor::{Request, KeyRange};
@@ -567,7 +567,12 @@ fn init_with_data(tbl: &ProductTable,
     store.commit();
 
     let mut end_point = Worker::new("test select worker");
-    let runner = EndPointHost::new(store.get_engine(), end_point.scheduler(), 8, 2, 2);
+    let runner = EndPointHost::new(store.get_engine(),
+                                   MaxReadTs::new(),
+                                   end_point.scheduler(),
+                                   8,
+                                   2,
+                                   2);
     end_point.start_batch(runner, 5).unwrap();
 
     (store, end_point)
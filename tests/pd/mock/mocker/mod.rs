@@ -98,5 +98,11 @@ pub trait PdMocker {
         None
     }
 
+    fn get_gc_safe_point(&self,
+                         _: &GetGCSafePointRequest)
+                         -> Option<Result<GetGCSafePointResponse>> {
+        None
+    }
+
     fn set_endpoints(&self, _: Vec<String>) {}
 }
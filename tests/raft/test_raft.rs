@@ -101,6 +101,18 @@ pub fn new_test_raft_with_prevote(id: u64,
     new_test_raft_with_config(&config, storage)
 }
 
+pub fn new_test_learner_raft(id: u64,
+                             peers: Vec<u64>,
+                             learners: Vec<u64>,
+                             election: usize,
+                             heartbeat: usize,
+                             storage: MemStorage)
+                             -> Interface {
+    let mut config = new_test_config(id, peers, election, heartbeat);
+    config.learners = learners;
+    new_test_raft_with_config(&config, storage)
+}
+
 pub fn new_test_raft_with_config(config: &Config, storage: MemStorage) -> Interface {
     Interface::new(Raft::new(config, storage))
 }
@@ -184,9 +196,14 @@ impl Interface {
     fn initial(&mut self, id: u64, ids: &[u64]) {
         if self.raft.is_some() {
             self.id = id;
+            let learners = self.learner_nodes();
             self.prs = RaftFlatMap::with_capacity(ids.len());
             for id in ids {
-                self.prs.insert(*id, Progress { ..Default::default() });
+                self.prs.insert(*id,
+                                Progress {
+                                    is_learner: learners.contains(id),
+                                    ..Default::default()
+                                });
             }
             let term = self.term;
             self.reset(term);
@@ -2133,6 +2150,41 @@ fn test_read_only_for_new_leader() {
     assert_eq!(rs.request_ctx, wctx.as_bytes().to_vec());
 }
 
+// `test_read_only_forwarded_by_follower` ensures that a follower tracks the read only
+// requests it forwards to the leader until the responses arrive.
+#[test]
+fn test_read_only_forwarded_by_follower() {
+    let a = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    let b = new_test_raft(2, vec![1, 2, 3], 10, 1, new_storage());
+    let c = new_test_raft(3, vec![1, 2, 3], 10, 1, new_storage());
+
+    let mut nt = Network::new(vec![Some(a), Some(b), Some(c)]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+
+    // The response is lost, so the request is still pending on the follower.
+    nt.ignore(MessageType::MsgReadIndexResp);
+    let e = new_entry(0, 0, Some("ctx1"));
+    nt.send(vec![new_message_with_entries(2, 2, MessageType::MsgReadIndex, vec![e])]);
+    assert_eq!(nt.peers[&2].pending_read_count(), 1);
+    assert!(nt.peers[&2].read_states.is_empty());
+
+    // The response of a later request clears the lost one.
+    nt.recover();
+    let e = new_entry(0, 0, Some("ctx2"));
+    nt.send(vec![new_message_with_entries(2, 2, MessageType::MsgReadIndex, vec![e])]);
+    assert_eq!(nt.peers[&2].pending_read_count(), 0);
+    let read_states: Vec<ReadState> = nt.peers.get_mut(&2).unwrap().read_states.drain(..).collect();
+    assert_eq!(read_states.len(), 1);
+    assert_eq!(read_states[0].request_ctx, b"ctx2".to_vec());
+
+    // An outdated response is ignored.
+    let e = new_entry(0, 0, Some("ctx1"));
+    let m = new_message_with_entries(1, 2, MessageType::MsgReadIndexResp, vec![e]);
+    nt.peers.get_mut(&2).unwrap().step(m).unwrap();
+    assert!(nt.peers[&2].read_states.is_empty());
+}
+
 #[test]
 fn test_leader_append_response() {
     // initial progress: match = 0; next = 3
@@ -3044,3 +3096,366 @@ fn test_transfer_non_member() {
     raft.step(new_message(3, 1, MessageType::MsgRequestVoteResponse, 0)).expect("");;
     assert_eq!(raft.state, StateRole::Follower);
 }
+
+// test_learner_election_timeout verifies that a learner never starts an election.
+#[test]
+fn test_learner_election_timeout() {
+    let mut n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    n2.become_follower(1, INVALID_ID);
+
+    let timeout = n2.get_election_timeout();
+    n2.set_randomized_election_timeout(timeout);
+    for _ in 0..timeout {
+        n2.tick();
+    }
+    assert_eq!(n2.state, StateRole::Follower);
+    assert!(n2.read_messages().is_empty());
+
+    n2.step(new_message(2, 2, MessageType::MsgHup, 0)).expect("");
+    assert_eq!(n2.state, StateRole::Follower);
+    assert!(n2.read_messages().is_empty());
+}
+
+// test_learner_promotion verifies that a learner can campaign after it is promoted.
+#[test]
+fn test_learner_promotion() {
+    let n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    let n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    let mut nt = Network::new(vec![Some(n1), Some(n2)]);
+
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    assert_eq!(nt.peers[&2].state, StateRole::Follower);
+
+    nt.send(vec![new_message(2, 2, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    assert_eq!(nt.peers[&2].state, StateRole::Follower);
+
+    nt.peers.get_mut(&1).unwrap().add_node(2);
+    nt.peers.get_mut(&2).unwrap().add_node(2);
+    assert_eq!(nt.peers[&2].nodes(), vec![1, 2]);
+    assert!(nt.peers[&2].learner_nodes().is_empty());
+    assert!(nt.peers[&2].promotable());
+
+    nt.send(vec![new_message(2, 2, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Follower);
+    assert_eq!(nt.peers[&2].state, StateRole::Leader);
+}
+
+// test_learner_log_replication verifies that a learner receives the entries but is not
+// counted in the quorum.
+#[test]
+fn test_learner_log_replication() {
+    let mut r = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    r.become_candidate();
+    r.become_leader();
+    // The entry is committed without the acknowledgement of the learner.
+    assert_eq!(r.raft_log.committed, r.raft_log.last_index());
+    assert_eq!(r.prs[&2].matched, 0);
+
+    let n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    let n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    let mut nt = Network::new(vec![Some(n1), Some(n2)]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+
+    let last_index = nt.peers[&1].raft_log.last_index();
+    assert_eq!(nt.peers[&1].raft_log.committed, last_index);
+    assert_eq!(nt.peers[&2].raft_log.committed, last_index);
+    assert_eq!(nt.peers[&1].prs[&2].matched, last_index);
+}
+
+// test_learner_not_in_quorum verifies that learners are asked for neither votes nor
+// leader activity.
+#[test]
+fn test_learner_not_in_quorum() {
+    let mut r = new_test_learner_raft(1, vec![1, 2], vec![3], 10, 1, new_storage());
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+    let msgs = r.read_messages();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].get_to(), 2);
+    assert_eq!(msgs[0].get_msg_type(), MessageType::MsgRequestVote);
+
+    r.step(new_message(2, 1, MessageType::MsgRequestVoteResponse, 0)).expect("");
+    assert_eq!(r.state, StateRole::Leader);
+
+    // Only the learner is active, the leader should step down.
+    r.check_quorum = true;
+    r.prs.get_mut(&3).unwrap().recent_active = true;
+    r.step(new_message(1, 1, MessageType::MsgCheckQuorum, 0)).expect("");
+    assert_eq!(r.state, StateRole::Follower);
+}
+
+#[test]
+fn test_restore_with_learner() {
+    let mut s = new_snapshot(11, 11, vec![1, 2]);
+    s.mut_metadata().mut_conf_state().set_learners(vec![3]);
+
+    let mut sm = new_test_learner_raft(3, vec![1, 2], vec![3], 10, 1, new_storage());
+    assert!(sm.restore(s.clone()));
+    assert_eq!(sm.nodes(), vec![1, 2]);
+    assert_eq!(sm.learner_nodes(), vec![3]);
+    assert!(sm.prs[&3].is_learner);
+    assert!(!sm.promotable());
+}
+
+// test_add_learner verifies that a voter can't be demoted and a learner can be promoted.
+#[test]
+fn test_add_learner() {
+    let mut r = new_test_raft(1, vec![1], 10, 1, new_storage());
+    r.add_learner(2);
+    assert_eq!(r.nodes(), vec![1]);
+    assert_eq!(r.learner_nodes(), vec![2]);
+
+    r.add_learner(1);
+    assert_eq!(r.nodes(), vec![1]);
+    assert!(r.promotable());
+
+    r.add_node(2);
+    assert_eq!(r.nodes(), vec![1, 2]);
+    assert!(r.learner_nodes().is_empty());
+
+    r.add_learner(3);
+    r.remove_node(3);
+    assert_eq!(r.nodes(), vec![1, 2]);
+    assert!(r.learner_nodes().is_empty());
+}
+
+fn new_conf_state(nodes: Vec<u64>) -> ConfState {
+    let mut cs = ConfState::new();
+    cs.set_nodes(nodes);
+    cs
+}
+
+// test_joint_consensus_commit verifies that an entry is committed only when it's committed
+// by both the old and the new configurations.
+#[test]
+fn test_joint_consensus_commit() {
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.become_candidate();
+    r.become_leader();
+    r.read_messages();
+
+    r.begin_membership_change(&new_conf_state(vec![1, 2, 4]));
+    assert!(r.in_joint_consensus());
+    assert!(r.prs[&4].is_learner);
+    assert_eq!(r.nodes(), vec![1, 2, 3]);
+    let last_index = r.raft_log.last_index();
+    let msgs = r.read_messages();
+    // The new node gets the log too.
+    assert!(msgs.iter().any(|m| m.get_to() == 4));
+
+    let term = r.term;
+    let mut m = new_message(3, 1, MessageType::MsgAppendResponse, 0);
+    m.set_index(last_index);
+    m.set_term(term);
+    r.step(m).expect("");
+    // Only the old configuration has a quorum.
+    assert_eq!(r.raft_log.committed, 0);
+
+    let mut m = new_message(4, 1, MessageType::MsgAppendResponse, 0);
+    m.set_index(last_index);
+    m.set_term(term);
+    r.step(m).expect("");
+    assert_eq!(r.raft_log.committed, last_index);
+}
+
+// test_joint_consensus_election verifies that a candidate in a joint consensus needs the
+// votes of both configurations, and it proposes to finalize the change once elected.
+#[test]
+fn test_joint_consensus_election() {
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.begin_membership_change(&new_conf_state(vec![1, 4, 5]));
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+    let mut to: Vec<_> = r.read_messages().iter().map(|m| m.get_to()).collect();
+    to.sort();
+    assert_eq!(to, vec![2, 3, 4, 5]);
+
+    r.step(new_message(2, 1, MessageType::MsgRequestVoteResponse, 0)).expect("");
+    assert_eq!(r.state, StateRole::Candidate);
+    r.step(new_message(4, 1, MessageType::MsgRequestVoteResponse, 0)).expect("");
+    assert_eq!(r.state, StateRole::Leader);
+
+    let last_index = r.raft_log.last_index();
+    let e = &r.raft_log.entries(last_index, NO_LIMIT).unwrap()[0];
+    assert_eq!(e.get_entry_type(), EntryType::EntryConfChange);
+    let cc: ConfChange = protobuf::parse_from_bytes(e.get_data()).unwrap();
+    assert_eq!(cc.get_change_type(), ConfChangeType::FinalizeMembershipChange);
+}
+
+// test_joint_consensus_quorum verifies that only the voters of the current configuration are
+// counted against its quorum, so the votes or the read index acks of the next configuration
+// alone are not enough.
+#[test]
+fn test_joint_consensus_quorum() {
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.begin_membership_change(&new_conf_state(vec![1, 4, 5]));
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+    r.step(new_message(4, 1, MessageType::MsgRequestVoteResponse, 0)).expect("");
+    r.step(new_message(5, 1, MessageType::MsgRequestVoteResponse, 0)).expect("");
+    assert_eq!(r.state, StateRole::Candidate);
+    r.step(new_message(2, 1, MessageType::MsgRequestVoteResponse, 0)).expect("");
+    assert_eq!(r.state, StateRole::Leader);
+
+    // Commit an entry of the current term so that the leader serves read index requests.
+    let term = r.term;
+    let last_index = r.raft_log.last_index();
+    for &id in &[2, 4] {
+        let mut m = new_message(id, 1, MessageType::MsgAppendResponse, 0);
+        m.set_index(last_index);
+        m.set_term(term);
+        r.step(m).expect("");
+    }
+    assert_eq!(r.raft_log.committed, last_index);
+    r.read_messages();
+
+    let ctx = b"ctx".to_vec();
+    let e = new_entry(0, 0, Some("ctx"));
+    r.step(new_message_with_entries(1, 1, MessageType::MsgReadIndex, vec![e])).expect("");
+    for &id in &[4, 5, 2] {
+        assert!(r.read_states.is_empty());
+        let mut m = new_message(id, 1, MessageType::MsgHeartbeatResponse, 0);
+        m.set_term(term);
+        m.set_context(ctx.clone());
+        r.step(m).expect("");
+    }
+    assert_eq!(r.read_states.len(), 1);
+    assert_eq!(r.read_states[0].request_ctx, ctx);
+}
+
+// test_finalize_membership_change verifies that the next configuration is used after
+// finalizing, and a leader removed by the change steps down.
+#[test]
+fn test_finalize_membership_change() {
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.begin_membership_change(&new_conf_state(vec![1, 2, 4]));
+    // Another membership change is ignored in a joint consensus.
+    r.begin_membership_change(&new_conf_state(vec![1, 2, 5]));
+    assert!(!r.prs.contains_key(&5));
+    r.finalize_membership_change();
+    assert!(!r.in_joint_consensus());
+    assert_eq!(r.nodes(), vec![1, 2, 4]);
+    assert!(!r.prs.contains_key(&3));
+
+    r.become_candidate();
+    r.become_leader();
+    r.begin_membership_change(&new_conf_state(vec![2, 4, 5]));
+    assert_eq!(r.state, StateRole::Leader);
+    r.finalize_membership_change();
+    assert_eq!(r.nodes(), vec![2, 4, 5]);
+    assert_eq!(r.state, StateRole::Follower);
+    assert!(!r.promotable());
+}
+
+// test_joint_consensus_restart verifies that the joint consensus is rebuilt from the
+// storage after restart and from a snapshot taken in it.
+#[test]
+fn test_joint_consensus_restart() {
+    let mut s = new_snapshot(11, 11, vec![1, 2, 3]);
+    s.mut_metadata().set_next_conf_state(new_conf_state(vec![1, 2, 4]));
+
+    let store = new_storage();
+    store.wl().apply_snapshot(s.clone()).expect("");
+    let r = new_test_raft(1, vec![], 10, 1, store);
+    assert!(r.in_joint_consensus());
+    assert_eq!(r.nodes(), vec![1, 2, 3]);
+    assert_eq!(r.learner_nodes(), vec![4]);
+
+    let mut r = new_test_raft(1, vec![1, 2], 10, 1, new_storage());
+    assert!(r.restore(s));
+    assert!(r.in_joint_consensus());
+    assert_eq!(r.nodes(), vec![1, 2, 3]);
+    assert_eq!(r.learner_nodes(), vec![4]);
+    r.finalize_membership_change();
+    assert_eq!(r.nodes(), vec![1, 2, 4]);
+
+    // A snapshot taken out of a joint consensus leaves it.
+    r.begin_membership_change(&new_conf_state(vec![1, 2, 5]));
+    assert!(r.restore(new_snapshot(12, 12, vec![1, 2, 5])));
+    assert!(!r.in_joint_consensus());
+    assert_eq!(r.nodes(), vec![1, 2, 5]);
+}
+
+// test_leader_append_stall verifies that the leader counts the ticks its appended entries
+// are not persisted.
+#[test]
+fn test_leader_append_stall() {
+    let mut config = new_test_config(1, vec![1, 2, 3], 10, 1);
+    config.async_persist = true;
+    let mut r = new_test_raft_with_config(&config, new_storage());
+    r.become_candidate();
+    r.become_leader();
+    r.read_messages();
+    for _ in 0..3 {
+        r.tick();
+    }
+    assert_eq!(r.append_stall_ticks(), 3);
+
+    // Persisting the entries resets the stall.
+    let (last_index, term) = (r.raft_log.last_index(), r.term);
+    r.on_persist_entries(last_index, term);
+    r.tick();
+    assert_eq!(r.append_stall_ticks(), 0);
+}
+
+// test_transfer_leader_to_healthy_follower verifies that the leader transfers its
+// leadership to the active follower with the most up-to-date log.
+#[test]
+fn test_transfer_leader_to_healthy_follower() {
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.become_candidate();
+    r.become_leader();
+    r.read_messages();
+    // No follower is active.
+    assert_eq!(r.transfer_leader_to_healthy_follower(), None);
+
+    let last_index = r.raft_log.last_index();
+    r.prs.get_mut(&2).unwrap().recent_active = true;
+    {
+        let pr = r.prs.get_mut(&3).unwrap();
+        pr.recent_active = true;
+        pr.matched = last_index;
+    }
+    assert_eq!(r.most_up_to_date_follower(), Some(3));
+    assert_eq!(r.transfer_leader_to_healthy_follower(), Some(3));
+    assert_eq!(r.lead_transferee, Some(3));
+    let msgs = r.read_messages();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].get_to(), 3);
+    assert_eq!(msgs[0].get_msg_type(), MessageType::MsgTimeoutNow);
+
+    // The transfer is in progress.
+    assert_eq!(r.transfer_leader_to_healthy_follower(), None);
+}
+
+// test_throttle_proposal verifies that the leader reports the saturated followers, and
+// throttles the proposals when they make a quorum.
+#[test]
+fn test_throttle_proposal() {
+    let mut config = new_test_config(1, vec![1, 2, 3], 10, 1);
+    config.max_inflight_msgs = 2;
+    let mut r = new_test_raft_with_config(&config, new_storage());
+    assert!(!r.should_throttle_proposal());
+    r.become_candidate();
+    r.become_leader();
+    r.step(new_message(1, 1, MessageType::MsgPropose, 1)).expect("");
+    r.read_messages();
+    // The followers are being probed.
+    assert_eq!(r.saturated_followers(), vec![2, 3]);
+    assert!(r.should_throttle_proposal());
+
+    let (last_index, term) = (r.raft_log.last_index(), r.term);
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.set_index(last_index);
+    m.set_term(term);
+    r.step(m).expect("");
+    assert_eq!(r.saturated_followers(), vec![3]);
+    assert!(!r.should_throttle_proposal());
+
+    // The in-flight appends to 2 reach the limit.
+    for _ in 0..2 {
+        r.step(new_message(1, 1, MessageType::MsgPropose, 1)).expect("");
+    }
+    assert_eq!(r.saturated_followers(), vec![2, 3]);
+    assert!(r.should_throttle_proposal());
+}
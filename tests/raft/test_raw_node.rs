@@ -258,6 +258,174 @@ fn test_raw_node_propose_add_duplicate_node() {
     assert_eq!(entries[2].take_data(), ccdata2);
 }
 
+// test_raw_node_propose_add_learner_node ensures that RawNode.apply_conf_change adds and
+// promotes learners.
+#[test]
+fn test_raw_node_propose_add_learner_node() {
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![], 10, 1, s.clone(), vec![new_peer(1)]);
+    let rd = raw_node.ready();
+    s.wl().append(&rd.entries).expect("");
+    raw_node.advance(rd);
+    raw_node.campaign().expect("");
+    loop {
+        let rd = raw_node.ready();
+        s.wl().append(&rd.entries).expect("");
+        if rd.ss.as_ref().map_or(false, |ss| ss.leader_id == raw_node.raft.id) {
+            raw_node.advance(rd);
+            break;
+        }
+        raw_node.advance(rd);
+    }
+
+    let cs = raw_node.apply_conf_change(&conf_change(ConfChangeType::AddLearnerNode, 2));
+    assert_eq!(cs.get_nodes(), &[1]);
+    assert_eq!(cs.get_learners(), &[2]);
+    assert!(raw_node.raft.prs[&2].is_learner);
+
+    let cs = raw_node.apply_conf_change(&conf_change(ConfChangeType::AddNode, 2));
+    assert_eq!(cs.get_nodes(), &[1, 2]);
+    assert!(cs.get_learners().is_empty());
+}
+
+// test_raw_node_membership_change ensures that RawNode.apply_conf_change enters the joint
+// consensus and the leader proposes to finalize it.
+#[test]
+fn test_raw_node_membership_change() {
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![], 10, 1, s.clone(), vec![new_peer(1)]);
+    let rd = raw_node.ready();
+    s.wl().append(&rd.entries).expect("");
+    raw_node.advance(rd);
+    raw_node.campaign().expect("");
+    loop {
+        let rd = raw_node.ready();
+        s.wl().append(&rd.entries).expect("");
+        if rd.ss.as_ref().map_or(false, |ss| ss.leader_id == raw_node.raft.id) {
+            raw_node.advance(rd);
+            break;
+        }
+        raw_node.advance(rd);
+    }
+
+    let mut cc = ConfChange::new();
+    cc.set_change_type(ConfChangeType::BeginMembershipChange);
+    cc.mut_configuration().set_nodes(vec![1, 2]);
+    let cs = raw_node.apply_conf_change(&cc);
+    // The old configuration is still in use before finalizing.
+    assert_eq!(cs.get_nodes(), &[1]);
+    assert!(raw_node.raft.in_joint_consensus());
+
+    let rd = raw_node.ready();
+    let e = rd.entries.last().unwrap();
+    assert_eq!(e.get_entry_type(), EntryType::EntryConfChange);
+    let finalize: ConfChange = protobuf::parse_from_bytes(e.get_data()).unwrap();
+    assert_eq!(finalize.get_change_type(),
+               ConfChangeType::FinalizeMembershipChange);
+    s.wl().append(&rd.entries).expect("");
+    raw_node.advance(rd);
+
+    let cs = raw_node.apply_conf_change(&finalize);
+    assert_eq!(cs.get_nodes(), &[1, 2]);
+    assert!(!raw_node.raft.in_joint_consensus());
+}
+
+fn new_async_raw_node(id: u64, peers: Vec<u64>, storage: MemStorage) -> RawNode<MemStorage> {
+    let mut config = new_test_config(id, peers, 10, 1);
+    config.async_persist = true;
+    let peer_nodes = if config.peers.is_empty() {
+        vec![new_peer(id)]
+    } else {
+        vec![]
+    };
+    RawNode::new(&config, storage, &peer_nodes).unwrap()
+}
+
+fn persist_ready(s: &MemStorage, rd: &Ready) {
+    if let Some(ref hs) = rd.hs {
+        s.wl().set_hardstate(hs.clone());
+    }
+    s.wl().append(&rd.entries).expect("");
+}
+
+// test_raw_node_async_persist ensures that the entries of a Ready are handed out only once
+// when they are persisted asynchronously, and the leader commits them after persisted.
+#[test]
+fn test_raw_node_async_persist() {
+    let s = new_storage();
+    let mut raw_node = new_async_raw_node(1, vec![], s.clone());
+    let rd = raw_node.ready();
+    assert_eq!(rd.entries.len(), 1);
+    // The committed entries are not applied until persisted.
+    assert_eq!(rd.committed_entries, Some(vec![]));
+    raw_node.advance_append_async(&rd);
+    assert!(!raw_node.has_ready());
+    persist_ready(&s, &rd);
+    raw_node.on_persist_ready(rd.number);
+    let rd = raw_node.ready();
+    assert_eq!(rd.committed_entries.as_ref().unwrap().len(), 1);
+    raw_node.advance_append_async(&rd);
+    raw_node.advance_apply(1);
+
+    raw_node.campaign().expect("");
+    let rd = raw_node.ready();
+    assert_eq!(rd.ss.as_ref().unwrap().raft_state, StateRole::Leader);
+    raw_node.advance_append_async(&rd);
+    persist_ready(&s, &rd);
+    raw_node.on_persist_ready(rd.number);
+    let rd = raw_node.ready();
+    assert_eq!(rd.committed_entries.as_ref().unwrap().len(), 1);
+    raw_node.advance_append_async(&rd);
+    raw_node.advance_apply(2);
+
+    raw_node.propose(b"foo".to_vec()).expect("");
+    let rd1 = raw_node.ready();
+    assert_eq!(rd1.entries.len(), 1);
+    raw_node.advance_append_async(&rd1);
+    raw_node.propose(b"bar".to_vec()).expect("");
+    let rd2 = raw_node.ready();
+    assert_eq!(rd2.entries.len(), 1);
+    assert_eq!(rd2.entries[0].get_index(), 4);
+    assert!(rd2.committed_entries.as_ref().unwrap().is_empty());
+    raw_node.advance_append_async(&rd2);
+    assert_eq!(raw_node.raft.raft_log.committed, 2);
+
+    persist_ready(&s, &rd1);
+    persist_ready(&s, &rd2);
+    raw_node.on_persist_ready(rd2.number);
+    assert_eq!(raw_node.raft.raft_log.committed, 4);
+    let rd = raw_node.ready();
+    let committed: Vec<_> = rd.committed_entries.unwrap().iter().map(|e| e.get_index()).collect();
+    assert_eq!(committed, vec![3, 4]);
+}
+
+// test_raw_node_async_persist_follower ensures that a follower responds only after the
+// Ready is persisted.
+#[test]
+fn test_raw_node_async_persist_follower() {
+    let s = new_storage();
+    let mut raw_node = new_async_raw_node(2, vec![1, 2], s.clone());
+    let mut m = new_message_with_entries(1, 2, MessageType::MsgAppend, vec![empty_entry(1, 1)]);
+    m.set_term(1);
+    m.set_commit(1);
+    raw_node.step(m).expect("");
+
+    let rd = raw_node.ready();
+    assert!(rd.messages.is_empty());
+    assert_eq!(rd.persisted_messages.len(), 1);
+    assert_eq!(rd.persisted_messages[0].get_msg_type(),
+               MessageType::MsgAppendResponse);
+    assert_eq!(rd.persisted_messages[0].get_index(), 1);
+    assert_eq!(rd.committed_entries, Some(vec![]));
+    raw_node.advance_append_async(&rd);
+    assert!(!raw_node.has_ready());
+
+    persist_ready(&s, &rd);
+    raw_node.on_persist_ready(rd.number);
+    let rd = raw_node.ready();
+    assert_eq!(rd.committed_entries, Some(vec![empty_entry(1, 1)]));
+}
+
 // test_raw_node_read_index ensures that RawNode.read_index sends the MsgReadIndex message
 // to the underlying raft. It also ensures that ReadState can be read out.
 #[test]
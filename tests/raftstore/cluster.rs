@@ -17,7 +17,7 @@ use std::sync::{self, Arc, RwLock};
 use std::time::*;
 use std::{result, thread};
 
-use rocksdb::DB;
+use rocksdb::{DB, Options};
 use tempdir::TempDir;
 use futures::Future;
 
@@ -121,6 +121,18 @@ impl<T: Simulator> Cluster<T> {
         }
     }
 
+    // Reopens the engine of store `node_id` with `cfs_opts`, it must be called before the
+    // cluster starts.
+    pub fn reopen_engine(&mut self, node_id: u64, cfs_opts: Vec<rocksdb::CFOptions>) {
+        assert!(self.engines.is_empty());
+        let idx = node_id as usize - 1;
+        // The old engine must be closed to release the lock of the db.
+        drop(self.dbs.remove(idx));
+        let path = self.paths[idx].path().to_str().unwrap();
+        let engine = rocksdb::new_engine_opt(path, Options::new(), cfs_opts).unwrap();
+        self.dbs.insert(idx, Arc::new(engine));
+    }
+
     pub fn start(&mut self) {
         if self.engines.is_empty() {
             let mut sim = self.sim.wl();
@@ -34,4 +34,5 @@ mod test_snap;
 mod test_region_heartbeat;
 mod test_stale_peer;
 mod test_lease_read;
+mod test_replica_read;
 mod test_bootstrap;
@@ -18,6 +18,7 @@ use std::sync::mpsc::Sender;
 use std::time::Duration;
 use std::boxed::FnBox;
 use std::ops::Deref;
+use std::path::Path;
 
 use rocksdb::DB;
 use tempdir::TempDir;
@@ -35,7 +36,8 @@ use tikv::util::transport::SendCh;
 use tikv::server::Config as ServerConfig;
 use tikv::server::transport::{ServerRaftStoreRouter, RaftStoreRouter};
 use tikv::raft::SnapshotStatus;
-use tikv::storage::ALL_CFS;
+use tikv::storage::{ALL_CFS, MaxReadTs};
+use tikv::import::SSTImporter;
 use super::pd::TestPdClient;
 use super::transport_simulate::*;
 
@@ -178,12 +180,19 @@ impl Simulator for NodeCluster {
             let &(ref snap_mgr, _) = &trans.snap_paths[&node_id];
             (snap_mgr.clone(), None)
         };
+        let import_dir = match tmp {
+            Some(ref tmp) => tmp.path().join("import"),
+            None => Path::new(&self.get_snap_dir(node_id)).join("import"),
+        };
+        let importer = Arc::new(SSTImporter::new(import_dir).unwrap());
 
         node.start(event_loop,
                    engine.clone(),
                    simulate_trans.clone(),
                    snap_mgr.clone(),
-                   snap_status_receiver)
+                   snap_status_receiver,
+                   importer,
+                   MaxReadTs::new())
             .unwrap();
         assert!(engine.get_msg::<metapb::Region>(&keys::prepare_bootstrap_key())
             .unwrap()
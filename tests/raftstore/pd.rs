@@ -12,11 +12,13 @@
 // limitations under the License.
 
 
+use std::cmp;
 use std::collections::{HashMap, BTreeMap, HashSet};
 use std::vec::Vec;
 use std::collections::Bound::{Excluded, Unbounded};
 use std::sync::{Arc, RwLock};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use futures::{Future, Stream};
 use futures::future::{ok, err};
@@ -28,6 +30,7 @@ use kvproto::eraftpb;
 use tikv::pd::{PdClient, Result, Error, Key, PdFuture, RegionStat};
 use tikv::raftstore::store::keys::{self, enc_end_key, enc_start_key, data_key};
 use tikv::raftstore::store::util::check_key_in_region;
+use tikv::storage::mvcc::compose_ts;
 use tikv::util::{HandyRwLock, escape};
 use super::util::*;
 
@@ -68,6 +71,7 @@ struct Cluster {
 
     store_stats: HashMap<u64, pdpb::StoreStats>,
     split_count: usize,
+    gc_safe_point: u64,
 
     down_peers: HashMap<u64, pdpb::PeerStats>,
     pending_peers: HashMap<u64, metapb::Peer>,
@@ -89,6 +93,7 @@ impl Cluster {
             rule: None,
             store_stats: HashMap::new(),
             split_count: 0,
+            gc_safe_point: 0,
             down_peers: HashMap::new(),
             pending_peers: HashMap::new(),
             is_bootstraped: false,
@@ -235,8 +240,9 @@ impl Cluster {
         let cur_region_peer_len = cur_region.get_peers().len();
 
         if conf_ver > cur_conf_ver {
-            // If ConfVer changed, TiKV has added/removed one peer already.
-            // So pd and TiKV can't have same peer count and can only have
+            // If ConfVer changed, TiKV has added/removed one peer already,
+            // or replaced the peers by a membership change.
+            // Otherwise pd and TiKV can't have same peer count and can only have
             // only one different peer.
             // E.g, we can't meet following cases:
             // 1) pd is (1, 2, 3), TiKV is (1)
@@ -244,9 +250,10 @@ impl Cluster {
             // 3) pd is (1, 2), TiKV is (3)
             // 4) pd id (1), TiKV is (2, 3)
 
-            assert_ne!(region_peer_len, cur_region_peer_len);
-
-            if cur_region_peer_len > region_peer_len {
+            if region_peer_len == cur_region_peer_len {
+                // A membership change replaces the peers at once.
+                assert!(!setdiff_peers(&region, &cur_region).is_empty());
+            } else if cur_region_peer_len > region_peer_len {
                 // must pd is (1, 2), TiKV is (1)
                 assert_eq!(cur_region_peer_len - region_peer_len, 1);
                 let peers = setdiff_peers(&cur_region, &region);
@@ -388,6 +395,7 @@ pub fn bootstrap_with_first_region(pd_client: Arc<TestPdClient>) -> Result<()> {
 pub struct TestPdClient {
     cluster_id: u64,
     cluster: RwLock<Cluster>,
+    last_tso: AtomicUsize,
 }
 
 impl TestPdClient {
@@ -395,6 +403,7 @@ impl TestPdClient {
         TestPdClient {
             cluster_id: cluster_id,
             cluster: RwLock::new(Cluster::new(cluster_id)),
+            last_tso: AtomicUsize::new(0),
         }
     }
 
@@ -446,7 +455,7 @@ impl TestPdClient {
             };
 
             if let Some(p) = find_peer(&region, peer.get_store_id()) {
-                if p.get_id() == peer.get_id() {
+                if p.get_id() == peer.get_id() && p.get_is_learner() == peer.get_is_learner() {
                     return;
                 }
             }
@@ -499,6 +508,21 @@ impl TestPdClient {
         self.must_have_peer(region_id, peer);
     }
 
+    pub fn add_learner(&self, region_id: u64, peer: metapb::Peer) {
+        self.set_rule(box move |region: &metapb::Region, _: &metapb::Peer| {
+            if region.get_id() != region_id {
+                return None;
+            }
+            new_pd_add_learner_change_peer(region, peer.clone())
+        });
+    }
+
+    pub fn must_add_learner(&self, region_id: u64, mut peer: metapb::Peer) {
+        self.add_learner(region_id, peer.clone());
+        peer.set_is_learner(true);
+        self.must_have_peer(region_id, peer);
+    }
+
     pub fn remove_peer(&self, region_id: u64, peer: metapb::Peer) {
         self.set_rule(box move |region: &metapb::Region, _: &metapb::Peer| {
             if region.get_id() != region_id {
@@ -560,6 +584,10 @@ impl TestPdClient {
     pub fn set_bootstrap(&self, is_bootstraped: bool) {
         self.cluster.wl().set_bootstrap(is_bootstraped);
     }
+
+    pub fn set_gc_safe_point(&self, safe_point: u64) {
+        self.cluster.wl().gc_safe_point = safe_point;
+    }
 }
 
 impl PdClient for TestPdClient {
@@ -701,4 +729,27 @@ impl PdClient for TestPdClient {
         self.cluster.wl().split_count += 1;
         ok(()).boxed()
     }
+
+    fn get_gc_safe_point(&self) -> PdFuture<u64> {
+        if let Err(e) = self.check_bootstrap() {
+            return err(e).boxed();
+        }
+        ok(self.cluster.rl().gc_safe_point).boxed()
+    }
+
+    fn get_tso(&self) -> PdFuture<u64> {
+        let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let physical = elapsed.as_secs() * 1000 + elapsed.subsec_nanos() as u64 / 1_000_000;
+        let ts = compose_ts(physical, 0) as usize;
+        // The timestamps must increase even within a millisecond.
+        let mut last = self.last_tso.load(Ordering::SeqCst);
+        loop {
+            let next = cmp::max(ts, last + 1);
+            let prev = self.last_tso.compare_and_swap(last, next, Ordering::SeqCst);
+            if prev == last {
+                return ok(next as u64).boxed();
+            }
+            last = prev;
+        }
+    }
 }
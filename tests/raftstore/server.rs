@@ -16,6 +16,7 @@ use std::net::SocketAddr;
 use std::sync::{Arc, RwLock, mpsc};
 use std::time::Duration;
 use std::boxed::FnBox;
+use std::path::Path;
 
 use grpc::Environment;
 use rocksdb::DB;
@@ -23,7 +24,9 @@ use tempdir::TempDir;
 
 use super::cluster::{Simulator, Cluster};
 use tikv::server::{Server, ServerTransport};
-use tikv::server::{Node, Config, create_raft_storage, PdStoreAddrResolver, RaftClient};
+use tikv::server::{Node, Config, create_raft_storage, PdStoreAddrResolver, RaftClient,
+                   ImportClient};
+use tikv::import::SSTImporter;
 use tikv::server::transport::ServerRaftStoreRouter;
 use tikv::server::transport::RaftStoreRouter;
 use tikv::raftstore::{Error, Result, store};
@@ -99,7 +102,15 @@ impl Simulator for ServerCluster {
         let (snap_status_sender, snap_status_receiver) = mpsc::channel();
 
         // Create storage.
-        let mut store = create_raft_storage(sim_router.clone(), engine.clone(), &cfg).unwrap();
+        let importer = Arc::new(SSTImporter::new(Path::new(&tmp_str).join("import")).unwrap());
+        let sst_sender = Arc::new(ImportClient::new(Arc::new(Environment::new(1)),
+                                                    self.pd_client.clone()));
+        let mut store = create_raft_storage(sim_router.clone(),
+                                            engine.clone(),
+                                            importer.clone(),
+                                            sst_sender,
+                                            &cfg)
+            .unwrap();
         store.start(&cfg.storage).unwrap();
         self.storages.insert(node_id, store.get_engine());
 
@@ -113,7 +124,8 @@ impl Simulator for ServerCluster {
                                      sim_router.clone(),
                                      snap_status_sender,
                                      resolver,
-                                     snap_mgr.clone())
+                                     snap_mgr.clone(),
+                                     importer.clone())
             .unwrap();
         let addr = server.listening_addr();
         cfg.addr = format!("{}", addr);
@@ -126,7 +138,9 @@ impl Simulator for ServerCluster {
                    engine,
                    simulate_trans.clone(),
                    snap_mgr.clone(),
-                   snap_status_receiver)
+                   snap_status_receiver,
+                   importer,
+                   store.get_max_read_ts())
             .unwrap();
         assert!(node_id == 0 || node_id == node.id());
         let node_id = node.id();
@@ -136,7 +150,7 @@ impl Simulator for ServerCluster {
         self.store_chs.insert(node_id, node.get_sendch());
         self.sim_trans.insert(node_id, simulate_trans);
 
-        server.start(&cfg).unwrap();
+        server.start(&cfg, self.pd_client.clone()).unwrap();
 
         self.nodes.insert(node_id, node);
         self.servers.insert(node_id, server);
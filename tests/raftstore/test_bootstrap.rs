@@ -14,7 +14,8 @@
 use std::sync::{Arc, mpsc};
 use tikv::raftstore::store::{keys, Peekable, SnapManager, create_event_loop, bootstrap_store};
 use tikv::server::Node;
-use tikv::storage::ALL_CFS;
+use tikv::import::SSTImporter;
+use tikv::storage::{ALL_CFS, MaxReadTs};
 use tikv::util::rocksdb;
 use tempdir::TempDir;
 use kvproto::metapb;
@@ -74,11 +75,14 @@ fn test_node_bootstrap_with_prepared_data() {
     assert!(engine.get_msg::<RegionLocalState>(&region_state_key).unwrap().is_some());
 
     // try to restart this node, will clear the prepare data
+    let importer = Arc::new(SSTImporter::new(tmp_mgr.path().join("import")).unwrap());
     node.start(event_loop,
                engine.clone(),
                simulate_trans,
                snap_mgr,
-               snapshot_status_receiver)
+               snapshot_status_receiver,
+               importer,
+               MaxReadTs::new())
         .unwrap();
     assert!(engine.clone()
         .get_msg::<metapb::Region>(&keys::prepare_bootstrap_key())
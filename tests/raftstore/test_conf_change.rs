@@ -241,6 +241,172 @@ fn test_server_simple_conf_change() {
     test_simple_conf_change(&mut cluster);
 }
 
+fn test_replace_peer<T: Simulator>(cluster: &mut Cluster<T>) {
+    let pd_client = cluster.pd_client.clone();
+    // Disable default max peer count check.
+    pd_client.disable_default_rule();
+
+    let r1 = cluster.run_conf_change();
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+    pd_client.must_add_peer(r1, new_peer(3, 3));
+    cluster.must_put(b"k1", b"v1");
+    let engine_3 = cluster.get_engine(3);
+    must_get_equal(&engine_3, b"k1", b"v1");
+
+    // Replace peer (3, 3) with peer (4, 4) in one membership change.
+    let change = new_membership_change_request(vec![new_peer(1, 1),
+                                                    new_peer(2, 2),
+                                                    new_peer(4, 4)]);
+    let epoch = pd_client.get_region_epoch(r1);
+    let admin_req = new_admin_request(r1, &epoch, change);
+    let resp = cluster.call_command_on_leader(admin_req, Duration::from_secs(3)).unwrap();
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+    pd_client.must_have_peer(r1, new_peer(4, 4));
+    pd_client.must_none_peer(r1, new_peer(3, 3));
+
+    cluster.must_put(b"k2", b"v2");
+    let engine_4 = cluster.get_engine(4);
+    must_get_equal(&engine_4, b"k1", b"v1");
+    must_get_equal(&engine_4, b"k2", b"v2");
+    // peer 3 is destroyed after the change is finalized.
+    must_get_none(&engine_3, b"k1");
+    must_get_none(&engine_3, b"k2");
+
+    // A peer in the region can't change its id.
+    let change = new_membership_change_request(vec![new_peer(1, 1),
+                                                    new_peer(2, 2),
+                                                    new_peer(4, 5)]);
+    let epoch = pd_client.get_region_epoch(r1);
+    let admin_req = new_admin_request(r1, &epoch, change);
+    let resp = cluster.call_command_on_leader(admin_req, Duration::from_secs(3)).unwrap();
+    assert!(resp.get_header().has_error(), "{:?}", resp);
+}
+
+fn must_get_region_state<T: Simulator>(cluster: &Cluster<T>,
+                                       node_id: u64,
+                                       region_id: u64)
+                                       -> RegionLocalState {
+    let state_key = keys::region_state_key(region_id);
+    cluster.get_engine(node_id).get_msg(&state_key).unwrap().unwrap()
+}
+
+fn test_restart_in_membership_change<T: Simulator>(cluster: &mut Cluster<T>) {
+    let pd_client = cluster.pd_client.clone();
+    // Disable default max peer count check.
+    pd_client.disable_default_rule();
+
+    let r1 = cluster.run_conf_change();
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+    pd_client.must_add_peer(r1, new_peer(3, 3));
+    // Peer 4 catches up as a learner, so that it's healthy for the membership change.
+    pd_client.must_add_learner(r1, new_peer(4, 4));
+    cluster.must_put(b"k1", b"v1");
+    let engine_4 = cluster.get_engine(4);
+    must_get_equal(&engine_4, b"k1", b"v1");
+
+    // The change begins with the quorum of (1, 2, 3), but (1, 2, 4) has no quorum to
+    // finalize it.
+    cluster.add_send_filter(IsolationFilterFactory::new(2));
+    cluster.add_send_filter(IsolationFilterFactory::new(4));
+    let change = new_membership_change_request(vec![new_peer(1, 1),
+                                                    new_peer(2, 2),
+                                                    new_peer(4, 4)]);
+    let epoch = pd_client.get_region_epoch(r1);
+    let admin_req = new_admin_request(r1, &epoch, change);
+    let resp = cluster.call_command_on_leader(admin_req, Duration::from_secs(3)).unwrap();
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+
+    // Both configurations are persisted.
+    let state = must_get_region_state(cluster, 1, r1);
+    let ids = |peers: &[metapb::Peer]| peers.iter().map(|p| p.get_id()).collect::<Vec<_>>();
+    assert_eq!(ids(state.get_region().get_peers()), vec![1, 2, 4]);
+    assert_eq!(ids(state.get_region().get_prev_peers()), vec![1, 2, 3]);
+
+    // The joint consensus is rebuilt after restart and finalized once (1, 2, 4) is back.
+    cluster.stop_node(1);
+    cluster.run_node(1);
+    cluster.clear_send_filters();
+    pd_client.must_none_peer(r1, new_peer(3, 3));
+    cluster.must_put(b"k2", b"v2");
+    must_get_equal(&engine_4, b"k2", b"v2");
+    must_get_none(&cluster.get_engine(3), b"k1");
+
+    let state = must_get_region_state(cluster, 1, r1);
+    assert_eq!(ids(state.get_region().get_peers()), vec![1, 2, 4]);
+    assert!(state.get_region().get_prev_peers().is_empty());
+}
+
+#[test]
+fn test_node_restart_in_membership_change() {
+    let count = 4;
+    let mut cluster = new_node_cluster(0, count);
+    test_restart_in_membership_change(&mut cluster);
+}
+
+#[test]
+fn test_server_restart_in_membership_change() {
+    let count = 4;
+    let mut cluster = new_server_cluster(0, count);
+    test_restart_in_membership_change(&mut cluster);
+}
+
+#[test]
+fn test_node_replace_peer() {
+    let count = 5;
+    let mut cluster = new_node_cluster(0, count);
+    test_replace_peer(&mut cluster);
+}
+
+#[test]
+fn test_server_replace_peer() {
+    let count = 5;
+    let mut cluster = new_server_cluster(0, count);
+    test_replace_peer(&mut cluster);
+}
+
+fn test_learner_conf_change<T: Simulator>(cluster: &mut Cluster<T>) {
+    let pd_client = cluster.pd_client.clone();
+    // Disable default max peer count check.
+    pd_client.disable_default_rule();
+
+    let r1 = cluster.run_conf_change();
+    pd_client.must_add_learner(r1, new_peer(2, 2));
+    cluster.must_put(b"k1", b"v1");
+    let engine_2 = cluster.get_engine(2);
+    must_get_equal(&engine_2, b"k1", b"v1");
+
+    // The learner doesn't vote, so peer 1 is still the quorum on its own.
+    cluster.stop_node(2);
+    cluster.must_put(b"k2", b"v2");
+
+    // The learner is persisted in the region meta, so it is still a learner after restart.
+    cluster.stop_node(1);
+    cluster.run_node(1);
+    cluster.must_put(b"k3", b"v3");
+
+    cluster.run_node(2);
+    must_get_equal(&engine_2, b"k3", b"v3");
+
+    // Promote the learner to a voter.
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+    cluster.must_put(b"k4", b"v4");
+    must_get_equal(&engine_2, b"k4", b"v4");
+}
+
+#[test]
+fn test_node_learner_conf_change() {
+    let count = 2;
+    let mut cluster = new_node_cluster(0, count);
+    test_learner_conf_change(&mut cluster);
+}
+
+#[test]
+fn test_server_learner_conf_change() {
+    let count = 2;
+    let mut cluster = new_server_cluster(0, count);
+    test_learner_conf_change(&mut cluster);
+}
+
 #[test]
 fn test_node_pd_conf_change() {
     let count = 5;
@@ -299,6 +299,30 @@ fn test_multi_server_random_restart() {
     test_multi_random_restart(&mut cluster, count, 10);
 }
 
+#[test]
+fn test_multi_node_async_persist_random_restart() {
+    let count = 5;
+    let mut cluster = new_node_cluster(0, count);
+    cluster.cfg.raft_store.raft_async_persist = true;
+    test_multi_random_restart(&mut cluster, count, 10);
+}
+
+#[test]
+fn test_multi_server_async_persist_random_restart() {
+    let count = 5;
+    let mut cluster = new_server_cluster(0, count);
+    cluster.cfg.raft_store.raft_async_persist = true;
+    test_multi_random_restart(&mut cluster, count, 10);
+}
+
+#[test]
+fn test_multi_node_async_persist_cluster_restart() {
+    let count = 5;
+    let mut cluster = new_node_cluster(0, count);
+    cluster.cfg.raft_store.raft_async_persist = true;
+    test_multi_cluster_restart(&mut cluster)
+}
+
 fn test_leader_change_with_uncommitted_log<T: Simulator>(cluster: &mut Cluster<T>) {
     cluster.cfg.raft_store.raft_election_timeout_ticks = 50;
     // disable compact log to make test more stable.
@@ -721,3 +745,46 @@ fn test_server_batch_write() {
     let mut cluster = new_server_cluster(0, 3);
     test_batch_write(&mut cluster);
 }
+
+fn test_proposal_batch<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.cfg.raft_store.max_proposal_batch_size = 256 * 1024;
+    cluster.run();
+    cluster.must_put(b"k0", b"v0");
+
+    let leader = cluster.leader_of_region(1).unwrap();
+    let epoch = cluster.get_region_epoch(1);
+    let ch = cluster.sim.rl().get_store_sendch(leader.get_store_id()).unwrap();
+    // Sends the puts at once, so they are proposed in fewer entries.
+    let (tx, rx) = mpsc::channel();
+    for i in 1..33 {
+        let (k, v) = (format!("k{}", i), format!("v{}", i));
+        let put = new_put_cmd(k.as_bytes(), v.as_bytes());
+        let mut req = new_request(1, epoch.clone(), vec![put], false);
+        req.mut_header().set_peer(leader.clone());
+        let tx = tx.clone();
+        let cb = box move |resp: RaftCmdResponse| tx.send(resp).unwrap();
+        ch.send(Msg::new_raft_cmd(req, cb)).unwrap();
+    }
+    // Every command gets its own response.
+    for _ in 1..33 {
+        let resp = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(!resp.get_header().has_error(), "{:?}", resp);
+        assert_eq!(resp.get_responses().len(), 1);
+    }
+    for i in 1..33 {
+        let (k, v) = (format!("k{}", i), format!("v{}", i));
+        assert_eq!(cluster.must_get(k.as_bytes()), Some(v.into_bytes()));
+    }
+}
+
+#[test]
+fn test_node_proposal_batch() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_proposal_batch(&mut cluster);
+}
+
+#[test]
+fn test_server_proposal_batch() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_proposal_batch(&mut cluster);
+}
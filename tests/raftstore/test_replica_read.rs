@@ -0,0 +1,135 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A module contains test cases for replica read on Raft followers.
+
+use std::thread;
+use std::time::Duration;
+
+use futures::Future;
+use kvproto::metapb::{Peer, Region};
+use kvproto::raft_cmdpb::CmdType;
+use tikv::pd::PdClient;
+use tikv::raftstore::{Error, Result};
+use tikv::util::escape;
+
+use super::cluster::{Cluster, Simulator};
+use super::node::new_node_cluster;
+use super::server::new_server_cluster;
+use super::transport_simulate::*;
+use super::util::*;
+
+// Issue a replica read request at `read_ts` on the specified peer.
+fn replica_read<T: Simulator>(cluster: &mut Cluster<T>,
+                              peer: Peer,
+                              region: &Region,
+                              key: &[u8],
+                              read_ts: u64,
+                              timeout: Duration)
+                              -> Result<Vec<u8>> {
+    let mut request = new_request(region.get_id(),
+                                  region.get_region_epoch().clone(),
+                                  vec![new_get_cmd(key)],
+                                  false);
+    request.mut_header().set_peer(peer);
+    request.mut_header().set_replica_read(true);
+    request.mut_header().set_read_ts(read_ts);
+    let mut resp = try!(cluster.call_command(request, timeout));
+    if resp.get_header().has_error() {
+        return Err(Error::Other(box_err!(resp.mut_header().take_error().take_message())));
+    }
+    assert_eq!(resp.get_responses().len(), 1);
+    assert_eq!(resp.get_responses()[0].get_cmd_type(), CmdType::Get);
+    Ok(resp.mut_responses()[0].mut_get().take_value())
+}
+
+fn must_replica_read<T: Simulator>(cluster: &mut Cluster<T>,
+                                   peer: Peer,
+                                   region: &Region,
+                                   key: &[u8],
+                                   read_ts: u64,
+                                   value: &[u8]) {
+    let timeout = Duration::from_secs(3);
+    match replica_read(cluster, peer, region, key, read_ts, timeout) {
+        Ok(v) => {
+            if v != value {
+                panic!("read key {} at {}, expect value {}, got {}",
+                       escape(key),
+                       read_ts,
+                       escape(value),
+                       escape(&v))
+            }
+        }
+        Err(e) => panic!("failed to read key {} at {}, err {:?}", escape(key), read_ts, e),
+    }
+}
+
+fn test_replica_read<T: Simulator>(cluster: &mut Cluster<T>) {
+    // Use large election timeout to make leadership stable.
+    cluster.cfg.raft_store.raft_election_timeout_ticks = 10000;
+    cluster.cfg.raft_store.safe_ts_tick_interval = 50;
+    cluster.run();
+
+    let region = cluster.get_region(b"");
+    let region_id = region.get_id();
+    let leader = region.get_peers().iter().find(|p| p.get_store_id() == 1).cloned().unwrap();
+    let follower = region.get_peers().iter().find(|p| p.get_store_id() == 2).cloned().unwrap();
+    cluster.must_transfer_leader(region_id, leader);
+    cluster.must_put(b"k1", b"v1");
+    // Wait for the stores to get timestamps from PD.
+    thread::sleep(Duration::from_millis(200));
+
+    // The follower has no safe ts yet, it asks the leader for the read index, and advances
+    // the safe ts to the timestamp got from PD before the read index is requested.
+    let ts = cluster.pd_client.get_tso().wait().unwrap();
+    must_replica_read(cluster, follower.clone(), &region, b"k1", ts, b"v1");
+
+    // Reads before the safe ts are served by the isolated follower locally.
+    cluster.add_send_filter(IsolationFilterFactory::new(2));
+    cluster.must_put(b"k1", b"v2");
+    must_replica_read(cluster, follower.clone(), &region, b"k1", 5, b"v1");
+    must_replica_read(cluster, follower.clone(), &region, b"k1", 10, b"v1");
+
+    // Reads after the safe ts can't be served without the leader, the timestamps of the
+    // reads never advance the safe ts.
+    let ts = cluster.pd_client.get_tso().wait().unwrap();
+    let timeout = Duration::from_millis(500);
+    assert!(replica_read(cluster, follower.clone(), &region, b"k1", ts, timeout).is_err());
+
+    // Writes are never served by followers.
+    let mut request = new_request(region_id,
+                                  region.get_region_epoch().clone(),
+                                  vec![new_put_cmd(b"k2", b"v2")],
+                                  false);
+    request.mut_header().set_peer(follower.clone());
+    request.mut_header().set_replica_read(true);
+    let resp = cluster.call_command(request, Duration::from_secs(3)).unwrap();
+    assert!(resp.get_header().get_error().has_not_leader());
+
+    // The follower catches up after the partition heals.
+    cluster.clear_send_filters();
+    let ts = cluster.pd_client.get_tso().wait().unwrap();
+    must_replica_read(cluster, follower, &region, b"k1", ts, b"v2");
+}
+
+#[test]
+fn test_node_replica_read() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_replica_read(&mut cluster);
+}
+
+#[test]
+fn test_server_replica_read() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_replica_read(&mut cluster);
+}
@@ -98,6 +98,14 @@ fn test_server_huge_snapshot() {
     test_huge_snapshot(&mut cluster);
 }
 
+#[test]
+fn test_node_async_persist_huge_snapshot() {
+    let count = 5;
+    let mut cluster = new_node_cluster(0, count);
+    cluster.cfg.raft_store.raft_async_persist = true;
+    test_huge_snapshot(&mut cluster);
+}
+
 fn test_snap_gc<T: Simulator>(cluster: &mut Cluster<T>) {
     // truncate the log quickly so that we can force sending snapshot.
     cluster.cfg.raft_store.raft_log_gc_tick_interval = 20;
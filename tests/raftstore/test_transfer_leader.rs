@@ -11,10 +11,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::thread;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use rocksdb::{CompactionFilter, CompactOptions, Options};
 use kvproto::eraftpb::MessageType;
+use tikv::storage::{ALL_CFS, CF_RAFT};
+use tikv::util::HandyRwLock;
+use tikv::util::rocksdb::CFOptions;
 
 use super::util::*;
 use super::cluster::{Cluster, Simulator};
@@ -179,3 +185,105 @@ fn test_node_transfer_leader_during_snapshot() {
     let mut cluster = new_node_cluster(0, 3);
     test_transfer_leader_during_snapshot(&mut cluster);
 }
+
+// Blocks the compactions while `stalled` is set, so the level 0 files pile up.
+struct StallCompactionFilter {
+    stalled: Arc<AtomicBool>,
+}
+
+impl CompactionFilter for StallCompactionFilter {
+    fn filter(&mut self, _: usize, _: &[u8], _: &[u8]) -> bool {
+        while self.stalled.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+}
+
+fn test_transfer_leader_on_stalled_append<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.cfg.raft_store.raft_async_persist = true;
+    cluster.cfg.raft_store.max_leader_stall_duration = Duration::from_millis(500);
+
+    // Writes to the engine of store 1 stop when the raft cf has 2 level 0 files.
+    let stalled = Arc::new(AtomicBool::new(false));
+    let mut cfs_opts = vec![];
+    for cf in ALL_CFS {
+        let mut opts = Options::new();
+        if *cf == CF_RAFT {
+            let filter = StallCompactionFilter { stalled: stalled.clone() };
+            opts.set_compaction_filter("stall_compaction_filter", false, box filter).unwrap();
+            opts.set_level_zero_file_num_compaction_trigger(1);
+            opts.set_level_zero_slowdown_writes_trigger(2);
+            opts.set_level_zero_stop_writes_trigger(2);
+        }
+        cfs_opts.push(CFOptions::new(cf, opts));
+    }
+    cluster.reopen_engine(1, cfs_opts);
+    cluster.run();
+
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    cluster.must_put(b"k0", b"v0");
+    for id in 1..6 {
+        must_get_equal(&cluster.get_engine(id), b"k0", b"v0");
+    }
+
+    let engine = cluster.get_engine(1);
+    let handle = engine.cf_handle(CF_RAFT).unwrap();
+    // Moves the raft log to level 1, so the following level 0 files can't be moved down
+    // without being compacted.
+    engine.flush_cf(handle, true).unwrap();
+    engine.compact_range_cf_opt(handle, &CompactOptions::new(), None, None);
+    stalled.store(true, Ordering::SeqCst);
+    for i in 1..3 {
+        let key = format!("k{}", i);
+        cluster.must_put(key.as_bytes(), b"v");
+        must_get_equal(&engine, key.as_bytes(), b"v");
+        engine.flush_cf(handle, true).unwrap();
+    }
+
+    // Only store 2 and 3 receive the new entry, so it can't be committed without the leader
+    // and only the append of the leader stalls.
+    for id in 4..6 {
+        let filter = RegionPacketFilter::new(1, id)
+            .direction(Direction::Recv)
+            .msg_type(MessageType::MsgAppend);
+        cluster.sim.wl().add_recv_filter(id, box filter);
+    }
+    let epoch = cluster.get_region_epoch(1);
+    let mut put = new_request(1, epoch, vec![new_put_cmd(b"k3", b"v3")], false);
+    put.mut_header().set_peer(new_peer(1, 1));
+    let _ = cluster.call_command(put, Duration::from_millis(100));
+
+    let timer = Instant::now();
+    loop {
+        let leader = cluster.query_leader(2, 1);
+        if leader.is_some() && leader != Some(new_peer(1, 1)) {
+            break;
+        }
+        if timer.elapsed() > Duration::from_secs(5) {
+            panic!("leader is still {:?} after its append stalls", leader);
+        }
+        sleep_ms(100);
+    }
+
+    stalled.store(false, Ordering::SeqCst);
+    for id in 4..6 {
+        cluster.sim.wl().clear_recv_filters(id);
+    }
+    cluster.must_put(b"k4", b"v4");
+    for id in 1..6 {
+        must_get_equal(&cluster.get_engine(id), b"k4", b"v4");
+    }
+}
+
+#[test]
+fn test_node_transfer_leader_on_stalled_append() {
+    let mut cluster = new_node_cluster(0, 5);
+    test_transfer_leader_on_stalled_append(&mut cluster);
+}
+
+#[test]
+fn test_server_transfer_leader_on_stalled_append() {
+    let mut cluster = new_server_cluster(0, 5);
+    test_transfer_leader_on_stalled_append(&mut cluster);
+}
@@ -17,7 +17,7 @@ use std::time::Duration;
 use std::thread;
 
 use rocksdb::DB;
-use protobuf;
+use protobuf::{self, RepeatedField};
 use time::Duration as TimeDuration;
 
 use kvproto::metapb::{self, RegionEpoch};
@@ -200,6 +200,14 @@ pub fn new_change_peer_request(change_type: ConfChangeType, peer: metapb::Peer)
     req
 }
 
+pub fn new_membership_change_request(peers: Vec<metapb::Peer>) -> AdminRequest {
+    let mut req = AdminRequest::new();
+    req.set_cmd_type(AdminCmdType::ChangePeer);
+    req.mut_change_peer().set_change_type(ConfChangeType::BeginMembershipChange);
+    req.mut_change_peer().set_peers(RepeatedField::from_vec(peers));
+    req
+}
+
 pub fn new_transfer_leader_cmd(peer: metapb::Peer) -> AdminRequest {
     let mut cmd = AdminRequest::new();
     cmd.set_cmd_type(AdminCmdType::TransferLeader);
@@ -248,12 +256,26 @@ pub fn new_pd_add_change_peer(region: &metapb::Region,
                               -> Option<RegionHeartbeatResponse> {
     if let Some(p) = find_peer(region, peer.get_store_id()) {
         assert_eq!(p.get_id(), peer.get_id());
-        return None;
+        // A learner is promoted by adding it again.
+        if !p.get_is_learner() {
+            return None;
+        }
     }
 
     Some(new_pd_change_peer(ConfChangeType::AddNode, peer))
 }
 
+pub fn new_pd_add_learner_change_peer(region: &metapb::Region,
+                                      peer: metapb::Peer)
+                                      -> Option<RegionHeartbeatResponse> {
+    if let Some(p) = find_peer(region, peer.get_store_id()) {
+        assert_eq!(p.get_id(), peer.get_id());
+        return None;
+    }
+
+    Some(new_pd_change_peer(ConfChangeType::AddLearnerNode, peer))
+}
+
 pub fn new_pd_remove_change_peer(region: &metapb::Region,
                                  peer: metapb::Peer)
                                  -> Option<RegionHeartbeatResponse> {
@@ -19,6 +19,7 @@ use tikv::storage::txn;
 use raftstore::cluster::Cluster;
 use raftstore::server::ServerCluster;
 use tikv::util::HandyRwLock;
+use tikv::util::collections::HashMap;
 use super::util::new_raft_storage_with_store_count;
 use tikv::storage::config::Config;
 use tikv::storage::engine;
@@ -211,6 +212,29 @@ impl AssertionStorage {
         assert_eq!(result, expect);
     }
 
+    pub fn reverse_scan_ok(&self,
+                           start_key: &[u8],
+                           end_key: &[u8],
+                           limit: usize,
+                           ts: u64,
+                           expect: Vec<Option<(&[u8], &[u8])>>) {
+        let end_key = if end_key.is_empty() {
+            None
+        } else {
+            Some(make_key(end_key))
+        };
+        let result = self.store
+            .reverse_scan(self.ctx.clone(), make_key(start_key), end_key, limit, ts)
+            .unwrap();
+        let result: Vec<Option<KvPair>> = result.into_iter()
+            .map(Result::ok)
+            .collect();
+        let expect: Vec<Option<KvPair>> = expect.into_iter()
+            .map(|x| x.map(|(k, v)| (k.to_vec(), v.to_vec())))
+            .collect();
+        assert_eq!(result, expect);
+    }
+
     pub fn scan_key_only_ok(&self,
                             start_key: &[u8],
                             limit: usize,
@@ -283,16 +307,45 @@ impl AssertionStorage {
     }
 
     pub fn scan_lock_ok(&self, max_ts: u64, expect: Vec<LockInfo>) {
-        assert_eq!(self.store.scan_lock(self.ctx.clone(), max_ts).unwrap(),
+        assert_eq!(self.store.scan_lock(self.ctx.clone(), max_ts, None, None, 0).unwrap(),
+                   expect);
+    }
+
+    pub fn scan_lock_range_ok(&self,
+                              max_ts: u64,
+                              start_key: &[u8],
+                              end_key: &[u8],
+                              limit: usize,
+                              expect: Vec<LockInfo>) {
+        let start_key = if start_key.is_empty() {
+            None
+        } else {
+            Some(make_key(start_key))
+        };
+        let end_key = if end_key.is_empty() {
+            None
+        } else {
+            Some(make_key(end_key))
+        };
+        assert_eq!(self.store
+                       .scan_lock(self.ctx.clone(), max_ts, start_key, end_key, limit)
+                       .unwrap(),
                    expect);
     }
 
     pub fn resolve_lock_ok(&self, start_ts: u64, commit_ts: Option<u64>) {
-        self.store.resolve_lock(self.ctx.clone(), start_ts, commit_ts).unwrap();
+        self.resolve_lock_batch_ok(vec![(start_ts, commit_ts.unwrap_or(0))]);
+    }
+
+    pub fn resolve_lock_batch_ok(&self, txns: Vec<(u64, u64)>) {
+        let txn_status = txns.into_iter().collect();
+        self.store.resolve_lock(self.ctx.clone(), txn_status).unwrap();
     }
 
     pub fn resolve_lock_with_illegal_tso(&self, start_ts: u64, commit_ts: Option<u64>) {
-        let resp = self.store.resolve_lock(self.ctx.clone(), start_ts, commit_ts);
+        let mut txn_status = HashMap::default();
+        txn_status.insert(start_ts, commit_ts.unwrap());
+        let resp = self.store.resolve_lock(self.ctx.clone(), txn_status);
         self.expect_invalid_tso_err(resp, start_ts, commit_ts.unwrap())
     }
 
@@ -328,6 +381,52 @@ impl AssertionStorage {
         self.store.raw_delete(self.ctx.clone(), key).unwrap()
     }
 
+    pub fn raw_batch_get_ok(&self, keys: Vec<&[u8]>, expect: Vec<(&[u8], &[u8])>) {
+        let keys = keys.into_iter().map(|k| k.to_vec()).collect();
+        let result: Vec<KvPair> = self.store
+            .raw_batch_get(self.ctx.clone(), keys)
+            .unwrap()
+            .into_iter()
+            .map(|x| x.unwrap())
+            .collect();
+        let expect: Vec<KvPair> =
+            expect.into_iter().map(|(k, v)| (k.to_vec(), v.to_vec())).collect();
+        assert_eq!(result, expect);
+    }
+
+    pub fn raw_batch_put_ok(&self, pairs: Vec<(&[u8], &[u8])>) {
+        let pairs = pairs.into_iter().map(|(k, v)| (k.to_vec(), v.to_vec())).collect();
+        self.store.raw_batch_put(self.ctx.clone(), pairs).unwrap();
+    }
+
+    pub fn raw_batch_delete_ok(&self, keys: Vec<&[u8]>) {
+        let keys = keys.into_iter().map(|k| k.to_vec()).collect();
+        self.store.raw_batch_delete(self.ctx.clone(), keys).unwrap();
+    }
+
+    pub fn raw_scan_ok(&self,
+                       start_key: &[u8],
+                       limit: usize,
+                       key_only: bool,
+                       reverse: bool,
+                       expect: Vec<(&[u8], &[u8])>) {
+        let result: Vec<KvPair> = self.store
+            .raw_scan(self.ctx.clone(), start_key.to_vec(), limit, key_only, reverse)
+            .unwrap()
+            .into_iter()
+            .map(|x| x.unwrap())
+            .collect();
+        let expect: Vec<KvPair> =
+            expect.into_iter().map(|(k, v)| (k.to_vec(), v.to_vec())).collect();
+        assert_eq!(result, expect);
+    }
+
+    pub fn raw_delete_range_ok(&self, start_key: &[u8], end_key: &[u8]) {
+        self.store
+            .raw_delete_range(self.ctx.clone(), start_key.to_vec(), end_key.to_vec())
+            .unwrap();
+    }
+
     pub fn test_txn_store_gc(&self, key: &str) {
         let key_bytes = key.as_bytes();
         self.put_ok(key_bytes, b"v1", 5, 10);
@@ -13,9 +13,13 @@
 
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
 use tikv::storage::{Storage, Engine, Key, Value, KvPair, Mutation, Result, Options};
 use tikv::storage::config::Config;
+use tikv::pd::PdClient;
+use tikv::server::transport::RaftStoreRouter;
+use tikv::util::collections::HashMap;
 use kvproto::kvrpcpb::{Context, LockInfo};
 
 /// `SyncStorage` wraps `Storage` with sync API, usually used for testing.
@@ -47,6 +51,14 @@ impl SyncStorage {
         self.store.get_engine()
     }
 
+    #[allow(dead_code)]
+    pub fn start_auto_gc<C, R>(&self, pd_client: Arc<C>, router: R, interval: Duration)
+        where C: PdClient + 'static,
+              R: RaftStoreRouter + 'static
+    {
+        self.store.start_auto_gc(pd_client, router, interval).unwrap()
+    }
+
     pub fn get(&self, ctx: Context, key: &Key, start_ts: u64) -> Result<Option<Value>> {
         wait_op!(|cb| self.store.async_get(ctx, key.to_owned(), start_ts, cb).unwrap()).unwrap()
     }
@@ -72,9 +84,33 @@ impl SyncStorage {
                 self.store
                     .async_scan(ctx,
                                 key,
+                                None,
                                 limit,
                                 start_ts,
                                 Options::new(0, false, key_only),
+                                false,
+                                cb)
+                    .unwrap()
+            })
+            .unwrap()
+    }
+
+    pub fn reverse_scan(&self,
+                        ctx: Context,
+                        key: Key,
+                        end_key: Option<Key>,
+                        limit: usize,
+                        start_ts: u64)
+                        -> Result<Vec<Result<KvPair>>> {
+        wait_op!(|cb| {
+                self.store
+                    .async_scan(ctx,
+                                key,
+                                end_key,
+                                limit,
+                                start_ts,
+                                Options::default(),
+                                true,
                                 cb)
                     .unwrap()
             })
@@ -112,12 +148,23 @@ impl SyncStorage {
         wait_op!(|cb| self.store.async_rollback(ctx, keys, start_ts, cb).unwrap()).unwrap()
     }
 
-    pub fn scan_lock(&self, ctx: Context, max_ts: u64) -> Result<Vec<LockInfo>> {
-        wait_op!(|cb| self.store.async_scan_lock(ctx, max_ts, cb).unwrap()).unwrap()
+    pub fn scan_lock(&self,
+                     ctx: Context,
+                     max_ts: u64,
+                     start_key: Option<Key>,
+                     end_key: Option<Key>,
+                     limit: usize)
+                     -> Result<Vec<LockInfo>> {
+        wait_op!(|cb| {
+                self.store
+                    .async_scan_lock(ctx, max_ts, start_key, end_key, limit, cb)
+                    .unwrap()
+            })
+            .unwrap()
     }
 
-    pub fn resolve_lock(&self, ctx: Context, start_ts: u64, commit_ts: Option<u64>) -> Result<()> {
-        wait_op!(|cb| self.store.async_resolve_lock(ctx, start_ts, commit_ts, cb).unwrap()).unwrap()
+    pub fn resolve_lock(&self, ctx: Context, txn_status: HashMap<u64, u64>) -> Result<()> {
+        wait_op!(|cb| self.store.async_resolve_lock(ctx, txn_status, cb).unwrap()).unwrap()
     }
 
     pub fn gc(&self, ctx: Context, safe_point: u64) -> Result<()> {
@@ -129,12 +176,71 @@ impl SyncStorage {
     }
 
     pub fn raw_put(&self, ctx: Context, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
-        wait_op!(|cb| self.store.async_raw_put(ctx, key, value, cb).unwrap()).unwrap()
+        self.raw_put_with_ttl(ctx, key, value, 0)
+    }
+
+    pub fn raw_put_with_ttl(&self,
+                            ctx: Context,
+                            key: Vec<u8>,
+                            value: Vec<u8>,
+                            ttl: u64)
+                            -> Result<()> {
+        wait_op!(|cb| self.store.async_raw_put(ctx, key, value, ttl, cb).unwrap()).unwrap()
     }
 
     pub fn raw_delete(&self, ctx: Context, key: Vec<u8>) -> Result<()> {
         wait_op!(|cb| self.store.async_raw_delete(ctx, key, cb).unwrap()).unwrap()
     }
+
+    pub fn raw_batch_get(&self, ctx: Context, keys: Vec<Vec<u8>>) -> Result<Vec<Result<KvPair>>> {
+        wait_op!(|cb| self.store.async_raw_batch_get(ctx, keys, cb).unwrap()).unwrap()
+    }
+
+    pub fn raw_batch_put(&self, ctx: Context, pairs: Vec<KvPair>) -> Result<()> {
+        wait_op!(|cb| self.store.async_raw_batch_put(ctx, pairs, 0, cb).unwrap()).unwrap()
+    }
+
+    pub fn raw_batch_delete(&self, ctx: Context, keys: Vec<Vec<u8>>) -> Result<()> {
+        wait_op!(|cb| self.store.async_raw_batch_delete(ctx, keys, cb).unwrap()).unwrap()
+    }
+
+    pub fn raw_scan(&self,
+                    ctx: Context,
+                    start_key: Vec<u8>,
+                    limit: usize,
+                    key_only: bool,
+                    reverse: bool)
+                    -> Result<Vec<Result<KvPair>>> {
+        wait_op!(|cb| {
+                self.store
+                    .async_raw_scan(ctx, start_key, limit, key_only, reverse, cb)
+                    .unwrap()
+            })
+            .unwrap()
+    }
+
+    pub fn raw_delete_range(&self,
+                            ctx: Context,
+                            start_key: Vec<u8>,
+                            end_key: Vec<u8>)
+                            -> Result<()> {
+        wait_op!(|cb| self.store.async_raw_delete_range(ctx, start_key, end_key, cb).unwrap())
+            .unwrap()
+    }
+
+    pub fn raw_compare_and_swap(&self,
+                                ctx: Context,
+                                key: Vec<u8>,
+                                previous_value: Option<Value>,
+                                value: Option<Value>)
+                                -> Result<(Option<Value>, bool)> {
+        wait_op!(|cb| {
+                self.store
+                    .async_raw_compare_and_swap(ctx, key, previous_value, value, 0, cb)
+                    .unwrap()
+            })
+            .unwrap()
+    }
 }
 
 impl Clone for SyncStorage {
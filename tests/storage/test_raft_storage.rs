@@ -12,14 +12,16 @@
 // limitations under the License.
 
 use std::sync::mpsc::channel;
+use std::thread;
 use std::time::Duration;
 use tikv::util::HandyRwLock;
 use tikv::storage::{self, Storage, Mutation, make_key, ALL_CFS, Options, Engine};
 use tikv::storage::{txn, engine, mvcc};
 use tikv::storage::config::Config;
+use tikv::server::transport::ServerRaftStoreRouter;
 use kvproto::kvrpcpb::Context;
 use raftstore::server::new_server_cluster_with_cfs;
-use raftstore::cluster::Cluster;
+use raftstore::cluster::{Cluster, Simulator};
 use raftstore::server::ServerCluster;
 use raftstore::util::*;
 use storage::util;
@@ -50,7 +52,7 @@ fn test_raft_storage() {
     assert!(storage.get(ctx.clone(), &key, 20).is_err());
     assert!(storage.batch_get(ctx.clone(), &[key.clone()], 20).is_err());
     assert!(storage.scan(ctx.clone(), key.clone(), 1, false, 20).is_err());
-    assert!(storage.scan_lock(ctx.clone(), 20).is_err());
+    assert!(storage.scan_lock(ctx.clone(), 20, None, None, 0).is_err());
 }
 
 #[test]
@@ -104,7 +106,7 @@ fn test_raft_storage_store_not_match() {
     }
     assert!(storage.batch_get(ctx.clone(), &[key.clone()], 20).is_err());
     assert!(storage.scan(ctx.clone(), key.clone(), 1, false, 20).is_err());
-    assert!(storage.scan_lock(ctx.clone(), 20).is_err());
+    assert!(storage.scan_lock(ctx.clone(), 20, None, None, 0).is_err());
 }
 
 #[test]
@@ -185,3 +187,35 @@ fn test_scheduler_leader_change_twice() {
 
     rx.recv_timeout(Duration::from_secs(5)).unwrap();
 }
+
+#[test]
+fn test_auto_gc() {
+    let (cluster, storage, ctx) = new_raft_storage();
+    let key = make_key(b"k");
+    storage.prewrite(ctx.clone(),
+                  vec![Mutation::Put((key.clone(), b"v1".to_vec()))],
+                  b"k".to_vec(),
+                  10)
+        .unwrap();
+    storage.commit(ctx.clone(), vec![key.clone()], 10, 11).unwrap();
+    storage.prewrite(ctx.clone(),
+                  vec![Mutation::Put((key.clone(), b"v2".to_vec()))],
+                  b"k".to_vec(),
+                  20)
+        .unwrap();
+    storage.commit(ctx.clone(), vec![key.clone()], 20, 21).unwrap();
+
+    let store_id = ctx.get_peer().get_store_id();
+    let router = ServerRaftStoreRouter::new(cluster.sim.rl().get_store_sendch(store_id).unwrap());
+    storage.start_auto_gc(cluster.pd_client.clone(), router, Duration::from_millis(100));
+    cluster.pd_client.set_gc_safe_point(30);
+
+    for _ in 0..50 {
+        if storage.get(ctx.clone(), &key, 15).unwrap().is_none() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(storage.get(ctx.clone(), &key, 15).unwrap(), None);
+    assert_eq!(storage.get(ctx.clone(), &key, 25).unwrap().unwrap(), b"v2".to_vec());
+}
@@ -1,11 +1,12 @@
 use tikv::util::HandyRwLock;
 use tikv::storage::engine::*;
-use tikv::storage::{Key, CfName, CF_DEFAULT, CF_RAFT, Statistics};
+use tikv::storage::{Key, CfName, CF_DEFAULT, CF_RAFT, ALL_CFS, Statistics};
 use tikv::util::codec::bytes;
 use tikv::util::escape;
 use kvproto::kvrpcpb::Context;
 use raftstore::transport_simulate::IsolationFilterFactory;
 use raftstore::server::new_server_cluster_with_cfs;
+use raftstore::util::must_get_equal;
 use tikv::raftstore::store::engine::IterOption;
 
 #[test]
@@ -68,6 +69,38 @@ fn test_read_leader_in_lease() {
     assert_eq!(can_read(&ctx, storage.as_ref(), k2, v2), true);
 }
 
+#[test]
+fn test_ingest_sst() {
+    let count = 3;
+    let mut cluster = new_server_cluster_with_cfs(0, count, ALL_CFS);
+    cluster.run();
+
+    // make sure leader has been elected.
+    assert_eq!(cluster.must_get(b"k1"), None);
+
+    let region = cluster.get_region(b"");
+    let leader = cluster.leader_of_region(region.get_id()).unwrap();
+    let storage = cluster.sim.rl().storages[&leader.get_id()].clone();
+
+    let mut ctx = Context::new();
+    ctx.set_region_id(region.get_id());
+    ctx.set_region_epoch(region.get_region_epoch().clone());
+    ctx.set_peer(leader.clone());
+
+    let kvs: Vec<_> = (0..10)
+        .map(|i| (make_key(format!("k{}", i).as_bytes()), format!("v{}", i).into_bytes()))
+        .collect();
+    storage.write(&ctx, vec![Modify::Ingest(CF_DEFAULT, kvs.clone())]).unwrap();
+
+    // Only the metadata of the file goes through raft, every replica ingests the file it
+    // received out of band.
+    for engine in cluster.engines.values() {
+        for &(ref k, ref v) in &kvs {
+            must_get_equal(engine, k.encoded(), v);
+        }
+    }
+}
+
 pub fn make_key(k: &[u8]) -> Key {
     Key::from_raw(k)
 }
@@ -19,11 +19,14 @@ use std::thread;
 use rand::random;
 use super::sync_storage::SyncStorage;
 use kvproto::kvrpcpb::{Context, LockInfo};
-use tikv::storage::{self, Mutation, Key, make_key, ALL_CFS, Storage};
+use tikv::storage::{self, Mutation, Key, make_key, ALL_CFS, CF_RAW_TTL, SHORT_VALUE_MAX_LEN,
+                    Storage};
 use tikv::storage::engine::{self, TEMP_DIR, Engine};
-use tikv::storage::txn::{GC_BATCH_SIZE, RESOLVE_LOCK_BATCH_SIZE};
+use tikv::storage::txn::{RESOLVE_LOCK_BATCH_SIZE, RAW_DELETE_RANGE_BATCH_SIZE};
+use tikv::storage::gc_worker::GC_BATCH_SIZE;
 use tikv::storage::mvcc::MAX_TXN_WRITE_SIZE;
 use tikv::storage::config::Config;
+use tikv::storage::ttl;
 
 use super::util::new_raft_engine;
 use super::assert_storage::AssertionStorage;
@@ -217,6 +220,62 @@ fn test_txn_store_scan() {
     check_v40();
 }
 
+fn test_txn_store_reverse_scan_impl(store: AssertionStorage) {
+    // A(10) - B(20) - C(10) - D(_) - E(10)
+    store.put_ok(b"A", b"A10", 5, 10);
+    store.put_ok(b"C", b"C10", 5, 10);
+    store.put_ok(b"E", b"E10", 5, 10);
+    store.put_ok(b"B", b"B20", 15, 20);
+    store.put_ok(b"D", b"D20", 15, 20);
+    store.delete_ok(b"D", 25, 30);
+
+    store.reverse_scan_ok(b"F", b"", 0, 30, vec![]);
+    store.reverse_scan_ok(b"F",
+                          b"",
+                          5,
+                          30,
+                          vec![Some((b"E", b"E10")),
+                               Some((b"C", b"C10")),
+                               Some((b"B", b"B20")),
+                               Some((b"A", b"A10"))]);
+    store.reverse_scan_ok(b"F",
+                          b"",
+                          2,
+                          30,
+                          vec![Some((b"E", b"E10")), Some((b"C", b"C10"))]);
+    // The start key is exclusive.
+    store.reverse_scan_ok(b"E", b"", 1, 30, vec![Some((b"C", b"C10"))]);
+    store.reverse_scan_ok(b"E\x00", b"", 1, 30, vec![Some((b"E", b"E10"))]);
+    // The end key is inclusive.
+    store.reverse_scan_ok(b"F",
+                          b"C",
+                          5,
+                          30,
+                          vec![Some((b"E", b"E10")), Some((b"C", b"C10"))]);
+    store.reverse_scan_ok(b"F", b"C\x00", 5, 30, vec![Some((b"E", b"E10"))]);
+    store.reverse_scan_ok(b"F",
+                          b"B",
+                          5,
+                          15,
+                          vec![Some((b"E", b"E10")), Some((b"C", b"C10"))]);
+    store.reverse_scan_ok(b"F",
+                          b"B",
+                          5,
+                          25,
+                          vec![Some((b"E", b"E10")),
+                               Some((b"D", b"D20")),
+                               Some((b"C", b"C10")),
+                               Some((b"B", b"B20"))]);
+    store.reverse_scan_ok(b"A", b"", 5, 30, vec![]);
+}
+
+#[test]
+fn test_txn_store_reverse_scan() {
+    test_txn_store_reverse_scan_impl(AssertionStorage::default());
+    let (_cluster, store) = AssertionStorage::new_raft_storage_with_store_count(1, "");
+    test_txn_store_reverse_scan_impl(store);
+}
+
 #[test]
 fn test_txn_store_scan_key_only() {
     let store = AssertionStorage::default();
@@ -261,6 +320,36 @@ fn test_txn_store_scan_lock() {
                             lock(b"p2", b"p2", 10),
                             lock(b"s1", b"p1", 5),
                             lock(b"s2", b"p2", 10)]);
+    // scan locks in a range.
+    store.scan_lock_range_ok(10,
+                             b"p2",
+                             b"s2",
+                             0,
+                             vec![lock(b"p2", b"p2", 10), lock(b"s1", b"p1", 5)]);
+    store.scan_lock_range_ok(20,
+                             b"p3",
+                             b"",
+                             0,
+                             vec![lock(b"p3", b"p3", 20),
+                                  lock(b"s1", b"p1", 5),
+                                  lock(b"s2", b"p2", 10),
+                                  lock(b"s3", b"p3", 20)]);
+    // scan locks page by page.
+    store.scan_lock_range_ok(20,
+                             b"",
+                             b"",
+                             2,
+                             vec![lock(b"p1", b"p1", 5), lock(b"p2", b"p2", 10)]);
+    store.scan_lock_range_ok(20,
+                             b"p2\x00",
+                             b"",
+                             2,
+                             vec![lock(b"p3", b"p3", 20), lock(b"s1", b"p1", 5)]);
+    store.scan_lock_range_ok(10,
+                             b"s1\x00",
+                             b"",
+                             2,
+                             vec![lock(b"s2", b"p2", 10)]);
 }
 
 #[test]
@@ -284,6 +373,25 @@ fn test_txn_store_resolve_lock() {
     store.scan_lock_ok(30, vec![]);
 }
 
+#[test]
+fn test_txn_store_resolve_lock_of_txns() {
+    let store = AssertionStorage::default();
+
+    for &(primary, secondary, ts) in &[(b"p1", b"s1", 5), (b"p2", b"s2", 10), (b"p3", b"s3", 15)] {
+        store.prewrite_ok(vec![Mutation::Put((make_key(primary), b"v".to_vec())),
+                               Mutation::Put((make_key(secondary), b"v".to_vec()))],
+                          primary,
+                          ts);
+    }
+    // Rolls back txn 5 and commits txn 10 in one pass, the lock of txn 15 is left.
+    store.resolve_lock_batch_ok(vec![(5, 0), (10, 20)]);
+    store.get_none(b"p1", 30);
+    store.get_none(b"s1", 30);
+    store.get_ok(b"p2", 20, b"v");
+    store.get_ok(b"s2", 20, b"v");
+    store.scan_lock_ok(30, vec![lock(b"p3", b"p3", 15), lock(b"s3", b"p3", 15)]);
+}
+
 fn test_txn_store_resolve_lock_batch(key_prefix_len: usize, n: usize) {
     let prefix = String::from_utf8(vec![b'k'; key_prefix_len]).unwrap();
     let keys: Vec<String> = (0..n).map(|i| format!("{}{}", prefix, i)).collect();
@@ -430,6 +538,119 @@ fn test_txn_store_rawkv() {
     store.raw_get_ok(b"key".to_vec(), None);
 }
 
+#[test]
+fn test_txn_store_raw_batch() {
+    let store = AssertionStorage::default();
+    store.raw_batch_put_ok(vec![(b"a", b"aa"), (b"b", b"bb"), (b"c", b"cc")]);
+    store.raw_batch_get_ok(vec![b"a", b"x", b"c"], vec![(b"a", b"aa"), (b"c", b"cc")]);
+    store.raw_batch_delete_ok(vec![b"a", b"c"]);
+    store.raw_batch_get_ok(vec![b"a", b"b", b"c"], vec![(b"b", b"bb")]);
+}
+
+#[test]
+fn test_txn_store_raw_scan() {
+    let store = AssertionStorage::default();
+    store.raw_batch_put_ok(vec![(b"a", b"aa"), (b"b", b"bb"), (b"c", b"cc"), (b"d", b"dd")]);
+    store.raw_scan_ok(b"",
+                      10,
+                      false,
+                      false,
+                      vec![(b"a", b"aa"), (b"b", b"bb"), (b"c", b"cc"), (b"d", b"dd")]);
+    store.raw_scan_ok(b"b", 2, false, false, vec![(b"b", b"bb"), (b"c", b"cc")]);
+    store.raw_scan_ok(b"b", 2, true, false, vec![(b"b", b""), (b"c", b"")]);
+    store.raw_scan_ok(b"e",
+                      3,
+                      false,
+                      true,
+                      vec![(b"d", b"dd"), (b"c", b"cc"), (b"b", b"bb")]);
+    store.raw_scan_ok(b"c", 10, true, true, vec![(b"b", b""), (b"a", b"")]);
+    store.raw_scan_ok(b"a", 10, false, true, vec![]);
+}
+
+#[test]
+fn test_txn_store_raw_delete_range() {
+    let store = AssertionStorage::default();
+    store.raw_batch_put_ok(vec![(b"a", b"aa"), (b"b", b"bb"), (b"c", b"cc"), (b"d", b"dd")]);
+    store.raw_delete_range_ok(b"b", b"d");
+    store.raw_scan_ok(b"", 10, false, false, vec![(b"a", b"aa"), (b"d", b"dd")]);
+    store.raw_delete_range_ok(b"", b"");
+    store.raw_scan_ok(b"", 10, false, false, vec![]);
+
+    // Ranges larger than a single batch are deleted by several commands.
+    let n = RAW_DELETE_RANGE_BATCH_SIZE * 2 + 1;
+    let keys: Vec<Vec<u8>> = (0..n).map(|i| format!("k{:06}", i).into_bytes()).collect();
+    store.raw_batch_put_ok(keys.iter().map(|k| (k.as_slice(), b"v" as &[u8])).collect());
+    store.raw_delete_range_ok(b"k", b"l");
+    store.raw_scan_ok(b"", 10, true, false, vec![]);
+}
+
+#[test]
+fn test_txn_store_raw_compare_and_swap() {
+    let store = AssertionStorage::default();
+    let cas = |previous_value: Option<&[u8]>, value: Option<&[u8]>| {
+        store.store
+            .raw_compare_and_swap(Context::new(),
+                                  b"k".to_vec(),
+                                  previous_value.map(|v| v.to_vec()),
+                                  value.map(|v| v.to_vec()))
+            .unwrap()
+    };
+    // The key doesn't exist.
+    assert_eq!(cas(Some(b"v0"), Some(b"v1")), (None, false));
+    store.raw_get_ok(b"k".to_vec(), None);
+    assert_eq!(cas(None, Some(b"v1")), (None, true));
+    store.raw_get_ok(b"k".to_vec(), Some(b"v1".to_vec()));
+    // The previous value mismatches.
+    assert_eq!(cas(None, Some(b"v2")), (Some(b"v1".to_vec()), false));
+    assert_eq!(cas(Some(b"v0"), Some(b"v2")), (Some(b"v1".to_vec()), false));
+    store.raw_get_ok(b"k".to_vec(), Some(b"v1".to_vec()));
+    assert_eq!(cas(Some(b"v1"), Some(b"v2")), (Some(b"v1".to_vec()), true));
+    store.raw_get_ok(b"k".to_vec(), Some(b"v2".to_vec()));
+    // Delete.
+    assert_eq!(cas(Some(b"v2"), None), (Some(b"v2".to_vec()), true));
+    store.raw_get_ok(b"k".to_vec(), None);
+}
+
+#[test]
+fn test_txn_store_raw_ttl() {
+    let mut config = Config::default();
+    config.enable_ttl = true;
+    let store = AssertionStorage {
+        ctx: Context::new(),
+        store: SyncStorage::new(&config),
+    };
+    store.raw_put_ok(b"a".to_vec(), b"aa".to_vec());
+    store.store.raw_put_with_ttl(Context::new(), b"b".to_vec(), b"bb".to_vec(), 100).unwrap();
+    // Write an expired value directly.
+    let mut value = b"cc".to_vec();
+    ttl::append_expire_ts(&mut value, 1);
+    store.store
+        .get_engine()
+        .put_cf(&Context::new(), CF_RAW_TTL, Key::from_encoded(b"c".to_vec()), value)
+        .unwrap();
+    store.raw_batch_put_ok(vec![(b"d", b"dd")]);
+    // Transactional data is kept apart from the raw key-value pairs.
+    let long_value = vec![b'x'; SHORT_VALUE_MAX_LEN + 1];
+    store.put_ok(b"a", &long_value, 5, 10);
+
+    store.raw_get_ok(b"a".to_vec(), Some(b"aa".to_vec()));
+    store.raw_get_ok(b"b".to_vec(), Some(b"bb".to_vec()));
+    store.raw_get_ok(b"c".to_vec(), None);
+    store.raw_batch_get_ok(vec![b"a", b"c", b"d"], vec![(b"a", b"aa"), (b"d", b"dd")]);
+    store.raw_scan_ok(b"",
+                      3,
+                      false,
+                      false,
+                      vec![(b"a", b"aa"), (b"b", b"bb"), (b"d", b"dd")]);
+    store.raw_scan_ok(b"e", 2, true, true, vec![(b"d", b""), (b"b", b"")]);
+    store.get_ok(b"a", 20, &long_value);
+
+    // A ttl is rejected if ttl is not enabled.
+    let store = AssertionStorage::default();
+    let res = store.store.raw_put_with_ttl(Context::new(), b"a".to_vec(), b"aa".to_vec(), 100);
+    assert!(res.is_err());
+}
+
 #[test]
 fn test_txn_store_lock_primary() {
     let store = AssertionStorage::default();